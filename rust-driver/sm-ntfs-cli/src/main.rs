@@ -2,7 +2,17 @@
 //!
 //! Command-line interface for mounting and managing NTFS volumes.
 
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, IsTerminal, Read, Seek, SeekFrom, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::sync::OnceLock;
+use anyhow::Context;
 use clap::{Parser, Subcommand};
+use ntfs::Ntfs;
+use sm_ntfs_core::io::{rollback, BitLockerDevice, BitLockerKey, BlockDevice, UndoDevice, DEFAULT_SECTOR_SIZE};
+use sm_ntfs_core::parser::{export_file, file_extents, open_volume, open_volume_writable, probe_device, BlockDeviceAdapter, Filesystem, NtfsVolume, PatchedDevice, RecoveryChance, ReparsePoint, Sid, UserMapping, WriteOptions, WslMetadata};
+use sm_ntfs_core::utils::error::SMNtfsError;
 use sm_ntfs_core::utils::logging;
 
 #[derive(Parser)]
@@ -12,8 +22,27 @@ use sm_ntfs_core::utils::logging;
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+
+    /// Prompt for the BitLocker recovery password (8 groups of 6 digits), to read encrypted volumes
+    #[arg(long, global = true, default_value_t = false, conflicts_with_all = ["recovery_password_file", "bek", "password"])]
+    recovery_password: bool,
+
+    /// Read the BitLocker recovery password from the first line of a file
+    #[arg(long, global = true, conflicts_with_all = ["bek", "password"])]
+    recovery_password_file: Option<String>,
+
+    /// BitLocker startup key file (.BEK), to read encrypted volumes
+    #[arg(long, global = true, conflicts_with = "password")]
+    bek: Option<String>,
+
+    /// Prompt for the BitLocker password, to read encrypted volumes
+    #[arg(long, global = true, default_value_t = false)]
+    password: bool,
 }
 
+/// Key for BitLocker volumes, from the global options
+static BITLOCKER_KEY: OnceLock<BitLockerKey> = OnceLock::new();
+
 #[derive(Subcommand)]
 enum Commands {
     /// Mount an NTFS volume
@@ -37,8 +66,146 @@ enum Commands {
         mount_point: String,
     },
 
-    /// List NTFS volumes
+    /// List partitions with their filesystem and NTFS volume names
     List,
+
+    /// Show volume information and space usage
+    Info {
+        /// Device path (e.g., /dev/disk2s1)
+        #[arg(short, long)]
+        device: String,
+    },
+
+    /// Check the volume for inconsistencies without modifying it
+    Check {
+        /// Device path (e.g., /dev/disk2s1)
+        #[arg(short, long)]
+        device: String,
+
+        /// Apply the safe fixes (writes to the device)
+        #[arg(long, default_value_t = false, requires = "undo")]
+        repair: bool,
+
+        /// File to save the pre-image of every changed sector to (must not exist)
+        #[arg(long)]
+        undo: Option<String>,
+
+        /// Discard the session of a hibernated Windows instead of refusing to repair
+        #[arg(long, default_value_t = false, requires = "repair")]
+        remove_hiberfile: bool,
+    },
+
+    /// Undo a repair by writing back the sectors saved in its undo file
+    Rollback {
+        /// Device path (e.g., /dev/disk2s1)
+        #[arg(short, long)]
+        device: String,
+
+        /// Undo file written by `check --repair`
+        undo: String,
+    },
+
+    /// Show the on-disk extents of a file, like filefrag
+    Extents {
+        /// Device path (e.g., /dev/disk2s1)
+        #[arg(short, long)]
+        device: String,
+
+        /// Path of the file on the volume (e.g., /Users/alice/file.txt)
+        path: String,
+    },
+
+    /// Copy a file or stream to the host, keeping holes in sparse files
+    Extract {
+        /// Device path (e.g., /dev/disk2s1)
+        #[arg(short, long)]
+        device: String,
+
+        /// Path on the volume, optionally with a stream (e.g., /vm/disk.vhdx or /file.txt:stream)
+        path: String,
+
+        /// Host file to create (must not exist)
+        output: String,
+    },
+
+    /// Show the reparse point of a file and, for links, the host target
+    Readlink {
+        /// Device path (e.g., /dev/disk2s1)
+        #[arg(short, long)]
+        device: String,
+
+        /// Path of the link on the volume (e.g., /Users/alice/link)
+        path: String,
+    },
+
+    /// Show the security descriptor of a file and its POSIX mapping
+    Security {
+        /// Device path (e.g., /dev/disk2s1)
+        #[arg(short, long)]
+        device: String,
+
+        /// Path of the file on the volume (e.g., /Users/alice/file.txt)
+        path: String,
+
+        /// User mapping file with uid:gid:SID lines, as used by ntfs-3g
+        #[arg(short, long)]
+        usermap: Option<String>,
+    },
+
+    /// List the extended attributes of a file and the WSL metadata among them
+    Ea {
+        /// Device path (e.g., /dev/disk2s1)
+        #[arg(short, long)]
+        device: String,
+
+        /// Path of the file on the volume (e.g., /Users/alice/file.txt)
+        path: String,
+    },
+
+    /// List deleted files, or recover one to a host directory
+    Undelete {
+        /// Device path (e.g., /dev/disk2s1)
+        #[arg(short, long)]
+        device: String,
+
+        /// MFT record number of the file to recover
+        #[arg(short, long, requires = "output")]
+        record: Option<u64>,
+
+        /// Directory to write recovered streams to (must not be on the device)
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+
+    /// Print change journal ($UsnJrnl) records
+    Usn {
+        /// Device path (e.g., /dev/disk2s1)
+        #[arg(short, long)]
+        device: String,
+
+        /// First USN to print (defaults to the oldest record)
+        #[arg(long)]
+        from: Option<u64>,
+
+        /// Keep printing records as they are appended
+        #[arg(short, long, default_value_t = false)]
+        follow: bool,
+    },
+
+    /// Show transactions pending in $LogFile, or replay them
+    Logfile {
+        /// Device path (e.g., /dev/disk2s1)
+        #[arg(short, long)]
+        device: String,
+
+        /// Apply pending transactions and mark the log clean (writes to the device)
+        #[arg(long, default_value_t = false)]
+        replay: bool,
+
+        /// Discard the session of a hibernated Windows instead of refusing to replay
+        #[arg(long, default_value_t = false, requires = "replay")]
+        remove_hiberfile: bool,
+    },
 }
 
 #[tokio::main]
@@ -47,6 +214,9 @@ async fn main() -> anyhow::Result<()> {
     logging::init_logging();
 
     let cli = Cli::parse();
+    if let Some(key) = bitlocker_key(&cli)? {
+        let _ = BITLOCKER_KEY.set(key);
+    }
 
     match cli.command {
         Commands::Mount {
@@ -60,6 +230,11 @@ async fn main() -> anyhow::Result<()> {
                 mount_point,
                 read_write
             );
+            // Only refuses volumes that are unsafe to write; mounting does not exist yet,
+            // so nothing irreversible such as removing hiberfil.sys may happen here
+            if read_write {
+                open_ntfs_with_options(&device, Some(WriteOptions::default()))?;
+            }
             println!("TODO: Implement mount functionality");
             // TODO: Implement in Week 1-2
         }
@@ -70,10 +245,692 @@ async fn main() -> anyhow::Result<()> {
         }
         Commands::List => {
             tracing::info!("Listing NTFS volumes");
-            println!("TODO: Implement list functionality");
-            // TODO: Implement in Week 1-2
+            list_volumes()?;
+        }
+        Commands::Info { device } => {
+            print_info(&device)?;
+        }
+        Commands::Check { device, repair, undo, remove_hiberfile } => match (repair, undo) {
+            (true, Some(undo)) => repair_volume(&device, &undo, remove_hiberfile)?,
+            _ => check_volume(&device)?,
+        },
+        Commands::Rollback { device, undo } => {
+            rollback_repair(&device, &undo)?;
+        }
+        Commands::Extents { device, path } => {
+            print_extents(&device, &path)?;
+        }
+        Commands::Extract { device, path, output } => {
+            extract_stream(&device, &path, &output)?;
+        }
+        Commands::Readlink { device, path } => {
+            print_reparse_point(&device, &path)?;
+        }
+        Commands::Security { device, path, usermap } => {
+            print_security(&device, &path, usermap.as_deref())?;
+        }
+        Commands::Ea { device, path } => {
+            print_extended_attributes(&device, &path)?;
+        }
+        Commands::Undelete { device, record, output } => match (record, output) {
+            (Some(record), Some(output)) => recover_deleted(&device, record, &output)?,
+            _ => print_deleted(&device)?,
+        },
+        Commands::Usn { device, from, follow } => {
+            print_usn_journal(&device, from, follow)?;
+        }
+        Commands::Logfile { device, replay, remove_hiberfile } => {
+            if replay {
+                replay_logfile(&device, remove_hiberfile)?;
+            } else {
+                print_logfile(&device)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// A device, decrypted on read if it is BitLocker-encrypted
+enum VolumeDevice {
+    Plain(BlockDeviceAdapter),
+    BitLocker(BitLockerDevice<BlockDeviceAdapter>),
+}
+
+impl Read for VolumeDevice {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::Plain(device) => device.read(buf),
+            Self::BitLocker(device) => device.read(buf),
+        }
+    }
+}
+
+impl Write for VolumeDevice {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Plain(device) => device.write(buf),
+            Self::BitLocker(_) => Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "BitLocker volumes are read-only",
+            )),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Plain(device) => device.flush(),
+            Self::BitLocker(_) => Ok(()),
+        }
+    }
+}
+
+impl Seek for VolumeDevice {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            Self::Plain(device) => device.seek(pos),
+            Self::BitLocker(device) => device.seek(pos),
+        }
+    }
+}
+
+/// The BitLocker key given on the command line, if any
+///
+/// Secrets are prompted for or read from a file, never taken as arguments,
+/// which other users can see in the process list.
+fn bitlocker_key(cli: &Cli) -> anyhow::Result<Option<BitLockerKey>> {
+    if cli.recovery_password {
+        return Ok(Some(BitLockerKey::RecoveryPassword(prompt_secret("BitLocker recovery password")?)));
+    }
+    if let Some(path) = &cli.recovery_password_file {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read recovery password file {}", path))?;
+        let password = contents.lines().next().unwrap_or_default().trim().to_string();
+        return Ok(Some(BitLockerKey::RecoveryPassword(password)));
+    }
+    if let Some(bek) = &cli.bek {
+        let data = std::fs::read(bek).with_context(|| format!("Failed to read startup key file {}", bek))?;
+        return Ok(Some(BitLockerKey::KeyFile(data)));
+    }
+    if cli.password {
+        return Ok(Some(BitLockerKey::Password(prompt_secret("BitLocker password")?)));
+    }
+    Ok(None)
+}
+
+/// Terminal settings, restored when dropped
+struct TerminalGuard {
+    fd: i32,
+    original: libc::termios,
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        // SAFETY: restores settings read from the same descriptor
+        unsafe {
+            libc::tcsetattr(self.fd, libc::TCSANOW, &self.original);
+        }
+    }
+}
+
+/// Read a line from stdin without echoing it to the terminal
+///
+/// The prompt and echo handling are skipped when stdin is not a terminal,
+/// so secrets can be piped in.
+fn prompt_secret(prompt: &str) -> anyhow::Result<String> {
+    let stdin = io::stdin();
+    let mut guard = None;
+    if stdin.is_terminal() {
+        eprint!("{}: ", prompt);
+        io::stderr().flush()?;
+
+        let fd = stdin.as_raw_fd();
+        // SAFETY: termios is plain data, filled in by tcgetattr before use
+        let mut original: libc::termios = unsafe { std::mem::zeroed() };
+        if unsafe { libc::tcgetattr(fd, &mut original) } != 0 {
+            return Err(io::Error::last_os_error()).context("Failed to read the terminal settings");
+        }
+        let mut silent = original;
+        // Hide what is typed but still echo the final newline
+        silent.c_lflag &= !libc::ECHO;
+        silent.c_lflag |= libc::ECHONL;
+        if unsafe { libc::tcsetattr(fd, libc::TCSAFLUSH, &silent) } != 0 {
+            return Err(io::Error::last_os_error()).context("Failed to turn off terminal echo");
+        }
+        guard = Some(TerminalGuard { fd, original });
+    }
+
+    let mut secret = String::new();
+    stdin.lock().read_line(&mut secret)?;
+    drop(guard);
+    Ok(secret.trim_end_matches(['\r', '\n']).to_string())
+}
+
+/// Unlock a BitLocker device with the key given on the command line
+///
+/// Anything else, and BitLocker devices without a key, are returned as they
+/// are, for [`open_volume`] to accept or refuse.
+fn unlock_device(device: BlockDevice, read_only: bool) -> anyhow::Result<VolumeDevice> {
+    let mut fs = BlockDeviceAdapter::new(device);
+    let Some(key) = BITLOCKER_KEY.get() else {
+        return Ok(VolumeDevice::Plain(fs));
+    };
+    if probe_device(&mut fs)? != Some(Filesystem::BitLocker) {
+        return Ok(VolumeDevice::Plain(fs));
+    }
+    if !read_only {
+        anyhow::bail!("BitLocker volumes can only be opened read-only");
+    }
+    let device = BitLockerDevice::unlock(fs, key).map_err(explain)?;
+    eprintln!("Unlocked BitLocker volume ({})", device.method().name());
+    Ok(VolumeDevice::BitLocker(device))
+}
+
+/// Open a device read-only and parse its NTFS structures
+fn open_ntfs(device: &str) -> anyhow::Result<(PatchedDevice<VolumeDevice>, Ntfs)> {
+    open_ntfs_with_options(device, None)
+}
+
+/// Open a device and parse its NTFS structures
+///
+/// A damaged boot sector or system record is replaced by its backup copy,
+/// with a warning on stderr. BitLocker volumes are unlocked with the key
+/// given on the command line. With `write` options, the device is opened
+/// read-write and volumes Windows has not released are refused.
+fn open_ntfs_with_options(
+    device: &str,
+    write: Option<WriteOptions>,
+) -> anyhow::Result<(PatchedDevice<VolumeDevice>, Ntfs)> {
+    let read_only = write.is_none();
+    let device = BlockDevice::open_with_options(device, read_only)
+        .with_context(|| format!("Failed to open {}", device))?;
+    let fs = unlock_device(device, read_only)?;
+    let opened = match write {
+        Some(options) => open_volume_writable(fs, options),
+        None => open_volume(fs),
+    }
+    .map_err(explain)?;
+    for warning in &opened.warnings {
+        eprintln!("Warning: {}", warning);
+    }
+    Ok((opened.fs, opened.ntfs))
+}
+
+/// Print the filesystem of every partition, with the name of NTFS volumes
+fn list_volumes() -> anyhow::Result<()> {
+    let mut devices: Vec<String> = std::fs::read_dir("/dev")
+        .context("Failed to list /dev")?
+        .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+        .filter(|name| is_partition_name(name))
+        .map(|name| format!("/dev/{}", name))
+        .collect();
+    devices.sort();
+
+    for device in &devices {
+        match describe_volume(device) {
+            Ok(description) => println!("{:<16} {}", device, description),
+            Err(e) => println!("{:<16} {}", device, e.user_message()),
+        }
+    }
+    Ok(())
+}
+
+/// Check for a partition device name such as `disk2s1`
+fn is_partition_name(name: &str) -> bool {
+    let Some((disk, partition)) = name.strip_prefix("disk").and_then(|rest| rest.split_once('s')) else {
+        return false;
+    };
+    [disk, partition]
+        .iter()
+        .all(|number| !number.is_empty() && number.bytes().all(|byte| byte.is_ascii_digit()))
+}
+
+/// Filesystem, size and, for NTFS, the volume name of a device
+fn describe_volume(device: &str) -> Result<String, SMNtfsError> {
+    let block_device = BlockDevice::open_with_options(device, true)?;
+    let size = block_device.device_size();
+    let mut fs = BlockDeviceAdapter::new(block_device);
+
+    let (filesystem, name) = match probe_device(&mut fs)? {
+        Some(Filesystem::Ntfs) => {
+            let opened = open_volume(fs)?;
+            let (mut fs, ntfs) = (opened.fs, opened.ntfs);
+            let volume = NtfsVolume::new(&ntfs, &mut fs)?;
+            let name = volume.volume_name().to_string();
+            (Filesystem::Ntfs.name(), name)
+        }
+        Some(filesystem) => (filesystem.name(), String::new()),
+        None => ("unknown", String::new()),
+    };
+    Ok(format!("{:<10} {:>16} bytes  {}", filesystem, size, name).trim_end().to_string())
+}
+
+/// Add the user-facing explanation of a core error
+fn explain(error: SMNtfsError) -> anyhow::Error {
+    let message = error.user_message();
+    anyhow::Error::new(error).context(message)
+}
+
+/// Print volume geometry and free space from $Bitmap
+fn print_info(device: &str) -> anyhow::Result<()> {
+    let (mut fs, ntfs) = open_ntfs(device)?;
+    let volume = NtfsVolume::new(&ntfs, &mut fs)?;
+    let bitmap = volume.cluster_bitmap(&mut fs)?;
+    let cluster_size = volume.cluster_size() as u64;
+
+    let unknown = || "unknown".to_string();
+
+    println!("Volume name:   {}", volume.volume_name());
+    println!("Serial number: {:016X}", volume.serial_number());
+    match volume.volume_information() {
+        Some(info) => {
+            let flags = info.flags.names();
+            println!("NTFS version:  {}", info.version());
+            println!("Flags:         {}", if flags.is_empty() { "-".to_string() } else { flags.join(" ") });
+            if info.is_chkdsk_scheduled() {
+                println!("               (not cleanly unmounted, chkdsk is scheduled)");
+            }
+        }
+        None => println!("NTFS version:  unknown ($Volume could not be read)"),
+    }
+    println!("Cluster size:  {} bytes", cluster_size);
+    println!("Sector size:   {} bytes", volume.sector_size());
+    println!("MFT record:    {} bytes", volume.file_record_size());
+    println!(
+        "Index record:  {}",
+        volume.index_record_size().map_or_else(unknown, |size| format!("{} bytes", size))
+    );
+    println!("MFT at:        byte {}", volume.mft_offset());
+    println!(
+        "MFT mirror at: {}",
+        volume.mft_mirror_offset().map_or_else(unknown, |offset| format!("byte {}", offset))
+    );
+    println!("Total:         {} clusters ({} bytes)", bitmap.cluster_count(), volume.size());
+    println!(
+        "Used:          {} clusters ({} bytes)",
+        bitmap.allocated_clusters(),
+        bitmap.allocated_clusters() * cluster_size
+    );
+    println!(
+        "Free:          {} clusters ({} bytes)",
+        bitmap.free_clusters(),
+        bitmap.free_clusters() * cluster_size
+    );
+    Ok(())
+}
+
+/// Check a volume and print every problem found, failing if there are any
+fn check_volume(device: &str) -> anyhow::Result<()> {
+    let (mut fs, ntfs) = open_ntfs(device)?;
+    let volume = NtfsVolume::new(&ntfs, &mut fs)?;
+    let report = volume.check(&mut fs)?;
+
+    for problem in &report.problems {
+        println!("{}", problem);
+    }
+    println!(
+        "Checked {} MFT records and {} directories",
+        report.records_checked, report.directories_checked
+    );
+    if report.is_clean() {
+        println!("No problems found");
+        return Ok(());
+    }
+
+    for (class, count) in report.counts() {
+        println!("  {:<28} {}", class.name(), count);
+    }
+    anyhow::bail!("{} problems found", report.problems.len())
+}
+
+/// Repair a volume, saving sector pre-images to a new undo file
+fn repair_volume(device: &str, undo: &str, remove_hiberfile: bool) -> anyhow::Result<()> {
+    let undo_file = File::options()
+        .write(true)
+        .create_new(true)
+        .open(undo)
+        .with_context(|| format!("Failed to create undo file {}", undo))?;
+    let block_device = BlockDevice::open_with_options(device, false)
+        .with_context(|| format!("Failed to open {}", device))?;
+    let undo_device = UndoDevice::new(BlockDeviceAdapter::new(block_device), undo_file, DEFAULT_SECTOR_SIZE as u32)?;
+
+    let options = WriteOptions {
+        remove_hiberfile,
+        unclean_logfile: true,
+    };
+    let opened = open_volume_writable(undo_device, options).map_err(explain)?;
+    for warning in &opened.warnings {
+        eprintln!("Warning: {}", warning);
+    }
+    let (mut fs, ntfs) = (opened.fs, opened.ntfs);
+    let volume = NtfsVolume::new(&ntfs, &mut fs)?;
+    let report = volume.check(&mut fs)?;
+    for problem in &report.problems {
+        println!("{}", problem);
+    }
+    if report.repairable().next().is_none() && !volume.is_dirty() {
+        println!("Nothing to repair");
+        return Ok(());
+    }
+
+    let summary = volume.repair(&mut fs, &report)?;
+    println!("Link counts fixed:       {}", summary.link_counts_fixed);
+    println!("Index entries removed:   {}", summary.index_entries_removed);
+    println!("Clusters marked used:    {}", summary.clusters_marked_used);
+    println!("Clusters marked free:    {}", summary.clusters_marked_free);
+    println!("$MFTMirr records synced: {}", summary.mirror_records_synced);
+    println!(
+        "Undo file: {} ({} sectors saved; run `rollback` to undo)",
+        undo,
+        fs.get_mut().saved_sectors()
+    );
+
+    if !summary.remaining.is_empty() {
+        for problem in &summary.remaining {
+            println!("{}", problem);
         }
+        anyhow::bail!("{} problems need chkdsk on Windows", summary.remaining.len());
     }
+    if summary.dirty_flag_cleared {
+        println!("Volume marked clean");
+    }
+    Ok(())
+}
+
+/// Write back the sectors saved by a repair
+fn rollback_repair(device: &str, undo: &str) -> anyhow::Result<()> {
+    let undo_file = File::open(undo).with_context(|| format!("Failed to open undo file {}", undo))?;
+    let block_device = BlockDevice::open_with_options(device, false)
+        .with_context(|| format!("Failed to open {}", device))?;
+    let mut fs = BlockDeviceAdapter::new(block_device);
+
+    let restored = rollback(BufReader::new(undo_file), &mut fs)?;
+    println!("Restored {} sectors from {}", restored, undo);
+    Ok(())
+}
+
+/// Print the extents of every non-resident stream of a file
+fn print_extents(device: &str, path: &str) -> anyhow::Result<()> {
+    let (mut fs, ntfs) = open_ntfs(device)?;
+    let volume = NtfsVolume::new(&ntfs, &mut fs)?;
+    let file = volume.open_path(&mut fs, path)?;
+    let cluster_size = volume.cluster_size() as u64;
+
+    println!("Filesystem cluster size is {}", cluster_size);
+
+    let streams = file_extents(&file, &mut fs)?;
+    if streams.is_empty() {
+        println!("{}: resident, 0 extents found", path);
+    }
+
+    for stream in streams {
+        println!();
+        println!("{} {} ({} bytes)", path, stream.display_name(), stream.data_size);
+        println!(" ext: {:>21} {:>23} {:>10}", "logical_offset:", "physical_offset:", "length:");
+
+        for (i, extent) in stream.extents.iter().enumerate() {
+            let physical = match extent.lcn {
+                Some(lcn) => format!("{:>10}..{:>10}:", lcn, lcn + extent.length - 1),
+                None => format!("{:>22}:", "hole"),
+            };
+            println!(
+                "{:>4}: {:>10}..{:>10}: {} {:>9}:",
+                i,
+                extent.vcn,
+                extent.end_vcn() - 1,
+                physical,
+                extent.length
+            );
+        }
+
+        println!("{}: {} extents found", stream.display_name(), stream.extent_count());
+    }
+
+    Ok(())
+}
+
+/// Copy a stream to a new host file without writing its holes
+fn extract_stream(device: &str, path: &str, output: &str) -> anyhow::Result<()> {
+    let (mut fs, ntfs) = open_ntfs(device)?;
+    let volume = NtfsVolume::new(&ntfs, &mut fs)?;
+    let mut reader = volume.open_stream(&mut fs, path)?;
+    let data_bytes: u64 = reader.data_ranges().iter().map(|range| range.end - range.start).sum();
+
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(output)
+        .with_context(|| format!("Failed to create {}", output))?;
+    let length = reader.copy_sparse(&mut file)?;
+    file.set_len(length).with_context(|| format!("Failed to extend {}", output))?;
+
+    println!(
+        "Extracted {} bytes to {} ({} bytes of data, {} in holes)",
+        length,
+        output,
+        data_bytes,
+        length - data_bytes
+    );
+    Ok(())
+}
+
+/// Print the reparse tag of a file and where a link points on the host
+fn print_reparse_point(device: &str, path: &str) -> anyhow::Result<()> {
+    let (mut fs, ntfs) = open_ntfs(device)?;
+    let volume = NtfsVolume::new(&ntfs, &mut fs)?;
+    let file = volume.open_path(&mut fs, path)?;
+    let Some(point) = volume.reparse_point(&mut fs, &file)? else {
+        println!("{} is not a reparse point", path);
+        return Ok(());
+    };
+
+    println!("Tag:    {:#010x} ({})", point.tag().0, point.tag().name());
+    match &point {
+        ReparsePoint::Symlink { print_name, .. } | ReparsePoint::MountPoint { print_name, .. } => {
+            println!("Target: {}", print_name);
+        }
+        ReparsePoint::LxSymlink { target } => println!("Target: {}", target),
+        _ => {}
+    }
+    if let Some(target) = point.host_target(path) {
+        println!("Host:   {}", target);
+    }
+    Ok(())
+}
+
+/// Print a file's owner, group and DACL as SDDL, and the POSIX view of them
+fn print_security(device: &str, path: &str, usermap: Option<&str>) -> anyhow::Result<()> {
+    let mapping = match usermap {
+        Some(usermap) => UserMapping::load(usermap, 0, 0).with_context(|| format!("Failed to read {}", usermap))?,
+        None => UserMapping::default(),
+    };
+
+    let (mut fs, ntfs) = open_ntfs(device)?;
+    let volume = NtfsVolume::new(&ntfs, &mut fs)?;
+    let file = volume.open_path(&mut fs, path)?;
+    let secure = volume.secure_file(&mut fs)?;
+    let Some(descriptor) = volume.security_descriptor(&mut fs, &file, &secure)? else {
+        println!("{} has no security descriptor", path);
+        return Ok(());
+    };
+
+    let sid = |sid: Option<&Sid>| sid.map_or_else(|| "-".to_string(), Sid::to_string);
+    // What WSL set takes precedence over the DACL
+    let permissions = match volume.wsl_metadata(&mut fs, &file)? {
+        Some(metadata) => metadata.apply(mapping.permissions(&descriptor)),
+        None => mapping.permissions(&descriptor),
+    };
+    println!("Owner: {}", sid(descriptor.owner.as_ref()));
+    println!("Group: {}", sid(descriptor.group.as_ref()));
+    println!("SDDL:  {}", descriptor.to_sddl());
+    println!(
+        "POSIX: uid {} gid {} mode {:03o}",
+        permissions.uid, permissions.gid, permissions.mode
+    );
+    Ok(())
+}
+
+/// Print the extended attributes of a file, decoding WSL metadata
+fn print_extended_attributes(device: &str, path: &str) -> anyhow::Result<()> {
+    let (mut fs, ntfs) = open_ntfs(device)?;
+    let volume = NtfsVolume::new(&ntfs, &mut fs)?;
+    let file = volume.open_path(&mut fs, path)?;
+    let attributes = volume.extended_attributes(&mut fs, &file)?;
+    if attributes.is_empty() {
+        println!("{} has no extended attributes", path);
+        return Ok(());
+    }
+
+    for attribute in &attributes {
+        let needed = if attribute.is_needed() { " (needed)" } else { "" };
+        println!("{:<16} {:>6} bytes{}", attribute.name, attribute.value.len(), needed);
+    }
+    if let Some(metadata) = WslMetadata::from_attributes(&attributes) {
+        let field = |value: Option<u32>| value.map_or_else(|| "-".to_string(), |v| v.to_string());
+        println!("WSL: uid {} gid {}", field(metadata.uid), field(metadata.gid));
+        if let Some(mode) = metadata.mode {
+            println!("WSL: mode {:06o}", mode);
+        }
+        if let Some((major, minor)) = metadata.device {
+            println!("WSL: device {}:{}", major, minor);
+        }
+    }
+    Ok(())
+}
+
+/// List deleted files with how much of each can still be recovered
+fn print_deleted(device: &str) -> anyhow::Result<()> {
+    let (mut fs, ntfs) = open_ntfs(device)?;
+    let volume = NtfsVolume::new(&ntfs, &mut fs)?;
+    let files = volume.deleted_files(&mut fs)?;
+
+    println!("{:>10} {:>8} {:>12}  path", "record", "intact", "size");
+    for file in &files {
+        let recoverability = file.recoverability();
+        let chance = match recoverability.chance() {
+            RecoveryChance::Full => "",
+            RecoveryChance::Partial => " (partially overwritten)",
+            RecoveryChance::Lost => " (overwritten)",
+        };
+        let path = if file.is_directory { format!("{}/", file.path) } else { file.path.clone() };
+        println!(
+            "{:>10} {:>7.0}% {:>12}  {}{}",
+            file.record_number,
+            recoverability.score() * 100.0,
+            file.size(),
+            path,
+            chance
+        );
+    }
+
+    println!("{} deleted files found", files.len());
+    Ok(())
+}
+
+/// Copy the streams of one deleted file to a host directory
+fn recover_deleted(device: &str, record: u64, output: &str) -> anyhow::Result<()> {
+    let (mut fs, ntfs) = open_ntfs(device)?;
+    let volume = NtfsVolume::new(&ntfs, &mut fs)?;
+    let file = volume
+        .deleted_files(&mut fs)?
+        .into_iter()
+        .find(|file| file.record_number == record)
+        .with_context(|| format!("Record {} is not a recoverable deleted file", record))?;
+
+    if file.recoverability().chance() != RecoveryChance::Full {
+        eprintln!("warning: some clusters of {} have been reused; the copy will be damaged", file.path);
+    }
+
+    for path in export_file(&mut fs, &file, volume.cluster_size(), Path::new(output))? {
+        println!("Recovered {}", path.display());
+    }
+
+    Ok(())
+}
+
+/// Print change journal records, optionally waiting for new ones
+fn print_usn_journal(device: &str, from: Option<u64>, follow: bool) -> anyhow::Result<()> {
+    let (mut fs, ntfs) = open_ntfs(device)?;
+    let volume = NtfsVolume::new(&ntfs, &mut fs)?;
+    let mut journal = volume.usn_journal(&mut fs)?;
+    if let Some(usn) = from {
+        journal.seek_usn(usn)?;
+    }
+
+    println!("Journal ID {:#x}, next USN {}", journal.info().journal_id, journal.end_usn());
+
+    loop {
+        for record in journal.by_ref() {
+            println!(
+                "{:>12} {:>10} {:>10}  {}  {}",
+                record.usn,
+                record.file.record_number,
+                record.parent.record_number,
+                record.name,
+                record.reason.names().join("|")
+            );
+        }
+
+        if !follow {
+            break;
+        }
+        while !journal.refresh()? {
+            std::thread::sleep(std::time::Duration::from_secs(1));
+        }
+    }
+
+    Ok(())
+}
+
+/// Print the redo and undo operations pending in $LogFile
+fn print_logfile(device: &str) -> anyhow::Result<()> {
+    let (mut fs, ntfs) = open_ntfs(device)?;
+    let volume = NtfsVolume::new(&ntfs, &mut fs)?;
+    let report = volume.logfile_report(&mut fs)?;
+
+    match &report.restart {
+        Some(restart) => println!(
+            "$LogFile {}.{}, current LSN {:#x}",
+            restart.major_version, restart.minor_version, restart.current_lsn
+        ),
+        None => println!("$LogFile has been reset"),
+    }
+    if report.is_clean() {
+        println!("Journal is clean");
+        return Ok(());
+    }
+
+    for transaction in &report.transactions {
+        let action = if transaction.committed { "redo" } else { "undo" };
+        println!();
+        println!("Transaction {:#x} ({})", transaction.transaction_id, action);
+        for record in transaction.changes() {
+            println!(
+                "  {:#014x}  redo {:<28} undo {:<28} LCN {:?}",
+                record.lsn,
+                record.redo_operation.name(),
+                record.undo_operation.name(),
+                record.lcns
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Replay $LogFile so the volume can be mounted read-write
+fn replay_logfile(device: &str, remove_hiberfile: bool) -> anyhow::Result<()> {
+    let options = WriteOptions {
+        remove_hiberfile,
+        unclean_logfile: true,
+    };
+    let (mut fs, ntfs) = open_ntfs_with_options(device, Some(options))?;
+    let volume = NtfsVolume::new(&ntfs, &mut fs)?;
+    let summary = volume.replay_journal(&mut fs)?;
 
+    println!(
+        "Replayed $LogFile: {} changes redone, {} undone, {} already consistent",
+        summary.redone, summary.undone, summary.skipped
+    );
     Ok(())
 }
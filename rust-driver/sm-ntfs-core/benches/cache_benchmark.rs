@@ -0,0 +1,123 @@
+//! Cache policy benchmark
+//!
+//! Replays block access traces against every cache replacement policy and
+//! reports hit ratios, then measures replay throughput with criterion.
+//!
+//! Recorded traces are picked up from `SM_NTFS_CACHE_TRACES`, which may point
+//! to a single trace file or to a directory of `*.trace` files (see
+//! `sm_ntfs_core::cache::trace` for the format). The cache size in blocks can
+//! be set with `SM_NTFS_CACHE_BLOCKS` (default 1024).
+//!
+//! ```text
+//! SM_NTFS_CACHE_TRACES=~/traces cargo bench --bench cache_benchmark
+//! ```
+
+use std::path::Path;
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use sm_ntfs_core::cache::{replay, AccessTrace, CachePolicy};
+
+const DEFAULT_CAPACITY: usize = 1024;
+
+fn capacity() -> usize {
+    std::env::var("SM_NTFS_CACHE_BLOCKS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_CAPACITY)
+}
+
+/// Small deterministic generator so runs are comparable without extra deps
+struct Lcg(u64);
+
+impl Lcg {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        self.0 >> 33
+    }
+}
+
+/// Hot metadata blocks (MFT and index records) interleaved with one large
+/// sequential copy, the pattern that makes plain LRU thrash
+fn synthetic_metadata_with_copy(capacity: usize) -> AccessTrace {
+    let hot_blocks = (capacity / 2).max(1) as u64;
+    let copy_start = 1_000_000;
+    let mut rng = Lcg(0x5EED);
+    let mut accesses = Vec::new();
+
+    for step in 0..(capacity as u64 * 40) {
+        // Skew towards the low end of the hot set
+        let hot = (rng.next() % hot_blocks).min(rng.next() % hot_blocks);
+        accesses.push(hot);
+        accesses.push(copy_start + step);
+        accesses.push(copy_start + step * 2 + 1);
+    }
+
+    AccessTrace::new("synthetic-metadata-with-copy", accesses)
+}
+
+/// Uniformly random accesses over twice the cache size
+fn synthetic_random(capacity: usize) -> AccessTrace {
+    let mut rng = Lcg(0xC0FFEE);
+    let span = capacity as u64 * 2;
+    let accesses = (0..capacity * 40).map(|_| rng.next() % span).collect();
+
+    AccessTrace::new("synthetic-random", accesses)
+}
+
+fn recorded_traces() -> Vec<AccessTrace> {
+    let Ok(location) = std::env::var("SM_NTFS_CACHE_TRACES") else {
+        return Vec::new();
+    };
+    let location = Path::new(&location);
+
+    let paths = if location.is_dir() {
+        let mut paths: Vec<_> = std::fs::read_dir(location)
+            .expect("failed to read trace directory")
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "trace"))
+            .collect();
+        paths.sort();
+        paths
+    } else {
+        vec![location.to_path_buf()]
+    };
+
+    paths
+        .iter()
+        .map(|path| AccessTrace::load(path).expect("failed to load trace"))
+        .collect()
+}
+
+fn cache_policies(c: &mut Criterion) {
+    let capacity = capacity();
+    let mut traces = recorded_traces();
+    traces.push(synthetic_metadata_with_copy(capacity));
+    traces.push(synthetic_random(capacity));
+
+    println!("\nHit ratios with {} cached blocks:", capacity);
+    for trace in &traces {
+        for policy in CachePolicy::ALL {
+            let stats = replay(trace, policy, capacity);
+            println!(
+                "  {:<32} {:<4} {:>6.2}%  ({} hits, {} misses)",
+                trace.name,
+                policy.name(),
+                stats.hit_ratio() * 100.0,
+                stats.hits,
+                stats.misses
+            );
+        }
+    }
+
+    for trace in &traces {
+        let mut group = c.benchmark_group(format!("replay/{}", trace.name));
+        for policy in CachePolicy::ALL {
+            group.bench_function(policy.name(), |b| {
+                b.iter(|| replay(black_box(trace), policy, capacity))
+            });
+        }
+        group.finish();
+    }
+}
+
+criterion_group!(benches, cache_policies);
+criterion_main!(benches);
@@ -0,0 +1,198 @@
+//! ARC: Adaptive Replacement Cache (Megiddo & Modha, FAST 2003)
+//!
+//! Resident entries are split between `T1` (seen once recently) and `T2` (seen
+//! at least twice). Ghost lists `B1` and `B2` remember keys recently evicted
+//! from each side, and hits on them shift the target size `p` of `T1` towards
+//! whichever side would have avoided the miss.
+
+use std::hash::Hash;
+use lru::LruCache;
+use super::policy::{CachePolicy, ReplacementPolicy};
+
+/// Adaptive Replacement Cache
+pub struct ArcCache<K: Hash + Eq, V> {
+    t1: LruCache<K, V>,
+    t2: LruCache<K, V>,
+    b1: LruCache<K, ()>,
+    b2: LruCache<K, ()>,
+    /// Target size of `t1`
+    p: usize,
+    capacity: usize,
+}
+
+impl<K: Hash + Eq + Clone, V> ArcCache<K, V> {
+    /// Create a new ARC cache holding at most `capacity` entries
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            t1: LruCache::unbounded(),
+            t2: LruCache::unbounded(),
+            b1: LruCache::unbounded(),
+            b2: LruCache::unbounded(),
+            p: 0,
+            capacity: capacity.max(1),
+        }
+    }
+
+    /// Current target size of the recency side
+    pub fn target_recent(&self) -> usize {
+        self.p
+    }
+
+    /// Evict one resident entry into the matching ghost list (the paper's REPLACE)
+    fn replace(&mut self, hit_in_b2: bool) -> Option<(K, V)> {
+        let t1_len = self.t1.len();
+        if t1_len > 0 && (t1_len > self.p || (hit_in_b2 && t1_len == self.p)) {
+            let (key, value) = self.t1.pop_lru()?;
+            self.b1.push(key.clone(), ());
+            Some((key, value))
+        } else {
+            let (key, value) = self.t2.pop_lru().or_else(|| self.t1.pop_lru())?;
+            self.b2.push(key.clone(), ());
+            Some((key, value))
+        }
+    }
+
+    fn is_full(&self) -> bool {
+        self.t1.len() + self.t2.len() >= self.capacity
+    }
+}
+
+impl<K, V> ReplacementPolicy<K, V> for ArcCache<K, V>
+where
+    K: Hash + Eq + Clone + Send,
+    V: Send,
+{
+    fn get(&mut self, key: &K) -> Option<&V> {
+        if let Some(value) = self.t1.pop(key) {
+            self.t2.push(key.clone(), value);
+        }
+        self.t2.get(key)
+    }
+
+    fn peek(&self, key: &K) -> Option<&V> {
+        self.t1.peek(key).or_else(|| self.t2.peek(key))
+    }
+
+    fn insert(&mut self, key: K, value: V) -> Option<(K, V)> {
+        // Case I: resident, update and treat as a repeated reference
+        if self.t1.pop(&key).is_some() || self.t2.contains(&key) {
+            self.t2.push(key, value);
+            return None;
+        }
+
+        // Case II: ghost hit in B1, favour recency
+        if self.b1.pop(&key).is_some() {
+            let delta = (self.b2.len() / (self.b1.len() + 1)).max(1);
+            self.p = (self.p + delta).min(self.capacity);
+            let evicted = if self.is_full() { self.replace(false) } else { None };
+            self.t2.push(key, value);
+            return evicted;
+        }
+
+        // Case III: ghost hit in B2, favour frequency
+        if self.b2.pop(&key).is_some() {
+            let delta = (self.b1.len() / (self.b2.len() + 1)).max(1);
+            self.p = self.p.saturating_sub(delta);
+            let evicted = if self.is_full() { self.replace(true) } else { None };
+            self.t2.push(key, value);
+            return evicted;
+        }
+
+        // Case IV: complete miss
+        let mut evicted = None;
+        let l1_len = self.t1.len() + self.b1.len();
+        let total = l1_len + self.t2.len() + self.b2.len();
+        if l1_len >= self.capacity {
+            if self.t1.len() < self.capacity {
+                self.b1.pop_lru();
+                evicted = self.replace(false);
+            } else {
+                evicted = self.t1.pop_lru();
+            }
+        } else if total >= self.capacity {
+            if total >= 2 * self.capacity {
+                self.b2.pop_lru();
+            }
+            if self.is_full() {
+                evicted = self.replace(false);
+            }
+        }
+        self.t1.push(key, value);
+        evicted
+    }
+
+    fn remove(&mut self, key: &K) -> Option<V> {
+        self.t1.pop(key).or_else(|| self.t2.pop(key))
+    }
+
+    fn len(&self) -> usize {
+        self.t1.len() + self.t2.len()
+    }
+
+    fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    fn clear(&mut self) {
+        self.t1.clear();
+        self.t2.clear();
+        self.b1.clear();
+        self.b2.clear();
+        self.p = 0;
+    }
+
+    fn policy(&self) -> CachePolicy {
+        CachePolicy::Arc
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_repeated_reference_moves_to_t2() {
+        let mut cache = ArcCache::new(4);
+        cache.insert(1, "a");
+        assert!(cache.t1.contains(&1));
+
+        assert_eq!(cache.get(&1), Some(&"a"));
+        assert!(cache.t2.contains(&1));
+        assert!(!cache.t1.contains(&1));
+    }
+
+    #[test]
+    fn test_ghost_hit_adapts_target() {
+        let mut cache = ArcCache::new(2);
+        cache.insert(1, ());
+        cache.insert(2, ());
+        cache.get(&1);
+        cache.get(&2);
+        // T2 is full; a new key in T1 followed by another evicts into B1
+        cache.insert(3, ());
+        cache.insert(4, ());
+        assert_eq!(cache.target_recent(), 0);
+
+        let ghost = if cache.b1.contains(&3) { 3 } else { 4 };
+        cache.insert(ghost, ());
+        assert!(cache.target_recent() > 0);
+    }
+
+    #[test]
+    fn test_scan_does_not_flush_frequent_set() {
+        let mut cache = ArcCache::new(8);
+        for _ in 0..2 {
+            for key in 0..4 {
+                if cache.get(&key).is_none() {
+                    cache.insert(key, ());
+                }
+            }
+        }
+        for key in 1000..2000 {
+            cache.insert(key, ());
+        }
+        for key in 0..4 {
+            assert!(cache.contains(&key), "hot key {} was evicted by scan", key);
+        }
+    }
+}
@@ -0,0 +1,123 @@
+//! Device block read cache
+
+use crate::utils::config::Config;
+use super::policy::{CachePolicy, ReplacementPolicy};
+use super::CacheStats;
+
+/// Read cache of fixed-size device blocks, keyed by block number
+pub struct BlockCache {
+    entries: Box<dyn ReplacementPolicy<u64, Vec<u8>>>,
+    block_size: usize,
+    stats: CacheStats,
+}
+
+impl BlockCache {
+    /// Create a cache holding at most `capacity` blocks of `block_size` bytes
+    pub fn new(policy: CachePolicy, capacity: usize, block_size: usize) -> Self {
+        Self {
+            entries: policy.build(capacity),
+            block_size,
+            stats: CacheStats::default(),
+        }
+    }
+
+    /// Create a cache sized and configured from `config`
+    pub fn from_config(config: &Config, block_size: usize) -> Self {
+        let capacity = config.cache_size_mb * 1024 * 1024 / block_size.max(1);
+        tracing::debug!(
+            "Block cache: policy {}, {} blocks of {} bytes",
+            config.cache_policy.name(),
+            capacity,
+            block_size
+        );
+        Self::new(config.cache_policy, capacity, block_size)
+    }
+
+    /// Look up a block, counting a hit or a miss
+    pub fn get(&mut self, block: u64) -> Option<&[u8]> {
+        match self.entries.get(&block) {
+            Some(data) => {
+                self.stats.hits += 1;
+                Some(data.as_slice())
+            }
+            None => {
+                self.stats.misses += 1;
+                None
+            }
+        }
+    }
+
+    /// Store a block read from the device
+    pub fn insert(&mut self, block: u64, data: Vec<u8>) {
+        debug_assert_eq!(data.len(), self.block_size);
+        if self.entries.insert(block, data).is_some() {
+            self.stats.evictions += 1;
+        }
+    }
+
+    /// Drop a block, e.g. after it was written
+    pub fn invalidate(&mut self, block: u64) {
+        self.entries.remove(&block);
+    }
+
+    /// Drop all blocks
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Number of cached blocks
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Check if no blocks are cached
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Size of a cached block in bytes
+    pub fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    /// Replacement policy in use
+    pub fn policy(&self) -> CachePolicy {
+        self.entries.policy()
+    }
+
+    /// Hit/miss counters since creation
+    pub fn stats(&self) -> CacheStats {
+        self.stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_block_cache_hit_miss() {
+        let mut cache = BlockCache::new(CachePolicy::Arc, 4, 512);
+        assert!(cache.get(7).is_none());
+
+        cache.insert(7, vec![0xAB; 512]);
+        assert_eq!(cache.get(7).unwrap()[0], 0xAB);
+
+        let stats = cache.stats();
+        assert_eq!(stats.hits, 1);
+        assert_eq!(stats.misses, 1);
+        assert_eq!(stats.hit_ratio(), 0.5);
+    }
+
+    #[test]
+    fn test_block_cache_from_config() {
+        let config = Config {
+            cache_size_mb: 1,
+            cache_policy: CachePolicy::TwoQueue,
+            ..Config::default()
+        };
+        let cache = BlockCache::from_config(&config, 4096);
+        assert_eq!(cache.policy(), CachePolicy::TwoQueue);
+        assert_eq!(cache.entries.capacity(), 256);
+    }
+}
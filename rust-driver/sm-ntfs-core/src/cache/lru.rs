@@ -0,0 +1,88 @@
+//! LRU read cache
+
+use std::hash::Hash;
+use std::num::NonZeroUsize;
+use super::policy::{CachePolicy, ReplacementPolicy};
+
+/// Plain Least Recently Used cache
+pub struct LruCache<K: Hash + Eq, V> {
+    inner: lru::LruCache<K, V>,
+}
+
+impl<K: Hash + Eq, V> LruCache<K, V> {
+    /// Create a new LRU cache holding at most `capacity` entries
+    pub fn new(capacity: usize) -> Self {
+        let capacity = NonZeroUsize::new(capacity).unwrap_or(NonZeroUsize::MIN);
+        Self {
+            inner: lru::LruCache::new(capacity),
+        }
+    }
+}
+
+impl<K, V> ReplacementPolicy<K, V> for LruCache<K, V>
+where
+    K: Hash + Eq + Send,
+    V: Send,
+{
+    fn get(&mut self, key: &K) -> Option<&V> {
+        self.inner.get(key)
+    }
+
+    fn peek(&self, key: &K) -> Option<&V> {
+        self.inner.peek(key)
+    }
+
+    fn insert(&mut self, key: K, value: V) -> Option<(K, V)> {
+        // `push` also hands back the old value on update; only report real evictions
+        match self.inner.push(key, value) {
+            Some((old_key, old_value)) if !self.inner.contains(&old_key) => Some((old_key, old_value)),
+            _ => None,
+        }
+    }
+
+    fn remove(&mut self, key: &K) -> Option<V> {
+        self.inner.pop(key)
+    }
+
+    fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    fn capacity(&self) -> usize {
+        self.inner.cap().get()
+    }
+
+    fn clear(&mut self) {
+        self.inner.clear();
+    }
+
+    fn policy(&self) -> CachePolicy {
+        CachePolicy::Lru
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lru_evicts_least_recent() {
+        let mut cache = LruCache::new(2);
+        cache.insert(1, "a");
+        cache.insert(2, "b");
+        cache.get(&1);
+
+        let evicted = cache.insert(3, "c");
+        assert_eq!(evicted, Some((2, "b")));
+        assert!(cache.contains(&1));
+        assert!(cache.contains(&3));
+    }
+
+    #[test]
+    fn test_lru_update_is_not_eviction() {
+        let mut cache = LruCache::new(2);
+        cache.insert(1, "a");
+        assert_eq!(cache.insert(1, "b"), None);
+        assert_eq!(cache.peek(&1), Some(&"b"));
+    }
+}
@@ -1,6 +1,45 @@
 //! Caching module
+//!
+//! Block caches are built on a pluggable [`ReplacementPolicy`]; the policy is
+//! selected through [`Config::cache_policy`](crate::Config::cache_policy).
 
-// TODO: Implement cache system
-// - lru.rs: LRU read cache
-// - writeback.rs: Write-back buffer
-// - policy.rs: Cache policies
+// TODO: writeback.rs: Write-back buffer
+
+pub mod policy;
+pub mod lru;
+pub mod two_queue;
+pub mod arc;
+pub mod block;
+pub mod trace;
+
+pub use policy::{CachePolicy, ReplacementPolicy};
+pub use lru::LruCache;
+pub use two_queue::TwoQueueCache;
+pub use arc::ArcCache;
+pub use block::BlockCache;
+pub use trace::{AccessTrace, replay};
+
+/// Hit/miss counters for a cache
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Lookups served from the cache
+    pub hits: u64,
+
+    /// Lookups that had to go to the device
+    pub misses: u64,
+
+    /// Resident entries dropped to make room
+    pub evictions: u64,
+}
+
+impl CacheStats {
+    /// Fraction of lookups served from the cache (0.0 when there were none)
+    pub fn hit_ratio(&self) -> f64 {
+        let total = self.hits + self.misses;
+        if total == 0 {
+            0.0
+        } else {
+            self.hits as f64 / total as f64
+        }
+    }
+}
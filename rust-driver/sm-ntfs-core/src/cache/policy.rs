@@ -0,0 +1,131 @@
+//! Cache replacement policies
+//!
+//! Every cache in SM-NTFS stores its entries through a [`ReplacementPolicy`],
+//! so the eviction strategy can be chosen per workload via [`CachePolicy`].
+
+use std::hash::Hash;
+use serde::{Deserialize, Serialize};
+use super::arc::ArcCache;
+use super::lru::LruCache;
+use super::two_queue::TwoQueueCache;
+
+/// Selectable cache replacement policy
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CachePolicy {
+    /// Least Recently Used
+    #[default]
+    Lru,
+
+    /// 2Q: a FIFO probation queue in front of an LRU main queue
+    TwoQueue,
+
+    /// Adaptive Replacement Cache
+    Arc,
+}
+
+impl CachePolicy {
+    /// All available policies, in a stable order
+    pub const ALL: [CachePolicy; 3] = [CachePolicy::Lru, CachePolicy::TwoQueue, CachePolicy::Arc];
+
+    /// Short name of the policy
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Lru => "lru",
+            Self::TwoQueue => "2q",
+            Self::Arc => "arc",
+        }
+    }
+
+    /// Build an empty cache using this policy
+    pub fn build<K, V>(&self, capacity: usize) -> Box<dyn ReplacementPolicy<K, V>>
+    where
+        K: Hash + Eq + Clone + Send + 'static,
+        V: Send + 'static,
+    {
+        match self {
+            Self::Lru => Box::new(LruCache::new(capacity)),
+            Self::TwoQueue => Box::new(TwoQueueCache::new(capacity)),
+            Self::Arc => Box::new(ArcCache::new(capacity)),
+        }
+    }
+}
+
+impl std::str::FromStr for CachePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "lru" => Ok(Self::Lru),
+            "2q" | "two_queue" | "twoqueue" => Ok(Self::TwoQueue),
+            "arc" => Ok(Self::Arc),
+            other => Err(format!("Unknown cache policy '{}'", other)),
+        }
+    }
+}
+
+/// A bounded key/value store with a specific eviction strategy
+///
+/// `get` counts as an access and may reorder entries, `peek` does not.
+/// `insert` returns the resident entry that had to be evicted, if any.
+pub trait ReplacementPolicy<K, V>: Send {
+    /// Look up an entry and record the access
+    fn get(&mut self, key: &K) -> Option<&V>;
+
+    /// Look up an entry without affecting eviction order
+    fn peek(&self, key: &K) -> Option<&V>;
+
+    /// Insert or update an entry, returning an evicted entry if the cache was full
+    fn insert(&mut self, key: K, value: V) -> Option<(K, V)>;
+
+    /// Remove an entry
+    fn remove(&mut self, key: &K) -> Option<V>;
+
+    /// Check whether an entry is resident
+    fn contains(&self, key: &K) -> bool {
+        self.peek(key).is_some()
+    }
+
+    /// Number of resident entries
+    fn len(&self) -> usize;
+
+    /// Check if the cache holds no entries
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Maximum number of resident entries
+    fn capacity(&self) -> usize;
+
+    /// Drop all entries, including any history the policy keeps
+    fn clear(&mut self);
+
+    /// The policy implemented by this cache
+    fn policy(&self) -> CachePolicy;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_policy_from_str() {
+        assert_eq!("LRU".parse::<CachePolicy>().unwrap(), CachePolicy::Lru);
+        assert_eq!("2q".parse::<CachePolicy>().unwrap(), CachePolicy::TwoQueue);
+        assert_eq!("arc".parse::<CachePolicy>().unwrap(), CachePolicy::Arc);
+        assert!("mru".parse::<CachePolicy>().is_err());
+    }
+
+    #[test]
+    fn test_build_respects_capacity() {
+        for policy in CachePolicy::ALL {
+            let mut cache = policy.build::<u64, u64>(8);
+            for key in 0..100 {
+                cache.insert(key, key);
+                assert!(cache.len() <= 8, "{} exceeded capacity", policy.name());
+            }
+            assert_eq!(cache.policy(), policy);
+            assert_eq!(cache.capacity(), 8);
+        }
+    }
+}
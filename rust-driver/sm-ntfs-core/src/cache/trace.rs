@@ -0,0 +1,139 @@
+//! Access trace recording and replay
+//!
+//! A trace is a plain text file with one block number per line (decimal or
+//! `0x` hex). Anything after the first whitespace-separated token is ignored,
+//! as are blank lines and lines starting with `#`, so traces can be annotated.
+
+use std::fs;
+use std::path::Path;
+use crate::utils::error::{Result, SMNtfsError};
+use super::policy::CachePolicy;
+use super::CacheStats;
+
+/// A recorded sequence of block accesses
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AccessTrace {
+    /// Trace name (file stem when loaded from disk)
+    pub name: String,
+
+    /// Accessed block numbers, in order
+    pub accesses: Vec<u64>,
+}
+
+impl AccessTrace {
+    /// Create a trace from a list of accesses
+    pub fn new(name: impl Into<String>, accesses: Vec<u64>) -> Self {
+        Self {
+            name: name.into(),
+            accesses,
+        }
+    }
+
+    /// Load a trace from a file
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)
+            .map_err(|e| SMNtfsError::ReadError(format!("{}: {}", path.display(), e)))?;
+        let name = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+
+        Self::parse(name, &text)
+    }
+
+    /// Parse a trace from its text form
+    pub fn parse(name: impl Into<String>, text: &str) -> Result<Self> {
+        let mut accesses = Vec::new();
+
+        for (line_number, line) in text.lines().enumerate() {
+            let token = match line.split_whitespace().next() {
+                Some(token) if !token.starts_with('#') => token,
+                _ => continue,
+            };
+
+            let block = match token.strip_prefix("0x").or_else(|| token.strip_prefix("0X")) {
+                Some(hex) => u64::from_str_radix(hex, 16),
+                None => token.parse(),
+            }
+            .map_err(|_| {
+                SMNtfsError::SystemError(format!(
+                    "Invalid block number '{}' on trace line {}",
+                    token,
+                    line_number + 1
+                ))
+            })?;
+
+            accesses.push(block);
+        }
+
+        Ok(Self::new(name, accesses))
+    }
+
+    /// Render the trace in the text form accepted by [`AccessTrace::parse`]
+    pub fn to_text(&self) -> String {
+        let mut text = String::with_capacity(self.accesses.len() * 8);
+        for block in &self.accesses {
+            text.push_str(&block.to_string());
+            text.push('\n');
+        }
+        text
+    }
+
+    /// Number of accesses
+    pub fn len(&self) -> usize {
+        self.accesses.len()
+    }
+
+    /// Check if the trace has no accesses
+    pub fn is_empty(&self) -> bool {
+        self.accesses.is_empty()
+    }
+}
+
+/// Replay a trace against an empty cache and report hit statistics
+///
+/// Every miss inserts the block, as a read cache would after fetching it.
+pub fn replay(trace: &AccessTrace, policy: CachePolicy, capacity: usize) -> CacheStats {
+    let mut cache = policy.build::<u64, ()>(capacity);
+    let mut stats = CacheStats::default();
+
+    for &block in &trace.accesses {
+        if cache.get(&block).is_some() {
+            stats.hits += 1;
+        } else {
+            stats.misses += 1;
+            if cache.insert(block, ()).is_some() {
+                stats.evictions += 1;
+            }
+        }
+    }
+
+    stats
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_trace() {
+        let trace = AccessTrace::parse("t", "# header\n1\n0x10 read\n\n  42\n").unwrap();
+        assert_eq!(trace.accesses, vec![1, 16, 42]);
+        assert_eq!(AccessTrace::parse("t", &trace.to_text()).unwrap().accesses, trace.accesses);
+    }
+
+    #[test]
+    fn test_parse_invalid_trace() {
+        assert!(AccessTrace::parse("t", "12\nabc\n").is_err());
+    }
+
+    #[test]
+    fn test_replay_counts() {
+        let trace = AccessTrace::new("t", vec![1, 2, 1, 3, 1]);
+        let stats = replay(&trace, CachePolicy::Lru, 2);
+        assert_eq!(stats.hits, 2);
+        assert_eq!(stats.misses, 3);
+        assert_eq!(stats.evictions, 1);
+    }
+}
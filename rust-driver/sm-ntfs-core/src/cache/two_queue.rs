@@ -0,0 +1,163 @@
+//! 2Q cache (Johnson & Shasha, VLDB 1994)
+//!
+//! New blocks enter a small FIFO (`A1in`). Only blocks referenced again after
+//! falling out of it, tracked by key in a ghost queue (`A1out`), are promoted to
+//! the LRU main queue (`Am`). A long sequential scan therefore cycles through
+//! `A1in` without disturbing the hot set in `Am`.
+
+use std::hash::Hash;
+use lru::LruCache;
+use super::policy::{CachePolicy, ReplacementPolicy};
+
+/// Share of the capacity reserved for the `A1in` FIFO, in percent
+const IN_QUEUE_PERCENT: usize = 25;
+
+/// Size of the `A1out` ghost queue relative to the capacity, in percent
+const GHOST_QUEUE_PERCENT: usize = 50;
+
+/// Full 2Q cache
+pub struct TwoQueueCache<K: Hash + Eq, V> {
+    /// Probation FIFO for first-time entries (never promoted on hit)
+    a1_in: LruCache<K, V>,
+    /// Keys recently evicted from `a1_in`
+    a1_out: LruCache<K, ()>,
+    /// Main LRU queue for entries referenced more than once
+    am: LruCache<K, V>,
+    capacity: usize,
+    in_capacity: usize,
+    out_capacity: usize,
+}
+
+impl<K: Hash + Eq + Clone, V> TwoQueueCache<K, V> {
+    /// Create a new 2Q cache holding at most `capacity` entries
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        Self {
+            a1_in: LruCache::unbounded(),
+            a1_out: LruCache::unbounded(),
+            am: LruCache::unbounded(),
+            capacity,
+            in_capacity: (capacity * IN_QUEUE_PERCENT / 100).max(1),
+            out_capacity: (capacity * GHOST_QUEUE_PERCENT / 100).max(1),
+        }
+    }
+
+    /// Free one slot, preferring the probation queue while it is over its share
+    fn reclaim(&mut self) -> Option<(K, V)> {
+        if self.a1_in.len() + self.am.len() < self.capacity {
+            return None;
+        }
+
+        if self.a1_in.len() > self.in_capacity || self.am.is_empty() {
+            let (key, value) = self.a1_in.pop_lru()?;
+            self.a1_out.push(key.clone(), ());
+            if self.a1_out.len() > self.out_capacity {
+                self.a1_out.pop_lru();
+            }
+            Some((key, value))
+        } else {
+            self.am.pop_lru()
+        }
+    }
+}
+
+impl<K, V> ReplacementPolicy<K, V> for TwoQueueCache<K, V>
+where
+    K: Hash + Eq + Clone + Send,
+    V: Send,
+{
+    fn get(&mut self, key: &K) -> Option<&V> {
+        if self.am.contains(key) {
+            return self.am.get(key);
+        }
+        self.a1_in.peek(key)
+    }
+
+    fn peek(&self, key: &K) -> Option<&V> {
+        self.am.peek(key).or_else(|| self.a1_in.peek(key))
+    }
+
+    fn insert(&mut self, key: K, value: V) -> Option<(K, V)> {
+        if let Some(slot) = self.am.get_mut(&key) {
+            *slot = value;
+            return None;
+        }
+        if let Some(slot) = self.a1_in.peek_mut(&key) {
+            *slot = value;
+            return None;
+        }
+
+        // Check the ghost queue before reclaiming, which may push it past its size
+        let seen_before = self.a1_out.pop(&key).is_some();
+        let evicted = self.reclaim();
+        if seen_before {
+            self.am.push(key, value);
+        } else {
+            self.a1_in.push(key, value);
+        }
+        evicted
+    }
+
+    fn remove(&mut self, key: &K) -> Option<V> {
+        self.am.pop(key).or_else(|| self.a1_in.pop(key))
+    }
+
+    fn len(&self) -> usize {
+        self.a1_in.len() + self.am.len()
+    }
+
+    fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    fn clear(&mut self) {
+        self.a1_in.clear();
+        self.a1_out.clear();
+        self.am.clear();
+    }
+
+    fn policy(&self) -> CachePolicy {
+        CachePolicy::TwoQueue
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_second_reference_promotes_to_main_queue() {
+        let mut cache = TwoQueueCache::new(4);
+        cache.insert(1, ());
+        // Push 1 out of A1in into the ghost queue
+        for key in 10..14 {
+            cache.insert(key, ());
+        }
+        assert!(!cache.contains(&1));
+
+        cache.insert(1, ());
+        assert!(cache.am.contains(&1));
+    }
+
+    #[test]
+    fn test_scan_does_not_flush_hot_set() {
+        let mut cache = TwoQueueCache::new(8);
+        for key in 0..4 {
+            cache.insert(key, ());
+        }
+        // Re-reference the hot set after it was demoted to the ghost queue
+        for key in 100..108 {
+            cache.insert(key, ());
+        }
+        for key in 0..4 {
+            cache.insert(key, ());
+        }
+
+        for key in 1000..2000 {
+            cache.insert(key, ());
+        }
+        for key in 0..4 {
+            assert!(cache.contains(&key), "hot key {} was evicted by scan", key);
+        }
+    }
+}
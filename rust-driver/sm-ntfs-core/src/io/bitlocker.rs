@@ -0,0 +1,771 @@
+//! Decrypted, read-only view of a BitLocker volume
+//!
+//! [`BitLockerDevice::unlock`] reads the FVE metadata, decrypts the volume
+//! master key (VMK) with one of its protectors, decrypts the full volume
+//! encryption key (FVEK) with the VMK, and then decrypts sectors as they are
+//! read. A clear key, left when BitLocker is suspended, is always tried
+//! before the given key. Recovery passwords and user passwords are
+//! stretched as BitLocker does, with 2^20 rounds of SHA-256.
+//!
+//! The first sectors read as the original boot sectors, which BitLocker
+//! keeps encrypted elsewhere; the metadata copies and the moved boot sectors
+//! read as zeros; sectors past the encrypted size of a volume whose
+//! encryption is still in progress are returned as they are.
+
+use std::io::{self, Read, Seek, SeekFrom};
+use std::ops::Range;
+use aes::cipher::generic_array::GenericArray;
+use aes::cipher::{BlockDecrypt, BlockEncrypt, KeyInit};
+use aes::{Aes128, Aes256};
+use ccm::aead::AeadInPlace;
+use ccm::consts::{U12, U16};
+use ccm::Ccm;
+use sha2::{Digest, Sha256};
+use crate::io::fve::{
+    parse_entries, parse_key, protection, value_types, EncryptedKey, EncryptionMethod, FveMetadata, StartupKey,
+};
+use crate::utils::bytes::le_u16;
+use crate::utils::error::{Result, SMNtfsError};
+
+/// Bytes read as zeros from each FVE metadata copy
+pub const METADATA_REGION_SIZE: u64 = 64 * 1024;
+
+/// Rounds of SHA-256 applied to a password
+const STRETCH_ROUNDS: u64 = 0x10_0000;
+
+/// Largest read passed to the underlying device at once
+const MAX_READ_SIZE: usize = 1024 * 1024;
+
+/// AES-CCM as used for BitLocker keys: 16-byte tag, 12-byte nonce
+type KeyCipher = Ccm<Aes256, U16, U12>;
+
+/// What unlocks a BitLocker volume
+#[derive(Clone)]
+pub enum BitLockerKey {
+    /// The 48-digit recovery password, in 8 groups of 6 digits
+    RecoveryPassword(String),
+
+    /// Contents of a .BEK startup key file
+    KeyFile(Vec<u8>),
+
+    /// A user password
+    Password(String),
+}
+
+impl std::fmt::Debug for BitLockerKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Never print the secret itself
+        f.write_str(match self {
+            Self::RecoveryPassword(_) => "RecoveryPassword(..)",
+            Self::KeyFile(_) => "KeyFile(..)",
+            Self::Password(_) => "Password(..)",
+        })
+    }
+}
+
+/// Decode a recovery password into its 128-bit key
+///
+/// Each group is a multiple of 11 below 11 * 65536, and holds 16 bits of
+/// the key once divided by 11.
+pub fn recovery_password_key(password: &str) -> Result<[u8; 16]> {
+    let invalid = |reason: &str| SMNtfsError::BitLockerKeyRejected(format!("Invalid recovery password: {}", reason));
+    let groups: Vec<&str> = password.trim().split('-').collect();
+    if groups.len() != 8 {
+        return Err(invalid("expected 8 groups of 6 digits"));
+    }
+
+    let mut key = [0u8; 16];
+    for (index, group) in groups.iter().enumerate() {
+        if group.len() != 6 || !group.bytes().all(|byte| byte.is_ascii_digit()) {
+            return Err(invalid("expected 8 groups of 6 digits"));
+        }
+        let value: u32 = group.parse().map_err(|_| invalid("expected 8 groups of 6 digits"))?;
+        if value % 11 != 0 || value / 11 > u16::MAX as u32 {
+            return Err(invalid(&format!("group {} is mistyped", index + 1)));
+        }
+        key[index * 2..index * 2 + 2].copy_from_slice(&((value / 11) as u16).to_le_bytes());
+    }
+    Ok(key)
+}
+
+/// Stretch a password hash with the salt of its protector
+fn stretch_key(password_hash: &[u8], salt: &[u8; 16], rounds: u64) -> [u8; 32] {
+    // Last hash, password hash, salt and round number, hashed each round
+    let mut state = [0u8; 88];
+    state[32..64].copy_from_slice(password_hash);
+    state[64..80].copy_from_slice(salt);
+    for round in 0..rounds {
+        state[80..88].copy_from_slice(&round.to_le_bytes());
+        let hash = Sha256::digest(state);
+        state[..32].copy_from_slice(&hash);
+    }
+
+    let mut key = [0u8; 32];
+    key.copy_from_slice(&state[..32]);
+    key
+}
+
+/// Decrypt an AES-CCM encrypted key, returning its method and key bytes
+///
+/// Returns `None` if `key` is not the right one.
+fn decrypt_key(key: &[u8], encrypted: &EncryptedKey) -> Option<(u16, Vec<u8>)> {
+    let cipher = KeyCipher::new_from_slice(key).ok()?;
+    let mut data = encrypted.data.clone();
+    cipher
+        .decrypt_in_place_detached(
+            GenericArray::from_slice(&encrypted.nonce),
+            b"",
+            &mut data,
+            GenericArray::from_slice(&encrypted.mac),
+        )
+        .ok()?;
+
+    let entry = parse_entries(&data)
+        .ok()?
+        .into_iter()
+        .find(|entry| entry.value_type == value_types::KEY)?;
+    let (method, key) = parse_key(&entry.data).ok()?;
+    Some((method, key.to_vec()))
+}
+
+/// Decrypt the volume master key with a clear key or the given key
+fn unlock_volume_master_key(metadata: &FveMetadata, key: &BitLockerKey) -> Result<Vec<u8>> {
+    let protectors = metadata.volume_master_keys();
+
+    for protector in protectors.iter().filter(|protector| protector.protection == protection::CLEAR_KEY) {
+        let decrypted = protector
+            .clear_key()
+            .zip(protector.encrypted_key().ok())
+            .and_then(|(clear_key, encrypted)| decrypt_key(clear_key, &encrypted));
+        if let Some((_, vmk)) = decrypted {
+            tracing::info!("BitLocker is suspended; unlocked with the clear key");
+            return Ok(vmk);
+        }
+    }
+
+    let (kind, protection_type) = match key {
+        BitLockerKey::RecoveryPassword(_) => ("recovery password", protection::RECOVERY_PASSWORD),
+        BitLockerKey::KeyFile(_) => ("startup key", protection::STARTUP_KEY),
+        BitLockerKey::Password(_) => ("password", protection::PASSWORD),
+    };
+    let candidates: Vec<_> = protectors
+        .iter()
+        .filter(|protector| protector.protection == protection_type)
+        .collect();
+    if candidates.is_empty() {
+        return Err(SMNtfsError::BitLockerKeyRejected(format!("The volume has no {} protector", kind)));
+    }
+
+    let password_hash = match key {
+        BitLockerKey::RecoveryPassword(password) => Some(Sha256::digest(recovery_password_key(password)?)),
+        BitLockerKey::Password(password) => {
+            let utf16: Vec<u8> = password.encode_utf16().flat_map(u16::to_le_bytes).collect();
+            Some(Sha256::digest(Sha256::digest(utf16)))
+        }
+        BitLockerKey::KeyFile(_) => None,
+    };
+    let startup_key = match key {
+        BitLockerKey::KeyFile(data) => Some(StartupKey::parse(data)?),
+        _ => None,
+    };
+
+    for protector in candidates {
+        let Ok(encrypted) = protector.encrypted_key() else {
+            continue;
+        };
+        let protector_key = match (&password_hash, &startup_key) {
+            (Some(hash), _) => match protector.stretch_salt() {
+                Some(salt) => stretch_key(hash, &salt, STRETCH_ROUNDS).to_vec(),
+                None => continue,
+            },
+            (None, Some(startup_key)) if startup_key.identifier == protector.identifier => startup_key.key.clone(),
+            _ => continue,
+        };
+        if let Some((_, vmk)) = decrypt_key(&protector_key, &encrypted) {
+            tracing::info!("Unlocked BitLocker protector {}", protector.identifier_string());
+            return Ok(vmk);
+        }
+    }
+    Err(SMNtfsError::BitLockerKeyRejected(format!("The {} does not unlock the volume", kind)))
+}
+
+/// An AES key of either size
+enum AesKey {
+    Aes128(Box<Aes128>),
+    Aes256(Box<Aes256>),
+}
+
+impl AesKey {
+    fn new(key: &[u8]) -> Result<Self> {
+        match key.len() {
+            16 => Ok(Self::Aes128(Box::new(Aes128::new(GenericArray::from_slice(key))))),
+            32 => Ok(Self::Aes256(Box::new(Aes256::new(GenericArray::from_slice(key))))),
+            length => Err(SMNtfsError::BitLockerMetadata(format!("Invalid AES key of {} bytes", length))),
+        }
+    }
+
+    fn encrypt(&self, block: &mut [u8]) {
+        let block = GenericArray::from_mut_slice(block);
+        match self {
+            Self::Aes128(cipher) => cipher.encrypt_block(block),
+            Self::Aes256(cipher) => cipher.encrypt_block(block),
+        }
+    }
+
+    fn decrypt(&self, block: &mut [u8]) {
+        let block = GenericArray::from_mut_slice(block);
+        match self {
+            Self::Aes128(cipher) => cipher.decrypt_block(block),
+            Self::Aes256(cipher) => cipher.decrypt_block(block),
+        }
+    }
+}
+
+/// Sector decryption with the FVEK
+enum SectorCipher {
+    /// AES-CBC with an IV derived from the byte offset, optionally followed
+    /// by the Elephant diffuser and a per-sector key
+    Cbc { key: AesKey, tweak: Option<AesKey> },
+    /// AES-XTS with the sector number as tweak
+    Xts { key: AesKey, tweak: AesKey },
+}
+
+impl SectorCipher {
+    /// Split the FVEK key bytes as the method lays them out
+    fn new(method: EncryptionMethod, fvek: &[u8]) -> Result<Self> {
+        let size = method.key_size();
+        let part = |range: Range<usize>| {
+            fvek.get(range).ok_or_else(|| {
+                SMNtfsError::BitLockerMetadata(format!("FVEK of {} bytes is too short for {}", fvek.len(), method.name()))
+            })
+        };
+
+        Ok(match method {
+            EncryptionMethod::Aes128Cbc | EncryptionMethod::Aes256Cbc => Self::Cbc {
+                key: AesKey::new(part(0..size)?)?,
+                tweak: None,
+            },
+            EncryptionMethod::Aes128CbcDiffuser | EncryptionMethod::Aes256CbcDiffuser => Self::Cbc {
+                key: AesKey::new(part(0..size)?)?,
+                tweak: Some(AesKey::new(part(32..32 + size)?)?),
+            },
+            EncryptionMethod::Aes128Xts | EncryptionMethod::Aes256Xts => Self::Xts {
+                key: AesKey::new(part(0..size)?)?,
+                tweak: AesKey::new(part(size..2 * size)?)?,
+            },
+        })
+    }
+
+    /// Decrypt the sector at byte `offset` of the volume in place
+    fn decrypt_sector(&self, offset: u64, sector_size: u64, data: &mut [u8]) {
+        match self {
+            Self::Cbc { key, tweak } => {
+                let mut iv = [0u8; 16];
+                iv[..8].copy_from_slice(&offset.to_le_bytes());
+                key.encrypt(&mut iv);
+
+                let mut previous = iv;
+                for block in data.chunks_exact_mut(16) {
+                    let mut ciphertext = [0u8; 16];
+                    ciphertext.copy_from_slice(block);
+                    key.decrypt(block);
+                    xor(block, &previous);
+                    previous = ciphertext;
+                }
+
+                if let Some(tweak) = tweak {
+                    let mut sector_key = [0u8; 32];
+                    sector_key[..8].copy_from_slice(&offset.to_le_bytes());
+                    sector_key[16..24].copy_from_slice(&offset.to_le_bytes());
+                    sector_key[31] = 0x80;
+                    tweak.encrypt(&mut sector_key[..16]);
+                    tweak.encrypt(&mut sector_key[16..]);
+
+                    diffuser_b_decrypt(data);
+                    diffuser_a_decrypt(data);
+                    for block in data.chunks_exact_mut(32) {
+                        xor(block, &sector_key);
+                    }
+                }
+            }
+            Self::Xts { key, tweak } => {
+                let mut tweak_block = [0u8; 16];
+                tweak_block[..8].copy_from_slice(&(offset / sector_size).to_le_bytes());
+                tweak.encrypt(&mut tweak_block);
+
+                for block in data.chunks_exact_mut(16) {
+                    xor(block, &tweak_block);
+                    key.decrypt(block);
+                    xor(block, &tweak_block);
+                    next_tweak(&mut tweak_block);
+                }
+            }
+        }
+    }
+}
+
+fn xor(data: &mut [u8], key: &[u8]) {
+    for (byte, key_byte) in data.iter_mut().zip(key) {
+        *byte ^= key_byte;
+    }
+}
+
+/// Multiply an XTS tweak by x in GF(2^128)
+fn next_tweak(tweak: &mut [u8; 16]) {
+    let mut carry = 0;
+    for byte in tweak.iter_mut() {
+        let next_carry = *byte >> 7;
+        *byte = (*byte << 1) | carry;
+        carry = next_carry;
+    }
+    if carry != 0 {
+        tweak[0] ^= 0x87;
+    }
+}
+
+fn to_words(data: &[u8]) -> Vec<u32> {
+    data.chunks_exact(4)
+        .map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]]))
+        .collect()
+}
+
+fn from_words(words: &[u32], data: &mut [u8]) {
+    for (chunk, word) in data.chunks_exact_mut(4).zip(words) {
+        chunk.copy_from_slice(&word.to_le_bytes());
+    }
+}
+
+/// Undo Elephant diffuser A: 5 cycles mixing each word with the words 2
+/// and 5 before it
+fn diffuser_a_decrypt(data: &mut [u8]) {
+    const ROTATIONS: [u32; 4] = [9, 0, 13, 0];
+    let mut words = to_words(data);
+    let n = words.len();
+    for _ in 0..5 {
+        for i in 0..n {
+            let mix = words[(i + n - 2) % n] ^ words[(i + n - 5) % n].rotate_left(ROTATIONS[i % 4]);
+            words[i] = words[i].wrapping_add(mix);
+        }
+    }
+    from_words(&words, data);
+}
+
+/// Undo Elephant diffuser B: 3 cycles mixing each word with the words 2
+/// and 5 after it
+fn diffuser_b_decrypt(data: &mut [u8]) {
+    const ROTATIONS: [u32; 4] = [0, 10, 0, 25];
+    let mut words = to_words(data);
+    let n = words.len();
+    for _ in 0..3 {
+        for i in 0..n {
+            let mix = words[(i + 2) % n] ^ words[(i + 5) % n].rotate_left(ROTATIONS[i % 4]);
+            words[i] = words[i].wrapping_add(mix);
+        }
+    }
+    from_words(&words, data);
+}
+
+/// A BitLocker volume, decrypted on read
+pub struct BitLockerDevice<T> {
+    inner: T,
+    cipher: SectorCipher,
+    method: EncryptionMethod,
+    sector_size: u64,
+    size: u64,
+    encrypted_size: u64,
+    header_offset: u64,
+    header_size: u64,
+    zeroed: Vec<Range<u64>>,
+    position: u64,
+}
+
+impl<T: Read + Seek> BitLockerDevice<T> {
+    /// Unlock a BitLocker volume
+    pub fn unlock(mut inner: T, key: &BitLockerKey) -> Result<Self> {
+        let metadata = FveMetadata::read(&mut inner)?;
+        let vmk = unlock_volume_master_key(&metadata, key)?;
+        let (method, fvek) = decrypt_key(&vmk, &metadata.encrypted_fvek()?)
+            .ok_or_else(|| SMNtfsError::BitLockerMetadata("The FVEK does not decrypt with the VMK".to_string()))?;
+        let method = EncryptionMethod::from_u16(method).ok_or_else(|| {
+            SMNtfsError::BitLockerMetadata(format!("Unsupported encryption method {:#06x}", method))
+        })?;
+        let cipher = SectorCipher::new(method, &fvek)?;
+
+        let mut boot_sector = [0u8; 512];
+        inner.seek(SeekFrom::Start(0))?;
+        inner.read_exact(&mut boot_sector)?;
+        let sector_size = le_u16(&boot_sector, 0x0B) as u64;
+        if !(512..=4096).contains(&sector_size) || !sector_size.is_power_of_two() {
+            return Err(SMNtfsError::BitLockerMetadata(format!("Invalid sector size {}", sector_size)));
+        }
+        let device_size = inner.seek(SeekFrom::End(0))?;
+        if device_size == 0 {
+            return Err(SMNtfsError::BitLockerMetadata("The device reports a size of 0 bytes".to_string()));
+        }
+        let size = device_size / sector_size * sector_size;
+
+        // On-disk offsets are checked, so crafted metadata cannot overflow
+        let region = |offset: u64, length: u64, name: &str| {
+            offset
+                .checked_add(length)
+                .filter(|end| *end <= size)
+                .map(|end| offset..end)
+                .ok_or_else(|| {
+                    SMNtfsError::BitLockerMetadata(format!(
+                        "{} at byte {} runs past the end of the {} byte volume",
+                        name, offset, size
+                    ))
+                })
+        };
+        let mut zeroed = Vec::new();
+        for offset in metadata.metadata_offsets.iter().filter(|offset| **offset != 0) {
+            zeroed.push(region(*offset, METADATA_REGION_SIZE, "FVE metadata copy")?);
+        }
+        let header_size = metadata.header_sectors as u64 * sector_size;
+        if header_size > 0 && metadata.header_offset == 0 {
+            return Err(SMNtfsError::BitLockerMetadata(
+                "The original boot sectors have no location".to_string(),
+            ));
+        }
+        zeroed.push(region(metadata.header_offset, header_size, "The original boot sectors")?);
+
+        tracing::info!(
+            "Unlocked BitLocker volume: {}, {} of {} bytes encrypted",
+            method.name(),
+            metadata.encrypted_size,
+            size
+        );
+        Ok(Self {
+            inner,
+            cipher,
+            method,
+            sector_size,
+            size,
+            encrypted_size: metadata.encrypted_size,
+            header_offset: metadata.header_offset,
+            header_size,
+            zeroed,
+            position: 0,
+        })
+    }
+
+    /// Cipher and mode of the volume data
+    pub fn method(&self) -> EncryptionMethod {
+        self.method
+    }
+
+    /// Size of the volume in bytes
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// The underlying device
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    /// Unwrap the underlying device
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    /// Read and decrypt whole sectors starting at sector `first`
+    fn read_sectors(&mut self, first: u64, buf: &mut [u8]) -> io::Result<()> {
+        let sector_size = self.sector_size;
+        let offset = first * sector_size;
+        self.inner.seek(SeekFrom::Start(offset))?;
+        self.inner.read_exact(buf)?;
+
+        for (index, sector) in buf.chunks_exact_mut(sector_size as usize).enumerate() {
+            let sector_offset = offset + index as u64 * sector_size;
+            if sector_offset < self.header_size {
+                let moved_offset = self.header_offset + sector_offset;
+                self.inner.seek(SeekFrom::Start(moved_offset))?;
+                self.inner.read_exact(sector)?;
+                self.cipher.decrypt_sector(moved_offset, sector_size, sector);
+            } else if self.zeroed.iter().any(|range| range.contains(&sector_offset)) {
+                sector.fill(0);
+            } else if sector_offset < self.encrypted_size {
+                self.cipher.decrypt_sector(sector_offset, sector_size, sector);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<T: Read + Seek> Read for BitLockerDevice<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() || self.position >= self.size {
+            return Ok(0);
+        }
+
+        let sector_size = self.sector_size as usize;
+        let first = self.position / self.sector_size;
+        let skip = (self.position % self.sector_size) as usize;
+        let remaining = (self.size - first * self.sector_size) as usize;
+        let length = (skip + buf.len()).min(MAX_READ_SIZE).next_multiple_of(sector_size).min(remaining);
+
+        let mut data = vec![0u8; length];
+        self.read_sectors(first, &mut data)?;
+        let count = (length - skip).min(buf.len());
+        buf[..count].copy_from_slice(&data[skip..skip + count]);
+        self.position += count as u64;
+        Ok(count)
+    }
+}
+
+impl<T> Seek for BitLockerDevice<T> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let new_position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+            SeekFrom::End(offset) => self.size.checked_add_signed(offset),
+        };
+
+        self.position = new_position.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "Seek to a negative or overflowing position")
+        })?;
+        Ok(self.position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Write};
+    use crate::io::BlockDevice;
+    use crate::parser::BlockDeviceAdapter;
+    use crate::io::fve::tests::{entry, key_value, metadata_header, vmk_value};
+    use crate::io::fve::{entry_types, BLOCK_HEADER_SIZE};
+
+    const SECTOR_SIZE: usize = 512;
+    const VOLUME_SIZE: usize = 512 * 1024;
+    const METADATA_OFFSETS: [u64; 3] = [0x10000, 0x20000, 0x30000];
+    const HEADER_OFFSET: u64 = 0x40000;
+    const HEADER_SECTORS: u32 = 16;
+    const ENCRYPTED_SIZE: u64 = 0x70000;
+
+    fn xts_encrypt(key: &AesKey, tweak: &AesKey, sector_number: u64, data: &mut [u8]) {
+        let mut tweak_block = [0u8; 16];
+        tweak_block[..8].copy_from_slice(&sector_number.to_le_bytes());
+        tweak.encrypt(&mut tweak_block);
+        for block in data.chunks_exact_mut(16) {
+            xor(block, &tweak_block);
+            key.encrypt(block);
+            xor(block, &tweak_block);
+            next_tweak(&mut tweak_block);
+        }
+    }
+
+    fn ccm_encrypt(key: &[u8], nonce: [u8; 12], entry_type: u16, value: Vec<u8>) -> Vec<u8> {
+        let mut data = entry(0, value_types::KEY, &value);
+        let tag = KeyCipher::new_from_slice(key)
+            .unwrap()
+            .encrypt_in_place_detached(GenericArray::from_slice(&nonce), b"", &mut data)
+            .unwrap();
+        let mut encrypted = nonce.to_vec();
+        encrypted.extend_from_slice(&tag);
+        encrypted.extend(data);
+        entry(entry_type, value_types::AES_CCM_ENCRYPTED_KEY, &encrypted)
+    }
+
+    /// A BitLocker image with one startup key protector and an AES-128-XTS
+    /// FVEK, its plain view, and the .BEK file
+    fn bitlocker_image() -> (Vec<u8>, Vec<u8>, Vec<u8>) {
+        let identifier = [0x5Au8; 16];
+        let startup_key = [0x44u8; 32];
+        let vmk = [0x33u8; 32];
+        let fvek = [[0x11u8; 16], [0x22u8; 16]].concat();
+
+        let mut plain: Vec<u8> = (0..VOLUME_SIZE).map(|i| (i / SECTOR_SIZE) as u8 ^ i as u8).collect();
+        plain[0x03..0x0B].copy_from_slice(b"NTFS    ");
+
+        let encrypted_vmk = ccm_encrypt(&startup_key, [1u8; 12], 0, key_value(0x2000, &vmk));
+        let vmk_entry = entry(
+            entry_types::VOLUME_MASTER_KEY,
+            value_types::VOLUME_MASTER_KEY,
+            &vmk_value(identifier, protection::STARTUP_KEY, &[encrypted_vmk]),
+        );
+        let fvek_entry = ccm_encrypt(&vmk, [2u8; 12], entry_types::FULL_VOLUME_ENCRYPTION_KEY, key_value(0x8004, &fvek));
+
+        let mut block = vec![0u8; BLOCK_HEADER_SIZE];
+        block[..8].copy_from_slice(b"-FVE-FS-");
+        block[0x0A..0x0C].copy_from_slice(&2u16.to_le_bytes());
+        block[0x10..0x18].copy_from_slice(&ENCRYPTED_SIZE.to_le_bytes());
+        block[0x1C..0x20].copy_from_slice(&HEADER_SECTORS.to_le_bytes());
+        for (copy, offset) in METADATA_OFFSETS.iter().enumerate() {
+            block[0x20 + copy * 8..0x28 + copy * 8].copy_from_slice(&offset.to_le_bytes());
+        }
+        block[0x38..0x40].copy_from_slice(&HEADER_OFFSET.to_le_bytes());
+        block.extend(metadata_header(0x8004, &[vmk_entry, fvek_entry]));
+
+        let key = AesKey::new(&fvek[..16]).unwrap();
+        let tweak = AesKey::new(&fvek[16..]).unwrap();
+        let header_size = HEADER_SECTORS as usize * SECTOR_SIZE;
+        let mut image = plain.clone();
+        let mut view = plain;
+        for sector in 0..ENCRYPTED_SIZE as usize / SECTOR_SIZE {
+            let range = sector * SECTOR_SIZE..(sector + 1) * SECTOR_SIZE;
+            xts_encrypt(&key, &tweak, sector as u64, &mut image[range]);
+        }
+        for offset in (0..header_size).step_by(SECTOR_SIZE) {
+            let moved = HEADER_OFFSET as usize + offset;
+            image[moved..moved + SECTOR_SIZE].copy_from_slice(&view[offset..offset + SECTOR_SIZE]);
+            xts_encrypt(&key, &tweak, (moved / SECTOR_SIZE) as u64, &mut image[moved..moved + SECTOR_SIZE]);
+        }
+        for offset in METADATA_OFFSETS {
+            let offset = offset as usize;
+            image[offset..offset + block.len()].copy_from_slice(&block);
+            view[offset..offset + METADATA_REGION_SIZE as usize].fill(0);
+        }
+        view[HEADER_OFFSET as usize..HEADER_OFFSET as usize + header_size].fill(0);
+
+        image[..SECTOR_SIZE].fill(0);
+        image[0x03..0x0B].copy_from_slice(b"-FVE-FS-");
+        image[0x0B..0x0D].copy_from_slice(&(SECTOR_SIZE as u16).to_le_bytes());
+        for (copy, offset) in METADATA_OFFSETS.iter().enumerate() {
+            image[0xB0 + copy * 8..0xB8 + copy * 8].copy_from_slice(&offset.to_le_bytes());
+        }
+
+        let mut external = identifier.to_vec();
+        external.resize(0x18, 0);
+        external.extend(entry(0, value_types::KEY, &key_value(0x2000, &startup_key)));
+        let bek = metadata_header(0x8004, &[entry(entry_types::STARTUP_KEY, value_types::EXTERNAL_KEY, &external)]);
+
+        (image, view, bek)
+    }
+
+    #[test]
+    fn test_unlock_with_startup_key() {
+        let (image, view, bek) = bitlocker_image();
+        let mut device = BitLockerDevice::unlock(Cursor::new(image), &BitLockerKey::KeyFile(bek.clone())).unwrap();
+        assert_eq!(device.method(), EncryptionMethod::Aes128Xts);
+        assert_eq!(device.size(), VOLUME_SIZE as u64);
+
+        let mut decrypted = Vec::new();
+        device.read_to_end(&mut decrypted).unwrap();
+        assert!(decrypted == view);
+
+        device.seek(SeekFrom::Start(1000)).unwrap();
+        let mut unaligned = [0u8; 100];
+        device.read_exact(&mut unaligned).unwrap();
+        assert_eq!(&unaligned[..], &view[1000..1100]);
+
+        let (image, _, mut wrong_bek) = bitlocker_image();
+        let key_offset = wrong_bek.len() - 1;
+        wrong_bek[key_offset] ^= 1;
+        assert!(matches!(
+            BitLockerDevice::unlock(Cursor::new(image.clone()), &BitLockerKey::KeyFile(wrong_bek)),
+            Err(SMNtfsError::BitLockerKeyRejected(_))
+        ));
+        let password = BitLockerKey::RecoveryPassword("000000-".repeat(8).trim_end_matches('-').to_string());
+        assert!(matches!(
+            BitLockerDevice::unlock(Cursor::new(image), &password),
+            Err(SMNtfsError::BitLockerKeyRejected(_))
+        ));
+    }
+
+    #[test]
+    fn test_unlock_block_device() {
+        let (image, view, bek) = bitlocker_image();
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(&image).unwrap();
+
+        let adapter = BlockDeviceAdapter::new(BlockDevice::open(file.path()).unwrap());
+        let mut device = BitLockerDevice::unlock(adapter, &BitLockerKey::KeyFile(bek)).unwrap();
+        assert_eq!(device.size(), VOLUME_SIZE as u64);
+
+        let mut decrypted = Vec::new();
+        device.read_to_end(&mut decrypted).unwrap();
+        assert!(decrypted == view);
+    }
+
+    /// Overwrite a field of every metadata block header
+    fn patch_metadata(image: &mut [u8], field: usize, value: u64) {
+        for offset in METADATA_OFFSETS {
+            let position = offset as usize + field;
+            image[position..position + 8].copy_from_slice(&value.to_le_bytes());
+        }
+    }
+
+    #[test]
+    fn test_invalid_metadata_offsets() {
+        let key = |bek: Vec<u8>| BitLockerKey::KeyFile(bek);
+        for (field, value) in [(0x38, u64::MAX - 100), (0x38, VOLUME_SIZE as u64), (0x38, 0), (0x28, u64::MAX)] {
+            let (mut image, _, bek) = bitlocker_image();
+            patch_metadata(&mut image, field, value);
+            assert!(matches!(
+                BitLockerDevice::unlock(Cursor::new(image), &key(bek)),
+                Err(SMNtfsError::BitLockerMetadata(_))
+            ));
+        }
+
+        // A missing copy is skipped rather than zeroing the start of the volume
+        let (mut image, view, bek) = bitlocker_image();
+        patch_metadata(&mut image, 0x30, 0);
+        let mut device = BitLockerDevice::unlock(Cursor::new(image), &key(bek)).unwrap();
+        let mut start = vec![0u8; METADATA_OFFSETS[0] as usize];
+        device.read_exact(&mut start).unwrap();
+        assert!(start == view[..start.len()]);
+    }
+
+    #[test]
+    fn test_recovery_password_key() {
+        let key = recovery_password_key("000011-000022-000033-000044-000055-000066-720885-000000").unwrap();
+        assert_eq!(&key[..4], &[1, 0, 2, 0]);
+        assert_eq!(&key[12..], &[0xFF, 0xFF, 0, 0]);
+
+        assert!(recovery_password_key("000012-000022-000033-000044-000055-000066-000077-000000").is_err());
+        assert!(recovery_password_key("720896-000022-000033-000044-000055-000066-000077-000000").is_err());
+        assert!(recovery_password_key("000011-000022").is_err());
+    }
+
+    #[test]
+    fn test_xts_vector() {
+        // IEEE 1619 XTS-AES-128 vector 1: zero keys, data unit 0, zero plaintext
+        let ciphertext = [
+            0x91, 0x7c, 0xf6, 0x9e, 0xbd, 0x68, 0xb2, 0xec, 0x9b, 0x9f, 0xe9, 0xa3, 0xea, 0xdd, 0xa6, 0x92, 0xcd, 0x43,
+            0xd2, 0xf5, 0x95, 0x98, 0xed, 0x85, 0x8c, 0x02, 0xc2, 0x65, 0x2f, 0xbf, 0x92, 0x2e,
+        ];
+        let cipher = SectorCipher::new(EncryptionMethod::Aes128Xts, &[0u8; 32]).unwrap();
+        let mut data = ciphertext;
+        cipher.decrypt_sector(0, 512, &mut data);
+        assert_eq!(data, [0u8; 32]);
+    }
+
+    #[test]
+    fn test_diffuser_round_trip() {
+        fn diffuser_a_encrypt(words: &mut [u32]) {
+            const ROTATIONS: [u32; 4] = [9, 0, 13, 0];
+            let n = words.len();
+            for _ in 0..5 {
+                for i in (0..n).rev() {
+                    let mix = words[(i + n - 2) % n] ^ words[(i + n - 5) % n].rotate_left(ROTATIONS[i % 4]);
+                    words[i] = words[i].wrapping_sub(mix);
+                }
+            }
+        }
+        fn diffuser_b_encrypt(words: &mut [u32]) {
+            const ROTATIONS: [u32; 4] = [0, 10, 0, 25];
+            let n = words.len();
+            for _ in 0..3 {
+                for i in (0..n).rev() {
+                    let mix = words[(i + 2) % n] ^ words[(i + 5) % n].rotate_left(ROTATIONS[i % 4]);
+                    words[i] = words[i].wrapping_sub(mix);
+                }
+            }
+        }
+
+        let plain: Vec<u8> = (0..512u32).map(|i| (i * 7 + 3) as u8).collect();
+        let mut words = to_words(&plain);
+        diffuser_a_encrypt(&mut words);
+        diffuser_b_encrypt(&mut words);
+        let mut data = vec![0u8; plain.len()];
+        from_words(&words, &mut data);
+        assert_ne!(data, plain);
+
+        diffuser_b_decrypt(&mut data);
+        diffuser_a_decrypt(&mut data);
+        assert_eq!(data, plain);
+    }
+}
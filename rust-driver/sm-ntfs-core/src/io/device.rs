@@ -3,6 +3,7 @@
 //! This module provides low-level I/O operations for accessing block devices.
 
 use std::fs::{File, OpenOptions};
+use std::io::{self, Seek, SeekFrom};
 use std::os::unix::fs::FileExt;
 use std::path::Path;
 use crate::utils::error::{Result, SMNtfsError};
@@ -39,10 +40,7 @@ impl BlockDevice {
                 SMNtfsError::DeviceNotFound(format!("{}: {}", path.display(), e))
             })?;
 
-        // Get device size
-        let device_size = file.metadata()
-            .map_err(|e| SMNtfsError::SystemError(format!("Failed to get device metadata: {}", e)))?
-            .len();
+        let device_size = device_size(&file)?;
 
         tracing::info!(
             "Device opened: {:?}, size: {} bytes ({} MB)",
@@ -202,6 +200,72 @@ impl BlockDevice {
     }
 }
 
+/// Size of an image file, or of a disk as its driver reports it
+///
+/// The metadata length of block and character devices is 0, so disks are
+/// asked with an ioctl, falling back to seeking to the end.
+fn device_size(file: &File) -> Result<u64> {
+    let metadata = file
+        .metadata()
+        .map_err(|e| SMNtfsError::SystemError(format!("Failed to get device metadata: {}", e)))?;
+    if metadata.is_file() {
+        return Ok(metadata.len());
+    }
+
+    match disk_size(file) {
+        Ok(size) => Ok(size),
+        Err(e) => {
+            tracing::debug!("Disk size ioctl failed ({}); seeking to the end instead", e);
+            let mut file = file;
+            file.seek(SeekFrom::End(0))
+                .map_err(|e| SMNtfsError::SystemError(format!("Failed to get device size: {}", e)))
+        }
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn disk_size(file: &File) -> io::Result<u64> {
+    use std::os::unix::io::AsRawFd;
+
+    // _IOR('d', 24, uint32_t) and _IOR('d', 25, uint64_t) from <sys/disk.h>
+    const DKIOCGETBLOCKSIZE: libc::c_ulong = 0x4004_6418;
+    const DKIOCGETBLOCKCOUNT: libc::c_ulong = 0x4008_6419;
+
+    let mut block_size: u32 = 0;
+    let mut block_count: u64 = 0;
+    // SAFETY: both requests write one integer of the type passed
+    unsafe {
+        if libc::ioctl(file.as_raw_fd(), DKIOCGETBLOCKSIZE as _, &mut block_size) != 0
+            || libc::ioctl(file.as_raw_fd(), DKIOCGETBLOCKCOUNT as _, &mut block_count) != 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    block_count
+        .checked_mul(block_size as u64)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "disk size overflows"))
+}
+
+#[cfg(target_os = "linux")]
+fn disk_size(file: &File) -> io::Result<u64> {
+    use std::os::unix::io::AsRawFd;
+
+    // _IOR(0x12, 114, size_t) from <linux/fs.h>
+    const BLKGETSIZE64: libc::c_ulong = 0x8008_1272;
+
+    let mut size: u64 = 0;
+    // SAFETY: the request writes one u64
+    if unsafe { libc::ioctl(file.as_raw_fd(), BLKGETSIZE64 as _, &mut size) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(size)
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "linux")))]
+fn disk_size(_file: &File) -> io::Result<u64> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
 impl Drop for BlockDevice {
     fn drop(&mut self) {
         if !self.read_only {
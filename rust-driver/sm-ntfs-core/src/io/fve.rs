@@ -0,0 +1,537 @@
+//! BitLocker full-volume encryption (FVE) metadata
+//!
+//! A BitLocker volume replaces the NTFS boot sector with one whose OEM ID is
+//! `-FVE-FS-` and which points at three copies of the FVE metadata. Each
+//! copy is a block header (the encrypted size of the volume and where the
+//! original boot sectors were moved), a metadata header, and a list of
+//! entries. The entries that matter for unlocking are the volume master key
+//! (VMK) protectors, each holding the VMK encrypted with AES-CCM under a key
+//! derived from its protector, and the full volume encryption key (FVEK)
+//! encrypted with the VMK. Entries nest: a VMK protector's value is itself
+//! a list of entries.
+//!
+//! Only the Windows 7 and later layout (metadata version 2) is read; Vista
+//! volumes are refused.
+
+use std::io::{Read, Seek, SeekFrom};
+use crate::parser::probe::{BITLOCKER_OEM_ID, BITLOCKER_TO_GO_OEM_ID};
+use crate::parser::security::format_guid;
+use crate::utils::bytes::{le_u16, le_u32, le_u64};
+use crate::utils::error::{Result, SMNtfsError};
+
+/// Size of the FVE block header
+pub const BLOCK_HEADER_SIZE: usize = 64;
+/// Size of the metadata header following the block header
+pub const METADATA_HEADER_SIZE: usize = 48;
+/// Largest metadata accepted, well above what Windows writes
+const MAX_METADATA_SIZE: usize = 64 * 1024;
+
+/// Offset of the three metadata offsets in the boot sector
+const METADATA_OFFSETS_OFFSET: usize = 0xB0;
+/// Same, in a BitLocker To Go boot sector
+const TO_GO_METADATA_OFFSETS_OFFSET: usize = 0x1B8;
+
+/// Metadata entry types
+pub mod entry_types {
+    pub const PROPERTY: u16 = 0x0000;
+    pub const VOLUME_MASTER_KEY: u16 = 0x0002;
+    pub const FULL_VOLUME_ENCRYPTION_KEY: u16 = 0x0003;
+    pub const VALIDATION: u16 = 0x0004;
+    pub const STARTUP_KEY: u16 = 0x0006;
+    pub const DESCRIPTION: u16 = 0x0007;
+    pub const VOLUME_HEADER_BLOCK: u16 = 0x000F;
+}
+
+/// Metadata entry value types
+pub mod value_types {
+    pub const ERASED: u16 = 0x0000;
+    pub const KEY: u16 = 0x0001;
+    pub const UNICODE_STRING: u16 = 0x0002;
+    pub const STRETCH_KEY: u16 = 0x0003;
+    pub const USE_KEY: u16 = 0x0004;
+    pub const AES_CCM_ENCRYPTED_KEY: u16 = 0x0005;
+    pub const TPM_ENCODED_KEY: u16 = 0x0006;
+    pub const VALIDATION: u16 = 0x0007;
+    pub const VOLUME_MASTER_KEY: u16 = 0x0008;
+    pub const EXTERNAL_KEY: u16 = 0x0009;
+    pub const UPDATE: u16 = 0x000A;
+    pub const ERROR: u16 = 0x000B;
+    pub const OFFSET_AND_SIZE: u16 = 0x000F;
+}
+
+/// How a volume master key protector is unlocked
+pub mod protection {
+    /// Unprotected, while BitLocker is suspended
+    pub const CLEAR_KEY: u16 = 0x0000;
+    pub const TPM: u16 = 0x0100;
+    /// Startup key in a .BEK file
+    pub const STARTUP_KEY: u16 = 0x0200;
+    pub const TPM_AND_PIN: u16 = 0x0500;
+    /// 48-digit recovery password
+    pub const RECOVERY_PASSWORD: u16 = 0x0800;
+    /// User password
+    pub const PASSWORD: u16 = 0x2000;
+}
+
+/// Cipher and mode of the volume data
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EncryptionMethod {
+    Aes128CbcDiffuser,
+    Aes256CbcDiffuser,
+    Aes128Cbc,
+    Aes256Cbc,
+    Aes128Xts,
+    Aes256Xts,
+}
+
+impl EncryptionMethod {
+    /// Decode an encryption method number
+    pub fn from_u16(value: u16) -> Option<Self> {
+        Some(match value {
+            0x8000 => Self::Aes128CbcDiffuser,
+            0x8001 => Self::Aes256CbcDiffuser,
+            0x8002 => Self::Aes128Cbc,
+            0x8003 => Self::Aes256Cbc,
+            0x8004 => Self::Aes128Xts,
+            0x8005 => Self::Aes256Xts,
+            _ => return None,
+        })
+    }
+
+    /// Display name, e.g. `AES-128-XTS`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Aes128CbcDiffuser => "AES-128-CBC with Elephant diffuser",
+            Self::Aes256CbcDiffuser => "AES-256-CBC with Elephant diffuser",
+            Self::Aes128Cbc => "AES-128-CBC",
+            Self::Aes256Cbc => "AES-256-CBC",
+            Self::Aes128Xts => "AES-128-XTS",
+            Self::Aes256Xts => "AES-256-XTS",
+        }
+    }
+
+    /// Size in bytes of each AES key
+    pub fn key_size(&self) -> usize {
+        match self {
+            Self::Aes128CbcDiffuser | Self::Aes128Cbc | Self::Aes128Xts => 16,
+            Self::Aes256CbcDiffuser | Self::Aes256Cbc | Self::Aes256Xts => 32,
+        }
+    }
+}
+
+/// A metadata entry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FveEntry {
+    /// Entry type, see [`entry_types`]
+    pub entry_type: u16,
+
+    /// Value type, see [`value_types`]
+    pub value_type: u16,
+
+    /// Value bytes, after the 8-byte entry header
+    pub data: Vec<u8>,
+}
+
+/// Parse a list of metadata entries, which ends at the first empty one
+pub fn parse_entries(data: &[u8]) -> Result<Vec<FveEntry>> {
+    let mut entries = Vec::new();
+    let mut offset = 0;
+    while offset + 8 <= data.len() {
+        let size = le_u16(data, offset) as usize;
+        if size == 0 {
+            break;
+        }
+        if size < 8 || offset + size > data.len() {
+            return Err(SMNtfsError::BitLockerMetadata(format!(
+                "Entry of {} bytes at byte {} overruns the metadata",
+                size, offset
+            )));
+        }
+        entries.push(FveEntry {
+            entry_type: le_u16(data, offset + 2),
+            value_type: le_u16(data, offset + 4),
+            data: data[offset + 8..offset + size].to_vec(),
+        });
+        offset += size;
+    }
+    Ok(entries)
+}
+
+/// Split a key value into its encryption method and key bytes
+pub fn parse_key(data: &[u8]) -> Result<(u16, &[u8])> {
+    if data.len() <= 4 {
+        return Err(SMNtfsError::BitLockerMetadata("Empty key value".to_string()));
+    }
+    Ok((le_u32(data, 0) as u16, &data[4..]))
+}
+
+fn guid(data: &[u8], offset: usize) -> [u8; 16] {
+    let mut guid = [0u8; 16];
+    if let Some(bytes) = data.get(offset..offset + 16) {
+        guid.copy_from_slice(bytes);
+    }
+    guid
+}
+
+/// A key encrypted with AES-CCM
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EncryptedKey {
+    /// Nonce: a FILETIME and a counter
+    pub nonce: [u8; 12],
+
+    /// Authentication tag
+    pub mac: [u8; 16],
+
+    /// Encrypted key entry
+    pub data: Vec<u8>,
+}
+
+impl EncryptedKey {
+    /// Decode an AES-CCM encrypted key value
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        if data.len() <= 28 {
+            return Err(SMNtfsError::BitLockerMetadata(format!(
+                "Truncated encrypted key of {} bytes",
+                data.len()
+            )));
+        }
+        let mut nonce = [0u8; 12];
+        nonce.copy_from_slice(&data[..12]);
+        let mut mac = [0u8; 16];
+        mac.copy_from_slice(&data[12..28]);
+        Ok(Self {
+            nonce,
+            mac,
+            data: data[28..].to_vec(),
+        })
+    }
+}
+
+/// A volume master key protector
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VolumeMasterKey {
+    /// Protector identifier, matched by startup key files
+    pub identifier: [u8; 16],
+
+    /// How the protector is unlocked, see [`protection`]
+    pub protection: u16,
+
+    /// Nested entries: the encrypted VMK, and a salt or clear key
+    pub entries: Vec<FveEntry>,
+}
+
+impl VolumeMasterKey {
+    /// Decode a volume master key value
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        if data.len() < 0x1C {
+            return Err(SMNtfsError::BitLockerMetadata(format!(
+                "Truncated volume master key of {} bytes",
+                data.len()
+            )));
+        }
+        Ok(Self {
+            identifier: guid(data, 0),
+            protection: le_u16(data, 0x1A),
+            entries: parse_entries(&data[0x1C..])?,
+        })
+    }
+
+    /// Identifier as a GUID string
+    pub fn identifier_string(&self) -> String {
+        format_guid(&self.identifier)
+    }
+
+    /// The VMK, encrypted under the protector's key
+    pub fn encrypted_key(&self) -> Result<EncryptedKey> {
+        let entry = self
+            .entries
+            .iter()
+            .find(|entry| entry.value_type == value_types::AES_CCM_ENCRYPTED_KEY)
+            .ok_or_else(|| {
+                SMNtfsError::BitLockerMetadata(format!("Protector {} has no encrypted key", self.identifier_string()))
+            })?;
+        EncryptedKey::parse(&entry.data)
+    }
+
+    /// Salt of the password stretching, for password and recovery password
+    /// protectors
+    pub fn stretch_salt(&self) -> Option<[u8; 16]> {
+        self.entries
+            .iter()
+            .find(|entry| entry.value_type == value_types::STRETCH_KEY && entry.data.len() >= 20)
+            .map(|entry| guid(&entry.data, 4))
+    }
+
+    /// The key the VMK is encrypted with, while BitLocker is suspended
+    pub fn clear_key(&self) -> Option<&[u8]> {
+        self.entries
+            .iter()
+            .find(|entry| entry.value_type == value_types::KEY)
+            .and_then(|entry| parse_key(&entry.data).ok())
+            .map(|(_, key)| key)
+    }
+}
+
+/// One copy of the FVE metadata
+#[derive(Debug, Clone)]
+pub struct FveMetadata {
+    /// Metadata version (2 from Windows 7 on)
+    pub version: u16,
+
+    /// Bytes from the start of the volume that are encrypted
+    pub encrypted_size: u64,
+
+    /// Number of sectors of the original boot sectors
+    pub header_sectors: u32,
+
+    /// Byte offsets of the three metadata copies
+    pub metadata_offsets: [u64; 3],
+
+    /// Byte offset where the original boot sectors are kept, encrypted
+    pub header_offset: u64,
+
+    /// Volume identifier
+    pub volume_identifier: [u8; 16],
+
+    /// Encryption method recorded in the metadata header
+    pub method: u16,
+
+    /// Top-level metadata entries
+    pub entries: Vec<FveEntry>,
+}
+
+impl FveMetadata {
+    /// Decode a metadata copy: block header, metadata header and entries
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        if data.len() < BLOCK_HEADER_SIZE + METADATA_HEADER_SIZE || &data[..8] != BITLOCKER_OEM_ID {
+            return Err(SMNtfsError::BitLockerMetadata("No FVE metadata block signature".to_string()));
+        }
+        let version = le_u16(data, 0x0A);
+        if version != 2 {
+            return Err(SMNtfsError::BitLockerMetadata(format!(
+                "FVE metadata version {} is not supported (only Windows 7 and later)",
+                version
+            )));
+        }
+
+        let header = &data[BLOCK_HEADER_SIZE..];
+        let size = le_u32(header, 0x00) as usize;
+        if size < METADATA_HEADER_SIZE || size > header.len() || le_u32(header, 0x08) as usize != METADATA_HEADER_SIZE {
+            return Err(SMNtfsError::BitLockerMetadata(format!("Invalid metadata size {}", size)));
+        }
+
+        Ok(Self {
+            version,
+            encrypted_size: le_u64(data, 0x10),
+            header_sectors: le_u32(data, 0x1C),
+            metadata_offsets: [le_u64(data, 0x20), le_u64(data, 0x28), le_u64(data, 0x30)],
+            header_offset: le_u64(data, 0x38),
+            volume_identifier: guid(header, 0x10),
+            method: le_u32(header, 0x24) as u16,
+            entries: parse_entries(&header[METADATA_HEADER_SIZE..size])?,
+        })
+    }
+
+    /// Read the first valid metadata copy of a BitLocker volume
+    pub fn read<T: Read + Seek>(fs: &mut T) -> Result<Self> {
+        let mut boot_sector = [0u8; 512];
+        fs.seek(SeekFrom::Start(0))?;
+        fs.read_exact(&mut boot_sector)?;
+
+        let offsets_offset = match &boot_sector[0x03..0x0B] {
+            id if id == BITLOCKER_OEM_ID => METADATA_OFFSETS_OFFSET,
+            id if id == BITLOCKER_TO_GO_OEM_ID => TO_GO_METADATA_OFFSETS_OFFSET,
+            _ => return Err(SMNtfsError::BitLockerMetadata("Not a BitLocker volume".to_string())),
+        };
+
+        let mut error = SMNtfsError::BitLockerMetadata("No FVE metadata offsets".to_string());
+        for copy in 0..3 {
+            let offset = le_u64(&boot_sector, offsets_offset + copy * 8);
+            if offset == 0 {
+                continue;
+            }
+            match Self::read_copy(fs, offset) {
+                Ok(metadata) => return Ok(metadata),
+                Err(e) => {
+                    tracing::warn!("FVE metadata copy {} at byte {} is unusable: {}", copy + 1, offset, e);
+                    error = e;
+                }
+            }
+        }
+        Err(error)
+    }
+
+    fn read_copy<T: Read + Seek>(fs: &mut T, offset: u64) -> Result<Self> {
+        let mut data = vec![0u8; BLOCK_HEADER_SIZE + METADATA_HEADER_SIZE];
+        fs.seek(SeekFrom::Start(offset))?;
+        fs.read_exact(&mut data)?;
+
+        let size = le_u32(&data, BLOCK_HEADER_SIZE) as usize;
+        if size > MAX_METADATA_SIZE {
+            return Err(SMNtfsError::BitLockerMetadata(format!("Metadata of {} bytes is too large", size)));
+        }
+        if size > METADATA_HEADER_SIZE {
+            data.resize(BLOCK_HEADER_SIZE + size, 0);
+            fs.read_exact(&mut data[BLOCK_HEADER_SIZE + METADATA_HEADER_SIZE..])?;
+        }
+        Self::parse(&data)
+    }
+
+    /// Volume master key protectors
+    pub fn volume_master_keys(&self) -> Vec<VolumeMasterKey> {
+        self.entries
+            .iter()
+            .filter(|entry| {
+                entry.entry_type == entry_types::VOLUME_MASTER_KEY && entry.value_type == value_types::VOLUME_MASTER_KEY
+            })
+            .filter_map(|entry| VolumeMasterKey::parse(&entry.data).ok())
+            .collect()
+    }
+
+    /// The FVEK, encrypted with the VMK
+    pub fn encrypted_fvek(&self) -> Result<EncryptedKey> {
+        let entry = self
+            .entries
+            .iter()
+            .find(|entry| {
+                entry.entry_type == entry_types::FULL_VOLUME_ENCRYPTION_KEY
+                    && entry.value_type == value_types::AES_CCM_ENCRYPTED_KEY
+            })
+            .ok_or_else(|| SMNtfsError::BitLockerMetadata("No full volume encryption key".to_string()))?;
+        EncryptedKey::parse(&entry.data)
+    }
+}
+
+/// A startup key, as saved to a .BEK file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StartupKey {
+    /// Identifier of the protector the key unlocks
+    pub identifier: [u8; 16],
+
+    /// The 256-bit key
+    pub key: Vec<u8>,
+}
+
+impl StartupKey {
+    /// Decode a .BEK file: a metadata header followed by an external key entry
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let size = (le_u32(data, 0x00) as usize).min(data.len());
+        if size <= METADATA_HEADER_SIZE || le_u32(data, 0x08) as usize != METADATA_HEADER_SIZE {
+            return Err(SMNtfsError::BitLockerMetadata("Not a BitLocker startup key file".to_string()));
+        }
+
+        let external = parse_entries(&data[METADATA_HEADER_SIZE..size])?
+            .into_iter()
+            .find(|entry| entry.entry_type == entry_types::STARTUP_KEY && entry.value_type == value_types::EXTERNAL_KEY)
+            .filter(|entry| entry.data.len() > 0x18)
+            .ok_or_else(|| SMNtfsError::BitLockerMetadata("Startup key file holds no key".to_string()))?;
+        let key = parse_entries(&external.data[0x18..])?
+            .into_iter()
+            .find(|entry| entry.value_type == value_types::KEY)
+            .ok_or_else(|| SMNtfsError::BitLockerMetadata("Startup key file holds no key".to_string()))?;
+
+        Ok(Self {
+            identifier: guid(&external.data, 0),
+            key: parse_key(&key.data)?.1.to_vec(),
+        })
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Build a metadata entry
+    pub(crate) fn entry(entry_type: u16, value_type: u16, data: &[u8]) -> Vec<u8> {
+        let mut entry = Vec::new();
+        entry.extend_from_slice(&((data.len() + 8) as u16).to_le_bytes());
+        entry.extend_from_slice(&entry_type.to_le_bytes());
+        entry.extend_from_slice(&value_type.to_le_bytes());
+        entry.extend_from_slice(&1u16.to_le_bytes());
+        entry.extend_from_slice(data);
+        entry
+    }
+
+    /// Build a key value
+    pub(crate) fn key_value(method: u32, key: &[u8]) -> Vec<u8> {
+        let mut data = method.to_le_bytes().to_vec();
+        data.extend_from_slice(key);
+        data
+    }
+
+    /// Build a volume master key value
+    pub(crate) fn vmk_value(identifier: [u8; 16], protection: u16, entries: &[Vec<u8>]) -> Vec<u8> {
+        let mut data = identifier.to_vec();
+        data.resize(0x1A, 0);
+        data.extend_from_slice(&protection.to_le_bytes());
+        data.extend(entries.concat());
+        data
+    }
+
+    /// Build a metadata header followed by entries
+    pub(crate) fn metadata_header(method: u16, entries: &[Vec<u8>]) -> Vec<u8> {
+        let entries = entries.concat();
+        let size = (METADATA_HEADER_SIZE + entries.len()) as u32;
+        let mut data = vec![0u8; METADATA_HEADER_SIZE];
+        data[0x00..0x04].copy_from_slice(&size.to_le_bytes());
+        data[0x04..0x08].copy_from_slice(&1u32.to_le_bytes());
+        data[0x08..0x0C].copy_from_slice(&(METADATA_HEADER_SIZE as u32).to_le_bytes());
+        data[0x0C..0x10].copy_from_slice(&size.to_le_bytes());
+        data[0x24..0x28].copy_from_slice(&(method as u32).to_le_bytes());
+        data.extend(entries);
+        data
+    }
+
+    #[test]
+    fn test_parse_entries() {
+        let data = [entry(entry_types::DESCRIPTION, value_types::UNICODE_STRING, b"a\0"), vec![0u8; 8]].concat();
+        let entries = parse_entries(&data).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].data, b"a\0");
+
+        let mut overrun = entry(entry_types::PROPERTY, value_types::KEY, &[0u8; 4]);
+        overrun[0] = 0x40;
+        assert!(parse_entries(&overrun).is_err());
+    }
+
+    #[test]
+    fn test_volume_master_key() {
+        let salt = [7u8; 16];
+        let mut stretch = key_value(0x1000, &salt);
+        stretch.extend(entry(entry_types::PROPERTY, value_types::AES_CCM_ENCRYPTED_KEY, &[0u8; 40]));
+        let mut encrypted = vec![1u8; 12];
+        encrypted.extend_from_slice(&[2u8; 16]);
+        encrypted.extend_from_slice(&[3u8; 44]);
+
+        let value = vmk_value(
+            [9u8; 16],
+            protection::RECOVERY_PASSWORD,
+            &[
+                entry(entry_types::PROPERTY, value_types::STRETCH_KEY, &stretch),
+                entry(entry_types::PROPERTY, value_types::AES_CCM_ENCRYPTED_KEY, &encrypted),
+            ],
+        );
+        let vmk = VolumeMasterKey::parse(&value).unwrap();
+
+        assert_eq!(vmk.protection, protection::RECOVERY_PASSWORD);
+        assert_eq!(vmk.stretch_salt(), Some(salt));
+        assert!(vmk.clear_key().is_none());
+        let key = vmk.encrypted_key().unwrap();
+        assert_eq!(key.nonce, [1u8; 12]);
+        assert_eq!(key.mac, [2u8; 16]);
+        assert_eq!(key.data, vec![3u8; 44]);
+    }
+
+    #[test]
+    fn test_startup_key_file() {
+        let mut external = [5u8; 16].to_vec();
+        external.resize(0x18, 0);
+        external.extend(entry(entry_types::PROPERTY, value_types::KEY, &key_value(0x2000, &[6u8; 32])));
+        let file = metadata_header(0x8004, &[entry(entry_types::STARTUP_KEY, value_types::EXTERNAL_KEY, &external)]);
+
+        let key = StartupKey::parse(&file).unwrap();
+        assert_eq!(key.identifier, [5u8; 16]);
+        assert_eq!(key.key, vec![6u8; 32]);
+        assert!(StartupKey::parse(&file[..40]).is_err());
+    }
+}
@@ -5,7 +5,12 @@
 pub mod device;
 pub mod buffer;
 pub mod sync;
+pub mod undo;
+pub mod fve;
+pub mod bitlocker;
 
 pub use device::{BlockDevice, DEFAULT_SECTOR_SIZE};
 pub use buffer::IOBuffer;
 pub use sync::{SyncPolicy, SyncManager};
+pub use undo::{UndoDevice, rollback};
+pub use bitlocker::{BitLockerDevice, BitLockerKey};
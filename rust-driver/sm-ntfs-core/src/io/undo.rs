@@ -0,0 +1,201 @@
+//! Undo log of sector pre-images
+//!
+//! Before anything is written through an [`UndoDevice`], the current
+//! contents of every sector about to change are appended to an undo file,
+//! once per sector, and the file is synced to disk. [`rollback`] writes them
+//! back, returning the device to the state it had before the first write.
+//!
+//! The file starts with [`UNDO_MAGIC`] and the sector size as a
+//! little-endian `u32`, followed by one entry per sector: its byte offset as
+//! a little-endian `u64`, then its contents.
+
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use crate::utils::error::{Result, SMNtfsError};
+
+/// Signature at the start of an undo file
+pub const UNDO_MAGIC: &[u8; 8] = b"SMNTUNDO";
+
+/// A device that saves the pre-image of every sector before writing it
+pub struct UndoDevice<T> {
+    inner: T,
+    undo: File,
+    sector_size: u64,
+    saved: HashSet<u64>,
+    position: u64,
+}
+
+impl<T: Read + Write + Seek> UndoDevice<T> {
+    /// Wrap a device, writing the undo file header
+    ///
+    /// `undo` should be a new, empty file.
+    pub fn new(inner: T, mut undo: File, sector_size: u32) -> Result<Self> {
+        undo.write_all(UNDO_MAGIC)?;
+        undo.write_all(&sector_size.to_le_bytes())?;
+        undo.sync_data()?;
+
+        Ok(Self {
+            inner,
+            undo,
+            sector_size: sector_size as u64,
+            saved: HashSet::new(),
+            position: 0,
+        })
+    }
+
+    /// Number of sectors saved so far
+    pub fn saved_sectors(&self) -> usize {
+        self.saved.len()
+    }
+
+    /// The underlying device
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    /// Unwrap the underlying device
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    /// Append the sectors overlapping `length` bytes at the position that
+    /// have not been saved yet, and sync the undo file
+    fn save(&mut self, length: usize) -> io::Result<()> {
+        let first = self.position / self.sector_size;
+        let end = (self.position + length as u64).div_ceil(self.sector_size);
+        let mut entries = Vec::new();
+        let mut sectors = Vec::new();
+
+        for sector in (first..end).filter(|sector| !self.saved.contains(sector)) {
+            let offset = sector * self.sector_size;
+            let mut data = vec![0u8; self.sector_size as usize];
+            self.inner.seek(SeekFrom::Start(offset))?;
+            self.inner.read_exact(&mut data)?;
+            entries.extend_from_slice(&offset.to_le_bytes());
+            entries.extend_from_slice(&data);
+            sectors.push(sector);
+        }
+
+        if !sectors.is_empty() {
+            self.undo.write_all(&entries)?;
+            self.undo.sync_data()?;
+            self.saved.extend(sectors);
+        }
+        Ok(())
+    }
+}
+
+impl<T: Read + Seek> Read for UndoDevice<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.seek(SeekFrom::Start(self.position))?;
+        let count = self.inner.read(buf)?;
+        self.position += count as u64;
+        Ok(count)
+    }
+}
+
+impl<T: Read + Write + Seek> Write for UndoDevice<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.save(buf.len())?;
+        self.inner.seek(SeekFrom::Start(self.position))?;
+        let count = self.inner.write(buf)?;
+        self.position += count as u64;
+        Ok(count)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<T: Seek> Seek for UndoDevice<T> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let new_position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+            SeekFrom::End(offset) => self.inner.seek(SeekFrom::End(0))?.checked_add_signed(offset),
+        };
+
+        self.position = new_position.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "Seek to a negative or overflowing position")
+        })?;
+        Ok(self.position)
+    }
+}
+
+/// Write the pre-images of an undo file back to the device
+///
+/// Returns the number of sectors restored. An entry cut short at the end of
+/// the file is ignored: its sector was never written, since the undo file
+/// is synced before each write.
+pub fn rollback<R: Read, T: Write + Seek>(mut undo: R, device: &mut T) -> Result<usize> {
+    let mut header = [0u8; 12];
+    undo.read_exact(&mut header)
+        .map_err(|e| SMNtfsError::ReadError(format!("Failed to read the undo file header: {}", e)))?;
+    if &header[..8] != UNDO_MAGIC {
+        return Err(SMNtfsError::ReadError("Not an undo file".to_string()));
+    }
+    let sector_size = u32::from_le_bytes([header[8], header[9], header[10], header[11]]) as usize;
+
+    let mut entry = vec![0u8; 8 + sector_size];
+    let mut restored = 0;
+    loop {
+        match undo.read_exact(&mut entry) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.into()),
+        }
+
+        let offset = u64::from_le_bytes(entry[..8].try_into().expect("8 bytes"));
+        device
+            .seek(SeekFrom::Start(offset))
+            .and_then(|_| device.write_all(&entry[8..]))
+            .map_err(|e| SMNtfsError::WriteError(format!("Failed to restore sector at byte {}: {}", offset, e)))?;
+        restored += 1;
+    }
+
+    device.flush()?;
+    Ok(restored)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_rollback_restores_first_pre_image() {
+        let original: Vec<u8> = (0..2048u32).map(|i| i as u8).collect();
+        let undo = tempfile::tempfile().unwrap();
+        let mut device = UndoDevice::new(Cursor::new(original.clone()), undo.try_clone().unwrap(), 512).unwrap();
+
+        // Straddles sectors 0 and 1, then overwrites sector 1 again
+        device.seek(SeekFrom::Start(500)).unwrap();
+        device.write_all(&[0xAA; 100]).unwrap();
+        device.seek(SeekFrom::Start(600)).unwrap();
+        device.write_all(&[0xBB; 10]).unwrap();
+        assert_eq!(device.saved_sectors(), 2);
+
+        let mut changed = device.into_inner();
+        assert_ne!(changed.get_ref(), &original);
+
+        let mut undo = undo;
+        undo.seek(SeekFrom::Start(0)).unwrap();
+        assert_eq!(rollback(&mut undo, &mut changed).unwrap(), 2);
+        assert_eq!(changed.get_ref(), &original);
+    }
+
+    #[test]
+    fn test_truncated_entry_is_ignored() {
+        let mut undo = UNDO_MAGIC.to_vec();
+        undo.extend_from_slice(&512u32.to_le_bytes());
+        undo.extend_from_slice(&0u64.to_le_bytes());
+        undo.extend_from_slice(&[1u8; 100]);
+
+        let mut device = Cursor::new(vec![0u8; 1024]);
+        assert_eq!(rollback(undo.as_slice(), &mut device).unwrap(), 0);
+        assert!(device.get_ref().iter().all(|byte| *byte == 0));
+        assert!(rollback(&b"NOTUNDO!\0\x02\0\0"[..], &mut device).is_err());
+    }
+}
@@ -29,8 +29,7 @@ mod tests {
     /// This test shows how to use the parser components together
     #[test]
     fn test_ntfs_parser_integration() {
-        use crate::parser::{BlockDeviceAdapter, NtfsVolume, FileInfo, list_directory};
-        use crate::parser::{list_streams, read_default_stream};
+        use crate::parser::{BlockDeviceAdapter, NtfsVolume, FileInfo};
 
         // This is a demonstration of the integration pattern.
         // In a real scenario, you would:
@@ -0,0 +1,669 @@
+//! $LogFile replay
+//!
+//! Brings a volume that was not shut down cleanly back to a consistent
+//! state: changes of committed transactions are redone and changes of
+//! transactions still in flight are undone, in the spirit of the restart
+//! pass Windows runs at mount time. Afterwards the log is reset the way
+//! ntfs-3g does it, by filling $LogFile with `0xFF`, which Windows accepts
+//! as an empty, clean log.
+//!
+//! Only the operations that can be applied without rebuilding records or
+//! index buffers are supported. If the log needs anything else, replay
+//! fails before writing a single byte.
+//!
+//! File records and INDX blocks are changed the way ntfs-3g's playlog does
+//! it: the whole block is read and its fixups removed, the LSN stored in it
+//! decides whether the change is already there, and the block is protected
+//! again before it is written back.
+
+use std::io::{Read, Seek, SeekFrom, Write};
+use ntfs::Ntfs;
+use crate::parser::attribute::AttributeStream;
+use crate::parser::boot::BootSector;
+use crate::parser::logfile::{logfile_stream, LogFile, LogFileReport, LogOperation, LogRecord};
+use crate::parser::record::{apply_fixups, attribute_flags, protect_fixups, record_flags, FILE_RECORD_SIGNATURE};
+use crate::utils::bytes::{le_u16, le_u32, le_u64, le_u8};
+use crate::utils::error::{Result, SMNtfsError};
+
+/// Unit of `cluster_block_offset` in log records
+const LOG_BLOCK_SIZE: u64 = 512;
+
+/// Bytes written at a time when resetting the log
+const RESET_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Signature of an index block
+const INDEX_BLOCK_SIGNATURE: &[u8; 4] = b"INDX";
+
+/// What a replay did
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReplaySummary {
+    /// Changes of committed transactions written to the volume
+    pub redone: usize,
+
+    /// Changes of unfinished transactions rolled back
+    pub undone: usize,
+
+    /// Changes skipped because the volume already had them (redo) or never
+    /// got them (undo)
+    pub skipped: usize,
+}
+
+/// Which way a change is being applied
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Pass {
+    Redo,
+    Undo,
+}
+
+/// Replay $LogFile and reset it
+///
+/// A clean log is left as it is and nothing is written. Otherwise the
+/// `Ntfs` structures cached by the caller may be stale afterwards; reopen
+/// the volume before using it.
+pub fn replay<T: Read + Write + Seek>(ntfs: &Ntfs, boot_sector: &BootSector, fs: &mut T) -> Result<ReplaySummary> {
+    let cluster_size = ntfs.cluster_size();
+    let stream = logfile_stream(ntfs, fs)?;
+    let report = LogFile::from_stream(fs, stream.clone(), cluster_size)?.report()?;
+    if report.is_clean() {
+        tracing::info!("$LogFile is clean, nothing to replay");
+        return Ok(ReplaySummary::default());
+    }
+    // A log that cannot be reset must fail before the replay writes anything
+    logfile_extents(&stream, cluster_size)?;
+
+    let summary = replay_report(fs, &report, boot_sector)?;
+    reset_logfile(fs, &stream, cluster_size)?;
+    fs.flush()?;
+
+    tracing::info!(
+        "Replayed $LogFile: {} redone, {} undone, {} already consistent",
+        summary.redone,
+        summary.undone,
+        summary.skipped
+    );
+    Ok(summary)
+}
+
+/// Apply the pending transactions of a report to the volume
+///
+/// Committed transactions are redone in LSN order, then the changes of
+/// unfinished transactions are undone newest first.
+pub fn replay_report<T: Read + Write + Seek>(
+    fs: &mut T,
+    report: &LogFileReport,
+    boot_sector: &BootSector,
+) -> Result<ReplaySummary> {
+    let mut redo: Vec<&LogRecord> = report.committed().flat_map(|transaction| transaction.changes()).collect();
+    redo.sort_by_key(|record| record.lsn);
+
+    let mut undo: Vec<&LogRecord> = report.uncommitted().flat_map(|transaction| transaction.changes()).collect();
+    undo.sort_by_key(|record| std::cmp::Reverse(record.lsn));
+
+    // Check everything up front so an unsupported log leaves the volume untouched
+    for record in &redo {
+        check_supported(record, record.redo_operation)?;
+    }
+    for record in &undo {
+        if record.undo_operation.0 == LogOperation::COMPENSATION_LOG_RECORD {
+            return Err(SMNtfsError::JournalError(format!(
+                "transaction {:#x} was being rolled back when the volume went offline",
+                record.transaction_id
+            )));
+        }
+        check_supported(record, record.undo_operation)?;
+    }
+
+    let mut target = Target {
+        fs,
+        cluster_size: boot_sector.cluster_size as u64,
+        record_size: boot_sector.file_record_size as usize,
+        index_record_size: boot_sector.index_record_size as usize,
+    };
+    let mut summary = ReplaySummary::default();
+
+    for record in redo {
+        match target.apply(record, Pass::Redo)? {
+            true => summary.redone += 1,
+            false => summary.skipped += 1,
+        }
+    }
+    for record in undo {
+        match target.apply(record, Pass::Undo)? {
+            true => summary.undone += 1,
+            false => summary.skipped += 1,
+        }
+    }
+
+    Ok(summary)
+}
+
+/// Fill the $LogFile data stream with `0xFF`, marking the log empty and clean
+pub fn reset_logfile<W: Write + Seek>(fs: &mut W, stream: &AttributeStream, cluster_size: u32) -> Result<()> {
+    let chunk = vec![0xFFu8; RESET_CHUNK_SIZE];
+
+    for (position, length) in logfile_extents(stream, cluster_size)? {
+        fs.seek(SeekFrom::Start(position))?;
+        let mut remaining = length;
+        while remaining > 0 {
+            let count = remaining.min(RESET_CHUNK_SIZE as u64) as usize;
+            fs.write_all(&chunk[..count])
+                .map_err(|e| SMNtfsError::WriteError(format!("Failed to reset $LogFile: {}", e)))?;
+            remaining -= count as u64;
+        }
+    }
+
+    Ok(())
+}
+
+/// Device position and length of every allocated part of the $LogFile stream
+fn logfile_extents(stream: &AttributeStream, cluster_size: u32) -> Result<Vec<(u64, u64)>> {
+    let cluster_size = cluster_size as u64;
+    let mut extents = Vec::new();
+    for run in &stream.runs {
+        let Some(lcn) = run.lcn else {
+            continue;
+        };
+        let extent = run.vcn.checked_mul(cluster_size).and_then(|start| {
+            let length = run.length.checked_mul(cluster_size)?.min(stream.data_size.saturating_sub(start));
+            Some((lcn.checked_mul(cluster_size)?, length))
+        });
+        extents.push(extent.ok_or_else(|| {
+            SMNtfsError::JournalError(format!("$LogFile run at LCN {} is out of range", lcn))
+        })?);
+    }
+    Ok(extents)
+}
+
+/// Fail unless `operation` of `record` can be applied
+fn check_supported(record: &LogRecord, operation: LogOperation) -> Result<()> {
+    let supported = operation.is_bookkeeping()
+        || matches!(
+            operation.0,
+            LogOperation::INITIALIZE_FILE_RECORD_SEGMENT
+                | LogOperation::DEALLOCATE_FILE_RECORD_SEGMENT
+                | LogOperation::UPDATE_RESIDENT_VALUE
+                | LogOperation::UPDATE_NONRESIDENT_VALUE
+                | LogOperation::SET_NEW_ATTRIBUTE_SIZES
+                | LogOperation::SET_BITS_IN_NONRESIDENT_BIT_MAP
+                | LogOperation::CLEAR_BITS_IN_NONRESIDENT_BIT_MAP
+        );
+
+    if !supported {
+        return Err(SMNtfsError::JournalError(format!(
+            "log record {} needs {}, which cannot be replayed yet; mount the volume on Windows to recover it",
+            record.lsn,
+            operation.name()
+        )));
+    }
+    if !operation.is_bookkeeping() && record.lcns.is_empty() {
+        return Err(SMNtfsError::JournalError(format!("log record {} has no target clusters", record.lsn)));
+    }
+    Ok(())
+}
+
+/// The device, addressed through the clusters listed in log records
+struct Target<'f, T> {
+    fs: &'f mut T,
+    cluster_size: u64,
+    record_size: usize,
+    index_record_size: usize,
+}
+
+impl<T: Read + Write + Seek> Target<'_, T> {
+    /// Apply the redo or undo half of a record; returns whether anything was written
+    fn apply(&mut self, record: &LogRecord, pass: Pass) -> Result<bool> {
+        let (operation, data) = match pass {
+            Pass::Redo => (record.redo_operation, record.redo_data.as_slice()),
+            Pass::Undo => (record.undo_operation, record.undo_data.as_slice()),
+        };
+        if operation.is_bookkeeping() {
+            return Ok(false);
+        }
+
+        if operation.targets_file_record() {
+            return self.apply_to_file_record(record, pass, operation, data);
+        }
+
+        let offset = record.record_offset as usize + record.attribute_offset as usize;
+        let (start, length) = match operation.0 {
+            LogOperation::UPDATE_NONRESIDENT_VALUE => (offset, data.len()),
+            _ => {
+                let first_bit = le_u32(data, 0) as usize;
+                let count = le_u32(data, 4) as usize;
+                (offset + first_bit / 8, (first_bit % 8 + count).div_ceil(8))
+            }
+        };
+
+        let signature = self.read(record, 0, 4)?;
+        let size = if signature == FILE_RECORD_SIGNATURE {
+            self.record_size
+        } else if signature == INDEX_BLOCK_SIGNATURE {
+            self.index_record_size
+        } else {
+            // Plain clusters such as $Bitmap have no LSN, but writing the
+            // same bytes or bits twice does no harm
+            let mut bytes = self.read(record, start as u64, length)?;
+            change_value(&mut bytes, operation, data);
+            self.write(record, start as u64, &bytes)?;
+            return Ok(true);
+        };
+
+        self.update_protected(record, pass, size, false, |buffer| {
+            let bytes = buffer.get_mut(start..start + length).ok_or_else(|| invalid_target(record))?;
+            change_value(bytes, operation, data);
+            Ok(())
+        })
+    }
+
+    /// Apply a change to a file record
+    fn apply_to_file_record(&mut self, record: &LogRecord, pass: Pass, operation: LogOperation, data: &[u8]) -> Result<bool> {
+        let initializing = operation.0 == LogOperation::INITIALIZE_FILE_RECORD_SEGMENT;
+        let position = record.record_offset as usize + record.attribute_offset as usize;
+
+        self.update_protected(record, pass, self.record_size, initializing, |buffer| {
+            match operation.0 {
+                LogOperation::INITIALIZE_FILE_RECORD_SEGMENT => {
+                    if data.len() > buffer.len() {
+                        return Err(invalid_target(record));
+                    }
+                    buffer.fill(0);
+                    buffer[..data.len()].copy_from_slice(data);
+                }
+                LogOperation::DEALLOCATE_FILE_RECORD_SEGMENT => {
+                    let flags = le_u16(buffer, 0x16) & !record_flags::IN_USE;
+                    buffer[0x16..0x18].copy_from_slice(&flags.to_le_bytes());
+                }
+                LogOperation::UPDATE_RESIDENT_VALUE => {
+                    // Only in-place updates; resizing the value would move the rest of the record
+                    let attribute = record.record_offset as usize;
+                    let attribute_end = attribute + le_u32(buffer, attribute + 4) as usize;
+                    if le_u8(buffer, attribute + 0x08) != 0 || position + data.len() > attribute_end.min(buffer.len()) {
+                        return Err(invalid_target(record));
+                    }
+                    buffer[position..position + data.len()].copy_from_slice(data);
+                }
+                LogOperation::SET_NEW_ATTRIBUTE_SIZES => {
+                    let attribute = record.record_offset as usize;
+                    // Only compressed and sparse attributes have the compressed size
+                    let flags = le_u16(buffer, attribute + 0x0C);
+                    let has_compressed_size = flags & (attribute_flags::COMPRESSED | attribute_flags::SPARSE) != 0;
+                    let length = data.len().min(if has_compressed_size { 32 } else { 24 });
+                    if le_u8(buffer, attribute + 0x08) == 0 || attribute + 0x28 + length > buffer.len() {
+                        return Err(invalid_target(record));
+                    }
+                    buffer[attribute + 0x28..attribute + 0x28 + length].copy_from_slice(&data[..length]);
+                }
+                _ => unreachable!("checked by check_supported"),
+            }
+            Ok(())
+        })
+    }
+
+    /// Read-modify-write the file record or INDX block of `size` bytes a log
+    /// record targets, honouring its LSN
+    ///
+    /// `change` sees the block with its fixups removed, and the block is
+    /// protected again before it is written back. Only a block being
+    /// initialized may be missing or damaged.
+    fn update_protected(
+        &mut self,
+        record: &LogRecord,
+        pass: Pass,
+        size: usize,
+        initializing: bool,
+        change: impl FnOnce(&mut [u8]) -> Result<()>,
+    ) -> Result<bool> {
+        let mut buffer = self.read(record, 0, size)?;
+        let signature = buffer.get(..4).unwrap_or_default();
+        let kind = if signature == INDEX_BLOCK_SIGNATURE { "INDX block" } else { "file record" };
+        let protected = signature == FILE_RECORD_SIGNATURE || signature == INDEX_BLOCK_SIGNATURE;
+        let intact = protected && apply_fixups(&mut buffer);
+
+        if !intact && !initializing {
+            return Err(SMNtfsError::JournalError(format!(
+                "{} at LCN {} targeted by log record {} is damaged",
+                kind, record.lcns[0], record.lsn
+            )));
+        }
+
+        // The block LSN tells whether the change reached the disk
+        let on_disk = intact && le_u64(&buffer, 0x08) >= record.lsn;
+        if (pass == Pass::Redo && on_disk) || (pass == Pass::Undo && !on_disk) {
+            return Ok(false);
+        }
+
+        change(&mut buffer)?;
+        if pass == Pass::Redo {
+            buffer[0x08..0x10].copy_from_slice(&record.lsn.to_le_bytes());
+        }
+        if !protect_fixups(&mut buffer) {
+            return Err(invalid_target(record));
+        }
+        self.write(record, 0, &buffer)?;
+        Ok(true)
+    }
+
+    /// Device position of byte `offset` of the area a log record targets
+    fn position(&self, record: &LogRecord, offset: u64) -> Result<u64> {
+        let position = record.cluster_block_offset as u64 * LOG_BLOCK_SIZE + offset;
+        let lcn = record
+            .lcns
+            .get((position / self.cluster_size) as usize)
+            .ok_or_else(|| invalid_target(record))?;
+        let start = lcn.checked_mul(self.cluster_size).ok_or_else(|| {
+            SMNtfsError::JournalError(format!("log record {} targets LCN {}, which is out of range", record.lsn, lcn))
+        })?;
+        Ok(start + position % self.cluster_size)
+    }
+
+    /// Run `f` on each cluster-contiguous piece of `offset..offset + length`
+    fn for_each_piece(
+        &mut self,
+        record: &LogRecord,
+        offset: u64,
+        length: usize,
+        mut f: impl FnMut(&mut T, u64, std::ops::Range<usize>) -> std::io::Result<()>,
+    ) -> Result<()> {
+        let mut done = 0;
+        while done < length {
+            let position = self.position(record, offset + done as u64)?;
+            let count = (length - done).min((self.cluster_size - position % self.cluster_size) as usize);
+            f(self.fs, position, done..done + count)
+                .map_err(|e| SMNtfsError::JournalError(format!("I/O error replaying log record {}: {}", record.lsn, e)))?;
+            done += count;
+        }
+        Ok(())
+    }
+
+    fn read(&mut self, record: &LogRecord, offset: u64, length: usize) -> Result<Vec<u8>> {
+        let mut data = vec![0u8; length];
+        self.for_each_piece(record, offset, length, |fs, position, range| {
+            fs.seek(SeekFrom::Start(position))?;
+            fs.read_exact(&mut data[range])
+        })?;
+        Ok(data)
+    }
+
+    fn write(&mut self, record: &LogRecord, offset: u64, data: &[u8]) -> Result<()> {
+        self.for_each_piece(record, offset, data.len(), |fs, position, range| {
+            fs.seek(SeekFrom::Start(position))?;
+            fs.write_all(&data[range])
+        })
+    }
+}
+
+/// Apply an UPDATE_NONRESIDENT_VALUE or bitmap operation to the bytes it changes
+fn change_value(bytes: &mut [u8], operation: LogOperation, data: &[u8]) {
+    if operation.0 == LogOperation::UPDATE_NONRESIDENT_VALUE {
+        bytes.copy_from_slice(data);
+        return;
+    }
+
+    let first_bit = le_u32(data, 0) % 8;
+    for bit in first_bit..first_bit + le_u32(data, 4) {
+        let mask = 1u8 << (bit % 8);
+        match operation.0 {
+            LogOperation::SET_BITS_IN_NONRESIDENT_BIT_MAP => bytes[(bit / 8) as usize] |= mask,
+            _ => bytes[(bit / 8) as usize] &= !mask,
+        }
+    }
+}
+
+fn invalid_target(record: &LogRecord) -> SMNtfsError {
+    SMNtfsError::JournalError(format!(
+        "{} of log record {} does not fit its target",
+        record.redo_operation.name(),
+        record.lsn
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::logfile::tests::{build_logfile, logfile_stream_at, Operation, PAGE_SIZE};
+    use crate::parser::record::tests::{build_record, non_resident_attribute, resident_attribute};
+    use crate::parser::record::FileRecord;
+    use crate::parser::recovery::open_volume;
+    use crate::parser::volume::tests::test_image;
+    use std::io::Cursor;
+
+    const CLUSTER_SIZE: u32 = 4096;
+
+    /// Where `index entry data` is in the INDX block, across its first sector end
+    const INDEX_DATA_OFFSET: usize = 0x1F8;
+
+    fn boot_sector() -> BootSector {
+        BootSector {
+            sector_size: 512,
+            cluster_size: CLUSTER_SIZE,
+            total_sectors: 88,
+            mft_lcn: 8,
+            mft_mirror_lcn: 8,
+            file_record_size: 1024,
+            index_record_size: 4096,
+            serial_number: 0,
+        }
+    }
+
+    /// Eight log pages, then an MFT cluster holding four records, a bitmap
+    /// cluster and an INDX block
+    fn volume(operations: &[Operation]) -> Cursor<Vec<u8>> {
+        let mut device = build_logfile(operations, 0);
+        for _ in 0..4 {
+            device.extend(build_record(&[resident_attribute(0x80, "", b"hello world")], record_flags::IN_USE));
+        }
+        device.extend(vec![0u8; PAGE_SIZE]);
+
+        let mut block = vec![0u8; PAGE_SIZE];
+        block[0..4].copy_from_slice(INDEX_BLOCK_SIGNATURE);
+        block[0x04..0x06].copy_from_slice(&0x28u16.to_le_bytes());
+        block[0x06..0x08].copy_from_slice(&9u16.to_le_bytes());
+        block[INDEX_DATA_OFFSET..INDEX_DATA_OFFSET + 16].copy_from_slice(b"index entry data");
+        assert!(protect_fixups(&mut block));
+        device.extend(block);
+        Cursor::new(device)
+    }
+
+    /// The INDX block with its fixups applied
+    fn index_block(device: &Cursor<Vec<u8>>) -> Vec<u8> {
+        let mut block = device.get_ref()[10 * PAGE_SIZE..11 * PAGE_SIZE].to_vec();
+        assert!(apply_fixups(&mut block));
+        block
+    }
+
+    fn report(device: &mut Cursor<Vec<u8>>) -> LogFileReport {
+        LogFile::from_stream(device, logfile_stream_at(0, 8), CLUSTER_SIZE).unwrap().report().unwrap()
+    }
+
+    fn mft_record(device: &Cursor<Vec<u8>>, index: usize) -> FileRecord {
+        let start = 8 * PAGE_SIZE + index * 1024;
+        FileRecord::from_bytes(device.get_ref()[start..start + 1024].to_vec(), index as u64, 0)
+    }
+
+    fn forget(transaction_id: u32) -> Operation<'static> {
+        Operation {
+            transaction_id,
+            redo: LogOperation::FORGET_TRANSACTION,
+            undo: LogOperation::COMPENSATION_LOG_RECORD,
+            ..Operation::default()
+        }
+    }
+
+    /// Overwrite "hello" in the $DATA value of MFT record `index`
+    fn update_value(transaction_id: u32, index: u16, redo_data: &'static [u8]) -> Operation<'static> {
+        Operation {
+            transaction_id,
+            redo: LogOperation::UPDATE_RESIDENT_VALUE,
+            undo: LogOperation::UPDATE_RESIDENT_VALUE,
+            redo_data,
+            undo_data: b"hello",
+            record_offset: 0x38,
+            attribute_offset: 0x18,
+            cluster_block_offset: index * 2,
+            lcn: 8,
+        }
+    }
+
+    #[test]
+    fn test_redo_committed_and_undo_uncommitted() {
+        let set_bits = [3u32.to_le_bytes(), 10u32.to_le_bytes()].concat();
+        let operations = [
+            update_value(0x18, 1, b"HELLO"),
+            Operation {
+                transaction_id: 0x18,
+                redo: LogOperation::SET_BITS_IN_NONRESIDENT_BIT_MAP,
+                undo: LogOperation::CLEAR_BITS_IN_NONRESIDENT_BIT_MAP,
+                redo_data: &set_bits,
+                undo_data: &set_bits,
+                lcn: 9,
+                ..Operation::default()
+            },
+            forget(0x18),
+            update_value(0x30, 2, b"WORLD"),
+        ];
+        let mut device = volume(&operations);
+
+        // The uncommitted change had already reached record 2
+        let lsn = report(&mut device).uncommitted().next().unwrap().records[0].lsn;
+        let start = 8 * PAGE_SIZE + 2 * 1024;
+        let mut raw = mft_record(&device, 2).data().to_vec();
+        raw[0x08..0x10].copy_from_slice(&lsn.to_le_bytes());
+        raw[0x50..0x55].copy_from_slice(b"WORLD");
+        assert!(protect_fixups(&mut raw));
+        device.get_mut()[start..start + 1024].copy_from_slice(&raw);
+
+        let report = report(&mut device);
+        let summary = replay_report(&mut device, &report, &boot_sector()).unwrap();
+        assert_eq!(summary, ReplaySummary { redone: 2, undone: 1, skipped: 0 });
+
+        let redone = mft_record(&device, 1);
+        assert!(redone.is_valid());
+        assert_eq!(&redone.data()[0x50..0x5B], b"HELLO world");
+        assert_eq!(redone.lsn(), report.committed().next().unwrap().records[0].lsn);
+
+        assert_eq!(&mft_record(&device, 2).data()[0x50..0x55], b"hello");
+        assert_eq!(&device.get_ref()[9 * PAGE_SIZE..9 * PAGE_SIZE + 2], &[0b1111_1000, 0b0001_1111]);
+
+        // Replaying again finds everything in place
+        let summary = replay_report(&mut device, &report, &boot_sector()).unwrap();
+        assert_eq!(summary.skipped, 1);
+    }
+
+    #[test]
+    fn test_index_block_update() {
+        let update = |transaction_id, redo_data| Operation {
+            transaction_id,
+            redo: LogOperation::UPDATE_NONRESIDENT_VALUE,
+            undo: LogOperation::UPDATE_NONRESIDENT_VALUE,
+            redo_data,
+            undo_data: b"index entry data",
+            record_offset: INDEX_DATA_OFFSET as u16,
+            lcn: 10,
+            ..Operation::default()
+        };
+        let operations = [update(0x18, b"INDEX ENTRY DATA"), forget(0x18), update(0x30, b"stale entry data")];
+        let mut device = volume(&operations);
+
+        let report = report(&mut device);
+        let summary = replay_report(&mut device, &report, &boot_sector()).unwrap();
+        // The uncommitted change is newer than the block, so it never reached it
+        assert_eq!(summary, ReplaySummary { redone: 1, undone: 0, skipped: 1 });
+
+        // Written through the fixups, with the sector end still protected
+        let block = index_block(&device);
+        assert_eq!(&block[INDEX_DATA_OFFSET..INDEX_DATA_OFFSET + 16], b"INDEX ENTRY DATA");
+        assert_eq!(le_u64(&block, 0x08), report.committed().next().unwrap().records[0].lsn);
+        let raw = &device.get_ref()[10 * PAGE_SIZE..11 * PAGE_SIZE];
+        assert_eq!(raw[0x1FE..0x200], raw[0x28..0x2A]);
+
+        // The block LSN shows the redo is already there
+        let summary = replay_report(&mut device, &report, &boot_sector()).unwrap();
+        assert_eq!(summary, ReplaySummary { redone: 0, undone: 0, skipped: 2 });
+    }
+
+    #[test]
+    fn test_clean_logfile_writes_nothing() {
+        let opened = open_volume(Cursor::new(test_image())).unwrap();
+        let (mut fs, ntfs) = (opened.fs, opened.ntfs);
+
+        assert_eq!(replay(&ntfs, &opened.boot_sector, &mut fs).unwrap(), ReplaySummary::default());
+        assert_eq!(fs.into_inner().into_inner(), test_image());
+    }
+
+    #[test]
+    fn test_unsupported_operation_writes_nothing() {
+        let operations = [
+            update_value(0x18, 1, b"HELLO"),
+            Operation {
+                redo: LogOperation::ADD_INDEX_ENTRY_ALLOCATION,
+                lcn: 9,
+                ..Operation::default()
+            },
+            forget(0x18),
+        ];
+        let mut device = volume(&operations);
+        let before = device.get_ref().clone();
+
+        let report = report(&mut device);
+        let error = replay_report(&mut device, &report, &boot_sector()).unwrap_err();
+        assert!(error.to_string().contains("AddIndexEntryAllocation"));
+        assert_eq!(device.get_ref(), &before);
+    }
+
+    #[test]
+    fn test_set_compressed_attribute_sizes() {
+        let sizes: Vec<u8> = [0x10000u64, 0x9000, 0x9000, 0x4000].iter().flat_map(|size| size.to_le_bytes()).collect();
+        let operations = [
+            Operation {
+                redo: LogOperation::SET_NEW_ATTRIBUTE_SIZES,
+                undo: LogOperation::SET_NEW_ATTRIBUTE_SIZES,
+                redo_data: &sizes,
+                undo_data: &sizes,
+                record_offset: 0x38,
+                cluster_block_offset: 6,
+                lcn: 8,
+                ..Operation::default()
+            },
+            forget(0x18),
+        ];
+        let mut device = volume(&operations);
+
+        // An unnamed compressed $DATA, its name offset at the end of the shorter header
+        let mut attribute = non_resident_attribute(0x80, "", attribute_flags::COMPRESSED, (0, 0, 0), &[]);
+        attribute[0x0A..0x0C].copy_from_slice(&0x40u16.to_le_bytes());
+        let start = 8 * PAGE_SIZE + 3 * 1024;
+        device.get_mut()[start..start + 1024].copy_from_slice(&build_record(&[attribute], record_flags::IN_USE));
+
+        let report = report(&mut device);
+        assert_eq!(replay_report(&mut device, &report, &boot_sector()).unwrap().redone, 1);
+        let record = mft_record(&device, 3);
+        let attribute = record.attributes().next().unwrap().unwrap();
+        assert_eq!(
+            (attribute.allocated_size(), attribute.data_size(), attribute.initialized_size()),
+            (0x10000, 0x9000, 0x9000)
+        );
+        assert_eq!(attribute.compressed_size(), Some(0x4000));
+    }
+
+    #[test]
+    fn test_out_of_range_lcns() {
+        let mut device = volume(&[Operation { lcn: 1 << 62, ..update_value(0x18, 1, b"HELLO") }, forget(0x18)]);
+        let before = device.get_ref().clone();
+
+        let report = report(&mut device);
+        let error = replay_report(&mut device, &report, &boot_sector()).unwrap_err();
+        assert!(error.to_string().contains("out of range"), "{}", error);
+        assert!(reset_logfile(&mut device, &logfile_stream_at(1 << 62, 8), CLUSTER_SIZE).is_err());
+        assert_eq!(device.get_ref(), &before);
+    }
+
+    #[test]
+    fn test_reset_logfile() {
+        let mut device = volume(&[update_value(0x18, 1, b"HELLO"), forget(0x18)]);
+        reset_logfile(&mut device, &logfile_stream_at(0, 8), CLUSTER_SIZE).unwrap();
+
+        assert!(device.get_ref()[..8 * PAGE_SIZE].iter().all(|byte| *byte == 0xFF));
+        assert!(mft_record(&device, 0).is_valid());
+        assert!(report(&mut device).is_clean());
+    }
+}
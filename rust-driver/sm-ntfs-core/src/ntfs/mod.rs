@@ -1,9 +1,11 @@
 //! NTFS operations module
 
+pub mod journal;
+pub mod repair;
+
 // TODO: Implement NTFS operations
 // - coordinator.rs: Operation coordinator
 // - read.rs: Read operations
 // - write.rs: Write operations
 // - metadata.rs: Metadata management
-// - journal.rs: Journal handling
 // - attributes.rs: Extended attributes
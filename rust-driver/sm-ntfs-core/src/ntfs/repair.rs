@@ -0,0 +1,529 @@
+//! Offline repair of problems found by the consistency check
+//!
+//! Only the fixes [`check_volume`] attaches to problems are applied, since
+//! they cannot lose data: $Bitmap bits are set for clusters in use and
+//! cleared for clusters nothing uses, $MFTMirr is rewritten from the MFT,
+//! index entries pointing at free records are removed from leaf nodes, and
+//! link counts are set to the number of names. The volume is then checked
+//! again, and if nothing is left the dirty flag is cleared so Windows does
+//! not run chkdsk at the next boot.
+//!
+//! Writes go straight to `fs`; wrap the device in an
+//! [`UndoDevice`](crate::io::UndoDevice) to keep the pre-image of every
+//! sector changed.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+use ntfs::{Ntfs, NtfsAttributeType};
+use crate::parser::attribute::AttributeStream;
+use crate::parser::bitmap::{ClusterBitmap, BITMAP_RECORD_NUMBER};
+use crate::parser::boot::BootSector;
+use crate::parser::check::{
+    check_volume, index_entry_flags, CheckReport, Fix, IndexLocation, Problem, DIRECTORY_INDEX,
+};
+use crate::parser::logfile::LogFile;
+use crate::parser::reader::StreamReader;
+use crate::parser::record::{apply_fixups, protect_fixups, FileRecord};
+use crate::parser::recovery::MIRRORED_RECORDS;
+use crate::parser::volume_info::{VolumeFlags, VOLUME_RECORD_NUMBER};
+use crate::utils::bytes::{le_u16, le_u32};
+use crate::utils::error::{Result, SMNtfsError};
+
+/// What a repair did
+#[derive(Debug, Clone, Default)]
+pub struct RepairSummary {
+    /// Clusters marked in use in $Bitmap
+    pub clusters_marked_used: u64,
+
+    /// Clusters marked free in $Bitmap
+    pub clusters_marked_free: u64,
+
+    /// MFT records copied to $MFTMirr
+    pub mirror_records_synced: usize,
+
+    /// Index entries removed
+    pub index_entries_removed: usize,
+
+    /// Link counts corrected
+    pub link_counts_fixed: usize,
+
+    /// Whether the volume was marked clean
+    pub dirty_flag_cleared: bool,
+
+    /// Problems left after the repair, from a second check
+    pub remaining: Vec<Problem>,
+}
+
+/// Apply the fixes of a check report, then check the volume again
+///
+/// Refuses to run while $LogFile has pending transactions, since replaying
+/// them afterwards could undo the repair.
+pub fn repair<T: Read + Write + Seek>(
+    ntfs: &Ntfs,
+    boot_sector: &BootSector,
+    fs: &mut T,
+    report: &CheckReport,
+) -> Result<RepairSummary> {
+    if !LogFile::open(ntfs, fs)?.report()?.is_clean() {
+        return Err(SMNtfsError::JournalError(
+            "$LogFile has pending transactions; replay it before repairing".to_string(),
+        ));
+    }
+
+    let mft_file = ntfs
+        .file(fs, 0)
+        .map_err(|e| SMNtfsError::ReadError(format!("Failed to read $MFT: {}", e)))?;
+    let mft = AttributeStream::collect(&mft_file, fs, NtfsAttributeType::Data, "")?
+        .ok_or(SMNtfsError::CorruptedMft { offset: 0 })?;
+    let mut volume = Volume {
+        fs,
+        mft,
+        boot_sector: *boot_sector,
+        written_records: BTreeSet::new(),
+    };
+    let mut summary = RepairSummary::default();
+
+    let mut removals: BTreeMap<(u64, Option<u64>), Vec<IndexLocation>> = BTreeMap::new();
+    let mut bitmap_fixes = Vec::new();
+    let mut mirror = BTreeSet::new();
+    for problem in report.repairable() {
+        let Some(fix) = problem.fix else {
+            continue;
+        };
+        match (fix, problem.record) {
+            (Fix::SetLinkCount(count), Some(record)) => {
+                volume.set_link_count(record, count)?;
+                summary.link_counts_fixed += 1;
+            }
+            (Fix::RemoveIndexEntry(location), Some(directory)) => {
+                removals.entry((directory, location.block)).or_default().push(location);
+            }
+            (Fix::SyncMirror, Some(record)) => {
+                mirror.insert(record);
+            }
+            (Fix::MarkClustersUsed { .. } | Fix::MarkClustersFree { .. }, _) => bitmap_fixes.push(fix),
+            _ => {}
+        }
+    }
+
+    for ((directory, block), mut locations) in removals {
+        // From the end of the node, so earlier offsets stay valid
+        locations.sort_by_key(|location| std::cmp::Reverse(location.offset));
+        match block {
+            None => volume.remove_root_entries(directory, &locations)?,
+            Some(block) => volume.remove_block_entries(ntfs, directory, block, &locations)?,
+        }
+        summary.index_entries_removed += locations.len();
+    }
+
+    if !bitmap_fixes.is_empty() {
+        let (used, free) = volume.fix_bitmap(ntfs, &bitmap_fixes)?;
+        summary.clusters_marked_used = used;
+        summary.clusters_marked_free = free;
+    }
+
+    let mirrored = MIRRORED_RECORDS.max(boot_sector.cluster_size as u64 / boot_sector.file_record_size as u64);
+    mirror.extend(volume.written_records.iter().filter(|record| **record < mirrored));
+    for record in &mirror {
+        volume.sync_mirror(*record)?;
+    }
+    summary.mirror_records_synced = mirror.len();
+    volume.fs.flush()?;
+
+    summary.remaining = check_volume(ntfs, boot_sector, &mut *volume.fs)?.problems;
+    if summary.remaining.is_empty() {
+        summary.dirty_flag_cleared = volume.clear_dirty_flag()?;
+        volume.fs.flush()?;
+    }
+
+    tracing::info!(
+        "Repaired volume: {} link counts, {} index entries, {} + {} bitmap clusters, {} mirror records; {} problems left",
+        summary.link_counts_fixed,
+        summary.index_entries_removed,
+        summary.clusters_marked_used,
+        summary.clusters_marked_free,
+        summary.mirror_records_synced,
+        summary.remaining.len()
+    );
+    Ok(summary)
+}
+
+/// The device, with the $MFT data stream for addressing records
+struct Volume<'f, T> {
+    fs: &'f mut T,
+    mft: AttributeStream,
+    boot_sector: BootSector,
+    written_records: BTreeSet<u64>,
+}
+
+impl<T: Read + Write + Seek> Volume<'_, T> {
+    fn cluster_size(&self) -> u32 {
+        self.boot_sector.cluster_size
+    }
+
+    fn record_size(&self) -> usize {
+        self.boot_sector.file_record_size as usize
+    }
+
+    fn read_stream(&mut self, stream: &AttributeStream, offset: u64, length: usize) -> Result<Vec<u8>> {
+        let mut data = vec![0u8; length];
+        let mut reader = StreamReader::new(&mut *self.fs, stream.clone(), self.boot_sector.cluster_size);
+        reader.seek(SeekFrom::Start(offset))?;
+        reader.read_exact(&mut data)?;
+        Ok(data)
+    }
+
+    /// Write `data` at `offset` of a non-resident stream
+    fn write_stream(&mut self, stream: &AttributeStream, offset: u64, data: &[u8]) -> Result<()> {
+        let cluster_size = self.cluster_size();
+        stream.write_at(&mut *self.fs, offset, data, cluster_size)
+    }
+
+    /// Read an MFT record with its fixups applied
+    fn read_record(&mut self, number: u64) -> Result<FileRecord> {
+        let mft = self.mft.clone();
+        let data = self.read_stream(&mft, number * self.record_size() as u64, self.record_size())?;
+        let record = FileRecord::from_bytes(data, number, 0);
+        record.validate()?;
+        Ok(record)
+    }
+
+    /// Protect an MFT record with fixups and write it back
+    fn write_record(&mut self, number: u64, mut data: Vec<u8>) -> Result<()> {
+        if !protect_fixups(&mut data) {
+            return Err(SMNtfsError::CorruptedMft { offset: number });
+        }
+        let mft = self.mft.clone();
+        self.write_stream(&mft, number * self.record_size() as u64, &data)?;
+        self.written_records.insert(number);
+        Ok(())
+    }
+
+    fn set_link_count(&mut self, number: u64, count: u16) -> Result<()> {
+        let mut data = self.read_record(number)?.data().to_vec();
+        data[0x12..0x14].copy_from_slice(&count.to_le_bytes());
+        self.write_record(number, data)
+    }
+
+    fn remove_root_entries(&mut self, directory: u64, locations: &[IndexLocation]) -> Result<()> {
+        let record = self.read_record(directory)?;
+        let attribute = record
+            .find_attribute(NtfsAttributeType::IndexRoot, DIRECTORY_INDEX)
+            .ok_or(SMNtfsError::CorruptedMft { offset: directory })?
+            .offset();
+        let mut data = record.data().to_vec();
+        for location in locations {
+            remove_root_entry(&mut data, attribute, location)?;
+        }
+        self.write_record(directory, data)
+    }
+
+    fn remove_block_entries(
+        &mut self,
+        ntfs: &Ntfs,
+        directory: u64,
+        block: u64,
+        locations: &[IndexLocation],
+    ) -> Result<()> {
+        let file = ntfs
+            .file(self.fs, directory)
+            .map_err(|e| SMNtfsError::ReadError(format!("Failed to read directory {}: {}", directory, e)))?;
+        let stream = AttributeStream::collect(&file, self.fs, NtfsAttributeType::IndexAllocation, DIRECTORY_INDEX)?
+            .ok_or(SMNtfsError::CorruptedMft { offset: directory })?;
+
+        let block_size = self.boot_sector.index_record_size as usize;
+        let offset = block * block_size as u64;
+        let mut data = self.read_stream(&stream, offset, block_size)?;
+        if &data[0..4] != b"INDX" || !apply_fixups(&mut data) {
+            return Err(SMNtfsError::ReadError(format!(
+                "INDX block {} of directory {} is damaged",
+                block, directory
+            )));
+        }
+        for location in locations {
+            remove_block_entry(&mut data, location)?;
+        }
+        if !protect_fixups(&mut data) {
+            return Err(SMNtfsError::WriteError(format!("INDX block {} has invalid fixups", block)));
+        }
+        self.write_stream(&stream, offset, &data)
+    }
+
+    /// Apply bitmap fixes, writing only the bytes that change; returns the
+    /// clusters marked used and free
+    fn fix_bitmap(&mut self, ntfs: &Ntfs, fixes: &[Fix]) -> Result<(u64, u64)> {
+        let mut bitmap = ClusterBitmap::load(ntfs, self.fs)?;
+        let original = bitmap.as_bytes().to_vec();
+        let (mut used, mut free) = (0, 0);
+        for fix in fixes {
+            match *fix {
+                Fix::MarkClustersUsed { lcn, length } => {
+                    bitmap.mark_allocated(lcn, length);
+                    used += length;
+                }
+                Fix::MarkClustersFree { lcn, length } => {
+                    bitmap.mark_free(lcn, length);
+                    free += length;
+                }
+                _ => {}
+            }
+        }
+
+        let file = ntfs
+            .file(self.fs, BITMAP_RECORD_NUMBER)
+            .map_err(|e| SMNtfsError::ReadError(format!("Failed to read $Bitmap: {}", e)))?;
+        let stream = AttributeStream::collect(&file, self.fs, NtfsAttributeType::Data, "")?
+            .ok_or(SMNtfsError::CorruptedMft { offset: BITMAP_RECORD_NUMBER })?;
+        for range in changed_ranges(&original, bitmap.as_bytes()) {
+            self.write_stream(&stream, range.start as u64, &bitmap.as_bytes()[range])?;
+        }
+        Ok((used, free))
+    }
+
+    /// Copy an MFT record, as stored, over its $MFTMirr copy
+    fn sync_mirror(&mut self, number: u64) -> Result<()> {
+        let mft = self.mft.clone();
+        let raw = self.read_stream(&mft, number * self.record_size() as u64, self.record_size())?;
+        if !FileRecord::from_bytes(raw.clone(), number, 0).is_valid() {
+            return Err(SMNtfsError::CorruptedMft { offset: number });
+        }
+
+        let position = self.boot_sector.mft_mirror_offset() + number * self.record_size() as u64;
+        self.fs
+            .seek(SeekFrom::Start(position))
+            .and_then(|_| self.fs.write_all(&raw))
+            .map_err(|e| SMNtfsError::WriteError(format!("Failed to update $MFTMirr: {}", e)))
+    }
+
+    /// Clear the dirty flag of $Volume; returns whether it was set
+    fn clear_dirty_flag(&mut self) -> Result<bool> {
+        let record = self.read_record(VOLUME_RECORD_NUMBER)?;
+        let attribute = record
+            .find_attribute(NtfsAttributeType::VolumeInformation, "")
+            .filter(|attribute| attribute.is_resident())
+            .ok_or(SMNtfsError::CorruptedMft { offset: VOLUME_RECORD_NUMBER })?;
+        let flags_offset = attribute.offset() + le_u16(record.data(), attribute.offset() + 0x14) as usize + 0x0A;
+
+        let mut data = record.data().to_vec();
+        let flags = le_u16(&data, flags_offset);
+        if flags & VolumeFlags::IS_DIRTY == 0 {
+            return Ok(false);
+        }
+        data[flags_offset..flags_offset + 2].copy_from_slice(&(flags & !VolumeFlags::IS_DIRTY).to_le_bytes());
+        self.write_record(VOLUME_RECORD_NUMBER, data)?;
+        self.sync_mirror(VOLUME_RECORD_NUMBER)?;
+        Ok(true)
+    }
+}
+
+/// Subtract `amount` from the little-endian `u32` at `offset`
+fn shrink_u32(data: &mut [u8], offset: usize, amount: usize) {
+    let value = le_u32(data, offset).saturating_sub(amount as u32);
+    data[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+/// Cut an entry out of the node starting at `node`, after checking it is
+/// still the leaf entry the check found there
+fn remove_entry(data: &mut [u8], node: usize, location: &IndexLocation) -> Result<()> {
+    let start = location.offset;
+    let end = start + location.length;
+    let node_end = node + le_u32(data, node + 0x04) as usize;
+    let flags = le_u16(data, start + 0x0C);
+    if le_u16(data, start + 0x08) as usize != location.length
+        || flags & (index_entry_flags::SUBNODE | index_entry_flags::LAST) != 0
+        || end > node_end
+        || node_end > data.len()
+    {
+        return Err(SMNtfsError::ReadError(format!(
+            "Index entry at offset {} changed since the check",
+            start
+        )));
+    }
+
+    data.copy_within(end..node_end, start);
+    data[node_end - location.length..node_end].fill(0);
+    shrink_u32(data, node + 0x04, location.length);
+    Ok(())
+}
+
+/// Remove an entry from the $INDEX_ROOT value at `attribute` of an MFT record
+///
+/// The root shrinks, so the attribute and everything after it in the record
+/// move up.
+fn remove_root_entry(record: &mut [u8], attribute: usize, location: &IndexLocation) -> Result<()> {
+    let value = attribute + le_u16(record, attribute + 0x14) as usize;
+    let absolute = IndexLocation {
+        offset: value + location.offset,
+        ..*location
+    };
+    remove_entry(record, value + 0x10, &absolute)?;
+    shrink_u32(record, value + 0x18, location.length);
+
+    // Close the gap left at the end of the attribute
+    let attribute_end = attribute + le_u32(record, attribute + 0x04) as usize;
+    let used = le_u32(record, 0x18) as usize;
+    record.copy_within(attribute_end..used, attribute_end - location.length);
+    record[used - location.length..used].fill(0);
+    shrink_u32(record, attribute + 0x04, location.length);
+    shrink_u32(record, attribute + 0x10, location.length);
+    shrink_u32(record, 0x18, location.length);
+    Ok(())
+}
+
+/// Remove an entry from an INDX block
+fn remove_block_entry(block: &mut [u8], location: &IndexLocation) -> Result<()> {
+    remove_entry(block, 0x18, location)
+}
+
+/// Byte ranges where `new` differs from `old`
+fn changed_ranges(old: &[u8], new: &[u8]) -> Vec<Range<usize>> {
+    let mut ranges: Vec<Range<usize>> = Vec::new();
+    for (index, _) in old.iter().zip(new).enumerate().filter(|(_, (old, new))| old != new) {
+        match ranges.last_mut() {
+            Some(range) if range.end == index => range.end += 1,
+            _ => ranges.push(index..index + 1),
+        }
+    }
+    ranges
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::check::node_entries;
+    use crate::parser::check::tests::index_root;
+    use crate::parser::check::ProblemClass;
+    use crate::parser::record::record_flags;
+    use crate::parser::record::tests::{build_record, resident_attribute};
+    use crate::parser::recovery::open_volume;
+    use crate::parser::values::namespace;
+    use crate::parser::values::tests::file_name_value;
+    use crate::parser::volume::tests::test_image;
+    use std::io::Cursor;
+
+    const FILE_NAME: u32 = 0x30;
+    const INDEX_ROOT: u32 = 0x90;
+    const DATA: u32 = 0x80;
+
+    /// Records of `empty-file` and `file-with-12345` in the test image
+    const EMPTY_FILE: u64 = 64;
+    const FILE_WITH_12345: u64 = 65;
+
+    fn names(data: &[u8], header: usize) -> Vec<String> {
+        let mut entries = Vec::new();
+        node_entries(data, header, None, &mut entries).unwrap();
+        entries.into_iter().map(|entry| entry.name).collect()
+    }
+
+    #[test]
+    fn test_remove_root_entry() {
+        let root = index_root(&[(20, "a.txt"), (21, "b.txt"), (22, "c.txt")]);
+        let attributes = [
+            resident_attribute(INDEX_ROOT, DIRECTORY_INDEX, &root),
+            resident_attribute(DATA, "", b"after the root"),
+        ];
+        let record = FileRecord::from_bytes(build_record(&attributes, record_flags::IN_USE), 5, 0);
+        let attribute = record.find_attribute(NtfsAttributeType::IndexRoot, DIRECTORY_INDEX).unwrap();
+        let value = attribute.offset() + le_u16(record.data(), attribute.offset() + 0x14) as usize;
+
+        let mut entries = Vec::new();
+        node_entries(attribute.resident_value(), 0x10, None, &mut entries).unwrap();
+        let used = record.used_size();
+        let mut data = record.data().to_vec();
+        remove_root_entry(&mut data, attribute.offset(), &entries[1].location).unwrap();
+
+        let record = FileRecord::from_bytes(data.clone(), 5, 0);
+        let length = entries[1].location.length as u32;
+        assert_eq!(record.used_size(), used - length);
+        assert_eq!(names(&data[value..], 0x10), vec!["a.txt", "c.txt"]);
+        assert_eq!(
+            record.find_attribute(NtfsAttributeType::Data, "").unwrap().resident_value(),
+            b"after the root"
+        );
+        // The entry is not where the check saw it any more
+        assert!(remove_root_entry(&mut data, attribute.offset(), &entries[2].location).is_err());
+    }
+
+    #[test]
+    fn test_remove_block_entry() {
+        // An INDX block is a root value with the node header 8 bytes further on
+        let root = index_root(&[(20, "a.txt"), (21, "b.txt")]);
+        let mut block = vec![0u8; 8];
+        block.extend_from_slice(&root);
+        block.resize(4096, 0);
+
+        let mut entries = Vec::new();
+        node_entries(&block, 0x18, Some(0), &mut entries).unwrap();
+        remove_block_entry(&mut block, &entries[0].location).unwrap();
+        assert_eq!(names(&block, 0x18), vec!["b.txt"]);
+    }
+
+    /// Position of an MFT record in the test image
+    fn record_position(image: &[u8], number: u64) -> usize {
+        let boot_sector = BootSector::parse(image).unwrap();
+        (boot_sector.mft_offset() + number * boot_sector.file_record_size as u64) as usize
+    }
+
+    /// The test image with an 8.3 alias added to `file-with-12345`, as a
+    /// second $FILE_NAME, and the record of `empty-file` torn
+    fn aliased_and_damaged_image() -> Vec<u8> {
+        let mut image = test_image();
+        let position = record_position(&image, FILE_WITH_12345);
+        let mut data = image[position..position + 1024].to_vec();
+        assert!(apply_fixups(&mut data));
+
+        let record = FileRecord::from_bytes(data.clone(), FILE_WITH_12345, 0);
+        let long_name = record.find_attribute(NtfsAttributeType::FileName, "").unwrap();
+        let after = long_name.offset() + le_u32(&data, long_name.offset() + 0x04) as usize;
+        let mut alias = resident_attribute(FILE_NAME, "", &file_name_value(5 | (5 << 48), "FILE-W~1", namespace::DOS));
+        alias[0x0E..0x10].copy_from_slice(&data[0x28..0x2A]);
+        let used = record.used_size() as usize;
+        data.copy_within(after..used, after + alias.len());
+        data[after..after + alias.len()].copy_from_slice(&alias);
+        data[0x18..0x1C].copy_from_slice(&((used + alias.len()) as u32).to_le_bytes());
+        data[0x28] += 1;
+        // Two names, so two links
+        data[0x12..0x14].copy_from_slice(&2u16.to_le_bytes());
+        assert!(protect_fixups(&mut data));
+        image[position..position + 1024].copy_from_slice(&data);
+
+        let position = record_position(&image, EMPTY_FILE);
+        image[position + 510] ^= 0xFF;
+        image
+    }
+
+    #[test]
+    fn test_repair_leaves_aliases_and_damaged_records() {
+        let opened = open_volume(Cursor::new(aliased_and_damaged_image())).unwrap();
+        let (mut fs, ntfs, boot_sector) = (opened.fs, opened.ntfs, opened.boot_sector);
+        let report = check_volume(&ntfs, &boot_sector, &mut fs).unwrap();
+        let classes: Vec<(ProblemClass, Option<u64>)> =
+            report.problems.iter().map(|problem| (problem.class, problem.record)).collect();
+        assert_eq!(
+            classes,
+            vec![
+                (ProblemClass::BadRecord, Some(EMPTY_FILE)),
+                (ProblemClass::DanglingIndexEntry, Some(5)),
+                (ProblemClass::MissingIndexEntry, Some(FILE_WITH_12345)),
+            ]
+        );
+        assert_eq!(report.repairable().count(), 0);
+
+        let summary = repair(&ntfs, &boot_sector, &mut fs, &report).unwrap();
+        assert_eq!(summary.link_counts_fixed, 0);
+        assert_eq!(summary.index_entries_removed, 0);
+        assert_eq!(summary.remaining, report.problems);
+        assert!(!summary.dirty_flag_cleared);
+
+        let image = fs.into_inner().into_inner();
+        assert_eq!(image, aliased_and_damaged_image());
+    }
+
+    #[test]
+    fn test_changed_ranges() {
+        let old = [0u8, 1, 2, 3, 4, 5];
+        let new = [0u8, 9, 9, 3, 4, 9];
+        assert_eq!(changed_ranges(&old, &new), vec![1..3, 5..6]);
+    }
+}
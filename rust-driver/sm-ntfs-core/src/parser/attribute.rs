@@ -0,0 +1,419 @@
+//! Attribute streams assembled from one or more attribute fragments
+//!
+//! A large or heavily fragmented non-resident attribute may be split into
+//! several fragments, each covering a VCN range and stored in a different
+//! extension record referenced by the base record's $ATTRIBUTE_LIST. This
+//! module gathers those fragments into a single description of the stream.
+
+use std::io::{Read, Seek, SeekFrom, Write};
+use ntfs::{NtfsAttributeType, NtfsFile};
+use crate::parser::reader::StreamReader;
+use crate::parser::record::{attribute_flags, FileRecord, RawAttribute};
+use crate::parser::runs::DataRun;
+use crate::parser::upcase::UpcaseTable;
+use crate::utils::bytes::{le_u16, le_u32, le_u64, le_u8, utf16_string};
+use crate::utils::error::{Result, SMNtfsError};
+
+/// An entry of an $ATTRIBUTE_LIST
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AttributeListEntry {
+    /// Attribute type code
+    pub type_code: u32,
+
+    /// Attribute name (empty for unnamed attributes)
+    pub name: String,
+
+    /// First VCN of the fragment (0 for resident attributes)
+    pub lowest_vcn: u64,
+
+    /// Record number of the record holding the fragment
+    pub record_number: u64,
+
+    /// Attribute instance within that record
+    pub instance: u16,
+}
+
+/// Parse the value of an $ATTRIBUTE_LIST attribute
+pub fn parse_attribute_list(data: &[u8]) -> Result<Vec<AttributeListEntry>> {
+    let mut entries = Vec::new();
+    let mut offset = 0;
+
+    while offset + 0x1A <= data.len() {
+        let length = le_u16(data, offset + 0x04) as usize;
+        if length < 0x1A || offset + length > data.len() {
+            return Err(SMNtfsError::ReadError(format!(
+                "Invalid attribute list entry length {} at offset {}",
+                length, offset
+            )));
+        }
+
+        let name_length = le_u8(data, offset + 0x06) as usize;
+        let name_offset = le_u8(data, offset + 0x07) as usize;
+
+        entries.push(AttributeListEntry {
+            type_code: le_u32(data, offset),
+            name: utf16_string(data, offset + name_offset, name_length),
+            lowest_vcn: le_u64(data, offset + 0x08),
+            record_number: le_u64(data, offset + 0x10) & 0x0000_FFFF_FFFF_FFFF,
+            instance: le_u16(data, offset + 0x18),
+        });
+
+        offset += length;
+    }
+
+    Ok(entries)
+}
+
+/// A complete attribute value, with the runs of every fragment merged
+#[derive(Debug, Clone, Default)]
+pub struct AttributeStream {
+    /// Attribute type code
+    pub type_code: u32,
+
+    /// Attribute name (empty for unnamed attributes)
+    pub name: String,
+
+    /// Attribute header flags, see [`attribute_flags`]
+    pub flags: u16,
+
+    /// Value bytes of a resident attribute
+    pub resident_data: Option<Vec<u8>>,
+
+    /// Data runs of a non-resident attribute, ordered by VCN
+    pub runs: Vec<DataRun>,
+
+    /// Bytes allocated on disk
+    pub allocated_size: u64,
+
+    /// Logical size of the value
+    pub data_size: u64,
+
+    /// Bytes written so far; the rest reads as zeros
+    pub initialized_size: u64,
+
+    /// Clusters actually in use (compressed and sparse attributes only)
+    pub compressed_size: Option<u64>,
+
+    /// Compression unit as a power of two in clusters (0 if uncompressed)
+    pub compression_unit: u8,
+}
+
+impl AttributeStream {
+    /// Check if the value is stored inside the MFT record
+    pub fn is_resident(&self) -> bool {
+        self.resident_data.is_some()
+    }
+
+    /// Check if the value is compressed
+    pub fn is_compressed(&self) -> bool {
+        self.flags & attribute_flags::COMPRESSED != 0
+    }
+
+    /// Check if the value is encrypted
+    pub fn is_encrypted(&self) -> bool {
+        self.flags & attribute_flags::ENCRYPTED != 0
+    }
+
+    /// Check if the value is sparse
+    pub fn is_sparse(&self) -> bool {
+        self.flags & attribute_flags::SPARSE != 0
+    }
+
+    /// Find the run covering `vcn`
+    pub fn run_at(&self, vcn: u64) -> Option<&DataRun> {
+        let index = self.runs.partition_point(|run| run.end_vcn() <= vcn);
+        self.runs.get(index).filter(|run| run.contains_vcn(vcn))
+    }
+
+    /// Byte offset on the device of byte `offset` of a non-resident value
+    ///
+    /// Returns `None` for resident values, sparse holes, unmapped offsets and
+    /// LCNs too large to address.
+    pub fn device_offset(&self, offset: u64, cluster_size: u32) -> Option<u64> {
+        let cluster_size = cluster_size as u64;
+        let run = self.run_at(offset / cluster_size)?;
+        run.lcn?.checked_mul(cluster_size)?.checked_add(offset - run.vcn * cluster_size)
+    }
+
+    /// Write `data` at byte `offset` of a non-resident value, a cluster at a time
+    ///
+    /// Every byte written must fall in an allocated run; the value is not
+    /// extended and compressed values are not handled.
+    pub fn write_at<T: Write + Seek>(&self, fs: &mut T, offset: u64, data: &[u8], cluster_size: u32) -> Result<()> {
+        let cluster_bytes = cluster_size as u64;
+        let mut done = 0;
+        while done < data.len() {
+            let position = offset + done as u64;
+            let device_offset = self.device_offset(position, cluster_size).ok_or_else(|| {
+                SMNtfsError::WriteError(format!("Byte {} of the stream is not allocated", position))
+            })?;
+            let count = (data.len() - done).min((cluster_bytes - position % cluster_bytes) as usize);
+            fs.seek(SeekFrom::Start(device_offset))
+                .and_then(|_| fs.write_all(&data[done..done + count]))
+                .map_err(|e| SMNtfsError::WriteError(format!("Failed to write byte {}: {}", device_offset, e)))?;
+            done += count;
+        }
+        Ok(())
+    }
+
+    /// Build from the fragments of one attribute, in any order
+    ///
+    /// Sizes and flags come from the fragment starting at VCN 0, which is the
+    /// only one where they are valid.
+    pub fn from_fragments(fragments: &[RawAttribute<'_>]) -> Result<Self> {
+        let mut sorted: Vec<&RawAttribute<'_>> = fragments.iter().collect();
+        sorted.sort_by_key(|fragment| fragment.lowest_vcn());
+
+        let first = sorted
+            .first()
+            .ok_or_else(|| SMNtfsError::ReadError("Attribute has no fragments".to_string()))?;
+
+        let mut stream = Self {
+            type_code: first.type_code(),
+            name: first.name(),
+            flags: first.flags(),
+            allocated_size: first.allocated_size(),
+            data_size: first.data_size(),
+            initialized_size: first.initialized_size(),
+            compressed_size: first.compressed_size(),
+            compression_unit: first.compression_unit(),
+            ..Self::default()
+        };
+
+        if first.is_resident() {
+            stream.resident_data = Some(first.resident_value().to_vec());
+            return Ok(stream);
+        }
+
+        for fragment in sorted {
+            let expected_vcn = stream.runs.last().map_or(0, |run| run.end_vcn());
+            if fragment.is_resident() || fragment.lowest_vcn() != expected_vcn {
+                return Err(SMNtfsError::CorruptedMft {
+                    offset: fragment.record_number(),
+                });
+            }
+            stream.runs.extend(fragment.data_runs()?);
+        }
+
+        Ok(stream)
+    }
+
+    /// Find an attribute of a file and merge all of its fragments
+    ///
+    /// Names are compared exactly, as suits the fixed names of system
+    /// attributes; see [`collect_ignore_case`](Self::collect_ignore_case)
+    /// for names given by users. Returns `None` if the file has no such
+    /// attribute.
+    pub fn collect<T: Read + Seek>(
+        file: &NtfsFile,
+        fs: &mut T,
+        ty: NtfsAttributeType,
+        name: &str,
+    ) -> Result<Option<Self>> {
+        Self::collect_matching(file, fs, ty, |other| other == name)
+    }
+
+    /// Like [`collect`](Self::collect), but compare names case-insensitively
+    /// through the $UpCase table of the volume, as Windows does
+    pub fn collect_ignore_case<T: Read + Seek>(
+        file: &NtfsFile,
+        fs: &mut T,
+        ty: NtfsAttributeType,
+        name: &str,
+        upcase: &UpcaseTable,
+    ) -> Result<Option<Self>> {
+        Self::collect_matching(file, fs, ty, |other| upcase.names_equal(other, name))
+    }
+
+    fn collect_matching<T: Read + Seek>(
+        file: &NtfsFile,
+        fs: &mut T,
+        ty: NtfsAttributeType,
+        matches: impl Fn(&str) -> bool,
+    ) -> Result<Option<Self>> {
+        let records = read_file_records(file, fs)?;
+        let mut fragments = Vec::new();
+
+        for record in &records {
+            for attribute in record.attributes() {
+                let attribute = attribute?;
+                if attribute.is_type(ty) && matches(&attribute.name()) {
+                    fragments.push(attribute);
+                }
+            }
+        }
+
+        match fragments.is_empty() {
+            true => Ok(None),
+            false => Self::from_fragments(&fragments).map(Some),
+        }
+    }
+
+    /// Collect every attribute of a file, in on-disk order
+    ///
+    /// Fragments of non-resident attributes are merged by type and name.
+    /// Resident attributes are returned one by one, since several (such as
+    /// $FILE_NAME) may share a type and an empty name.
+    pub fn collect_all<T: Read + Seek>(file: &NtfsFile, fs: &mut T) -> Result<Vec<Self>> {
+        let records = read_file_records(file, fs)?;
+        let mut groups: Vec<Vec<RawAttribute<'_>>> = Vec::new();
+
+        for record in &records {
+            for attribute in record.attributes() {
+                let attribute = attribute?;
+                let group = groups.iter_mut().find(|group| {
+                    let first = &group[0];
+                    !attribute.is_resident()
+                        && !first.is_resident()
+                        && first.type_code() == attribute.type_code()
+                        && first.name() == attribute.name()
+                });
+
+                match group {
+                    Some(group) => group.push(attribute),
+                    None => groups.push(vec![attribute]),
+                }
+            }
+        }
+
+        groups.iter().map(|group| Self::from_fragments(group)).collect()
+    }
+}
+
+/// Read the base record of a file and every extension record its
+/// $ATTRIBUTE_LIST refers to
+pub fn read_file_records<T: Read + Seek>(file: &NtfsFile, fs: &mut T) -> Result<Vec<FileRecord>> {
+    let base = FileRecord::read(file, fs)?;
+
+    // The attribute list itself always lives in the base record
+    let list_stream = match base.find_attribute(NtfsAttributeType::AttributeList, "") {
+        Some(list_attribute) => AttributeStream::from_fragments(&[list_attribute])?,
+        None => return Ok(vec![base]),
+    };
+    let list_data = StreamReader::new(fs, list_stream, file.ntfs().cluster_size()).read_all()?;
+
+    let mut records = vec![base];
+    for entry in parse_attribute_list(&list_data)? {
+        if records.iter().any(|record| record.record_number() == entry.record_number) {
+            continue;
+        }
+
+        let extension = file
+            .ntfs()
+            .file(fs, entry.record_number)
+            .map_err(|e| SMNtfsError::ReadError(format!("Failed to read extension record {}: {}", entry.record_number, e)))?;
+        let record = FileRecord::read(&extension, fs)?;
+
+        if record.base_record_number() != Some(file.file_record_number()) {
+            return Err(SMNtfsError::CorruptedMft {
+                offset: entry.record_number,
+            });
+        }
+        records.push(record);
+    }
+
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::record::tests::{build_record, non_resident_attribute, resident_attribute};
+    use crate::parser::record::record_flags;
+    use crate::parser::volume::tests::test_image;
+    use ntfs::Ntfs;
+    use std::io::Cursor;
+
+    fn list_entry(type_code: u32, name: &str, lowest_vcn: u64, record_number: u64, instance: u16) -> Vec<u8> {
+        let name: Vec<u16> = name.encode_utf16().collect();
+        let length = (0x1A + name.len() * 2 + 7) & !7;
+        let mut data = vec![0u8; length];
+        data[0..4].copy_from_slice(&type_code.to_le_bytes());
+        data[4..6].copy_from_slice(&(length as u16).to_le_bytes());
+        data[6] = name.len() as u8;
+        data[7] = 0x1A;
+        data[0x08..0x10].copy_from_slice(&lowest_vcn.to_le_bytes());
+        data[0x10..0x18].copy_from_slice(&(record_number | (1 << 48)).to_le_bytes());
+        data[0x18..0x1A].copy_from_slice(&instance.to_le_bytes());
+        for (i, unit) in name.iter().enumerate() {
+            data[0x1A + i * 2..0x1C + i * 2].copy_from_slice(&unit.to_le_bytes());
+        }
+        data
+    }
+
+    #[test]
+    fn test_parse_attribute_list() {
+        let mut data = list_entry(0x10, "", 0, 5, 0);
+        data.extend(list_entry(0x80, "ads", 16, 70, 3));
+
+        let entries = parse_attribute_list(&data).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1].name, "ads");
+        assert_eq!(entries[1].lowest_vcn, 16);
+        assert_eq!(entries[1].record_number, 70);
+        assert_eq!(entries[1].instance, 3);
+    }
+
+    #[test]
+    fn test_merge_fragments() {
+        let data_type = NtfsAttributeType::Data as u32;
+        let mut second = non_resident_attribute(data_type, "", 0, (0, 0, 0), &[0x11, 0x04, 0x40]);
+        second[0x10..0x18].copy_from_slice(&8u64.to_le_bytes());
+        let first = non_resident_attribute(data_type, "", 0, (6144, 6000, 6000), &[0x11, 0x08, 0x10]);
+
+        let record = FileRecord::from_bytes(build_record(&[second, first], record_flags::IN_USE), 30, 0);
+        let fragments: Vec<_> = record.attributes().map(|a| a.unwrap()).collect();
+        let stream = AttributeStream::from_fragments(&fragments).unwrap();
+
+        assert_eq!(stream.data_size, 6000);
+        assert_eq!(stream.runs.len(), 2);
+        assert_eq!(stream.runs[1].vcn, 8);
+        // Each fragment's mapping pairs restart from LCN 0
+        assert_eq!(stream.runs[1].lcn, Some(0x40));
+    }
+
+    #[test]
+    fn test_resident_stream() {
+        let record = FileRecord::from_bytes(
+            build_record(&[resident_attribute(NtfsAttributeType::Data as u32, "", b"hello")], record_flags::IN_USE),
+            31,
+            0,
+        );
+        let fragments: Vec<_> = record.attributes().map(|a| a.unwrap()).collect();
+        let stream = AttributeStream::from_fragments(&fragments).unwrap();
+
+        assert!(stream.is_resident());
+        assert_eq!(stream.data_size, 5);
+        assert_eq!(stream.resident_data.as_deref(), Some(b"hello".as_slice()));
+    }
+
+    #[test]
+    fn test_stream_names() {
+        let data_type = NtfsAttributeType::Data as u32;
+        let attributes = [
+            resident_attribute(data_type, "", b""),
+            resident_attribute(data_type, "Zone.Identifier", b"[ZoneTransfer]"),
+            resident_attribute(data_type, "\u{e9}", b"x"),
+        ];
+        let mut record = build_record(&attributes, record_flags::IN_USE);
+        record[0x2C..0x30].copy_from_slice(&64u32.to_le_bytes());
+        let mut image = test_image();
+        image[16384 + 64 * 1024..][..1024].copy_from_slice(&record);
+        let mut fs = Cursor::new(image);
+        let ntfs = Ntfs::new(&mut fs).unwrap();
+        let file = ntfs.file(&mut fs, 64).unwrap();
+
+        let find = |fs: &mut Cursor<Vec<u8>>, name: &str, upcase: &UpcaseTable| {
+            AttributeStream::collect_ignore_case(&file, fs, NtfsAttributeType::Data, name, upcase).unwrap()
+        };
+        let host = UpcaseTable::host();
+        assert_eq!(find(&mut fs, "ZONE.identifier", &host).unwrap().data_size, 14);
+        assert!(find(&mut fs, "\u{c9}", &host).is_some());
+
+        // Only what the table of the volume maps is folded
+        let identity = UpcaseTable::parse(&[]);
+        assert!(find(&mut fs, "Zone.Identifier", &identity).is_some());
+        assert!(find(&mut fs, "\u{c9}", &identity).is_none());
+        assert!(AttributeStream::collect(&file, &mut fs, NtfsAttributeType::Data, "zone.identifier").unwrap().is_none());
+    }
+}
@@ -0,0 +1,267 @@
+//! Cluster allocation bitmap ($Bitmap)
+//!
+//! Bit N of the $Bitmap data stream is set when cluster N is in use. The
+//! number of allocated clusters is counted once, a 64-bit word at a time,
+//! and then kept up to date as clusters are marked allocated or free.
+
+use std::io::{Read, Seek};
+use std::ops::Range;
+use ntfs::{Ntfs, NtfsAttributeType};
+use crate::parser::attribute::AttributeStream;
+use crate::parser::reader::StreamReader;
+use crate::utils::error::{Result, SMNtfsError};
+
+/// Record number of the $Bitmap system file
+pub const BITMAP_RECORD_NUMBER: u64 = 6;
+
+/// In-memory copy of the volume's cluster allocation bitmap
+#[derive(Debug, Clone)]
+pub struct ClusterBitmap {
+    bits: Vec<u8>,
+    cluster_count: u64,
+    allocated: u64,
+}
+
+impl ClusterBitmap {
+    /// Build from raw bitmap bytes (bit N of the stream is cluster N)
+    ///
+    /// The bitmap is padded with zeros if it is shorter than the volume.
+    pub fn from_bytes(mut bits: Vec<u8>, cluster_count: u64) -> Self {
+        let needed = cluster_count.div_ceil(8) as usize;
+        if bits.len() < needed {
+            bits.resize(needed, 0);
+        }
+        let allocated = count_ones(&bits, cluster_count);
+
+        Self {
+            bits,
+            cluster_count,
+            allocated,
+        }
+    }
+
+    /// Read the $Bitmap data stream of a volume
+    pub fn load<T: Read + Seek>(ntfs: &Ntfs, fs: &mut T) -> Result<Self> {
+        let file = ntfs
+            .file(fs, BITMAP_RECORD_NUMBER)
+            .map_err(|e| SMNtfsError::ReadError(format!("Failed to read $Bitmap: {}", e)))?;
+        let stream = AttributeStream::collect(&file, fs, NtfsAttributeType::Data, "")?
+            .ok_or(SMNtfsError::CorruptedMft {
+                offset: BITMAP_RECORD_NUMBER,
+            })?;
+        let bits = StreamReader::new(fs, stream, ntfs.cluster_size()).read_all()?;
+        let cluster_count = ntfs.size() / ntfs.cluster_size() as u64;
+
+        Ok(Self::from_bytes(bits, cluster_count))
+    }
+
+    /// Number of clusters on the volume
+    pub fn cluster_count(&self) -> u64 {
+        self.cluster_count
+    }
+
+    /// Number of clusters in use
+    pub fn allocated_clusters(&self) -> u64 {
+        self.allocated
+    }
+
+    /// Number of free clusters
+    pub fn free_clusters(&self) -> u64 {
+        self.cluster_count - self.allocated
+    }
+
+    /// Check if a cluster is in use
+    ///
+    /// Clusters beyond the end of the volume are reported as allocated, so
+    /// they are never mistaken for free space.
+    pub fn is_allocated(&self, lcn: u64) -> bool {
+        if lcn >= self.cluster_count {
+            return true;
+        }
+        self.bits[(lcn / 8) as usize] & (1 << (lcn % 8)) != 0
+    }
+
+    /// Count the allocated clusters in `lcn..lcn + length`
+    pub fn allocated_in_range(&self, lcn: u64, length: u64) -> u64 {
+        let end = lcn.saturating_add(length);
+        let inside = lcn.min(self.cluster_count)..end.min(self.cluster_count);
+        let beyond = end - lcn - (inside.end - inside.start);
+        self.count_in(inside) + beyond
+    }
+
+    /// Iterate over runs of free clusters, in LCN order
+    pub fn free_extents(&self) -> FreeExtents<'_> {
+        FreeExtents { bitmap: self, next: 0 }
+    }
+
+    /// Mark `lcn..lcn + length` as in use; clusters past the end are ignored
+    pub fn mark_allocated(&mut self, lcn: u64, length: u64) {
+        self.set_range(lcn, length, true);
+    }
+
+    /// Mark `lcn..lcn + length` as free; clusters past the end are ignored
+    pub fn mark_free(&mut self, lcn: u64, length: u64) {
+        self.set_range(lcn, length, false);
+    }
+
+    /// Raw bitmap bytes, as they would be written back to $Bitmap
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bits
+    }
+
+    fn set_range(&mut self, lcn: u64, length: u64, allocated: bool) {
+        let range = lcn.min(self.cluster_count)..lcn.saturating_add(length).min(self.cluster_count);
+        let before = self.count_in(range.clone());
+
+        for lcn in range.clone() {
+            let byte = &mut self.bits[(lcn / 8) as usize];
+            if allocated {
+                *byte |= 1 << (lcn % 8);
+            } else {
+                *byte &= !(1 << (lcn % 8));
+            }
+        }
+
+        let after = if allocated { range.end - range.start } else { 0 };
+        self.allocated = self.allocated - before + after;
+    }
+
+    /// Count set bits in a range of clusters that lies inside the volume
+    fn count_in(&self, range: Range<u64>) -> u64 {
+        if range.start >= range.end {
+            return 0;
+        }
+
+        // Unaligned head and tail bit by bit, whole bytes in between
+        let first_byte = range.start.div_ceil(8);
+        let last_byte = range.end / 8;
+        if first_byte >= last_byte {
+            return range.filter(|lcn| self.is_allocated(*lcn)).count() as u64;
+        }
+
+        let head = (range.start..first_byte * 8).filter(|lcn| self.is_allocated(*lcn)).count() as u64;
+        let tail = (last_byte * 8..range.end).filter(|lcn| self.is_allocated(*lcn)).count() as u64;
+        let bytes = &self.bits[first_byte as usize..last_byte as usize];
+        head + count_ones(bytes, bytes.len() as u64 * 8) + tail
+    }
+}
+
+/// Count the set bits among the first `bit_count` bits of `bits`
+fn count_ones(bits: &[u8], bit_count: u64) -> u64 {
+    let whole_bytes = ((bit_count / 8) as usize).min(bits.len());
+    let mut words = bits[..whole_bytes].chunks_exact(8);
+
+    let mut count: u64 = words
+        .by_ref()
+        .map(|word| u64::from_le_bytes(word.try_into().unwrap_or_default()).count_ones() as u64)
+        .sum();
+    count += words.remainder().iter().map(|byte| byte.count_ones() as u64).sum::<u64>();
+
+    let extra_bits = bit_count % 8;
+    if extra_bits != 0 {
+        if let Some(byte) = bits.get(whole_bytes) {
+            count += (byte & ((1u8 << extra_bits) - 1)).count_ones() as u64;
+        }
+    }
+    count
+}
+
+/// Iterator over runs of free clusters, see [`ClusterBitmap::free_extents`]
+pub struct FreeExtents<'b> {
+    bitmap: &'b ClusterBitmap,
+    next: u64,
+}
+
+impl FreeExtents<'_> {
+    /// Find the first cluster at or after `lcn` whose state is `allocated`
+    fn next_in_state(&self, mut lcn: u64, allocated: bool) -> u64 {
+        let bits = &self.bitmap.bits;
+        let end = self.bitmap.cluster_count;
+        // Bytes entirely in the other state are skipped whole
+        let skip = if allocated { 0x00 } else { 0xFF };
+
+        while lcn < end {
+            if lcn % 8 == 0 && bits[(lcn / 8) as usize] == skip {
+                lcn += 8;
+                continue;
+            }
+            if self.bitmap.is_allocated(lcn) == allocated {
+                return lcn;
+            }
+            lcn += 1;
+        }
+        end
+    }
+}
+
+impl Iterator for FreeExtents<'_> {
+    type Item = Range<u64>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let start = self.next_in_state(self.next, false);
+        if start >= self.bitmap.cluster_count {
+            self.next = start;
+            return None;
+        }
+        let end = self.next_in_state(start, true).min(self.bitmap.cluster_count);
+        self.next = end;
+        Some(start..end)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_allocated() {
+        let bitmap = ClusterBitmap::from_bytes(vec![0b0000_0101, 0xFF], 12);
+
+        assert!(bitmap.is_allocated(0));
+        assert!(!bitmap.is_allocated(1));
+        assert!(bitmap.is_allocated(2));
+        assert!(bitmap.is_allocated(11));
+        // Past the end of the volume
+        assert!(bitmap.is_allocated(12));
+        assert_eq!(bitmap.allocated_in_range(0, 8), 2);
+        assert_eq!(bitmap.allocated_in_range(10, 4), 4);
+    }
+
+    #[test]
+    fn test_counts() {
+        // 100 clusters: the first 70 in use, padding bits past the end set
+        let mut bits = vec![0xFF; 8];
+        bits.extend([0b0011_1111, 0, 0, 0, 0xF0]);
+        let bitmap = ClusterBitmap::from_bytes(bits, 100);
+
+        assert_eq!(bitmap.allocated_clusters(), 70);
+        assert_eq!(bitmap.free_clusters(), 30);
+        assert_eq!(bitmap.allocated_in_range(3, 90), 67);
+    }
+
+    #[test]
+    fn test_free_extents() {
+        let bitmap = ClusterBitmap::from_bytes(vec![0b1000_0011, 0xFF, 0x00, 0b1111_0000], 30);
+        let extents: Vec<Range<u64>> = bitmap.free_extents().collect();
+
+        assert_eq!(extents, vec![2..7, 16..28]);
+        assert_eq!(extents.iter().map(|e| e.end - e.start).sum::<u64>(), bitmap.free_clusters());
+    }
+
+    #[test]
+    fn test_mark_ranges() {
+        let mut bitmap = ClusterBitmap::from_bytes(vec![0; 4], 32);
+
+        bitmap.mark_allocated(5, 20);
+        assert_eq!(bitmap.allocated_clusters(), 20);
+        bitmap.mark_allocated(20, 10);
+        assert_eq!(bitmap.allocated_clusters(), 25);
+        bitmap.mark_free(0, 10);
+        assert_eq!(bitmap.allocated_clusters(), 20);
+        // Clusters past the end of the volume are ignored
+        bitmap.mark_allocated(30, 100);
+        assert_eq!(bitmap.allocated_clusters(), 22);
+
+        assert_eq!(bitmap.free_extents().collect::<Vec<_>>(), vec![0..10]);
+    }
+}
@@ -0,0 +1,178 @@
+//! NTFS boot sector
+//!
+//! The first sector of the volume holds the BIOS parameter block: sector
+//! and cluster sizes, the volume size, where the MFT and its mirror start,
+//! and the size of MFT and index records. Record sizes are stored as a
+//! signed byte: a positive value counts clusters, a negative value `-n`
+//! means `2^n` bytes.
+
+use std::io::{Read, Seek, SeekFrom};
+use crate::utils::bytes::{le_u16, le_u64, le_u8};
+use crate::utils::error::{Result, SMNtfsError};
+
+/// OEM ID of NTFS volumes
+pub const NTFS_OEM_ID: &[u8; 8] = b"NTFS    ";
+
+/// Size of the boot sector that is parsed
+pub const BOOT_SECTOR_SIZE: usize = 512;
+
+/// Decoded boot sector fields
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BootSector {
+    /// Bytes per sector
+    pub sector_size: u16,
+
+    /// Bytes per cluster
+    pub cluster_size: u32,
+
+    /// Number of sectors in the volume, excluding the backup boot sector
+    pub total_sectors: u64,
+
+    /// First cluster of the MFT
+    pub mft_lcn: u64,
+
+    /// First cluster of $MFTMirr
+    pub mft_mirror_lcn: u64,
+
+    /// Bytes per MFT record
+    pub file_record_size: u32,
+
+    /// Bytes per index record (INDX block)
+    pub index_record_size: u32,
+
+    /// Volume serial number
+    pub serial_number: u64,
+}
+
+impl BootSector {
+    /// Decode and validate a boot sector
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        if data.len() < BOOT_SECTOR_SIZE {
+            return Err(SMNtfsError::InvalidNtfs(format!(
+                "Boot sector of {} bytes is truncated",
+                data.len()
+            )));
+        }
+        if &data[0x03..0x0B] != NTFS_OEM_ID {
+            return Err(SMNtfsError::InvalidNtfs("Boot sector has no NTFS signature".to_string()));
+        }
+        if le_u16(data, 0x1FE) != 0xAA55 {
+            return Err(SMNtfsError::InvalidNtfs("Boot sector has no 0xAA55 end marker".to_string()));
+        }
+
+        let sector_size = le_u16(data, 0x0B);
+        if !(256..=4096).contains(&sector_size) || !sector_size.is_power_of_two() {
+            return Err(SMNtfsError::InvalidNtfs(format!("Invalid sector size {}", sector_size)));
+        }
+        // Values above 0x80 are a negative power of two, for clusters over 64 KiB
+        let sectors_per_cluster = match le_u8(data, 0x0D) {
+            count @ 1..=0x80 if count.is_power_of_two() => count as u32,
+            shift @ 0xF4..=0xFF => 1 << (256 - shift as u32),
+            other => {
+                return Err(SMNtfsError::InvalidNtfs(format!("Invalid sectors per cluster {:#x}", other)));
+            }
+        };
+        let cluster_size = sector_size as u32 * sectors_per_cluster;
+
+        let record_size = |offset: usize, what: &str| -> Result<u32> {
+            let size = match le_u8(data, offset) as i8 {
+                clusters @ 1.. => clusters as u32 * cluster_size,
+                shift @ -31..=-1 => 1 << -shift,
+                other => {
+                    return Err(SMNtfsError::InvalidNtfs(format!("Invalid {} record size {}", what, other)));
+                }
+            };
+            Ok(size)
+        };
+
+        Ok(Self {
+            sector_size,
+            cluster_size,
+            total_sectors: le_u64(data, 0x28),
+            mft_lcn: le_u64(data, 0x30),
+            mft_mirror_lcn: le_u64(data, 0x38),
+            file_record_size: record_size(0x40, "MFT")?,
+            index_record_size: record_size(0x44, "index")?,
+            serial_number: le_u64(data, 0x48),
+        })
+    }
+
+    /// Read and decode the boot sector at the start of a volume
+    pub fn read<T: Read + Seek>(fs: &mut T) -> Result<Self> {
+        let mut data = [0u8; BOOT_SECTOR_SIZE];
+        fs.seek(SeekFrom::Start(0))?;
+        fs.read_exact(&mut data)?;
+        Self::parse(&data)
+    }
+
+    /// Volume size in bytes
+    pub fn size(&self) -> u64 {
+        self.total_sectors * self.sector_size as u64
+    }
+
+    /// Byte offset of the MFT
+    pub fn mft_offset(&self) -> u64 {
+        self.mft_lcn * self.cluster_size as u64
+    }
+
+    /// Byte offset of $MFTMirr
+    pub fn mft_mirror_offset(&self) -> u64 {
+        self.mft_mirror_lcn * self.cluster_size as u64
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// A boot sector with 512-byte sectors, 4 KiB clusters and 1 KiB records
+    pub(crate) fn boot_sector(total_sectors: u64, mft_lcn: u64, mft_mirror_lcn: u64) -> Vec<u8> {
+        let mut data = vec![0u8; BOOT_SECTOR_SIZE];
+        data[0x00..0x03].copy_from_slice(&[0xEB, 0x52, 0x90]);
+        data[0x03..0x0B].copy_from_slice(NTFS_OEM_ID);
+        data[0x0B..0x0D].copy_from_slice(&512u16.to_le_bytes());
+        data[0x0D] = 8;
+        data[0x15] = 0xF8;
+        data[0x28..0x30].copy_from_slice(&total_sectors.to_le_bytes());
+        data[0x30..0x38].copy_from_slice(&mft_lcn.to_le_bytes());
+        data[0x38..0x40].copy_from_slice(&mft_mirror_lcn.to_le_bytes());
+        data[0x40] = (-10i8) as u8;
+        data[0x44] = 1;
+        data[0x48..0x50].copy_from_slice(&0x1234_5678_9ABC_DEF0u64.to_le_bytes());
+        data[0x1FE..0x200].copy_from_slice(&0xAA55u16.to_le_bytes());
+        data
+    }
+
+    #[test]
+    fn test_parse() {
+        let boot = BootSector::parse(&boot_sector(2048, 4, 128)).unwrap();
+
+        assert_eq!(boot.cluster_size, 4096);
+        assert_eq!(boot.file_record_size, 1024);
+        assert_eq!(boot.index_record_size, 4096);
+        assert_eq!(boot.size(), 1024 * 1024);
+        assert_eq!(boot.mft_offset(), 16384);
+        assert_eq!(boot.mft_mirror_offset(), 128 * 4096);
+        assert_eq!(boot.serial_number, 0x1234_5678_9ABC_DEF0);
+    }
+
+    #[test]
+    fn test_large_clusters() {
+        // 2^9 sectors of 512 bytes: 256 KiB clusters
+        let mut data = boot_sector(2048, 4, 128);
+        data[0x0D] = 0xF7;
+        assert_eq!(BootSector::parse(&data).unwrap().cluster_size, 256 * 1024);
+    }
+
+    #[test]
+    fn test_invalid() {
+        let mut data = boot_sector(2048, 4, 128);
+        data[0x03] = b'M';
+        assert!(BootSector::parse(&data).is_err());
+
+        let mut data = boot_sector(2048, 4, 128);
+        data[0x0D] = 3;
+        assert!(BootSector::parse(&data).is_err());
+        assert!(BootSector::parse(&data[..256]).is_err());
+    }
+}
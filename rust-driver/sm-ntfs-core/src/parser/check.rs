@@ -0,0 +1,974 @@
+//! Read-only consistency check of a volume
+//!
+//! One pass over the raw MFT collects what every in-use record claims: the
+//! clusters of its non-resident attributes, its names and, for directories,
+//! where the $I30 index lives. That is then checked against the rest of the
+//! volume: $MFTMirr against the first MFT records, $Bitmap against the
+//! clusters actually used, every directory index against the records it
+//! points to, and every $FILE_NAME against the index of its parent. Nothing
+//! is written; the result is a [`CheckReport`] listing each problem found.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::io::{Read, Seek, SeekFrom};
+use ntfs::{Ntfs, NtfsAttributeType};
+use crate::parser::attribute::AttributeStream;
+use crate::parser::bitmap::ClusterBitmap;
+use crate::parser::boot::BootSector;
+use crate::parser::mft::FileReference;
+use crate::parser::reader::StreamReader;
+use crate::parser::record::{apply_fixups, FileRecord, RawAttribute};
+use crate::parser::recovery::MIRRORED_RECORDS;
+use crate::parser::runs::DataRun;
+use crate::parser::scan::MftRecords;
+use crate::parser::values::{file_reference, FileNameValue};
+use crate::utils::bytes::{le_u16, le_u32, le_u64};
+use crate::utils::error::{Result, SMNtfsError};
+
+/// Name of the directory index
+pub const DIRECTORY_INDEX: &str = "$I30";
+
+/// Index entry flags
+pub mod index_entry_flags {
+    /// The entry points to a child node
+    pub const SUBNODE: u16 = 0x1;
+    /// Last entry of a node, without a key
+    pub const LAST: u16 = 0x2;
+}
+
+/// Kind of problem found by [`check_volume`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ProblemClass {
+    /// MFT record with a bad signature, failed fixups or unparsable attributes
+    BadRecord,
+    /// $MFTMirr copy that differs from the MFT record
+    MirrorMismatch,
+    /// Data run reaching past the end of the volume
+    RunOutOfRange,
+    /// Clusters used by more than one attribute
+    CrossLinkedClusters,
+    /// Clusters in use but free in $Bitmap
+    UsedClustersMarkedFree,
+    /// Clusters allocated in $Bitmap that nothing uses
+    FreeClustersMarkedUsed,
+    /// Directory index that is missing or cannot be read
+    BadIndex,
+    /// Index entry pointing at a free or non-base record
+    DanglingIndexEntry,
+    /// Index entry whose sequence number is not the record's
+    StaleIndexEntry,
+    /// Index entry whose name the record does not have in that directory
+    IndexEntryNameMismatch,
+    /// $FILE_NAME whose parent is not an in-use directory
+    BadParent,
+    /// $FILE_NAME missing from its parent's index
+    MissingIndexEntry,
+    /// Hard link count that does not match the names of the record
+    LinkCountMismatch,
+}
+
+impl ProblemClass {
+    /// Short name, e.g. `cross-linked-clusters`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::BadRecord => "bad-record",
+            Self::MirrorMismatch => "mirror-mismatch",
+            Self::RunOutOfRange => "run-out-of-range",
+            Self::CrossLinkedClusters => "cross-linked-clusters",
+            Self::UsedClustersMarkedFree => "used-clusters-marked-free",
+            Self::FreeClustersMarkedUsed => "free-clusters-marked-used",
+            Self::BadIndex => "bad-index",
+            Self::DanglingIndexEntry => "dangling-index-entry",
+            Self::StaleIndexEntry => "stale-index-entry",
+            Self::IndexEntryNameMismatch => "index-entry-name-mismatch",
+            Self::BadParent => "bad-parent",
+            Self::MissingIndexEntry => "missing-index-entry",
+            Self::LinkCountMismatch => "link-count-mismatch",
+        }
+    }
+}
+
+impl fmt::Display for ProblemClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Where an index entry is stored
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IndexLocation {
+    /// INDX block number within $INDEX_ALLOCATION, `None` for the index root
+    pub block: Option<u64>,
+
+    /// Offset of the entry in the index root value or the INDX block
+    pub offset: usize,
+
+    /// Length of the entry
+    pub length: usize,
+}
+
+/// A change that would repair a problem, see [`crate::ntfs::repair`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fix {
+    /// Set the bits of `lcn..lcn + length` in $Bitmap
+    MarkClustersUsed { lcn: u64, length: u64 },
+    /// Clear the bits of `lcn..lcn + length` in $Bitmap
+    MarkClustersFree { lcn: u64, length: u64 },
+    /// Copy the MFT record over its $MFTMirr copy
+    SyncMirror,
+    /// Remove a leaf entry from the index of the directory
+    RemoveIndexEntry(IndexLocation),
+    /// Set the hard link count of the record
+    SetLinkCount(u16),
+}
+
+/// One inconsistency
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Problem {
+    /// What kind of problem this is
+    pub class: ProblemClass,
+
+    /// MFT record concerned, if the problem belongs to one
+    pub record: Option<u64>,
+
+    /// Human-readable description
+    pub detail: String,
+
+    /// How to repair it, if it can be repaired safely
+    pub fix: Option<Fix>,
+}
+
+impl Problem {
+    /// A problem without a safe fix
+    pub fn new(class: ProblemClass, record: Option<u64>, detail: String) -> Self {
+        Self {
+            class,
+            record,
+            detail,
+            fix: None,
+        }
+    }
+
+    /// Attach a fix
+    pub fn with_fix(self, fix: Fix) -> Self {
+        Self { fix: Some(fix), ..self }
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.record {
+            Some(record) => write!(f, "[{}] record {}: {}", self.class, record, self.detail),
+            None => write!(f, "[{}] {}", self.class, self.detail),
+        }
+    }
+}
+
+/// Result of [`check_volume`]
+#[derive(Debug, Clone)]
+pub struct CheckReport {
+    /// Every problem found, in the order the checks ran
+    pub problems: Vec<Problem>,
+
+    /// Number of MFT records examined
+    pub records_checked: u64,
+
+    /// Number of directory indexes examined
+    pub directories_checked: u64,
+
+    /// Cluster usage computed from the data runs of every in-use record
+    pub computed_bitmap: ClusterBitmap,
+}
+
+impl CheckReport {
+    /// Number of problems of each class
+    pub fn counts(&self) -> BTreeMap<ProblemClass, usize> {
+        let mut counts = BTreeMap::new();
+        for problem in &self.problems {
+            *counts.entry(problem.class).or_insert(0) += 1;
+        }
+        counts
+    }
+
+    /// Check if no problem was found
+    pub fn is_clean(&self) -> bool {
+        self.problems.is_empty()
+    }
+
+    /// Problems that have a fix
+    pub fn repairable(&self) -> impl Iterator<Item = &Problem> {
+        self.problems.iter().filter(|problem| problem.fix.is_some())
+    }
+}
+
+/// Check a volume without modifying it
+///
+/// Fails only if the MFT or $Bitmap cannot be read at all; everything else
+/// ends up in the report.
+pub fn check_volume<T: Read + Seek>(ntfs: &Ntfs, boot_sector: &BootSector, fs: &mut T) -> Result<CheckReport> {
+    let cluster_count = ntfs.size() / ntfs.cluster_size() as u64;
+    let mirrored = MIRRORED_RECORDS.max(boot_sector.cluster_size as u64 / boot_sector.file_record_size as u64);
+    let mut checker = Checker::new(cluster_count, mirrored);
+
+    let mut records = MftRecords::new(ntfs, fs)?;
+    for record in records.by_ref() {
+        checker.add_record(&record);
+    }
+    for record_number in records.skipped_records().to_vec() {
+        checker.add_damaged(record_number, "unreadable or without a FILE signature");
+    }
+
+    checker.check_mirror(fs, boot_sector.mft_mirror_offset(), boot_sector.file_record_size as usize);
+    checker.check_bitmap(&ClusterBitmap::load(ntfs, fs)?);
+    checker.check_indexes(fs, ntfs.cluster_size(), boot_sector.index_record_size);
+    Ok(checker.finish())
+}
+
+/// One entry of a directory index
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct IndexEntry {
+    pub(crate) reference: FileReference,
+    pub(crate) name: String,
+    pub(crate) location: IndexLocation,
+    pub(crate) has_subnode: bool,
+}
+
+/// Fragments of a non-resident attribute, or the value of a resident one
+#[derive(Debug, Clone, Default)]
+struct StreamParts {
+    present: bool,
+    resident_data: Option<Vec<u8>>,
+    runs: Vec<DataRun>,
+    data_size: u64,
+    initialized_size: u64,
+}
+
+impl StreamParts {
+    fn add(&mut self, fragment: &RawAttribute<'_>, runs: Vec<DataRun>) {
+        self.present = true;
+        if fragment.lowest_vcn() == 0 {
+            self.data_size = fragment.data_size();
+            self.initialized_size = fragment.initialized_size();
+        }
+        match fragment.is_resident() {
+            true => self.resident_data = Some(fragment.resident_value().to_vec()),
+            false => self.runs.extend(runs),
+        }
+    }
+
+    fn read<T: Read + Seek>(&self, fs: &mut T, cluster_size: u32) -> Result<Vec<u8>> {
+        let mut runs = self.runs.clone();
+        runs.sort_by_key(|run| run.vcn);
+        let stream = AttributeStream {
+            resident_data: self.resident_data.clone(),
+            runs,
+            data_size: self.data_size,
+            initialized_size: self.initialized_size,
+            ..AttributeStream::default()
+        };
+        StreamReader::new(fs, stream, cluster_size).read_all()
+    }
+}
+
+/// What the records of one file claim, merged across extension records
+#[derive(Debug, Clone, Default)]
+struct FileSummary {
+    has_base: bool,
+    sequence_number: u16,
+    link_count: u16,
+    is_directory: bool,
+    names: Vec<FileNameValue>,
+    index_root: Option<Vec<u8>>,
+    index_allocation: StreamParts,
+    index_bitmap: StreamParts,
+}
+
+/// State gathered during the check
+struct Checker {
+    cluster_count: u64,
+    mirrored: u64,
+    problems: Vec<Problem>,
+    records_checked: u64,
+    directories_checked: u64,
+    files: HashMap<u64, FileSummary>,
+    damaged: HashSet<u64>,
+    mirrored_records: Vec<FileRecord>,
+    runs: Vec<(u64, u64, u64)>,
+    used: ClusterBitmap,
+    shared: ClusterBitmap,
+}
+
+impl Checker {
+    fn new(cluster_count: u64, mirrored: u64) -> Self {
+        let empty = || ClusterBitmap::from_bytes(Vec::new(), cluster_count);
+        Self {
+            cluster_count,
+            mirrored,
+            problems: Vec::new(),
+            records_checked: 0,
+            directories_checked: 0,
+            files: HashMap::new(),
+            damaged: HashSet::new(),
+            mirrored_records: Vec::new(),
+            runs: Vec::new(),
+            used: empty(),
+            shared: empty(),
+        }
+    }
+
+    fn add_problem(&mut self, class: ProblemClass, record: Option<u64>, detail: String) {
+        self.problems.push(Problem::new(class, record, detail));
+    }
+
+    /// Report a record that cannot be read, whose contents are therefore unknown
+    fn add_damaged(&mut self, record: u64, detail: &str) {
+        self.damaged.insert(record);
+        self.add_problem(ProblemClass::BadRecord, Some(record), detail.to_string());
+    }
+
+    /// Collect what one MFT record claims
+    fn add_record(&mut self, record: &FileRecord) {
+        self.records_checked += 1;
+        let number = record.record_number();
+        if !record.is_valid() {
+            self.add_damaged(number, "fixups failed");
+            return;
+        }
+        if number < self.mirrored {
+            self.mirrored_records.push(record.clone());
+        }
+        if !record.is_in_use() {
+            return;
+        }
+
+        let base = record.base_record_number().unwrap_or(number);
+        let file = self.files.entry(base).or_default();
+        if record.is_base_record() {
+            file.has_base = true;
+            file.sequence_number = record.sequence_number();
+            file.link_count = record.hard_link_count();
+            file.is_directory = record.is_directory();
+        }
+
+        let mut clusters = Vec::new();
+        let mut errors = Vec::new();
+        for attribute in record.attributes() {
+            let attribute = match attribute {
+                Ok(attribute) => attribute,
+                Err(e) => {
+                    errors.push(e.to_string());
+                    break;
+                }
+            };
+            let runs = match attribute.is_resident() {
+                true => Vec::new(),
+                false => match attribute.data_runs() {
+                    Ok(runs) => runs,
+                    Err(e) => {
+                        errors.push(e.to_string());
+                        continue;
+                    }
+                },
+            };
+            clusters.extend(runs.iter().filter_map(|run| run.lcn.map(|lcn| (lcn, run.length))));
+
+            if attribute.is_type(NtfsAttributeType::FileName) {
+                match FileNameValue::parse(attribute.resident_value()) {
+                    Ok(name) => file.names.push(name),
+                    Err(e) => errors.push(e.to_string()),
+                }
+            } else if attribute.name() == DIRECTORY_INDEX {
+                if attribute.is_type(NtfsAttributeType::IndexRoot) {
+                    file.index_root = Some(attribute.resident_value().to_vec());
+                } else if attribute.is_type(NtfsAttributeType::IndexAllocation) {
+                    file.index_allocation.add(&attribute, runs);
+                } else if attribute.is_type(NtfsAttributeType::Bitmap) {
+                    file.index_bitmap.add(&attribute, runs);
+                }
+            }
+        }
+
+        for error in errors {
+            self.add_problem(ProblemClass::BadRecord, Some(number), error);
+        }
+        for (lcn, length) in clusters {
+            self.claim_clusters(base, lcn, length);
+        }
+    }
+
+    /// Mark clusters used by `record`, noting any already in use
+    fn claim_clusters(&mut self, record: u64, lcn: u64, length: u64) {
+        if lcn.saturating_add(length) > self.cluster_count {
+            self.add_problem(
+                ProblemClass::RunOutOfRange,
+                Some(record),
+                format!(
+                    "{} clusters at LCN {} past the end of the volume ({} clusters)",
+                    length, lcn, self.cluster_count
+                ),
+            );
+        }
+        // Only the part inside the volume, which the bitmaps count as allocated past its end
+        let length = length.min(self.cluster_count.saturating_sub(lcn));
+        if self.used.allocated_in_range(lcn, length) > 0 {
+            for lcn in lcn..lcn + length {
+                if self.used.is_allocated(lcn) {
+                    self.shared.mark_allocated(lcn, 1);
+                }
+            }
+        }
+        self.used.mark_allocated(lcn, length);
+        self.runs.push((record, lcn, length));
+    }
+
+    /// Compare the first MFT records with their copies in $MFTMirr
+    fn check_mirror<T: Read + Seek>(&mut self, fs: &mut T, mirror_offset: u64, record_size: usize) {
+        for record in std::mem::take(&mut self.mirrored_records) {
+            let number = record.record_number();
+            let mut mirror = vec![0u8; record_size];
+            let read = fs
+                .seek(SeekFrom::Start(mirror_offset + number * record_size as u64))
+                .and_then(|_| fs.read_exact(&mut mirror));
+
+            let copy = FileRecord::from_bytes(mirror, number, 0);
+            let detail = if read.is_err() {
+                "$MFTMirr copy cannot be read"
+            } else if !copy.is_valid() {
+                "$MFTMirr copy is damaged"
+            } else if copy.data() != record.data() {
+                "$MFTMirr copy differs from the MFT record"
+            } else {
+                continue;
+            };
+            self.problems.push(
+                Problem::new(ProblemClass::MirrorMismatch, Some(number), detail.to_string()).with_fix(Fix::SyncMirror),
+            );
+        }
+    }
+
+    /// Compare $Bitmap with the computed cluster usage
+    fn check_bitmap(&mut self, bitmap: &ClusterBitmap) {
+        // (class, first cluster, cluster count)
+        let mut extents: Vec<(ProblemClass, u64, u64)> = Vec::new();
+        let computed = self.used.as_bytes();
+
+        for (index, (stored, used)) in bitmap.as_bytes().iter().zip(computed).enumerate() {
+            if stored == used {
+                continue;
+            }
+            for bit in 0..8 {
+                let lcn = index as u64 * 8 + bit;
+                if lcn >= self.cluster_count {
+                    break;
+                }
+                let class = match (used >> bit & 1 != 0, stored >> bit & 1 != 0) {
+                    (true, false) => ProblemClass::UsedClustersMarkedFree,
+                    (false, true) => ProblemClass::FreeClustersMarkedUsed,
+                    _ => continue,
+                };
+                match extents.last_mut() {
+                    Some((last, start, length)) if *last == class && *start + *length == lcn => *length += 1,
+                    _ => extents.push((class, lcn, 1)),
+                }
+            }
+        }
+
+        // Clusters of a damaged record were not counted, so they may look unused
+        let damaged = self.problems.iter().any(|problem| problem.class == ProblemClass::BadRecord);
+        for (class, lcn, length) in extents {
+            let (state, fix) = match class {
+                ProblemClass::UsedClustersMarkedFree => {
+                    ("in use but free in $Bitmap", Some(Fix::MarkClustersUsed { lcn, length }))
+                }
+                _ => {
+                    ("allocated in $Bitmap but unused", (!damaged).then_some(Fix::MarkClustersFree { lcn, length }))
+                }
+            };
+            let mut problem = Problem::new(class, None, format!("{} clusters at LCN {} {}", length, lcn, state));
+            problem.fix = fix;
+            self.problems.push(problem);
+        }
+    }
+
+    /// Check every directory index against the records, and every name against its index
+    fn check_indexes<T: Read + Seek>(&mut self, fs: &mut T, cluster_size: u32, index_record_size: u32) {
+        let mut directories: Vec<u64> = self
+            .files
+            .iter()
+            .filter(|(_, file)| file.has_base && file.is_directory)
+            .map(|(number, _)| *number)
+            .collect();
+        directories.sort_unstable();
+
+        let mut problems = Vec::new();
+        let mut indexed: HashSet<(u64, String)> = HashSet::new();
+        let mut unreadable: HashSet<u64> = HashSet::new();
+
+        for directory in directories {
+            self.directories_checked += 1;
+            let entries = match self.directory_entries(fs, cluster_size, index_record_size, directory, &mut problems) {
+                Ok(entries) => entries,
+                Err(e) => {
+                    problems.push(Problem::new(ProblemClass::BadIndex, Some(directory), e.to_string()));
+                    unreadable.insert(directory);
+                    continue;
+                }
+            };
+
+            for entry in entries {
+                match self.check_entry(directory, &entry) {
+                    Some((class, detail)) => {
+                        let mut problem = Problem::new(class, Some(directory), detail);
+                        // Removing an entry with a subnode would need the B+ tree rebalanced,
+                        // and a damaged record may well be in use
+                        let damaged = self.damaged.contains(&entry.reference.record_number);
+                        if class == ProblemClass::DanglingIndexEntry && !entry.has_subnode && !damaged {
+                            problem.fix = Some(Fix::RemoveIndexEntry(entry.location));
+                        }
+                        problems.push(problem);
+                    }
+                    None => {
+                        indexed.insert((directory, entry.name));
+                    }
+                }
+            }
+        }
+
+        let mut files: Vec<(&u64, &FileSummary)> = self.files.iter().filter(|(_, file)| file.has_base).collect();
+        files.sort_unstable_by_key(|(number, _)| **number);
+
+        for (number, file) in files {
+            // A separate DOS name is a $FILE_NAME of its own and counts as a link, as in chkdsk
+            let links = file.names.len();
+            if links != file.link_count as usize {
+                let detail = format!("link count is {} but the record has {} names", file.link_count, links);
+                problems.push(
+                    Problem::new(ProblemClass::LinkCountMismatch, Some(*number), detail)
+                        .with_fix(Fix::SetLinkCount(links as u16)),
+                );
+            }
+
+            for name in &file.names {
+                let parent = name.parent.record_number;
+                let valid_parent = self.files.get(&parent).is_some_and(|directory| {
+                    directory.has_base
+                        && directory.is_directory
+                        && directory.sequence_number == name.parent.sequence_number
+                });
+
+                if !valid_parent {
+                    let detail = format!(
+                        "parent of '{}' is not an in-use directory (record {}, sequence {})",
+                        name.name, parent, name.parent.sequence_number
+                    );
+                    problems.push(Problem::new(ProblemClass::BadParent, Some(*number), detail));
+                } else if !unreadable.contains(&parent) && !indexed.contains(&(parent, name.name.clone())) {
+                    let detail = format!("'{}' is missing from the index of directory {}", name.name, parent);
+                    problems.push(Problem::new(ProblemClass::MissingIndexEntry, Some(*number), detail));
+                }
+            }
+        }
+
+        self.problems.extend(problems);
+    }
+
+    /// Check that an index entry points at a live record with that name in `directory`
+    fn check_entry(&self, directory: u64, entry: &IndexEntry) -> Option<(ProblemClass, String)> {
+        let target = entry.reference;
+        if self.damaged.contains(&target.record_number) {
+            return Some((
+                ProblemClass::DanglingIndexEntry,
+                format!("'{}' points to record {} which cannot be read", entry.name, target.record_number),
+            ));
+        }
+        let Some(file) = self.files.get(&target.record_number).filter(|file| file.has_base) else {
+            return Some((
+                ProblemClass::DanglingIndexEntry,
+                format!("'{}' points to record {} which is not in use", entry.name, target.record_number),
+            ));
+        };
+
+        if file.sequence_number != target.sequence_number {
+            return Some((
+                ProblemClass::StaleIndexEntry,
+                format!(
+                    "'{}' points to record {} with sequence {}, but the record has sequence {}",
+                    entry.name, target.record_number, target.sequence_number, file.sequence_number
+                ),
+            ));
+        }
+
+        let named = file
+            .names
+            .iter()
+            .any(|name| name.parent.record_number == directory && name.name == entry.name);
+        (!named).then(|| {
+            (
+                ProblemClass::IndexEntryNameMismatch,
+                format!("record {} has no name '{}' in this directory", target.record_number, entry.name),
+            )
+        })
+    }
+
+    /// Read every entry of a directory's $I30 index
+    ///
+    /// INDX blocks marked in use in the index bitmap but failing their checks
+    /// are reported and skipped, so the rest of the index is still checked.
+    fn directory_entries<T: Read + Seek>(
+        &self,
+        fs: &mut T,
+        cluster_size: u32,
+        index_record_size: u32,
+        directory: u64,
+        problems: &mut Vec<Problem>,
+    ) -> Result<Vec<IndexEntry>> {
+        let file = &self.files[&directory];
+        let root = file
+            .index_root
+            .as_ref()
+            .ok_or_else(|| SMNtfsError::ReadError("directory has no $I30 index root".to_string()))?;
+
+        let mut entries = Vec::new();
+        node_entries(root, 0x10, None, &mut entries)?;
+        if !file.index_allocation.present {
+            return Ok(entries);
+        }
+
+        let block_size = le_u32(root, 0x08);
+        if block_size != index_record_size || !block_size.is_power_of_two() || block_size < 512 {
+            return Err(SMNtfsError::ReadError(format!(
+                "index root has a block size of {} bytes, but the volume uses {}",
+                block_size, index_record_size
+            )));
+        }
+        let block_size = block_size as usize;
+        let mut allocation = file.index_allocation.read(fs, cluster_size)?;
+        let bitmap = match file.index_bitmap.present {
+            true => file.index_bitmap.read(fs, cluster_size)?,
+            false => Vec::new(),
+        };
+
+        for (index, block) in allocation.chunks_exact_mut(block_size).enumerate() {
+            let in_use = bitmap.get(index / 8).is_some_and(|byte| byte >> (index % 8) & 1 != 0);
+            if !in_use {
+                continue;
+            }
+            if &block[0..4] != b"INDX" || !apply_fixups(block) {
+                let detail = format!("INDX block {} is damaged", index);
+                problems.push(Problem::new(ProblemClass::BadIndex, Some(directory), detail));
+                continue;
+            }
+            if let Err(e) = node_entries(block, 0x18, Some(index as u64), &mut entries) {
+                let detail = format!("INDX block {}: {}", index, e);
+                problems.push(Problem::new(ProblemClass::BadIndex, Some(directory), detail));
+            }
+        }
+
+        Ok(entries)
+    }
+
+    /// Attribute cross-linked clusters to their records and build the report
+    fn finish(mut self) -> CheckReport {
+        if self.shared.allocated_clusters() > 0 {
+            let mut shared: BTreeMap<u64, u64> = BTreeMap::new();
+            for (record, lcn, length) in &self.runs {
+                let count = self.shared.allocated_in_range(*lcn, *length);
+                if count > 0 {
+                    *shared.entry(*record).or_insert(0) += count;
+                }
+            }
+            for (record, count) in shared {
+                self.add_problem(
+                    ProblemClass::CrossLinkedClusters,
+                    Some(record),
+                    format!("{} clusters are also used by another file", count),
+                );
+            }
+        }
+
+        CheckReport {
+            problems: self.problems,
+            records_checked: self.records_checked,
+            directories_checked: self.directories_checked,
+            computed_bitmap: self.used,
+        }
+    }
+}
+
+/// Collect the entries of one $I30 index node whose header is at `header`
+pub(crate) fn node_entries(
+    data: &[u8],
+    header: usize,
+    block: Option<u64>,
+    entries: &mut Vec<IndexEntry>,
+) -> Result<()> {
+    let mut position = header + le_u32(data, header) as usize;
+    let end = (header + le_u32(data, header + 0x04) as usize).min(data.len());
+
+    while position + 0x10 <= end {
+        let length = le_u16(data, position + 0x08) as usize;
+        let key_length = le_u16(data, position + 0x0A) as usize;
+        let flags = le_u16(data, position + 0x0C);
+        // The last entry of a node has no key
+        if flags & index_entry_flags::LAST != 0 {
+            break;
+        }
+        if length < 0x10 + key_length || position + length > end {
+            return Err(SMNtfsError::ReadError(format!(
+                "Invalid index entry length {} at offset {}",
+                length, position
+            )));
+        }
+
+        let key = FileNameValue::parse(&data[position + 0x10..position + 0x10 + key_length])?;
+        entries.push(IndexEntry {
+            reference: file_reference(le_u64(data, position)),
+            name: key.name,
+            location: IndexLocation { block, offset: position, length },
+            has_subnode: flags & index_entry_flags::SUBNODE != 0,
+        });
+        position += length;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::parser::record::record_flags;
+    use crate::parser::record::tests::{build_record, non_resident_attribute, resident_attribute};
+    use crate::parser::recovery::open_volume;
+    use crate::parser::values::namespace;
+    use crate::parser::values::tests::file_name_value;
+    use crate::parser::volume::tests::test_image;
+    use std::io::Cursor;
+
+    const FILE_NAME: u32 = 0x30;
+    const INDEX_ROOT: u32 = 0x90;
+    const INDEX_ALLOCATION: u32 = 0xA0;
+    const BITMAP: u32 = 0xB0;
+    const DATA: u32 = 0x80;
+
+    /// A record with the given attributes, sequence number and link count
+    fn record(number: u64, attributes: &[Vec<u8>], flags: u16, sequence: u16, links: u16) -> FileRecord {
+        let mut data = build_record(attributes, flags);
+        data[0x10..0x12].copy_from_slice(&sequence.to_le_bytes());
+        data[0x12..0x14].copy_from_slice(&links.to_le_bytes());
+        FileRecord::from_bytes(data, number, 0)
+    }
+
+    /// An $I30 index root holding `(file reference, name)` entries
+    pub(crate) fn index_root(entries: &[(u64, &str)]) -> Vec<u8> {
+        let mut nodes = Vec::new();
+        for (reference, name) in entries {
+            let key = file_name_value(5 | (5 << 48), name, namespace::WIN32);
+            let length = (0x10 + key.len()).next_multiple_of(8);
+            let mut entry = vec![0u8; length];
+            entry[0..8].copy_from_slice(&reference.to_le_bytes());
+            entry[8..10].copy_from_slice(&(length as u16).to_le_bytes());
+            entry[0x0A..0x0C].copy_from_slice(&(key.len() as u16).to_le_bytes());
+            entry[0x10..0x10 + key.len()].copy_from_slice(&key);
+            nodes.extend(entry);
+        }
+        let mut last = vec![0u8; 0x10];
+        last[8..10].copy_from_slice(&0x10u16.to_le_bytes());
+        last[0x0C..0x0E].copy_from_slice(&2u16.to_le_bytes());
+        nodes.extend(last);
+
+        let mut root = vec![0u8; 0x20];
+        root[0x08..0x0C].copy_from_slice(&4096u32.to_le_bytes());
+        root[0x10..0x14].copy_from_slice(&0x10u32.to_le_bytes());
+        root[0x14..0x18].copy_from_slice(&(0x10 + nodes.len() as u32).to_le_bytes());
+        root[0x18..0x1C].copy_from_slice(&(0x10 + nodes.len() as u32).to_le_bytes());
+        root.extend(nodes);
+        root
+    }
+
+    fn name(parent: u64, name: &str, namespace: u8) -> Vec<u8> {
+        resident_attribute(FILE_NAME, "", &file_name_value(parent, name, namespace))
+    }
+
+    fn classes(report: &CheckReport) -> Vec<ProblemClass> {
+        report.problems.iter().map(|problem| problem.class).collect()
+    }
+
+    #[test]
+    fn test_cluster_usage() {
+        let mut checker = Checker::new(64, 0);
+        // 4 clusters at LCN 10, and 4 at LCN 12 overlapping them
+        let sizes = (16384, 16384, 16384);
+        let first = non_resident_attribute(DATA, "", 0, sizes, &[0x11, 0x04, 0x0A]);
+        let second = non_resident_attribute(DATA, "", 0, sizes, &[0x11, 0x04, 0x0C]);
+        let beyond = non_resident_attribute(DATA, "", 0, sizes, &[0x11, 0x04, 0x3E]);
+        checker.add_record(&record(30, &[first], record_flags::IN_USE, 1, 0));
+        checker.add_record(&record(31, &[second], record_flags::IN_USE, 1, 0));
+        checker.add_record(&record(32, &[beyond], record_flags::IN_USE, 1, 0));
+        // Not in use: its clusters do not count
+        let stale = non_resident_attribute(DATA, "", 0, sizes, &[0x11, 0x04, 0x20]);
+        checker.add_record(&record(33, &[stale], 0, 1, 0));
+
+        // $Bitmap has LCN 10-13 and 40-41, but not 14-15 or 62-63
+        let mut bitmap = ClusterBitmap::from_bytes(Vec::new(), 64);
+        bitmap.mark_allocated(10, 4);
+        bitmap.mark_allocated(40, 2);
+        checker.check_bitmap(&bitmap);
+        let report = checker.finish();
+
+        assert_eq!(report.computed_bitmap.allocated_clusters(), 8);
+        assert_eq!(
+            classes(&report),
+            vec![
+                ProblemClass::RunOutOfRange,
+                ProblemClass::UsedClustersMarkedFree,
+                ProblemClass::FreeClustersMarkedUsed,
+                ProblemClass::UsedClustersMarkedFree,
+                ProblemClass::CrossLinkedClusters,
+                ProblemClass::CrossLinkedClusters,
+            ]
+        );
+        assert_eq!(report.problems[1].detail, "2 clusters at LCN 14 in use but free in $Bitmap");
+        assert_eq!(report.problems[4].record, Some(30));
+        assert_eq!(report.counts()[&ProblemClass::CrossLinkedClusters], 2);
+    }
+
+    #[test]
+    fn test_directory_index() {
+        let mut checker = Checker::new(64, 0);
+        let root = index_root(&[
+            (5 | (5 << 48), "."),
+            (20 | (1 << 48), "kept.txt"),
+            (20 | (1 << 48), "KEPT.TXT"),
+            (21 | (1 << 48), "deleted.txt"),
+            (22 | (1 << 48), "reused.txt"),
+            (23 | (1 << 48), "renamed.txt"),
+        ]);
+        let directory = [
+            name(5 | (5 << 48), ".", namespace::WIN32_AND_DOS),
+            resident_attribute(INDEX_ROOT, DIRECTORY_INDEX, &root),
+        ];
+        let in_use = record_flags::IN_USE;
+        checker.add_record(&record(5, &directory, in_use | record_flags::DIRECTORY, 5, 1));
+        // Long and short name are two $FILE_NAME attributes, so two links
+        let names = [
+            name(5 | (5 << 48), "kept.txt", namespace::WIN32),
+            name(5 | (5 << 48), "KEPT.TXT", namespace::DOS),
+        ];
+        checker.add_record(&record(20, &names, in_use, 1, 2));
+        checker.add_record(&record(21, &[name(5 | (5 << 48), "deleted.txt", namespace::WIN32)], 0, 1, 1));
+        checker.add_record(&record(22, &[name(5 | (5 << 48), "reused.txt", namespace::WIN32)], in_use, 2, 1));
+        checker.add_record(&record(23, &[name(5 | (5 << 48), "new.txt", namespace::WIN32)], in_use, 1, 2));
+        checker.add_record(&record(24, &[name(99 | (1 << 48), "orphan", namespace::WIN32)], in_use, 1, 1));
+
+        checker.check_indexes(&mut Cursor::new(Vec::new()), 4096, 4096);
+        let report = checker.finish();
+
+        assert_eq!(report.directories_checked, 1);
+        let problems: Vec<(ProblemClass, Option<u64>)> =
+            report.problems.iter().map(|problem| (problem.class, problem.record)).collect();
+        assert_eq!(
+            problems,
+            vec![
+                (ProblemClass::DanglingIndexEntry, Some(5)),
+                (ProblemClass::StaleIndexEntry, Some(5)),
+                (ProblemClass::IndexEntryNameMismatch, Some(5)),
+                (ProblemClass::MissingIndexEntry, Some(22)),
+                (ProblemClass::LinkCountMismatch, Some(23)),
+                (ProblemClass::MissingIndexEntry, Some(23)),
+                (ProblemClass::BadParent, Some(24)),
+            ]
+        );
+        assert_eq!(report.problems[4].fix, Some(Fix::SetLinkCount(1)));
+    }
+
+    #[test]
+    fn test_bad_index_block_size() {
+        for block_size in [2u32, 1024] {
+            let mut checker = Checker::new(64, 0);
+            let mut root = index_root(&[(5 | (5 << 48), ".")]);
+            root[0x08..0x0C].copy_from_slice(&block_size.to_le_bytes());
+            let sizes = (4096, 4096, 4096);
+            let directory = [
+                name(5 | (5 << 48), ".", namespace::WIN32_AND_DOS),
+                resident_attribute(INDEX_ROOT, DIRECTORY_INDEX, &root),
+                non_resident_attribute(INDEX_ALLOCATION, DIRECTORY_INDEX, 0, sizes, &[0x11, 0x01, 0x02]),
+                resident_attribute(BITMAP, DIRECTORY_INDEX, &[0xFF; 8]),
+            ];
+            checker.add_record(&record(5, &directory, record_flags::IN_USE | record_flags::DIRECTORY, 5, 1));
+
+            checker.check_indexes(&mut Cursor::new(vec![0u8; 16 * 4096]), 4096, 4096);
+            let report = checker.finish();
+            assert_eq!(classes(&report), vec![ProblemClass::BadIndex]);
+            assert!(report.problems[0].detail.contains(&format!("block size of {} bytes", block_size)));
+        }
+    }
+
+    #[test]
+    fn test_entry_of_damaged_record() {
+        let mut checker = Checker::new(64, 0);
+        let root = index_root(&[(5 | (5 << 48), "."), (20 | (1 << 48), "torn.txt"), (21 | (1 << 48), "deleted.txt")]);
+        let directory = [
+            name(5 | (5 << 48), ".", namespace::WIN32_AND_DOS),
+            resident_attribute(INDEX_ROOT, DIRECTORY_INDEX, &root),
+        ];
+        checker.add_record(&record(5, &directory, record_flags::IN_USE | record_flags::DIRECTORY, 5, 1));
+        let mut torn = build_record(&[name(5 | (5 << 48), "torn.txt", namespace::WIN32)], record_flags::IN_USE);
+        torn[1022] ^= 0xFF;
+        checker.add_record(&FileRecord::from_bytes(torn, 20, 0));
+
+        checker.check_indexes(&mut Cursor::new(Vec::new()), 4096, 4096);
+        let report = checker.finish();
+
+        assert_eq!(
+            classes(&report),
+            vec![ProblemClass::BadRecord, ProblemClass::DanglingIndexEntry, ProblemClass::DanglingIndexEntry]
+        );
+        // Only the entry of the free record may be removed
+        assert_eq!(report.problems[1].detail, "'torn.txt' points to record 20 which cannot be read");
+        assert_eq!(report.problems[1].fix, None);
+        assert!(matches!(report.problems[2].fix, Some(Fix::RemoveIndexEntry(_))));
+    }
+
+    #[test]
+    fn test_clean_volume() {
+        let opened = open_volume(Cursor::new(test_image())).unwrap();
+        let (mut fs, ntfs) = (opened.fs, opened.ntfs);
+        let report = check_volume(&ntfs, &opened.boot_sector, &mut fs).unwrap();
+
+        assert!(report.is_clean(), "{:?}", report.problems);
+        // The root, $Extend, many_subdirs and its 512 subdirectories
+        assert_eq!(report.directories_checked, 515);
+    }
+
+    #[test]
+    fn test_records_and_mirror() {
+        let mut checker = Checker::new(64, 4);
+        let mut records: Vec<Vec<u8>> = (0..4).map(|_| build_record(&[], record_flags::IN_USE)).collect();
+        for (i, data) in records.iter_mut().enumerate() {
+            data[0x2C..0x30].copy_from_slice(&(i as u32).to_le_bytes());
+        }
+
+        let mut mirror: Vec<u8> = records.concat();
+        // $LogFile differs in its copy, $Volume's copy is torn
+        mirror[2 * 1024 + 0x100] = 0xAA;
+        mirror[3 * 1024 + 510] ^= 0xFF;
+        let mut torn = build_record(&[], record_flags::IN_USE);
+        torn[1022] ^= 0xFF;
+
+        for (i, data) in records.into_iter().enumerate() {
+            checker.add_record(&FileRecord::from_bytes(data, i as u64, 0));
+        }
+        checker.add_record(&FileRecord::from_bytes(torn, 40, 0));
+        checker.check_mirror(&mut Cursor::new(mirror), 0, 1024);
+        let report = checker.finish();
+
+        assert_eq!(report.records_checked, 5);
+        assert_eq!(
+            classes(&report),
+            vec![ProblemClass::BadRecord, ProblemClass::MirrorMismatch, ProblemClass::MirrorMismatch]
+        );
+        assert_eq!(report.problems[1].record, Some(2));
+        assert_eq!(report.problems[2].detail, "$MFTMirr copy is damaged");
+        assert!(!report.is_clean());
+    }
+}
@@ -0,0 +1,268 @@
+//! Extended attributes ($EA and $EA_INFORMATION)
+//!
+//! $EA holds a packed list of OS/2-style `FILE_FULL_EA_INFORMATION` entries:
+//! the offset of the next entry (0 for the last), flags, the name and value
+//! lengths, then the ASCII name with a terminating NUL and the value. Entries
+//! are 4-byte aligned. $EA_INFORMATION summarises them for Windows.
+//!
+//! WSL keeps POSIX metadata of files it creates in `$LXUID`, `$LXGID`,
+//! `$LXMOD` (a full `st_mode`, file type included) and `$LXDEV` (major and
+//! minor numbers of device files), each a little-endian `u32` or pair of
+//! them.
+
+use std::io::{Read, Seek};
+use ntfs::{Ntfs, NtfsAttributeType, NtfsFile};
+use crate::parser::attribute::AttributeStream;
+use crate::parser::permissions::PosixPermissions;
+use crate::parser::reader::StreamReader;
+use crate::utils::bytes::{le_u16, le_u32, le_u8};
+use crate::utils::error::{Result, SMNtfsError};
+
+/// Extended attribute flags
+pub mod ea_flags {
+    /// The file cannot be interpreted correctly without this attribute
+    pub const NEED_EA: u8 = 0x80;
+}
+
+/// WSL extended attribute names
+pub mod lx {
+    pub const UID: &str = "$LXUID";
+    pub const GID: &str = "$LXGID";
+    pub const MODE: &str = "$LXMOD";
+    pub const DEV: &str = "$LXDEV";
+}
+
+/// Size of an entry before its name
+const ENTRY_HEADER_SIZE: usize = 8;
+
+/// One extended attribute
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExtendedAttribute {
+    /// Attribute name
+    pub name: String,
+
+    /// Flags, see [`ea_flags`]
+    pub flags: u8,
+
+    /// Raw value
+    pub value: Vec<u8>,
+}
+
+impl ExtendedAttribute {
+    /// Check if the file depends on this attribute
+    pub fn is_needed(&self) -> bool {
+        self.flags & ea_flags::NEED_EA != 0
+    }
+
+    /// Decode a list of entries from a $EA value
+    pub fn parse_all(data: &[u8]) -> Result<Vec<Self>> {
+        let mut attributes = Vec::new();
+        let mut offset = 0;
+
+        while offset + ENTRY_HEADER_SIZE <= data.len() {
+            let next = le_u32(data, offset) as usize;
+            let name_length = le_u8(data, offset + 5) as usize;
+            let value_length = le_u16(data, offset + 6) as usize;
+            let name_start = offset + ENTRY_HEADER_SIZE;
+            let value_start = name_start + name_length + 1;
+            let value = data.get(value_start..value_start + value_length).ok_or_else(|| {
+                SMNtfsError::ReadError(format!("Extended attribute at offset {} is truncated", offset))
+            })?;
+
+            attributes.push(Self {
+                name: String::from_utf8_lossy(&data[name_start..name_start + name_length]).into_owned(),
+                flags: le_u8(data, offset + 4),
+                value: value.to_vec(),
+            });
+
+            if next == 0 {
+                break;
+            }
+            offset += next;
+        }
+
+        Ok(attributes)
+    }
+}
+
+/// Summary of a file's extended attributes from $EA_INFORMATION
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EaInformation {
+    /// Size of the attributes packed as `FILE_FULL_EA_INFORMATION`
+    pub packed_size: u16,
+
+    /// Number of attributes with [`ea_flags::NEED_EA`]
+    pub need_ea_count: u16,
+
+    /// Size of the $EA value
+    pub unpacked_size: u32,
+}
+
+impl EaInformation {
+    /// Decode a $EA_INFORMATION value
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        if data.len() < 8 {
+            return Err(SMNtfsError::ReadError(format!(
+                "Truncated $EA_INFORMATION of {} bytes",
+                data.len()
+            )));
+        }
+        Ok(Self {
+            packed_size: le_u16(data, 0),
+            need_ea_count: le_u16(data, 2),
+            unpacked_size: le_u32(data, 4),
+        })
+    }
+}
+
+/// POSIX metadata WSL stored in extended attributes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WslMetadata {
+    /// Owner user ID
+    pub uid: Option<u32>,
+
+    /// Owner group ID
+    pub gid: Option<u32>,
+
+    /// Full `st_mode`, file type included
+    pub mode: Option<u32>,
+
+    /// Major and minor number of a device file
+    pub device: Option<(u32, u32)>,
+}
+
+impl WslMetadata {
+    /// Collect the `$LX*` attributes, `None` if there are none
+    pub fn from_attributes(attributes: &[ExtendedAttribute]) -> Option<Self> {
+        let mut metadata = Self::default();
+        let value = |attribute: &ExtendedAttribute| {
+            (attribute.value.len() >= 4).then(|| le_u32(&attribute.value, 0))
+        };
+
+        for attribute in attributes {
+            match attribute.name.as_str() {
+                lx::UID => metadata.uid = value(attribute),
+                lx::GID => metadata.gid = value(attribute),
+                lx::MODE => metadata.mode = value(attribute),
+                lx::DEV if attribute.value.len() >= 8 => {
+                    metadata.device = Some((le_u32(&attribute.value, 0), le_u32(&attribute.value, 4)))
+                }
+                _ => {}
+            }
+        }
+
+        (metadata != Self::default()).then_some(metadata)
+    }
+
+    /// File type bits of the mode (`S_IFMT`)
+    pub fn file_type(&self) -> Option<u32> {
+        self.mode.map(|mode| mode & 0o170000)
+    }
+
+    /// Device number in the Linux `dev_t` encoding
+    pub fn rdev(&self) -> Option<u64> {
+        self.device.map(|(major, minor)| {
+            let (major, minor) = (major as u64, minor as u64);
+            ((major & 0xFFFF_F000) << 32) | ((major & 0xFFF) << 8) | ((minor & 0xFFFF_FF00) << 12) | (minor & 0xFF)
+        })
+    }
+
+    /// Override permissions derived from the security descriptor with what WSL set
+    pub fn apply(&self, permissions: PosixPermissions) -> PosixPermissions {
+        PosixPermissions {
+            uid: self.uid.unwrap_or(permissions.uid),
+            gid: self.gid.unwrap_or(permissions.gid),
+            mode: self.mode.map_or(permissions.mode, |mode| mode & 0o7777),
+        }
+    }
+}
+
+/// Read the extended attributes of a file, empty if it has none
+pub fn read_extended_attributes<T: Read + Seek>(
+    ntfs: &Ntfs,
+    file: &NtfsFile,
+    fs: &mut T,
+) -> Result<Vec<ExtendedAttribute>> {
+    let Some(stream) = AttributeStream::collect(file, fs, NtfsAttributeType::EA, "")? else {
+        return Ok(Vec::new());
+    };
+    let data = StreamReader::new(fs, stream, ntfs.cluster_size()).read_all()?;
+    ExtendedAttribute::parse_all(&data)
+}
+
+/// Read the $EA_INFORMATION of a file, if it has one
+pub fn read_ea_information<T: Read + Seek>(ntfs: &Ntfs, file: &NtfsFile, fs: &mut T) -> Result<Option<EaInformation>> {
+    let Some(stream) = AttributeStream::collect(file, fs, NtfsAttributeType::EAInformation, "")? else {
+        return Ok(None);
+    };
+    let data = StreamReader::new(fs, stream, ntfs.cluster_size()).read_all()?;
+    EaInformation::parse(&data).map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Pack entries into a $EA value
+    fn ea_value(entries: &[(&str, u8, &[u8])]) -> Vec<u8> {
+        let mut data = Vec::new();
+        for (i, (name, flags, value)) in entries.iter().enumerate() {
+            let mut entry = vec![0u8; ENTRY_HEADER_SIZE];
+            entry[4] = *flags;
+            entry[5] = name.len() as u8;
+            entry[6..8].copy_from_slice(&(value.len() as u16).to_le_bytes());
+            entry.extend_from_slice(name.as_bytes());
+            entry.push(0);
+            entry.extend_from_slice(value);
+            entry.resize(entry.len().next_multiple_of(4), 0);
+            if i + 1 < entries.len() {
+                let next = entry.len() as u32;
+                entry[0..4].copy_from_slice(&next.to_le_bytes());
+            }
+            data.extend(entry);
+        }
+        data
+    }
+
+    #[test]
+    fn test_parse_entries() {
+        let data = ea_value(&[(".KEEP", ea_flags::NEED_EA, b"x"), ("user.comment", 0, b"hello")]);
+        let attributes = ExtendedAttribute::parse_all(&data).unwrap();
+
+        assert_eq!(attributes.len(), 2);
+        assert!(attributes[0].is_needed());
+        assert_eq!(attributes[1].name, "user.comment");
+        assert_eq!(attributes[1].value, b"hello");
+        assert!(ExtendedAttribute::parse_all(&data[..data.len() - 8]).is_err());
+        assert_eq!(WslMetadata::from_attributes(&attributes), None);
+    }
+
+    #[test]
+    fn test_wsl_metadata() {
+        let mut device = 8u32.to_le_bytes().to_vec();
+        device.extend_from_slice(&300u32.to_le_bytes());
+        let data = ea_value(&[
+            (lx::UID, 0, &1000u32.to_le_bytes()),
+            (lx::GID, 0, &100u32.to_le_bytes()),
+            (lx::MODE, 0, &0o060640u32.to_le_bytes()),
+            (lx::DEV, 0, &device),
+        ]);
+        let metadata = WslMetadata::from_attributes(&ExtendedAttribute::parse_all(&data).unwrap()).unwrap();
+
+        assert_eq!(metadata.uid, Some(1000));
+        assert_eq!(metadata.file_type(), Some(0o060000));
+        // makedev(8, 300)
+        assert_eq!(metadata.rdev(), Some(0x10082C));
+
+        let permissions = PosixPermissions { uid: 0, gid: 0, mode: 0o777 };
+        assert_eq!(metadata.apply(permissions), PosixPermissions { uid: 1000, gid: 100, mode: 0o640 });
+    }
+
+    #[test]
+    fn test_ea_information() {
+        let info = EaInformation::parse(&[0x20, 0, 1, 0, 0x24, 0, 0, 0]).unwrap();
+        assert_eq!(info.need_ea_count, 1);
+        assert_eq!(info.unpacked_size, 0x24);
+        assert!(EaInformation::parse(&[0; 4]).is_err());
+    }
+}
@@ -0,0 +1,136 @@
+//! Extent maps: where each stream of a file lives on disk
+//!
+//! Extents are the data runs of every non-resident attribute, with
+//! physically contiguous runs merged and sparse holes kept as extents
+//! without an LCN.
+
+use std::io::{Read, Seek};
+use ntfs::NtfsFile;
+use crate::parser::attribute::AttributeStream;
+use crate::parser::record::attribute_type_name;
+use crate::parser::runs::{coalesce_runs, DataRun};
+use crate::utils::error::Result;
+
+/// The extents of one non-resident attribute
+#[derive(Debug, Clone)]
+pub struct StreamExtents {
+    /// Attribute type code
+    pub type_code: u32,
+
+    /// Attribute name (empty for unnamed attributes)
+    pub name: String,
+
+    /// Cluster size of the volume in bytes
+    pub cluster_size: u32,
+
+    /// Logical size of the attribute value
+    pub data_size: u64,
+
+    /// Extents ordered by VCN; sparse holes have no LCN
+    pub extents: Vec<DataRun>,
+}
+
+impl StreamExtents {
+    /// Build from a collected attribute (resident attributes have no extents)
+    pub fn from_stream(stream: &AttributeStream, cluster_size: u32) -> Self {
+        Self {
+            type_code: stream.type_code,
+            name: stream.name.clone(),
+            cluster_size,
+            data_size: stream.data_size,
+            extents: coalesce_runs(&stream.runs),
+        }
+    }
+
+    /// Attribute type name and stream name, e.g. `$DATA` or `$DATA:Zone.Identifier`
+    pub fn display_name(&self) -> String {
+        let type_name = attribute_type_name(self.type_code);
+        if self.name.is_empty() {
+            type_name.to_string()
+        } else {
+            format!("{}:{}", type_name, self.name)
+        }
+    }
+
+    /// Extents backed by clusters on disk
+    pub fn allocated_extents(&self) -> impl Iterator<Item = &DataRun> {
+        self.extents.iter().filter(|extent| !extent.is_sparse())
+    }
+
+    /// Sparse holes
+    pub fn holes(&self) -> impl Iterator<Item = &DataRun> {
+        self.extents.iter().filter(|extent| extent.is_sparse())
+    }
+
+    /// Number of allocated extents (what `filefrag` reports)
+    pub fn extent_count(&self) -> usize {
+        self.allocated_extents().count()
+    }
+
+    /// Clusters allocated on disk
+    pub fn allocated_clusters(&self) -> u64 {
+        self.allocated_extents().map(|extent| extent.length).sum()
+    }
+
+    /// Map a logical cluster back to the VCN of this stream that uses it
+    pub fn vcn_of_lcn(&self, lcn: u64) -> Option<u64> {
+        self.allocated_extents().find_map(|extent| {
+            let start = extent.lcn?;
+            (lcn >= start && lcn < start + extent.length).then(|| extent.vcn + (lcn - start))
+        })
+    }
+}
+
+/// Get the extents of every non-resident attribute of a file
+///
+/// This includes named streams, directory indexes and a non-resident
+/// $ATTRIBUTE_LIST. Attributes split across extension records are merged.
+pub fn file_extents<T: Read + Seek>(file: &NtfsFile, fs: &mut T) -> Result<Vec<StreamExtents>> {
+    let cluster_size = file.ntfs().cluster_size();
+    let extents = AttributeStream::collect_all(file, fs)?
+        .iter()
+        .filter(|stream| !stream.is_resident())
+        .map(|stream| StreamExtents::from_stream(stream, cluster_size))
+        .collect();
+
+    Ok(extents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sparse_stream() -> AttributeStream {
+        AttributeStream {
+            type_code: 0x80,
+            name: "ads".to_string(),
+            runs: vec![
+                DataRun { vcn: 0, lcn: Some(100), length: 2 },
+                DataRun { vcn: 2, lcn: Some(102), length: 2 },
+                DataRun { vcn: 4, lcn: None, length: 8 },
+                DataRun { vcn: 12, lcn: Some(50), length: 1 },
+            ],
+            ..AttributeStream::default()
+        }
+    }
+
+    #[test]
+    fn test_extents_merge_and_holes() {
+        let extents = StreamExtents::from_stream(&sparse_stream(), 4096);
+
+        assert_eq!(extents.display_name(), "$DATA:ads");
+        assert_eq!(extents.extents.len(), 3);
+        assert_eq!(extents.extent_count(), 2);
+        assert_eq!(extents.holes().count(), 1);
+        assert_eq!(extents.allocated_clusters(), 5);
+    }
+
+    #[test]
+    fn test_vcn_of_lcn() {
+        let extents = StreamExtents::from_stream(&sparse_stream(), 4096);
+
+        assert_eq!(extents.vcn_of_lcn(103), Some(3));
+        assert_eq!(extents.vcn_of_lcn(50), Some(12));
+        assert_eq!(extents.vcn_of_lcn(104), None);
+    }
+}
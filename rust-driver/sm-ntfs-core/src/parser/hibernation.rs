@@ -0,0 +1,137 @@
+//! Hibernated Windows and Fast Startup detection
+//!
+//! When Windows hibernates, and at every shut down while Fast Startup is
+//! enabled (the default since Windows 8), it saves its memory to
+//! hiberfil.sys instead of unmounting its volumes. Their state stays cached
+//! in the saved memory, so anything another system writes is overwritten
+//! or corrupts the volume when Windows resumes. Writing is therefore refused
+//! while the hibernation file holds a saved system, or while $LogFile still
+//! has transactions to apply, unless the caller asks for the hibernation
+//! file to be discarded.
+//!
+//! A saved system is recognised by the first four bytes of the file: `hibr`
+//! once hibernated and `rstr` while a resume is in progress, in either case.
+//! Windows replaces them with `wake` or zeros after resuming.
+
+use std::io::{Read, Seek, Write};
+use crate::parser::volume::NtfsVolume;
+use crate::utils::error::{Result, SMNtfsError};
+
+/// Path of the hibernation file
+pub const HIBERFIL_PATH: &str = "/hiberfil.sys";
+
+/// Bytes of the hibernation file header zeroed to discard it
+pub const HIBERFIL_HEADER_SIZE: u64 = 4096;
+
+/// Signatures of a hibernation file holding a saved system
+const HIBERNATED_SIGNATURES: [&[u8; 4]; 2] = [b"hibr", b"rstr"];
+
+/// Check if a hibernation file header starts with a saved-system signature
+pub fn is_hibernated_signature(header: &[u8]) -> bool {
+    header.len() >= 4
+        && HIBERNATED_SIGNATURES
+            .iter()
+            .any(|signature| header[..4].eq_ignore_ascii_case(&signature[..]))
+}
+
+/// Check if the volume holds the hibernation file of a saved system
+pub fn is_hibernated<T: Read + Seek>(volume: &NtfsVolume, fs: &mut T) -> Result<bool> {
+    let mut reader = match volume.open_stream(fs, HIBERFIL_PATH) {
+        Ok(reader) => reader,
+        Err(SMNtfsError::PathNotFound { .. }) => return Ok(false),
+        Err(e) => return Err(e),
+    };
+
+    let mut header = [0u8; 4];
+    let length = reader.len().min(header.len() as u64) as usize;
+    reader.read_exact(&mut header[..length])?;
+    Ok(is_hibernated_signature(&header))
+}
+
+/// Zero the header of the hibernation file so Windows boots afresh
+///
+/// The saved system is lost, including unsaved work in programs left open.
+/// Returns `false` if there is no hibernation file.
+pub fn discard_hibernation<T: Read + Write + Seek>(volume: &NtfsVolume, fs: &mut T) -> Result<bool> {
+    let stream = match volume.open_stream(fs, HIBERFIL_PATH) {
+        Ok(reader) => reader.stream().clone(),
+        Err(SMNtfsError::PathNotFound { .. }) => return Ok(false),
+        Err(e) => return Err(e),
+    };
+    if stream.is_resident() || stream.is_compressed() || stream.is_encrypted() {
+        return Err(SMNtfsError::WriteError(format!(
+            "{} is resident, compressed or encrypted and cannot be discarded",
+            HIBERFIL_PATH
+        )));
+    }
+
+    let length = stream.initialized_size.min(HIBERFIL_HEADER_SIZE) as usize;
+    stream.write_at(fs, 0, &vec![0u8; length], volume.cluster_size())?;
+    fs.flush()?;
+    tracing::warn!("Discarded the hibernated Windows session in {}", HIBERFIL_PATH);
+    Ok(true)
+}
+
+/// Refuse writing to a volume that a hibernated Windows may resume on
+///
+/// With `remove_hiberfile`, the hibernation file is discarded instead.
+pub fn ensure_not_hibernated<T: Read + Write + Seek>(
+    volume: &NtfsVolume,
+    fs: &mut T,
+    remove_hiberfile: bool,
+) -> Result<()> {
+    if is_hibernated(volume, fs)? {
+        if !remove_hiberfile {
+            return Err(hibernated_error());
+        }
+        discard_hibernation(volume, fs)?;
+    }
+    Ok(())
+}
+
+/// Refuse writing to a volume that is hibernated or has $LogFile
+/// transactions left to apply
+///
+/// With `remove_hiberfile`, the hibernation file is discarded instead, but
+/// only once $LogFile is known to be clean, so nothing is written when the
+/// volume is refused.
+pub fn ensure_writable<T: Read + Write + Seek>(volume: &NtfsVolume, fs: &mut T, remove_hiberfile: bool) -> Result<()> {
+    let hibernated = is_hibernated(volume, fs)?;
+    if hibernated && !remove_hiberfile {
+        return Err(hibernated_error());
+    }
+
+    let report = volume.logfile_report(fs)?;
+    if !report.is_clean() {
+        return Err(SMNtfsError::UncleanShutdown(format!(
+            "$LogFile has {} transactions to redo and {} to undo",
+            report.committed().count(),
+            report.uncommitted().count()
+        )));
+    }
+
+    if hibernated {
+        discard_hibernation(volume, fs)?;
+    }
+    Ok(())
+}
+
+fn hibernated_error() -> SMNtfsError {
+    SMNtfsError::VolumeHibernated(format!("{} holds a saved Windows session", HIBERFIL_PATH))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hibernated_signature() {
+        assert!(is_hibernated_signature(b"hibr\0\0\0\0"));
+        assert!(is_hibernated_signature(b"HIBR"));
+        assert!(is_hibernated_signature(b"RSTR"));
+        assert!(!is_hibernated_signature(b"wake"));
+        assert!(!is_hibernated_signature(b"WAKE"));
+        assert!(!is_hibernated_signature(&[0u8; 4]));
+        assert!(!is_hibernated_signature(b"hib"));
+    }
+}
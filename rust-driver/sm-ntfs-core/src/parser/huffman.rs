@@ -0,0 +1,176 @@
+//! Canonical Huffman decoding shared by the XPRESS and LZX decompressors
+//!
+//! Both formats transmit only code lengths; codes are assigned in order of
+//! length, then symbol. Bits are read most significant first from 16-bit
+//! little-endian words.
+
+use crate::utils::bytes::le_u16;
+use crate::utils::error::{Result, SMNtfsError};
+
+/// Lookup table indexed by the next `bits` bits of input
+#[derive(Debug, Clone)]
+pub(crate) struct HuffmanTable {
+    /// `(symbol, code length)` per index; a length of 0 marks an unused code
+    entries: Vec<(u16, u8)>,
+    bits: u32,
+}
+
+impl HuffmanTable {
+    /// Build a table from code lengths, none longer than `bits`
+    ///
+    /// Incomplete codes are accepted (unused entries fail to decode), which
+    /// covers empty trees; over-subscribed ones are rejected.
+    pub(crate) fn new(lengths: &[u8], bits: u32) -> Result<Self> {
+        let mut entries = vec![(0u16, 0u8); 1 << bits];
+        let mut code = 0usize;
+
+        for length in 1..=bits {
+            for (symbol, _) in lengths.iter().enumerate().filter(|(_, l)| **l as u32 == length) {
+                let span = 1usize << (bits - length);
+                let start = code << (bits - length);
+                if start + span > entries.len() {
+                    return Err(SMNtfsError::ReadError("Over-subscribed Huffman code".to_string()));
+                }
+                entries[start..start + span].fill((symbol as u16, length as u8));
+                code += 1;
+            }
+            code <<= 1;
+        }
+
+        if lengths.iter().any(|l| *l as u32 > bits) {
+            return Err(SMNtfsError::ReadError(format!("Huffman code longer than {} bits", bits)));
+        }
+        Ok(Self { entries, bits })
+    }
+
+    /// Decode the symbol at the top of `peeked`, the next `bits` bits of input
+    ///
+    /// Returns the symbol and the number of bits it used.
+    pub(crate) fn decode(&self, peeked: u32) -> Result<(u16, u32)> {
+        match self.entries[(peeked as usize) & ((1 << self.bits) - 1)] {
+            (_, 0) => Err(SMNtfsError::ReadError("Invalid Huffman code".to_string())),
+            (symbol, length) => Ok((symbol, length as u32)),
+        }
+    }
+}
+
+/// MSB-first reader over 16-bit little-endian words
+///
+/// Input past the end reads as zero bits; decoders bound their output
+/// instead. After every read fewer than 16 bits stay buffered, all from the
+/// last word loaded.
+pub(crate) struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+    buffer: u64,
+    count: u32,
+}
+
+impl<'a> BitReader<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            position: 0,
+            buffer: 0,
+            count: 0,
+        }
+    }
+
+    fn fill(&mut self, bits: u32) {
+        while self.count < bits {
+            self.buffer = (self.buffer << 16) | le_u16(self.data, self.position) as u64;
+            self.position += 2;
+            self.count += 16;
+        }
+    }
+
+    /// Look at the next `bits` bits (at most 16) without consuming them
+    pub(crate) fn peek(&mut self, bits: u32) -> u32 {
+        self.fill(bits);
+        ((self.buffer >> (self.count - bits)) & ((1 << bits) - 1)) as u32
+    }
+
+    /// Consume `bits` bits that were peeked
+    pub(crate) fn consume(&mut self, bits: u32) {
+        self.count -= bits;
+    }
+
+    /// Read `bits` bits (at most 32)
+    pub(crate) fn read(&mut self, bits: u32) -> u32 {
+        if bits > 16 {
+            let high = self.read(bits - 16);
+            return (high << 16) | self.read(16);
+        }
+        let value = self.peek(bits);
+        self.consume(bits);
+        value
+    }
+
+    /// Decode one symbol
+    pub(crate) fn decode(&mut self, table: &HuffmanTable) -> Result<u16> {
+        let (symbol, length) = table.decode(self.peek(table.bits))?;
+        self.consume(length);
+        Ok(symbol)
+    }
+
+    /// Drop the buffered bits and move to the next word boundary
+    ///
+    /// If no bits are buffered, a whole word of padding is skipped, as LZX
+    /// requires before an uncompressed block.
+    pub(crate) fn align(&mut self) {
+        if self.count == 0 {
+            self.position += 2;
+        }
+        self.count = 0;
+        self.buffer = 0;
+    }
+
+    /// Take `length` bytes at the current (aligned) position
+    pub(crate) fn bytes(&mut self, length: usize) -> Result<&'a [u8]> {
+        let bytes = self
+            .data
+            .get(self.position..self.position + length)
+            .ok_or_else(|| SMNtfsError::ReadError("Compressed data is truncated".to_string()))?;
+        self.position += length;
+        Ok(bytes)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Pack `(value, bit count)` pairs MSB-first into 16-bit little-endian words
+    pub(crate) fn pack_bits(fields: &[(u32, u32)]) -> Vec<u8> {
+        let mut bits = Vec::new();
+        for &(value, count) in fields {
+            bits.extend((0..count).rev().map(|i| (value >> i) & 1));
+        }
+        bits.resize(bits.len().next_multiple_of(16), 0);
+        bits.chunks(16)
+            .flat_map(|word| word.iter().fold(0u16, |acc, bit| (acc << 1) | *bit as u16).to_le_bytes())
+            .collect()
+    }
+
+    #[test]
+    fn test_canonical_codes() {
+        // A=2, B=1, C=3, D=3 gives B=0, A=10, C=110, D=111
+        let table = HuffmanTable::new(&[2, 1, 3, 3], 4).unwrap();
+        let data = pack_bits(&[(0b0, 1), (0b10, 2), (0b110, 3), (0b111, 3)]);
+        let mut reader = BitReader::new(&data);
+
+        let symbols: Vec<u16> = (0..4).map(|_| reader.decode(&table).unwrap()).collect();
+        assert_eq!(symbols, vec![1, 0, 2, 3]);
+        assert!(HuffmanTable::new(&[1, 1, 1], 4).is_err());
+    }
+
+    #[test]
+    fn test_incomplete_code() {
+        let table = HuffmanTable::new(&[1, 0], 4).unwrap();
+        let data = pack_bits(&[(0b0, 1), (0b1, 1)]);
+        let mut reader = BitReader::new(&data);
+
+        assert_eq!(reader.decode(&table).unwrap(), 0);
+        assert!(reader.decode(&table).is_err());
+    }
+}
@@ -0,0 +1,145 @@
+//! Hard links
+//!
+//! Every name of a file is a $FILE_NAME attribute in its MFT record (or an
+//! extension record) with the reference of the directory holding it. A file
+//! with a long name that is not valid in DOS has a second, DOS-only name in
+//! the same directory.
+
+use std::io::{Read, Seek};
+use ntfs::{Ntfs, NtfsAttributeType, NtfsFile};
+use crate::parser::attribute::read_file_records;
+use crate::parser::mft::FileReference;
+use crate::parser::record::FileRecord;
+use crate::parser::values::{namespace, FileNameValue};
+use crate::utils::error::{Result, SMNtfsError};
+
+/// Record number of the root directory
+pub const ROOT_RECORD_NUMBER: u64 = 5;
+
+/// Deepest directory nesting followed when building a path
+const MAX_PATH_DEPTH: usize = 256;
+
+/// One name of a file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HardLink {
+    /// Directory containing the name
+    pub parent: FileReference,
+
+    /// File name
+    pub name: String,
+
+    /// Namespace, see [`namespace`]
+    pub namespace: u8,
+}
+
+impl HardLink {
+    /// Check if this is only the short 8.3 alias of another name
+    pub fn is_dos_only(&self) -> bool {
+        self.namespace == namespace::DOS
+    }
+}
+
+/// Every name of a file, in on-disk order, including DOS-only aliases
+pub fn hard_links<T: Read + Seek>(file: &NtfsFile, fs: &mut T) -> Result<Vec<HardLink>> {
+    let records = read_file_records(file, fs)?;
+    links_of_records(&records)
+}
+
+/// Every name found in a base record and its extension records
+pub fn links_of_records(records: &[FileRecord]) -> Result<Vec<HardLink>> {
+    let mut links = Vec::new();
+
+    for record in records {
+        for attribute in record.attributes() {
+            let attribute = attribute?;
+            if !attribute.is_type(NtfsAttributeType::FileName) {
+                continue;
+            }
+            let value = FileNameValue::parse(attribute.resident_value())?;
+            links.push(HardLink {
+                parent: value.parent,
+                name: value.name,
+                namespace: value.namespace,
+            });
+        }
+    }
+
+    Ok(links)
+}
+
+/// Full path of every hard link of a file, DOS-only aliases excluded
+///
+/// Parent directories are named by their long names.
+pub fn hard_link_paths<T: Read + Seek>(ntfs: &Ntfs, file: &NtfsFile, fs: &mut T) -> Result<Vec<String>> {
+    let mut paths = Vec::new();
+
+    for link in hard_links(file, fs)?.into_iter().filter(|link| !link.is_dos_only()) {
+        let mut components = vec![link.name];
+        let mut parent = link.parent;
+
+        while parent.record_number != ROOT_RECORD_NUMBER {
+            if components.len() >= MAX_PATH_DEPTH {
+                return Err(SMNtfsError::CorruptedMft {
+                    offset: file.file_record_number(),
+                });
+            }
+
+            let directory = ntfs.file(fs, parent.record_number).map_err(|e| {
+                SMNtfsError::ReadError(format!("Failed to read directory {}: {}", parent.record_number, e))
+            })?;
+            // Directories cannot have hard links, so any long name will do
+            let name = hard_links(&directory, fs)?
+                .into_iter()
+                .find(|link| !link.is_dos_only())
+                .ok_or(SMNtfsError::CorruptedMft {
+                    offset: parent.record_number,
+                })?;
+
+            components.push(name.name);
+            parent = name.parent;
+        }
+
+        components.reverse();
+        paths.push(format!("/{}", components.join("/")));
+    }
+
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::record::record_flags;
+    use crate::parser::record::tests::{build_record, resident_attribute};
+    use crate::parser::values::tests::file_name_value;
+
+    #[test]
+    fn test_links_of_records() {
+        let name = |parent: u64, name: &str, namespace: u8| {
+            resident_attribute(0x30, "", &file_name_value(parent | (1 << 48), name, namespace))
+        };
+        let base = FileRecord::from_bytes(
+            build_record(
+                &[
+                    name(40, "Quarterly Report.docx", namespace::WIN32),
+                    name(40, "QUARTE~1.DOC", namespace::DOS),
+                ],
+                record_flags::IN_USE,
+            ),
+            64,
+            0,
+        );
+        let extension = FileRecord::from_bytes(
+            build_record(&[name(41, "report.docx", namespace::POSIX)], record_flags::IN_USE),
+            65,
+            0,
+        );
+
+        let links = links_of_records(&[base, extension]).unwrap();
+        assert_eq!(links.len(), 3);
+        assert_eq!(links[0].name, "Quarterly Report.docx");
+        assert!(links[1].is_dos_only());
+        assert_eq!(links[2].parent, FileReference { record_number: 41, sequence_number: 1 });
+        assert_eq!(links.iter().filter(|link| !link.is_dos_only()).count(), 2);
+    }
+}
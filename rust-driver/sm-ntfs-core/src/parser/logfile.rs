@@ -0,0 +1,1004 @@
+//! $LogFile parsing
+//!
+//! NTFS logs every metadata change to $LogFile before making it, as a log
+//! record carrying both redo and undo data. The log is a circular buffer of
+//! multi-sector protected pages: two restart pages (`RSTR`) at the start,
+//! then record pages (`RCRD`). A log sequence number (LSN) encodes the byte
+//! offset of a record in the file plus a wrap count in its upper bits.
+//!
+//! After an unclean shutdown, records between the last checkpoint and the
+//! end of the log may describe changes that never reached the disk
+//! (committed transactions to redo) or that must be rolled back
+//! (transactions still in flight to undo).
+
+use std::collections::HashMap;
+use std::io::{Read, Seek, SeekFrom};
+use ntfs::{Ntfs, NtfsAttributeType};
+use crate::parser::attribute::AttributeStream;
+use crate::parser::reader::StreamReader;
+use crate::parser::record::apply_fixups;
+use crate::utils::bytes::{le_u16, le_u32, le_u64, utf16_string};
+use crate::utils::error::{Result, SMNtfsError};
+
+/// Record number of the $LogFile system file
+pub const LOGFILE_RECORD_NUMBER: u64 = 2;
+
+/// Signature of a restart page
+const RESTART_PAGE_SIGNATURE: &[u8; 4] = b"RSTR";
+
+/// Signature of a log record page
+const RECORD_PAGE_SIGNATURE: &[u8; 4] = b"RCRD";
+
+/// Restart pages plus the two buffer pages that precede the circular area
+const RESERVED_PAGES: u64 = 4;
+
+/// Size of a log record header
+const LOG_RECORD_HEADER_LENGTH: usize = 0x30;
+
+/// Restart area flag: the volume was shut down cleanly
+const RESTART_VOLUME_IS_CLEAN: u16 = 0x0002;
+
+/// Marks the end of the log client list
+const LOGFILE_NO_CLIENT: u16 = 0xFFFF;
+
+/// Log record type of a checkpoint record
+const LOG_RECORD_CLIENT_RESTART: u32 = 2;
+
+/// NTFS log operation codes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct LogOperation(pub u16);
+
+impl LogOperation {
+    /// Nothing to do
+    pub const NOOP: u16 = 0x00;
+    /// Compensation log record, written while undoing
+    pub const COMPENSATION_LOG_RECORD: u16 = 0x01;
+    /// Write a whole new file record
+    pub const INITIALIZE_FILE_RECORD_SEGMENT: u16 = 0x02;
+    /// Mark a file record as not in use
+    pub const DEALLOCATE_FILE_RECORD_SEGMENT: u16 = 0x03;
+    /// Rewrite the tail of a file record
+    pub const WRITE_END_OF_FILE_RECORD_SEGMENT: u16 = 0x04;
+    /// Insert an attribute into a file record
+    pub const CREATE_ATTRIBUTE: u16 = 0x05;
+    /// Remove an attribute from a file record
+    pub const DELETE_ATTRIBUTE: u16 = 0x06;
+    /// Overwrite part of a resident value
+    pub const UPDATE_RESIDENT_VALUE: u16 = 0x07;
+    /// Overwrite part of a non-resident value
+    pub const UPDATE_NONRESIDENT_VALUE: u16 = 0x08;
+    /// Rewrite the mapping pairs of an attribute
+    pub const UPDATE_MAPPING_PAIRS: u16 = 0x09;
+    /// Drop clusters from the dirty page table
+    pub const DELETE_DIRTY_CLUSTERS: u16 = 0x0A;
+    /// Change the sizes in a non-resident attribute header
+    pub const SET_NEW_ATTRIBUTE_SIZES: u16 = 0x0B;
+    /// Add an entry to an $INDEX_ROOT
+    pub const ADD_INDEX_ENTRY_ROOT: u16 = 0x0C;
+    /// Remove an entry from an $INDEX_ROOT
+    pub const DELETE_INDEX_ENTRY_ROOT: u16 = 0x0D;
+    /// Add an entry to an index buffer
+    pub const ADD_INDEX_ENTRY_ALLOCATION: u16 = 0x0E;
+    /// Remove an entry from an index buffer
+    pub const DELETE_INDEX_ENTRY_ALLOCATION: u16 = 0x0F;
+    /// Rewrite the tail of an index buffer
+    pub const WRITE_END_OF_INDEX_BUFFER: u16 = 0x10;
+    /// Set the child VCN of an $INDEX_ROOT entry
+    pub const SET_INDEX_ENTRY_VCN_ROOT: u16 = 0x11;
+    /// Set the child VCN of an index buffer entry
+    pub const SET_INDEX_ENTRY_VCN_ALLOCATION: u16 = 0x12;
+    /// Update the duplicated $FILE_NAME in an $INDEX_ROOT
+    pub const UPDATE_FILE_NAME_ROOT: u16 = 0x13;
+    /// Update the duplicated $FILE_NAME in an index buffer
+    pub const UPDATE_FILE_NAME_ALLOCATION: u16 = 0x14;
+    /// Set a range of bits in a non-resident bitmap
+    pub const SET_BITS_IN_NONRESIDENT_BIT_MAP: u16 = 0x15;
+    /// Clear a range of bits in a non-resident bitmap
+    pub const CLEAR_BITS_IN_NONRESIDENT_BIT_MAP: u16 = 0x16;
+    /// Bad cluster replacement
+    pub const HOT_FIX: u16 = 0x17;
+    /// End of a nested top-level action
+    pub const END_TOP_LEVEL_ACTION: u16 = 0x18;
+    /// Transaction prepared
+    pub const PREPARE_TRANSACTION: u16 = 0x19;
+    /// Transaction committed
+    pub const COMMIT_TRANSACTION: u16 = 0x1A;
+    /// Transaction finished and forgotten
+    pub const FORGET_TRANSACTION: u16 = 0x1B;
+    /// Add an entry to the open attribute table
+    pub const OPEN_NONRESIDENT_ATTRIBUTE: u16 = 0x1C;
+    /// Checkpoint copy of the open attribute table
+    pub const OPEN_ATTRIBUTE_TABLE_DUMP: u16 = 0x1D;
+    /// Checkpoint copy of the attribute names
+    pub const ATTRIBUTE_NAMES_DUMP: u16 = 0x1E;
+    /// Checkpoint copy of the dirty page table
+    pub const DIRTY_PAGE_TABLE_DUMP: u16 = 0x1F;
+    /// Checkpoint copy of the transaction table
+    pub const TRANSACTION_TABLE_DUMP: u16 = 0x20;
+    /// Overwrite data of a view index entry in an $INDEX_ROOT
+    pub const UPDATE_RECORD_DATA_ROOT: u16 = 0x21;
+    /// Overwrite data of a view index entry in an index buffer
+    pub const UPDATE_RECORD_DATA_ALLOCATION: u16 = 0x22;
+
+    const NAMES: [&'static str; 0x23] = [
+        "Noop",
+        "CompensationLogRecord",
+        "InitializeFileRecordSegment",
+        "DeallocateFileRecordSegment",
+        "WriteEndOfFileRecordSegment",
+        "CreateAttribute",
+        "DeleteAttribute",
+        "UpdateResidentValue",
+        "UpdateNonresidentValue",
+        "UpdateMappingPairs",
+        "DeleteDirtyClusters",
+        "SetNewAttributeSizes",
+        "AddIndexEntryRoot",
+        "DeleteIndexEntryRoot",
+        "AddIndexEntryAllocation",
+        "DeleteIndexEntryAllocation",
+        "WriteEndOfIndexBuffer",
+        "SetIndexEntryVcnRoot",
+        "SetIndexEntryVcnAllocation",
+        "UpdateFileNameRoot",
+        "UpdateFileNameAllocation",
+        "SetBitsInNonresidentBitMap",
+        "ClearBitsInNonresidentBitMap",
+        "HotFix",
+        "EndTopLevelAction",
+        "PrepareTransaction",
+        "CommitTransaction",
+        "ForgetTransaction",
+        "OpenNonresidentAttribute",
+        "OpenAttributeTableDump",
+        "AttributeNamesDump",
+        "DirtyPageTableDump",
+        "TransactionTableDump",
+        "UpdateRecordDataRoot",
+        "UpdateRecordDataAllocation",
+    ];
+
+    /// Name used by Microsoft's documentation, e.g. `UpdateResidentValue`
+    pub fn name(&self) -> &'static str {
+        Self::NAMES.get(self.0 as usize).copied().unwrap_or("Unknown")
+    }
+
+    /// Check if the operation changes a file record in the MFT
+    pub fn targets_file_record(&self) -> bool {
+        matches!(
+            self.0,
+            Self::INITIALIZE_FILE_RECORD_SEGMENT
+                | Self::DEALLOCATE_FILE_RECORD_SEGMENT
+                | Self::WRITE_END_OF_FILE_RECORD_SEGMENT
+                | Self::CREATE_ATTRIBUTE
+                | Self::DELETE_ATTRIBUTE
+                | Self::UPDATE_RESIDENT_VALUE
+                | Self::UPDATE_MAPPING_PAIRS
+                | Self::SET_NEW_ATTRIBUTE_SIZES
+                | Self::ADD_INDEX_ENTRY_ROOT
+                | Self::DELETE_INDEX_ENTRY_ROOT
+                | Self::SET_INDEX_ENTRY_VCN_ROOT
+                | Self::UPDATE_FILE_NAME_ROOT
+                | Self::UPDATE_RECORD_DATA_ROOT
+        )
+    }
+
+    /// Check if the operation leaves the volume itself unchanged
+    pub fn is_bookkeeping(&self) -> bool {
+        matches!(
+            self.0,
+            Self::NOOP
+                | Self::COMPENSATION_LOG_RECORD
+                | Self::DELETE_DIRTY_CLUSTERS
+                | Self::END_TOP_LEVEL_ACTION
+                | Self::PREPARE_TRANSACTION
+                | Self::COMMIT_TRANSACTION
+                | Self::FORGET_TRANSACTION
+                | Self::OPEN_NONRESIDENT_ATTRIBUTE
+                | Self::OPEN_ATTRIBUTE_TABLE_DUMP
+                | Self::ATTRIBUTE_NAMES_DUMP
+                | Self::DIRTY_PAGE_TABLE_DUMP
+                | Self::TRANSACTION_TABLE_DUMP
+        )
+    }
+}
+
+/// A client of the log (NTFS itself is the only one in practice)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogClient {
+    /// Client name, normally `NTFS`
+    pub name: String,
+
+    /// Oldest LSN the client still needs
+    pub oldest_lsn: u64,
+
+    /// LSN of the client's last checkpoint record
+    pub restart_lsn: u64,
+}
+
+/// The restart area of a restart page
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RestartArea {
+    /// Log format major version (1 or 2)
+    pub major_version: i16,
+
+    /// Log format minor version
+    pub minor_version: i16,
+
+    /// Size of a log page
+    pub log_page_size: u32,
+
+    /// LSN of the last record written
+    pub current_lsn: u64,
+
+    /// Restart area flags
+    pub flags: u16,
+
+    /// Number of upper LSN bits holding the wrap count
+    pub seq_number_bits: u32,
+
+    /// Size of $LogFile in bytes
+    pub file_size: u64,
+
+    /// Offset of record data in each record page
+    pub log_page_data_offset: u16,
+
+    /// The client in use, if any
+    pub client: Option<LogClient>,
+}
+
+impl RestartArea {
+    /// Decode a restart page with fixups already applied
+    pub fn parse(page: &[u8]) -> Result<Self> {
+        if page.get(..4) != Some(RESTART_PAGE_SIGNATURE.as_slice()) {
+            return Err(SMNtfsError::ReadError("Missing $LogFile restart page signature".to_string()));
+        }
+
+        let log_page_size = le_u32(page, 0x14);
+        let major_version = le_u16(page, 0x1C) as i16;
+        let area = le_u16(page, 0x18) as usize;
+        if !(1..=2).contains(&major_version) || !log_page_size.is_power_of_two() || area + 0x30 > page.len() {
+            return Err(SMNtfsError::ReadError(format!(
+                "Unsupported $LogFile version {}.{} or page size {}",
+                major_version,
+                le_u16(page, 0x1A) as i16,
+                log_page_size
+            )));
+        }
+
+        let client_in_use = le_u16(page, area + 0x0C);
+        let client = if client_in_use == LOGFILE_NO_CLIENT {
+            None
+        } else {
+            let offset = area + le_u16(page, area + 0x16) as usize + client_in_use as usize * 0xA0;
+            let name_length = (le_u32(page, offset + 0x1C) as usize / 2).min(64);
+            Some(LogClient {
+                oldest_lsn: le_u64(page, offset),
+                restart_lsn: le_u64(page, offset + 0x08),
+                name: utf16_string(page, offset + 0x20, name_length),
+            })
+        };
+
+        let restart = Self {
+            major_version,
+            minor_version: le_u16(page, 0x1A) as i16,
+            log_page_size,
+            current_lsn: le_u64(page, area),
+            flags: le_u16(page, area + 0x0E),
+            seq_number_bits: le_u32(page, area + 0x10),
+            file_size: le_u64(page, area + 0x18),
+            log_page_data_offset: le_u16(page, area + 0x26),
+            client,
+        };
+
+        if !(3..64).contains(&restart.seq_number_bits) || restart.log_page_data_offset as u32 >= log_page_size {
+            return Err(SMNtfsError::ReadError("Invalid $LogFile restart area".to_string()));
+        }
+        Ok(restart)
+    }
+
+    /// Check if Windows flagged the shutdown as clean
+    pub fn is_clean(&self) -> bool {
+        self.flags & RESTART_VOLUME_IS_CLEAN != 0
+    }
+
+    /// Byte offset in $LogFile of the record with this LSN
+    pub fn lsn_to_offset(&self, lsn: u64) -> u64 {
+        (lsn << self.seq_number_bits) >> (self.seq_number_bits - 3)
+    }
+
+    /// LSN of the record at `offset` written during wrap `sequence`
+    pub fn offset_to_lsn(&self, offset: u64, sequence: u64) -> u64 {
+        (sequence << (64 - self.seq_number_bits)) | (offset >> 3)
+    }
+
+    /// Wrap count of an LSN
+    pub fn lsn_sequence(&self, lsn: u64) -> u64 {
+        lsn >> (64 - self.seq_number_bits)
+    }
+
+    /// LSN the scan for pending transactions starts at
+    pub fn scan_start_lsn(&self) -> Option<u64> {
+        let client = self.client.as_ref()?;
+        [client.oldest_lsn, client.restart_lsn].into_iter().filter(|lsn| *lsn != 0).min()
+    }
+}
+
+/// A decoded log record
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogRecord {
+    /// LSN of this record
+    pub lsn: u64,
+
+    /// Previous record of the same client
+    pub previous_lsn: u64,
+
+    /// Next record to undo in the same transaction
+    pub undo_next_lsn: u64,
+
+    /// 1 for a normal record, 2 for a checkpoint
+    pub record_type: u32,
+
+    /// Slot of the transaction in the transaction table (reused over time)
+    pub transaction_id: u32,
+
+    /// Operation that reapplies the change
+    pub redo_operation: LogOperation,
+
+    /// Operation that rolls it back
+    pub undo_operation: LogOperation,
+
+    /// Data for the redo operation
+    pub redo_data: Vec<u8>,
+
+    /// Data for the undo operation
+    pub undo_data: Vec<u8>,
+
+    /// Index of the target in the open attribute table
+    pub target_attribute: u16,
+
+    /// Offset of the attribute in the file record (0 outside the MFT)
+    pub record_offset: u16,
+
+    /// Offset of the change within the attribute or target area
+    pub attribute_offset: u16,
+
+    /// Offset of the target from its first cluster, in 512-byte blocks
+    pub cluster_block_offset: u16,
+
+    /// First VCN of the target in its attribute
+    pub target_vcn: u64,
+
+    /// Clusters holding the target
+    pub lcns: Vec<u64>,
+}
+
+impl LogRecord {
+    /// Decode a record from its header and client data
+    pub fn parse(header: &[u8], data: &[u8]) -> Result<Self> {
+        let slice = |offset: u16, length: u16| -> Result<Vec<u8>> {
+            let (offset, length) = (offset as usize, length as usize);
+            data.get(offset..offset + length).map(<[u8]>::to_vec).ok_or_else(|| {
+                SMNtfsError::ReadError(format!(
+                    "Log record {} has operation data past its end",
+                    le_u64(header, 0x00)
+                ))
+            })
+        };
+
+        let is_checkpoint = le_u32(header, 0x20) == LOG_RECORD_CLIENT_RESTART;
+        let lcn_count = le_u16(data, 0x0E) as usize;
+
+        Ok(Self {
+            lsn: le_u64(header, 0x00),
+            previous_lsn: le_u64(header, 0x08),
+            undo_next_lsn: le_u64(header, 0x10),
+            record_type: le_u32(header, 0x20),
+            transaction_id: le_u32(header, 0x24),
+            redo_operation: LogOperation(if is_checkpoint { 0 } else { le_u16(data, 0x00) }),
+            undo_operation: LogOperation(if is_checkpoint { 0 } else { le_u16(data, 0x02) }),
+            redo_data: if is_checkpoint { Vec::new() } else { slice(le_u16(data, 0x04), le_u16(data, 0x06))? },
+            undo_data: if is_checkpoint { Vec::new() } else { slice(le_u16(data, 0x08), le_u16(data, 0x0A))? },
+            target_attribute: le_u16(data, 0x0C),
+            record_offset: le_u16(data, 0x10),
+            attribute_offset: le_u16(data, 0x12),
+            cluster_block_offset: le_u16(data, 0x14),
+            target_vcn: le_u64(data, 0x18),
+            lcns: if is_checkpoint { Vec::new() } else { (0..lcn_count).map(|i| le_u64(data, 0x20 + i * 8)).collect() },
+        })
+    }
+
+    /// Check if this is a checkpoint rather than a change
+    pub fn is_checkpoint(&self) -> bool {
+        self.record_type == LOG_RECORD_CLIENT_RESTART
+    }
+
+    /// Check if the record ends its transaction
+    pub fn ends_transaction(&self) -> bool {
+        matches!(
+            self.redo_operation.0,
+            LogOperation::COMMIT_TRANSACTION | LogOperation::FORGET_TRANSACTION
+        )
+    }
+}
+
+/// The log records of one transaction found after the checkpoint
+#[derive(Debug, Clone)]
+pub struct LogTransaction {
+    /// Slot in the transaction table
+    pub transaction_id: u32,
+
+    /// Whether the transaction committed before the log ends
+    pub committed: bool,
+
+    /// Records in LSN order
+    pub records: Vec<LogRecord>,
+}
+
+impl LogTransaction {
+    /// Records that change the volume (not just log bookkeeping)
+    pub fn changes(&self) -> impl Iterator<Item = &LogRecord> {
+        self.records.iter().filter(|record| {
+            !record.redo_operation.is_bookkeeping() || !record.undo_operation.is_bookkeeping()
+        })
+    }
+}
+
+/// What $LogFile says about the state of the volume
+#[derive(Debug, Clone)]
+pub struct LogFileReport {
+    /// The newest valid restart area (`None` if the log was reset)
+    pub restart: Option<RestartArea>,
+
+    /// Transactions with changes logged after the last checkpoint
+    pub transactions: Vec<LogTransaction>,
+}
+
+impl LogFileReport {
+    /// Check if there is nothing to replay
+    pub fn is_clean(&self) -> bool {
+        let flagged_clean = self.restart.as_ref().map_or(true, RestartArea::is_clean);
+        flagged_clean || self.transactions.iter().all(|transaction| transaction.changes().next().is_none())
+    }
+
+    /// Transactions whose changes must be redone
+    pub fn committed(&self) -> impl Iterator<Item = &LogTransaction> {
+        self.transactions.iter().filter(|transaction| transaction.committed)
+    }
+
+    /// Transactions whose changes must be undone
+    pub fn uncommitted(&self) -> impl Iterator<Item = &LogTransaction> {
+        self.transactions.iter().filter(|transaction| !transaction.committed)
+    }
+}
+
+/// Reader over the pages and records of $LogFile
+pub struct LogFile<'a, T: Read + Seek> {
+    reader: StreamReader<'a, T>,
+    restart: Option<RestartArea>,
+    page: Vec<u8>,
+    page_start: Option<u64>,
+}
+
+impl<'a, T: Read + Seek> LogFile<'a, T> {
+    /// Open the $LogFile of a volume
+    pub fn open(ntfs: &Ntfs, fs: &'a mut T) -> Result<Self> {
+        let stream = logfile_stream(ntfs, fs)?;
+        Self::from_stream(fs, stream, ntfs.cluster_size())
+    }
+
+    /// Read an already collected $LogFile data stream
+    ///
+    /// Picks the newer of the two restart pages. A log whose restart pages
+    /// are both filled with `0xFF` has been reset and has no restart area.
+    pub fn from_stream(fs: &'a mut T, stream: AttributeStream, cluster_size: u32) -> Result<Self> {
+        let mut reader = StreamReader::new(fs, stream, cluster_size);
+
+        // Restart pages are at least 4 KiB; the first tells the real size
+        let mut first = vec![0u8; 0x1000];
+        reader.read_exact(&mut first)?;
+        let page_size = match le_u32(&first, 0x10) {
+            size if size.is_power_of_two() && (0x200..=0x10000).contains(&size) => size as u64,
+            _ => 0x1000,
+        };
+
+        let mut restart: Option<RestartArea> = None;
+        for page_start in [0, page_size] {
+            let mut page = vec![0u8; page_size as usize];
+            reader.seek(SeekFrom::Start(page_start))?;
+            reader.read_exact(&mut page)?;
+
+            if page.iter().all(|byte| *byte == 0xFF) {
+                continue;
+            }
+            if !apply_fixups(&mut page) {
+                tracing::warn!("Restart page at offset {} of $LogFile is damaged", page_start);
+                continue;
+            }
+            match RestartArea::parse(&page) {
+                Ok(area) if restart.as_ref().map_or(true, |best| area.current_lsn > best.current_lsn) => {
+                    restart = Some(area)
+                }
+                Ok(_) => {}
+                Err(e) => tracing::warn!("Ignoring restart page at offset {}: {}", page_start, e),
+            }
+        }
+
+        let reset = first.iter().all(|byte| *byte == 0xFF);
+        if restart.is_none() && !reset {
+            return Err(SMNtfsError::ReadError("$LogFile has no valid restart page".to_string()));
+        }
+
+        Ok(Self {
+            reader,
+            restart,
+            page: Vec::new(),
+            page_start: None,
+        })
+    }
+
+    /// The newest restart area (`None` if the log was reset)
+    pub fn restart_area(&self) -> Option<&RestartArea> {
+        self.restart.as_ref()
+    }
+
+    /// The $LogFile data stream
+    pub fn stream(&self) -> &AttributeStream {
+        self.reader.stream()
+    }
+
+    /// Read the log records from the last checkpoint to the end of the log
+    pub fn records(&mut self) -> Result<Vec<LogRecord>> {
+        let Some(restart) = self.restart.clone() else {
+            return Ok(Vec::new());
+        };
+        let Some(mut lsn) = restart.scan_start_lsn() else {
+            return Ok(Vec::new());
+        };
+
+        let mut records = Vec::new();
+        while lsn <= restart.current_lsn {
+            let (record, end_offset) = self.record_at(&restart, lsn)?;
+            records.push(record);
+            if lsn == restart.current_lsn {
+                break;
+            }
+            lsn = self.next_lsn(&restart, lsn, end_offset)?;
+        }
+
+        Ok(records)
+    }
+
+    /// Group the records after the last checkpoint into transactions
+    pub fn report(&mut self) -> Result<LogFileReport> {
+        let records = self.records()?;
+        let mut open: HashMap<u32, LogTransaction> = HashMap::new();
+        let mut transactions = Vec::new();
+
+        for record in records.into_iter().filter(|record| !record.is_checkpoint()) {
+            let ends = record.ends_transaction();
+            let transaction_id = record.transaction_id;
+            let transaction = open.entry(transaction_id).or_insert_with(|| LogTransaction {
+                transaction_id,
+                committed: false,
+                records: Vec::new(),
+            });
+            transaction.records.push(record);
+
+            // Transaction slots are reused once a transaction ends
+            if ends {
+                if let Some(mut transaction) = open.remove(&transaction_id) {
+                    transaction.committed = true;
+                    transactions.push(transaction);
+                }
+            }
+        }
+
+        transactions.extend(open.into_values());
+        transactions.retain(|transaction| transaction.changes().next().is_some());
+        transactions.sort_by_key(|transaction| transaction.records[0].lsn);
+
+        Ok(LogFileReport {
+            restart: self.restart.clone(),
+            transactions,
+        })
+    }
+
+    /// Load and verify the record page at `page_start`
+    fn load_page(&mut self, page_start: u64, page_size: usize) -> Result<()> {
+        if self.page_start == Some(page_start) {
+            return Ok(());
+        }
+
+        self.page_start = None;
+        self.page.clear();
+        self.page.resize(page_size, 0);
+        self.reader.seek(SeekFrom::Start(page_start))?;
+        self.reader.read_exact(&mut self.page)?;
+
+        if self.page.get(..4) != Some(RECORD_PAGE_SIGNATURE.as_slice()) || !apply_fixups(&mut self.page) {
+            return Err(SMNtfsError::ReadError(format!(
+                "Damaged $LogFile record page at offset {}",
+                page_start
+            )));
+        }
+        self.page_start = Some(page_start);
+        Ok(())
+    }
+
+    /// Start of the page after `page_start`, wrapping around the circular area
+    fn next_page(restart: &RestartArea, page_start: u64) -> (u64, bool) {
+        let page_size = restart.log_page_size as u64;
+        let next = page_start + page_size;
+        if next + page_size > restart.file_size {
+            (RESERVED_PAGES * page_size, true)
+        } else {
+            (next, false)
+        }
+    }
+
+    /// Read `length` bytes of record data starting at `offset`, skipping page headers
+    ///
+    /// Returns the bytes and the offset just past them.
+    fn read_log_bytes(&mut self, restart: &RestartArea, mut offset: u64, length: usize) -> Result<(Vec<u8>, u64)> {
+        let page_size = restart.log_page_size as u64;
+        let data_offset = restart.log_page_data_offset as u64;
+        // The length comes from a record header; past the log area it would wrap around it
+        let area = restart.file_size.saturating_sub(RESERVED_PAGES * page_size);
+        if length as u64 > area {
+            return Err(SMNtfsError::ReadError(format!(
+                "Log record of {} bytes at offset {} does not fit the {} byte log area",
+                length, offset, area
+            )));
+        }
+        let mut data = Vec::with_capacity(length);
+
+        loop {
+            let page_start = offset - offset % page_size;
+            self.load_page(page_start, page_size as usize)?;
+
+            let start = (offset - page_start).max(data_offset);
+            let count = (length - data.len()).min((page_size - start) as usize);
+            data.extend_from_slice(&self.page[start as usize..start as usize + count]);
+            offset = page_start + start + count as u64;
+
+            if data.len() == length {
+                return Ok((data, offset));
+            }
+            offset = Self::next_page(restart, page_start).0 + data_offset;
+        }
+    }
+
+    /// Read the record with LSN `lsn`; also returns the offset just past it
+    fn record_at(&mut self, restart: &RestartArea, lsn: u64) -> Result<(LogRecord, u64)> {
+        let offset = restart.lsn_to_offset(lsn);
+        let (header, after_header) = self.read_log_bytes(restart, offset, LOG_RECORD_HEADER_LENGTH)?;
+        if le_u64(&header, 0x00) != lsn {
+            return Err(SMNtfsError::ReadError(format!(
+                "Expected log record {} at offset {} of $LogFile",
+                lsn, offset
+            )));
+        }
+
+        let data_length = le_u32(&header, 0x18) as usize;
+        let (data, end) = self.read_log_bytes(restart, after_header, data_length)?;
+        Ok((LogRecord::parse(&header, &data)?, end))
+    }
+
+    /// LSN of the record following the one that ended at `end_offset`
+    fn next_lsn(&mut self, restart: &RestartArea, lsn: u64, end_offset: u64) -> Result<u64> {
+        let page_size = restart.log_page_size as u64;
+        let mut sequence = restart.lsn_sequence(lsn);
+        let mut offset = end_offset.next_multiple_of(8);
+
+        // A record that crossed the end of the circular area wrapped around
+        if offset < restart.lsn_to_offset(lsn) {
+            sequence += 1;
+        }
+
+        // Records start on a new page when the header no longer fits, and
+        // never inside a page header
+        let page_start = offset - offset % page_size;
+        let data_offset = restart.log_page_data_offset as u64;
+        if offset - page_start + LOG_RECORD_HEADER_LENGTH as u64 > page_size {
+            let (next, wrapped) = Self::next_page(restart, page_start);
+            offset = next + data_offset;
+            sequence += wrapped as u64;
+        } else if offset - page_start < data_offset {
+            offset = page_start + data_offset;
+        }
+
+        Ok(restart.offset_to_lsn(offset, sequence))
+    }
+}
+
+/// Collect the $DATA stream of $LogFile
+pub fn logfile_stream<T: Read + Seek>(ntfs: &Ntfs, fs: &mut T) -> Result<AttributeStream> {
+    let file = ntfs
+        .file(fs, LOGFILE_RECORD_NUMBER)
+        .map_err(|e| SMNtfsError::ReadError(format!("Failed to read $LogFile: {}", e)))?;
+    AttributeStream::collect(&file, fs, NtfsAttributeType::Data, "")?.ok_or(SMNtfsError::CorruptedMft {
+        offset: LOGFILE_RECORD_NUMBER,
+    })
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::parser::record::FIXUP_STRIDE;
+    use crate::parser::runs::DataRun;
+    use std::io::Cursor;
+
+    pub(crate) const PAGE_SIZE: usize = 0x1000;
+    pub(crate) const SEQ_NUMBER_BITS: u32 = 44;
+    const DATA_OFFSET: usize = 0x40;
+
+    /// Protect a page with update sequence number 1
+    fn protect(page: &mut [u8], usa_offset: usize) {
+        let count = page.len() / FIXUP_STRIDE + 1;
+        page[0x04..0x06].copy_from_slice(&(usa_offset as u16).to_le_bytes());
+        page[0x06..0x08].copy_from_slice(&(count as u16).to_le_bytes());
+        page[usa_offset..usa_offset + 2].copy_from_slice(&1u16.to_le_bytes());
+        for i in 1..count {
+            let end = i * FIXUP_STRIDE;
+            page[usa_offset + i * 2] = page[end - 2];
+            page[usa_offset + i * 2 + 1] = page[end - 1];
+            page[end - 2..end].copy_from_slice(&1u16.to_le_bytes());
+        }
+    }
+
+    /// LSN of the record at `offset` in the first pass through the log
+    pub(crate) fn lsn(offset: usize) -> u64 {
+        (1u64 << (64 - SEQ_NUMBER_BITS)) | (offset as u64 >> 3)
+    }
+
+    fn restart_page(current_lsn: u64, restart_lsn: u64, flags: u16, file_size: u64) -> Vec<u8> {
+        let mut page = vec![0u8; PAGE_SIZE];
+        page[..4].copy_from_slice(RESTART_PAGE_SIGNATURE);
+        page[0x10..0x14].copy_from_slice(&(PAGE_SIZE as u32).to_le_bytes());
+        page[0x14..0x18].copy_from_slice(&(PAGE_SIZE as u32).to_le_bytes());
+        page[0x18..0x1A].copy_from_slice(&0x30u16.to_le_bytes());
+        page[0x1A..0x1C].copy_from_slice(&1u16.to_le_bytes());
+        page[0x1C..0x1E].copy_from_slice(&1u16.to_le_bytes());
+
+        let area = 0x30;
+        page[area..area + 8].copy_from_slice(&current_lsn.to_le_bytes());
+        page[area + 0x08..area + 0x0A].copy_from_slice(&1u16.to_le_bytes());
+        page[area + 0x0E..area + 0x10].copy_from_slice(&flags.to_le_bytes());
+        page[area + 0x10..area + 0x14].copy_from_slice(&SEQ_NUMBER_BITS.to_le_bytes());
+        page[area + 0x16..area + 0x18].copy_from_slice(&0x40u16.to_le_bytes());
+        page[area + 0x18..area + 0x20].copy_from_slice(&file_size.to_le_bytes());
+        page[area + 0x26..area + 0x28].copy_from_slice(&(DATA_OFFSET as u16).to_le_bytes());
+
+        let client = area + 0x40;
+        page[client..client + 8].copy_from_slice(&restart_lsn.to_le_bytes());
+        page[client + 0x08..client + 0x10].copy_from_slice(&restart_lsn.to_le_bytes());
+        page[client + 0x1C..client + 0x20].copy_from_slice(&8u32.to_le_bytes());
+        for (i, unit) in "NTFS".encode_utf16().enumerate() {
+            page[client + 0x20 + i * 2..client + 0x22 + i * 2].copy_from_slice(&unit.to_le_bytes());
+        }
+
+        protect(&mut page, 0x1E);
+        page
+    }
+
+    /// A log operation to encode with [`log_record`]
+    pub(crate) struct Operation<'d> {
+        pub transaction_id: u32,
+        pub redo: u16,
+        pub undo: u16,
+        pub redo_data: &'d [u8],
+        pub undo_data: &'d [u8],
+        pub record_offset: u16,
+        pub attribute_offset: u16,
+        pub cluster_block_offset: u16,
+        pub lcn: u64,
+    }
+
+    impl Default for Operation<'_> {
+        fn default() -> Self {
+            Self {
+                transaction_id: 0x18,
+                redo: LogOperation::NOOP,
+                undo: LogOperation::NOOP,
+                redo_data: &[],
+                undo_data: &[],
+                record_offset: 0,
+                attribute_offset: 0,
+                cluster_block_offset: 0,
+                lcn: 0,
+            }
+        }
+    }
+
+    /// Encode a log record (header and client data)
+    pub(crate) fn log_record(lsn: u64, previous_lsn: u64, record_type: u32, operation: &Operation) -> Vec<u8> {
+        let redo_offset = 0x28;
+        let undo_offset = redo_offset + ((operation.redo_data.len() + 7) & !7);
+        let data_length = undo_offset + ((operation.undo_data.len() + 7) & !7);
+
+        let mut data = vec![0u8; LOG_RECORD_HEADER_LENGTH + data_length];
+        data[0x00..0x08].copy_from_slice(&lsn.to_le_bytes());
+        data[0x08..0x10].copy_from_slice(&previous_lsn.to_le_bytes());
+        data[0x10..0x18].copy_from_slice(&previous_lsn.to_le_bytes());
+        data[0x18..0x1C].copy_from_slice(&(data_length as u32).to_le_bytes());
+        data[0x20..0x24].copy_from_slice(&record_type.to_le_bytes());
+        data[0x24..0x28].copy_from_slice(&operation.transaction_id.to_le_bytes());
+
+        let client = &mut data[LOG_RECORD_HEADER_LENGTH..];
+        client[0x00..0x02].copy_from_slice(&operation.redo.to_le_bytes());
+        client[0x02..0x04].copy_from_slice(&operation.undo.to_le_bytes());
+        client[0x04..0x06].copy_from_slice(&(redo_offset as u16).to_le_bytes());
+        client[0x06..0x08].copy_from_slice(&(operation.redo_data.len() as u16).to_le_bytes());
+        client[0x08..0x0A].copy_from_slice(&(undo_offset as u16).to_le_bytes());
+        client[0x0A..0x0C].copy_from_slice(&(operation.undo_data.len() as u16).to_le_bytes());
+        client[0x0E..0x10].copy_from_slice(&1u16.to_le_bytes());
+        client[0x10..0x12].copy_from_slice(&operation.record_offset.to_le_bytes());
+        client[0x12..0x14].copy_from_slice(&operation.attribute_offset.to_le_bytes());
+        client[0x14..0x16].copy_from_slice(&operation.cluster_block_offset.to_le_bytes());
+        client[0x20..0x28].copy_from_slice(&operation.lcn.to_le_bytes());
+        client[redo_offset..redo_offset + operation.redo_data.len()].copy_from_slice(operation.redo_data);
+        client[undo_offset..undo_offset + operation.undo_data.len()].copy_from_slice(operation.undo_data);
+        data
+    }
+
+    /// Build a $LogFile of eight pages whose records start on page 4
+    ///
+    /// The first record is a checkpoint. Records are laid out back to back,
+    /// continuing on the next page when they do not fit.
+    pub(crate) fn build_logfile(operations: &[Operation], flags: u16) -> Vec<u8> {
+        let file_size = 8 * PAGE_SIZE;
+        let mut pages = vec![vec![0u8; PAGE_SIZE]; 4];
+        let mut stream = Vec::new();
+        let mut offset = 4 * PAGE_SIZE + DATA_OFFSET;
+        let mut previous = 0;
+        let mut last_lsn = 0;
+
+        let checkpoint = Operation::default();
+        for (i, operation) in std::iter::once(&checkpoint).chain(operations).enumerate() {
+            let record_type = if i == 0 { LOG_RECORD_CLIENT_RESTART } else { 1 };
+            let record = log_record(lsn(offset), previous, record_type, operation);
+            previous = lsn(offset);
+            last_lsn = previous;
+
+            // Append the record to the data pages, skipping page headers
+            for byte in record {
+                if offset % PAGE_SIZE == 0 {
+                    offset += DATA_OFFSET;
+                }
+                let page = offset / PAGE_SIZE - 4;
+                if stream.len() <= page {
+                    stream.push(vec![0u8; PAGE_SIZE]);
+                }
+                stream[page][offset % PAGE_SIZE] = byte;
+                offset += 1;
+            }
+            offset = (offset + 7) & !7;
+            if offset % PAGE_SIZE + LOG_RECORD_HEADER_LENGTH > PAGE_SIZE {
+                offset = (offset / PAGE_SIZE + 1) * PAGE_SIZE + DATA_OFFSET;
+            } else if offset % PAGE_SIZE < DATA_OFFSET {
+                offset = offset / PAGE_SIZE * PAGE_SIZE + DATA_OFFSET;
+            }
+        }
+
+        let first_lsn = lsn(4 * PAGE_SIZE + DATA_OFFSET);
+        pages[0] = restart_page(last_lsn, first_lsn, flags, file_size as u64);
+        pages[1] = restart_page(first_lsn, first_lsn, flags, file_size as u64);
+        for mut page in stream {
+            page[..4].copy_from_slice(RECORD_PAGE_SIGNATURE);
+            protect(&mut page, 0x28);
+            pages.push(page);
+        }
+        pages.resize(8, vec![0u8; PAGE_SIZE]);
+        pages.concat()
+    }
+
+    pub(crate) fn logfile_stream_at(lcn: u64, length: u64) -> AttributeStream {
+        AttributeStream {
+            runs: vec![DataRun { vcn: 0, lcn: Some(lcn), length }],
+            data_size: length * PAGE_SIZE as u64,
+            initialized_size: length * PAGE_SIZE as u64,
+            ..AttributeStream::default()
+        }
+    }
+
+    fn update(transaction_id: u32, redo_data: &'static [u8]) -> Operation<'static> {
+        Operation {
+            transaction_id,
+            redo: LogOperation::UPDATE_RESIDENT_VALUE,
+            undo: LogOperation::UPDATE_RESIDENT_VALUE,
+            redo_data,
+            undo_data: b"old",
+            ..Operation::default()
+        }
+    }
+
+    fn forget(transaction_id: u32) -> Operation<'static> {
+        Operation {
+            transaction_id,
+            redo: LogOperation::FORGET_TRANSACTION,
+            undo: LogOperation::COMPENSATION_LOG_RECORD,
+            ..Operation::default()
+        }
+    }
+
+    #[test]
+    fn test_restart_area() {
+        let mut page = restart_page(lsn(0x5000), lsn(0x4040), RESTART_VOLUME_IS_CLEAN, 0x8000);
+        assert!(apply_fixups(&mut page));
+        let restart = RestartArea::parse(&page).unwrap();
+
+        assert!(restart.is_clean());
+        assert_eq!(restart.log_page_size, 0x1000);
+        assert_eq!(restart.lsn_to_offset(lsn(0x5000)), 0x5000);
+        assert_eq!(restart.lsn_sequence(lsn(0x5000)), 1);
+        assert_eq!(restart.client.as_ref().unwrap().name, "NTFS");
+        assert_eq!(restart.scan_start_lsn(), Some(lsn(0x4040)));
+    }
+
+    #[test]
+    fn test_report_pending_transactions() {
+        let data = build_logfile(&[update(0x18, b"new"), update(0x30, b"abc"), forget(0x18)], 0);
+        let mut device = Cursor::new(data);
+        let mut log = LogFile::from_stream(&mut device, logfile_stream_at(0, 8), 4096).unwrap();
+
+        let records = log.records().unwrap();
+        assert_eq!(records.len(), 4);
+        assert!(records[0].is_checkpoint());
+        assert_eq!(records[1].redo_operation.name(), "UpdateResidentValue");
+        assert_eq!(records[1].redo_data, b"new");
+        assert_eq!(records[1].undo_data, b"old");
+
+        let report = log.report().unwrap();
+        assert!(!report.is_clean());
+        assert_eq!(report.committed().count(), 1);
+        assert_eq!(report.uncommitted().count(), 1);
+        assert_eq!(report.uncommitted().next().unwrap().transaction_id, 0x30);
+    }
+
+    #[test]
+    fn test_records_span_pages() {
+        let big = [0xAB; 3000];
+        let operations: Vec<Operation> = (0..3)
+            .map(|_| Operation {
+                redo: LogOperation::UPDATE_NONRESIDENT_VALUE,
+                redo_data: &big,
+                ..Operation::default()
+            })
+            .collect();
+        let mut device = Cursor::new(build_logfile(&operations, 0));
+        let mut log = LogFile::from_stream(&mut device, logfile_stream_at(0, 8), 4096).unwrap();
+
+        let records = log.records().unwrap();
+        assert_eq!(records.len(), 4);
+        assert!(records[1..].iter().all(|record| record.redo_data == big));
+    }
+
+    #[test]
+    fn test_oversized_record() {
+        let mut data = build_logfile(&[update(0x18, b"new"), forget(0x18)], 0);
+        // Data length of the checkpoint record
+        let length = 4 * PAGE_SIZE + DATA_OFFSET + 0x18;
+        data[length..length + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        let mut device = Cursor::new(data);
+        let mut log = LogFile::from_stream(&mut device, logfile_stream_at(0, 8), 4096).unwrap();
+
+        let error = log.records().unwrap_err();
+        assert!(error.to_string().contains("does not fit the 16384 byte log area"), "{}", error);
+    }
+
+    #[test]
+    fn test_clean_and_reset_logs() {
+        let mut device = Cursor::new(build_logfile(&[update(0x18, b"new")], RESTART_VOLUME_IS_CLEAN));
+        let mut log = LogFile::from_stream(&mut device, logfile_stream_at(0, 8), 4096).unwrap();
+        assert!(log.report().unwrap().is_clean());
+
+        let mut device = Cursor::new(vec![0xFF; 8 * PAGE_SIZE]);
+        let mut log = LogFile::from_stream(&mut device, logfile_stream_at(0, 8), 4096).unwrap();
+        assert!(log.restart_area().is_none());
+        assert!(log.report().unwrap().is_clean());
+
+        let mut device = Cursor::new(vec![0u8; 8 * PAGE_SIZE]);
+        assert!(LogFile::from_stream(&mut device, logfile_stream_at(0, 8), 4096).is_err());
+    }
+}
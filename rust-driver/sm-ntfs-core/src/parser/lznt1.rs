@@ -0,0 +1,191 @@
+//! LZNT1 decompression
+//!
+//! NTFS compresses a stream in compression units (16 clusters by default).
+//! Each unit is split into 4 KiB chunks, compressed separately. A chunk
+//! starts with a 16-bit header: the low 12 bits hold the chunk's stored size
+//! minus 3 (header included), bit 15 is set if the chunk is compressed. A
+//! header of zero ends the unit early.
+//!
+//! A compressed chunk is a series of groups of one flag byte and up to eight
+//! items. A clear flag bit means a literal byte, a set bit a 16-bit
+//! back-reference whose split between offset and length depends on how much
+//! of the chunk has been decompressed so far.
+
+use crate::utils::bytes::le_u16;
+use crate::utils::error::{Result, SMNtfsError};
+
+/// Uncompressed size of a chunk
+pub const CHUNK_SIZE: usize = 4096;
+
+/// Decompress LZNT1 data into `output`, returning the number of bytes produced
+///
+/// Every chunk starts at a 4 KiB boundary of `output`; a chunk that
+/// decompresses to less leaves the rest of its 4 KiB untouched, which is
+/// where Windows expects zeros. Data that would not fit is an error.
+pub fn decompress(input: &[u8], output: &mut [u8]) -> Result<usize> {
+    let mut position = 0;
+    let mut chunk_start = 0;
+    let mut produced = 0;
+
+    while position + 2 <= input.len() {
+        let header = le_u16(input, position);
+        if header == 0 {
+            break;
+        }
+
+        let size = (header & 0x0FFF) as usize + 1;
+        let data = input.get(position + 2..position + 2 + size).ok_or_else(|| {
+            SMNtfsError::ReadError(format!("LZNT1 chunk at offset {} is truncated", position))
+        })?;
+        let window = output
+            .get_mut(chunk_start..(chunk_start + CHUNK_SIZE).min(output.len()))
+            .filter(|window| !window.is_empty())
+            .ok_or_else(|| SMNtfsError::ReadError("LZNT1 data exceeds its compression unit".to_string()))?;
+
+        let length = if header & 0x8000 != 0 {
+            decompress_chunk(data, window)?
+        } else {
+            let length = data.len().min(window.len());
+            window[..length].copy_from_slice(&data[..length]);
+            length
+        };
+
+        produced = chunk_start + length;
+        position += 2 + size;
+        chunk_start += CHUNK_SIZE;
+    }
+
+    Ok(produced)
+}
+
+/// Decompress one compressed chunk into `output`
+fn decompress_chunk(data: &[u8], output: &mut [u8]) -> Result<usize> {
+    let mut input = 0;
+    let mut out = 0;
+
+    while input < data.len() {
+        let flags = data[input];
+        input += 1;
+
+        for bit in 0..8 {
+            if input >= data.len() {
+                break;
+            }
+
+            if flags & (1 << bit) == 0 {
+                let byte = output.get_mut(out).ok_or_else(overflow)?;
+                *byte = data[input];
+                input += 1;
+                out += 1;
+                continue;
+            }
+
+            if input + 2 > data.len() || out == 0 {
+                return Err(SMNtfsError::ReadError("Invalid LZNT1 back-reference".to_string()));
+            }
+            let token = le_u16(data, input);
+            input += 2;
+
+            // The offset field grows as the output does, taking bits from the length
+            let offset_bits = (usize::BITS - (out - 1).max(0x0F).leading_zeros()) as usize;
+            let length_mask = 0xFFFF >> offset_bits;
+            let length = (token as usize & length_mask) + 3;
+            let offset = (token as usize >> (16 - offset_bits)) + 1;
+
+            if offset > out {
+                return Err(SMNtfsError::ReadError(format!(
+                    "LZNT1 back-reference to {} bytes before the start of the chunk",
+                    offset - out
+                )));
+            }
+            if out + length > output.len() {
+                return Err(overflow());
+            }
+            // Byte by byte, since the source may overlap what is being written
+            for i in out..out + length {
+                output[i] = output[i - offset];
+            }
+            out += length;
+        }
+    }
+
+    Ok(out)
+}
+
+fn overflow() -> SMNtfsError {
+    SMNtfsError::ReadError("LZNT1 chunk decompresses past 4 KiB".to_string())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Encode data as uncompressed chunks
+    pub(crate) fn stored_chunks(data: &[u8]) -> Vec<u8> {
+        let mut encoded = Vec::new();
+        for chunk in data.chunks(CHUNK_SIZE) {
+            encoded.extend_from_slice(&(0x3000 | (chunk.len() as u16 - 1)).to_le_bytes());
+            encoded.extend_from_slice(chunk);
+        }
+        encoded
+    }
+
+    /// A compressed chunk decoding to `abc` repeated four times
+    pub(crate) fn abc_chunk() -> Vec<u8> {
+        // Three literals, then a 9 byte back-reference 3 bytes back
+        let data = [0x08, b'a', b'b', b'c', 0x06, 0x20];
+        let mut chunk = (0xB000u16 | (data.len() as u16 - 1)).to_le_bytes().to_vec();
+        chunk.extend_from_slice(&data);
+        chunk
+    }
+
+    #[test]
+    fn test_compressed_chunk() {
+        let mut output = [0u8; 16];
+        assert_eq!(decompress(&abc_chunk(), &mut output).unwrap(), 12);
+        assert_eq!(&output[..12], b"abcabcabcabc");
+    }
+
+    #[test]
+    fn test_wide_offsets() {
+        // 32 literals, then a back-reference once the offset field is 5 bits wide
+        let literals: Vec<u8> = (0..32).collect();
+        let mut data = Vec::new();
+        for group in literals.chunks(8) {
+            data.push(0);
+            data.extend_from_slice(group);
+        }
+        // Offset 32, length 4: (31 << 11) | (4 - 3)
+        data.push(0x01);
+        data.extend_from_slice(&((31u16 << 11) | 1).to_le_bytes());
+        let mut input = (0xB000u16 | (data.len() as u16 - 1)).to_le_bytes().to_vec();
+        input.extend_from_slice(&data);
+
+        let mut output = [0u8; 64];
+        assert_eq!(decompress(&input, &mut output).unwrap(), 36);
+        assert_eq!(&output[32..36], &[0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn test_short_chunks_leave_gaps() {
+        let mut input = abc_chunk();
+        input.extend_from_slice(&stored_chunks(b"tail"));
+        input.extend_from_slice(&[0, 0]);
+
+        let mut output = vec![0xFFu8; 2 * CHUNK_SIZE];
+        assert_eq!(decompress(&input, &mut output).unwrap(), CHUNK_SIZE + 4);
+        assert_eq!(&output[CHUNK_SIZE..CHUNK_SIZE + 4], b"tail");
+        assert_eq!(output[12], 0xFF);
+    }
+
+    #[test]
+    fn test_invalid_data() {
+        let mut output = [0u8; 8];
+        // Decompresses to 12 bytes
+        assert!(decompress(&abc_chunk(), &mut output).is_err());
+        // Back-reference before the start of the chunk
+        assert!(decompress(&[0x02, 0xB0, 0x01, 0x00, 0x00], &mut output).is_err());
+        // Truncated chunk
+        assert!(decompress(&abc_chunk()[..5], &mut [0u8; 16]).is_err());
+    }
+}
@@ -0,0 +1,288 @@
+//! LZX decompression, WIM flavour
+//!
+//! WOF's LZX algorithm compresses every 32 KiB chunk on its own with a
+//! 32 KiB window. A chunk is a series of blocks, each starting with a 3-bit
+//! type and its size:
+//!
+//! - verbatim blocks carry a main tree (literals and match headers) and a
+//!   length tree, both sent as deltas from the previous block's lengths
+//!   through a small pretree;
+//! - aligned blocks add an 8-symbol tree for the low 3 bits of large offsets;
+//! - uncompressed blocks hold raw bytes after a 16-bit alignment.
+//!
+//! Matches may repeat one of the last three offsets. Once decoded, the
+//! chunk's x86 `call` targets (E8 followed by an absolute address) are
+//! turned back into relative ones.
+
+use crate::parser::huffman::{BitReader, HuffmanTable};
+use crate::utils::bytes::le_u32;
+use crate::utils::error::{Result, SMNtfsError};
+
+/// Offset slots used by a 32 KiB window
+const OFFSET_SLOTS: usize = 30;
+
+/// Literals plus 8 length headers per offset slot
+const MAIN_SYMBOLS: usize = 256 + 8 * OFFSET_SLOTS;
+
+/// Lengths 9 and up, as `length - 9`
+const LENGTH_SYMBOLS: usize = 249;
+
+const PRETREE_SYMBOLS: usize = 20;
+const ALIGNED_SYMBOLS: usize = 8;
+
+/// Longest main or length code
+const MAX_CODE_LENGTH: u32 = 16;
+
+/// Block size when the size flag is set
+const DEFAULT_BLOCK_SIZE: usize = 32768;
+
+/// File size WIM uses for the x86 call translation
+const E8_FILE_SIZE: i32 = 12_000_000;
+
+mod block_type {
+    pub const VERBATIM: u32 = 1;
+    pub const ALIGNED: u32 = 2;
+    pub const UNCOMPRESSED: u32 = 3;
+}
+
+/// Extra bits for an offset slot
+fn footer_bits(slot: usize) -> u32 {
+    (slot as u32 / 2).saturating_sub(1)
+}
+
+/// Smallest formatted offset of an offset slot
+fn slot_base(slot: usize) -> usize {
+    (0..slot).map(|s| 1usize << footer_bits(s)).sum()
+}
+
+/// Decompress one LZX chunk filling `output`
+pub fn decompress(input: &[u8], output: &mut [u8]) -> Result<()> {
+    let mut reader = BitReader::new(input);
+    let mut main_lengths = [0u8; MAIN_SYMBOLS];
+    let mut length_lengths = [0u8; LENGTH_SYMBOLS];
+    let mut recent = [1usize; 3];
+    let mut out = 0;
+
+    while out < output.len() {
+        let kind = reader.read(3);
+        let size = match reader.read(1) {
+            1 => DEFAULT_BLOCK_SIZE,
+            _ => reader.read(16) as usize,
+        };
+        let end = out + size.min(output.len() - out);
+        if size == 0 {
+            return Err(SMNtfsError::ReadError("Empty LZX block".to_string()));
+        }
+
+        match kind {
+            block_type::VERBATIM | block_type::ALIGNED => {
+                let aligned = match kind {
+                    block_type::ALIGNED => {
+                        let lengths: Vec<u8> = (0..ALIGNED_SYMBOLS).map(|_| reader.read(3) as u8).collect();
+                        Some(HuffmanTable::new(&lengths, 7)?)
+                    }
+                    _ => None,
+                };
+                read_lengths(&mut reader, &mut main_lengths[..256])?;
+                read_lengths(&mut reader, &mut main_lengths[256..])?;
+                read_lengths(&mut reader, &mut length_lengths)?;
+                let main = HuffmanTable::new(&main_lengths, MAX_CODE_LENGTH)?;
+                let lengths = HuffmanTable::new(&length_lengths, MAX_CODE_LENGTH)?;
+
+                while out < end {
+                    let symbol = reader.decode(&main)? as usize;
+                    if symbol < 256 {
+                        output[out] = symbol as u8;
+                        out += 1;
+                        continue;
+                    }
+
+                    let mut length = (symbol - 256) & 7;
+                    if length == 7 {
+                        length += reader.decode(&lengths)? as usize;
+                    }
+                    let length = length + 2;
+
+                    let slot = (symbol - 256) >> 3;
+                    let offset = match slot {
+                        0 => recent[0],
+                        1 => {
+                            recent.swap(0, 1);
+                            recent[0]
+                        }
+                        2 => {
+                            recent.swap(0, 2);
+                            recent[0]
+                        }
+                        _ => {
+                            let extra = footer_bits(slot);
+                            let footer = match &aligned {
+                                Some(aligned) if extra >= 3 => {
+                                    let verbatim = (reader.read(extra - 3) as usize) << 3;
+                                    verbatim + reader.decode(aligned)? as usize
+                                }
+                                _ => reader.read(extra) as usize,
+                            };
+                            let offset = slot_base(slot) + footer - 2;
+                            recent = [offset, recent[0], recent[1]];
+                            offset
+                        }
+                    };
+
+                    if offset > out {
+                        return Err(SMNtfsError::ReadError(format!(
+                            "LZX match {} bytes before the start of the chunk",
+                            offset - out
+                        )));
+                    }
+                    // Byte by byte, since the source may overlap what is being written
+                    for i in out..(out + length).min(output.len()) {
+                        output[i] = output[i - offset];
+                    }
+                    out += length;
+                }
+            }
+            block_type::UNCOMPRESSED => {
+                reader.align();
+                let header = reader.bytes(12)?;
+                recent = [0, 4, 8].map(|at| le_u32(header, at) as usize);
+                output[out..end].copy_from_slice(reader.bytes(end - out)?);
+                if size % 2 == 1 {
+                    reader.bytes(1)?;
+                }
+                out = end;
+            }
+            _ => return Err(SMNtfsError::ReadError(format!("Invalid LZX block type {}", kind))),
+        }
+    }
+
+    undo_e8_translation(output);
+    Ok(())
+}
+
+/// Read code lengths as deltas from their previous values
+fn read_lengths(reader: &mut BitReader, lengths: &mut [u8]) -> Result<()> {
+    let pretree_lengths: Vec<u8> = (0..PRETREE_SYMBOLS).map(|_| reader.read(4) as u8).collect();
+    let pretree = HuffmanTable::new(&pretree_lengths, MAX_CODE_LENGTH)?;
+    let delta = |previous: u8, symbol: u16| ((previous as u16 + 17 - symbol) % 17) as u8;
+
+    let mut i = 0;
+    while i < lengths.len() {
+        let symbol = reader.decode(&pretree)?;
+        let (count, value) = match symbol {
+            17 => (4 + reader.read(4) as usize, None),
+            18 => (20 + reader.read(5) as usize, None),
+            19 => {
+                let count = 4 + reader.read(1) as usize;
+                let symbol = reader.decode(&pretree)?;
+                if symbol > 16 {
+                    return Err(SMNtfsError::ReadError("Invalid LZX pretree run".to_string()));
+                }
+                (count, Some(symbol))
+            }
+            _ => (1, Some(symbol)),
+        };
+
+        // A run repeats the value derived from its first length
+        let value = value.map_or(0, |symbol| delta(lengths[i], symbol));
+        lengths
+            .get_mut(i..i + count)
+            .ok_or_else(|| SMNtfsError::ReadError("LZX code length run overflows its tree".to_string()))?
+            .fill(value);
+        i += count;
+    }
+    Ok(())
+}
+
+/// Turn absolute `call` targets back into relative ones
+fn undo_e8_translation(data: &mut [u8]) {
+    if data.len() <= 10 {
+        return;
+    }
+
+    let mut i = 0;
+    while i < data.len() - 10 {
+        if data[i] != 0xE8 {
+            i += 1;
+            continue;
+        }
+        let absolute = le_u32(data, i + 1) as i32;
+        if absolute >= -(i as i32) && absolute < E8_FILE_SIZE {
+            let relative = match absolute >= 0 {
+                true => absolute - i as i32,
+                false => absolute + E8_FILE_SIZE,
+            };
+            data[i + 1..i + 5].copy_from_slice(&relative.to_le_bytes());
+        }
+        i += 5;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::huffman::tests::pack_bits;
+
+    /// Pretree giving codes 15 = 00, 16 = 01, 17 = 10, 18 = 11
+    fn pretree() -> Vec<(u32, u32)> {
+        (0..PRETREE_SYMBOLS as u32).map(|s| (if (15..=18).contains(&s) { 2 } else { 0 }, 4)).collect()
+    }
+
+    /// A run of `count` zero lengths, 20 to 51 long
+    fn zeros(count: u32) -> [(u32, u32); 2] {
+        [(0b11, 2), (count - 20, 5)]
+    }
+
+    #[test]
+    fn test_verbatim_block() {
+        // Main tree: 'a' = 1 bit, 'b' and a slot 4 match of length 6 = 2 bits
+        let mut bits = vec![(block_type::VERBATIM, 3), (0, 1), (8, 16)];
+        bits.extend(pretree());
+        bits.extend(zeros(51));
+        bits.extend(zeros(46));
+        bits.extend([(0b01, 2), (0b00, 2)]);
+        bits.extend(zeros(51).repeat(3));
+        bits.extend([(0b10, 2), (0, 4)]);
+
+        bits.extend(pretree());
+        bits.extend(zeros(36));
+        bits.push((0b00, 2));
+        bits.extend(zeros(51).repeat(3));
+        bits.extend(zeros(50));
+
+        bits.extend(pretree());
+        bits.extend(zeros(51).repeat(4));
+        bits.extend(zeros(45));
+
+        // a, b, then 6 bytes from offset 4 + 0 - 2
+        bits.extend([(0b0, 1), (0b10, 2), (0b11, 2), (0, 1)]);
+
+        let mut output = [0u8; 8];
+        decompress(&pack_bits(&bits), &mut output).unwrap();
+        assert_eq!(&output, b"abababab");
+    }
+
+    #[test]
+    fn test_uncompressed_block_and_e8() {
+        let data = [0x90, 0xE8, 0x10, 0, 0, 0, 1, 2, 3, 4, 5, 6, 7];
+        let mut input = pack_bits(&[(block_type::UNCOMPRESSED, 3), (0, 1), (data.len() as u32, 16)]);
+        for recent in [1u32, 1, 1] {
+            input.extend_from_slice(&recent.to_le_bytes());
+        }
+        input.extend_from_slice(&data);
+        input.push(0);
+
+        let mut output = [0u8; 13];
+        decompress(&input, &mut output).unwrap();
+        // The call target at 1 is relative again: 16 - 1
+        assert_eq!(&output[..6], &[0x90, 0xE8, 0x0F, 0, 0, 0]);
+        assert_eq!(&output[6..], &data[6..]);
+    }
+
+    #[test]
+    fn test_offset_slots() {
+        assert_eq!((0..6).map(slot_base).collect::<Vec<_>>(), vec![0, 1, 2, 3, 4, 6]);
+        assert_eq!(footer_bits(29), 13);
+        assert!(decompress(&pack_bits(&[(0, 3), (1, 1)]), &mut [0u8; 4]).is_err());
+    }
+}
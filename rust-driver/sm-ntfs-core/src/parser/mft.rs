@@ -1,12 +1,100 @@
 //! MFT (Master File Table) operations
 
-use ntfs::{Ntfs, NtfsFile};
+use ntfs::{Ntfs, NtfsFile, NtfsFileReference};
 use ntfs::structured_values::NtfsFileNamespace;
 use std::io::{Read, Seek};
+use crate::parser::time::FileTimes;
 use crate::utils::error::{Result, SMNtfsError};
 
+/// Reference to an MFT record: record number plus the sequence number it was valid for
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct FileReference {
+    /// File record number
+    pub record_number: u64,
+
+    /// Sequence number (incremented each time the record is reused)
+    pub sequence_number: u16,
+}
+
+impl From<NtfsFileReference> for FileReference {
+    fn from(reference: NtfsFileReference) -> Self {
+        Self {
+            record_number: reference.file_record_number(),
+            sequence_number: reference.sequence_number(),
+        }
+    }
+}
+
+/// Windows file attribute flags (FILE_ATTRIBUTE_*)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct FileAttributes(pub u32);
+
+impl FileAttributes {
+    /// Read-only
+    pub const READ_ONLY: u32 = 0x0001;
+    /// Hidden
+    pub const HIDDEN: u32 = 0x0002;
+    /// System file
+    pub const SYSTEM: u32 = 0x0004;
+    /// Marked for archival
+    pub const ARCHIVE: u32 = 0x0020;
+    /// Stored sparsely
+    pub const SPARSE: u32 = 0x0200;
+    /// Has a reparse point
+    pub const REPARSE_POINT: u32 = 0x0400;
+    /// Compressed
+    pub const COMPRESSED: u32 = 0x0800;
+    /// Encrypted (EFS)
+    pub const ENCRYPTED: u32 = 0x4000;
+
+    /// Check whether all bits of `flag` are set
+    pub fn contains(&self, flag: u32) -> bool {
+        self.0 & flag == flag
+    }
+
+    /// Check if the file is read-only
+    pub fn is_read_only(&self) -> bool {
+        self.contains(Self::READ_ONLY)
+    }
+
+    /// Check if the file is hidden
+    pub fn is_hidden(&self) -> bool {
+        self.contains(Self::HIDDEN)
+    }
+
+    /// Check if the file is a system file
+    pub fn is_system(&self) -> bool {
+        self.contains(Self::SYSTEM)
+    }
+
+    /// Check if the file is marked for archival
+    pub fn is_archive(&self) -> bool {
+        self.contains(Self::ARCHIVE)
+    }
+
+    /// Check if the file is sparse
+    pub fn is_sparse(&self) -> bool {
+        self.contains(Self::SPARSE)
+    }
+
+    /// Check if the file is a reparse point
+    pub fn is_reparse_point(&self) -> bool {
+        self.contains(Self::REPARSE_POINT)
+    }
+
+    /// Check if the file is compressed
+    pub fn is_compressed(&self) -> bool {
+        self.contains(Self::COMPRESSED)
+    }
+
+    /// Check if the file is encrypted
+    pub fn is_encrypted(&self) -> bool {
+        self.contains(Self::ENCRYPTED)
+    }
+}
+
 /// Information about a file/directory entry
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct FileInfo {
     /// File name
     pub name: String,
@@ -22,6 +110,30 @@ pub struct FileInfo {
 
     /// Allocated size (may be larger than actual size)
     pub allocated_size: u64,
+
+    /// Sequence number of the file record
+    pub sequence_number: u16,
+
+    /// Hard link count from the file record header
+    pub hard_link_count: u16,
+
+    /// Directory this entry's name belongs to
+    pub parent: FileReference,
+
+    /// Windows attribute flags from $STANDARD_INFORMATION
+    pub attributes: FileAttributes,
+
+    /// Timestamps from $STANDARD_INFORMATION (what Windows shows and updates)
+    pub times: FileTimes,
+
+    /// Timestamps from the $FILE_NAME attribute this name was read from
+    pub file_name_times: FileTimes,
+
+    /// Namespace of the name, see [`namespace`](crate::parser::values::namespace)
+    pub namespace: u8,
+
+    /// Index into $Secure (0 if the file has its own descriptor or predates NTFS 3.0)
+    pub security_id: u32,
 }
 
 impl FileInfo {
@@ -37,32 +149,50 @@ impl FileInfo {
                 .map_err(|_| SMNtfsError::CorruptedMft {
                     offset: file.file_record_number(),
                 })?
-                .name()
-                .to_string_lossy()
         } else if let Some(name_result) = file.name(fs, None, parent_record_number) {
             name_result
                 .map_err(|_| SMNtfsError::CorruptedMft {
                     offset: file.file_record_number(),
                 })?
-                .name()
-                .to_string_lossy()
         } else {
             return Err(SMNtfsError::CorruptedMft {
                 offset: file.file_record_number(),
             });
         };
 
+        let standard_info = file.info().map_err(|_| SMNtfsError::CorruptedMft {
+            offset: file.file_record_number(),
+        })?;
+
         // Get file information from file directly
         let size = file.data_size() as u64;
         let allocated_size = file.allocated_size() as u64;
         let is_directory = file.is_directory();
 
         Ok(Self {
-            name: file_name,
+            name: file_name.name().to_string_lossy(),
+            namespace: file_name.namespace() as u8,
             size,
             is_directory,
             record_number: file.file_record_number(),
             allocated_size,
+            sequence_number: file.sequence_number(),
+            hard_link_count: file.hard_link_count(),
+            parent: file_name.parent_directory_reference().into(),
+            attributes: FileAttributes(standard_info.file_attributes().bits()),
+            times: FileTimes::from_ntfs(
+                standard_info.creation_time(),
+                standard_info.modification_time(),
+                standard_info.mft_record_modification_time(),
+                standard_info.access_time(),
+            ),
+            file_name_times: FileTimes::from_ntfs(
+                file_name.creation_time(),
+                file_name.modification_time(),
+                file_name.mft_record_modification_time(),
+                file_name.access_time(),
+            ),
+            security_id: standard_info.security_id().unwrap_or(0),
         })
     }
 
@@ -73,10 +203,25 @@ impl FileInfo {
 }
 
 /// List directory contents
+///
+/// Returns one entry per name. Short 8.3 aliases of a long name are left
+/// out, see [`list_directory_with_options`].
 pub fn list_directory<T: Read + Seek>(
     ntfs: &Ntfs,
     directory: &NtfsFile,
     fs: &mut T,
+) -> Result<Vec<FileInfo>> {
+    list_directory_with_options(ntfs, directory, fs, false)
+}
+
+/// List directory contents, optionally including DOS-only 8.3 aliases
+///
+/// A file with several hard links in the directory is listed once per link.
+pub fn list_directory_with_options<T: Read + Seek>(
+    ntfs: &Ntfs,
+    directory: &NtfsFile,
+    fs: &mut T,
+    show_dos_names: bool,
 ) -> Result<Vec<FileInfo>> {
     let index = directory
         .directory_index(fs)
@@ -97,13 +242,27 @@ pub fn list_directory<T: Read + Seek>(
         };
 
         // Skip empty names
-        if file_name.name().len() == 0 {
+        if file_name.name().is_empty() {
+            continue;
+        }
+
+        // The long name of the same file has its own entry
+        if file_name.namespace() == NtfsFileNamespace::Dos && !show_dos_names {
             continue;
         }
 
         // Get the file for this entry
         if let Ok(file) = entry.to_file(ntfs, fs) {
-            if let Ok(info) = FileInfo::from_ntfs_file(&file, fs, Some(parent_record_number)) {
+            if let Ok(mut info) = FileInfo::from_ntfs_file(&file, fs, Some(parent_record_number)) {
+                // Report the name of this entry, not the file's preferred one
+                info.name = file_name.name().to_string_lossy();
+                info.namespace = file_name.namespace() as u8;
+                info.file_name_times = FileTimes::from_ntfs(
+                    file_name.creation_time(),
+                    file_name.modification_time(),
+                    file_name.mft_record_modification_time(),
+                    file_name.access_time(),
+                );
                 entries.push(info);
             }
         }
@@ -124,6 +283,7 @@ mod tests {
             is_directory: false,
             record_number: 0,
             allocated_size: 4096,
+            ..FileInfo::default()
         };
 
         assert!(info.is_system_file());
@@ -137,9 +297,19 @@ mod tests {
             is_directory: false,
             record_number: 100,
             allocated_size: 4096,
+            ..FileInfo::default()
         };
 
         assert!(!info.is_system_file());
         assert!(!info.is_directory);
     }
+
+    #[test]
+    fn test_file_attributes() {
+        let attributes = FileAttributes(FileAttributes::HIDDEN | FileAttributes::SYSTEM);
+        assert!(attributes.is_hidden());
+        assert!(attributes.is_system());
+        assert!(!attributes.is_read_only());
+        assert!(!attributes.is_reparse_point());
+    }
 }
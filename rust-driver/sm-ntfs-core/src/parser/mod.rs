@@ -4,9 +4,66 @@
 //! for parsing and accessing NTFS filesystem structures.
 
 pub mod volume;
+pub mod boot;
+pub mod volume_info;
+pub mod recovery;
+pub mod hibernation;
+pub mod probe;
 pub mod mft;
 pub mod streams;
+pub mod path;
+pub mod upcase;
+pub mod time;
+pub mod runs;
+pub mod record;
+pub mod attribute;
+pub mod reader;
+pub mod lznt1;
+pub(crate) mod huffman;
+pub mod xpress;
+pub mod lzx;
+pub mod wof;
+pub mod extents;
+pub mod scan;
+pub mod values;
+pub mod bitmap;
+pub mod undelete;
+pub mod usn;
+pub mod logfile;
+pub mod security;
+pub mod secure;
+pub mod check;
+pub mod permissions;
+pub mod reparse;
+pub mod links;
+pub mod ea;
 
-pub use volume::{NtfsVolume, BlockDeviceAdapter};
-pub use mft::{FileInfo, list_directory};
+pub use volume::{NtfsVolume, ResolvedPath, BlockDeviceAdapter};
+pub use path::NtfsPath;
+pub use upcase::UpcaseTable;
+pub use boot::BootSector;
+pub use volume_info::{VolumeFlags, VolumeInformation};
+pub use recovery::{OpenedVolume, PatchedDevice, WriteOptions, open_volume, open_volume_writable};
+pub use hibernation::{HIBERFIL_PATH, discard_hibernation, is_hibernated};
+pub use probe::{Filesystem, probe, probe_device};
+pub use mft::{FileInfo, FileAttributes, FileReference, list_directory, list_directory_with_options};
+pub use time::FileTimes;
+pub use runs::DataRun;
+pub use record::FileRecord;
+pub use attribute::AttributeStream;
+pub use reader::StreamReader;
+pub use extents::{StreamExtents, file_extents};
+pub use scan::MftRecords;
+pub use bitmap::{ClusterBitmap, FreeExtents};
+pub use undelete::{DeletedFile, DeletedStream, Recoverability, RecoveryChance, export_file, find_deleted_files};
+pub use usn::{UsnJournal, UsnJournalInfo, UsnReason, UsnRecord};
+pub use logfile::{LogFile, LogFileReport, LogOperation, LogRecord, LogTransaction, RestartArea};
+pub use security::{Ace, Acl, SecurityDescriptor, Sid};
+pub use secure::{SecureFile, file_security_descriptor};
+pub use check::{CheckReport, Problem, ProblemClass, check_volume};
+pub use permissions::{PosixPermissions, UserMapping};
+pub use reparse::{ReparsePoint, ReparseTag, read_reparse_point};
+pub use links::{HardLink, hard_link_paths, hard_links};
+pub use wof::{WofAlgorithm, WofChunks};
+pub use ea::{EaInformation, ExtendedAttribute, WslMetadata, read_extended_attributes};
 pub use streams::{StreamInfo, list_streams, read_default_stream, read_named_stream};
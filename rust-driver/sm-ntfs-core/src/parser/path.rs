@@ -0,0 +1,153 @@
+//! Path parsing for volume lookups
+//!
+//! Accepts `/` and `\` as separators, an optional drive prefix (`C:`), and the
+//! `file:stream` / `file:stream:$DATA` alternate data stream syntax on the last
+//! component.
+
+use crate::utils::error::{Result, SMNtfsError};
+
+/// A path split into directory components and an optional stream name
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NtfsPath<'p> {
+    /// File name components from the root, without `.` and `..`
+    pub components: Vec<&'p str>,
+
+    /// Alternate data stream name (empty for the default stream)
+    pub stream_name: &'p str,
+}
+
+impl<'p> NtfsPath<'p> {
+    /// Parse a path string
+    pub fn parse(path: &'p str) -> Result<Self> {
+        let path = strip_drive_prefix(path);
+        let raw: Vec<&str> = path.split(['/', '\\']).filter(|c| !c.is_empty()).collect();
+
+        let mut components = Vec::with_capacity(raw.len());
+        let mut stream_name = "";
+
+        for (i, component) in raw.iter().enumerate() {
+            let is_last = i + 1 == raw.len();
+
+            let name = match component.split_once(':') {
+                Some((name, stream)) => {
+                    if !is_last {
+                        return Err(SMNtfsError::InvalidPath(format!(
+                            "Stream syntax is only allowed on the last component: '{}'",
+                            component
+                        )));
+                    }
+                    stream_name = parse_stream_spec(component, stream)?;
+                    name
+                }
+                None => component,
+            };
+
+            match name {
+                "." => {}
+                ".." => {
+                    components.pop();
+                }
+                "" => {
+                    return Err(SMNtfsError::InvalidPath(format!(
+                        "Missing file name before stream in '{}'",
+                        component
+                    )))
+                }
+                _ => components.push(name),
+            }
+        }
+
+        if !stream_name.is_empty() && components.is_empty() {
+            return Err(SMNtfsError::InvalidPath(
+                "The root directory has no named streams".to_string(),
+            ));
+        }
+
+        Ok(Self {
+            components,
+            stream_name,
+        })
+    }
+
+    /// Check if the path refers to the root directory
+    pub fn is_root(&self) -> bool {
+        self.components.is_empty()
+    }
+}
+
+/// Strip a leading `X:` drive designator
+fn strip_drive_prefix(path: &str) -> &str {
+    let bytes = path.as_bytes();
+    if bytes.len() >= 2
+        && bytes[0].is_ascii_alphabetic()
+        && bytes[1] == b':'
+        && (bytes.len() == 2 || bytes[2] == b'/' || bytes[2] == b'\\')
+    {
+        &path[2..]
+    } else {
+        path
+    }
+}
+
+/// Validate `stream` or `stream:$DATA` and return the stream name
+fn parse_stream_spec<'p>(component: &str, spec: &'p str) -> Result<&'p str> {
+    let (name, ty) = match spec.split_once(':') {
+        Some((name, ty)) => (name, Some(ty)),
+        None => (spec, None),
+    };
+
+    if let Some(ty) = ty {
+        if !ty.eq_ignore_ascii_case("$DATA") {
+            return Err(SMNtfsError::InvalidPath(format!(
+                "Unsupported stream type '{}' in '{}'",
+                ty, component
+            )));
+        }
+    } else if name.is_empty() {
+        return Err(SMNtfsError::InvalidPath(format!(
+            "Empty stream name in '{}'",
+            component
+        )));
+    }
+
+    Ok(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_separators() {
+        let unix = NtfsPath::parse("/Users/alice/Documents/report.docx").unwrap();
+        let windows = NtfsPath::parse("C:\\Users\\alice\\Documents\\report.docx").unwrap();
+        assert_eq!(unix, windows);
+        assert_eq!(unix.components, vec!["Users", "alice", "Documents", "report.docx"]);
+        assert!(unix.stream_name.is_empty());
+    }
+
+    #[test]
+    fn test_parse_root_and_dots() {
+        assert!(NtfsPath::parse("/").unwrap().is_root());
+        assert!(NtfsPath::parse("").unwrap().is_root());
+        assert_eq!(NtfsPath::parse("/a/./b/../c").unwrap().components, vec!["a", "c"]);
+    }
+
+    #[test]
+    fn test_parse_streams() {
+        let path = NtfsPath::parse("/dir/file.txt:Zone.Identifier").unwrap();
+        assert_eq!(path.components, vec!["dir", "file.txt"]);
+        assert_eq!(path.stream_name, "Zone.Identifier");
+
+        assert_eq!(NtfsPath::parse("file:s:$DATA").unwrap().stream_name, "s");
+        assert_eq!(NtfsPath::parse("file::$DATA").unwrap().stream_name, "");
+    }
+
+    #[test]
+    fn test_parse_invalid_streams() {
+        assert!(NtfsPath::parse("dir:s/file").is_err());
+        assert!(NtfsPath::parse("file:").is_err());
+        assert!(NtfsPath::parse(":s").is_err());
+        assert!(NtfsPath::parse("file:s:$INDEX_ROOT").is_err());
+    }
+}
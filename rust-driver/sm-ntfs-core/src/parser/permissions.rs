@@ -0,0 +1,263 @@
+//! Mapping of Windows security to POSIX ownership and mode bits
+//!
+//! SIDs are mapped to uids and gids with a user mapping file in the format
+//! used by ntfs-3g, one `uid:gid:SID` line per mapping:
+//!
+//! ```text
+//! # Alice's account on the Windows machine
+//! 501::S-1-5-21-3141592653-589793238-462643383-1001
+//! :20:S-1-5-21-3141592653-589793238-462643383-513
+//! ```
+//!
+//! A line with a uid maps the SID as a file owner; a line with a gid maps it
+//! as a group. Unmapped SIDs get the default uid and gid. Mode bits are
+//! derived from the DACL entries for the owner, the group and Everyone.
+
+use std::path::Path;
+use crate::parser::security::{access, ace_type, Sid, SecurityDescriptor};
+use crate::utils::error::{Result, SMNtfsError};
+
+/// Ownership and permissions of a file as presented to POSIX
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PosixPermissions {
+    /// Owner user ID
+    pub uid: u32,
+
+    /// Owner group ID
+    pub gid: u32,
+
+    /// Permission bits (`0o777` at most, `0o7777` with WSL metadata)
+    pub mode: u32,
+}
+
+/// SID to uid/gid mapping, see the module documentation
+#[derive(Debug, Clone)]
+pub struct UserMapping {
+    users: Vec<(Sid, u32)>,
+    groups: Vec<(Sid, u32)>,
+    default_uid: u32,
+    default_gid: u32,
+}
+
+impl UserMapping {
+    /// Create an empty mapping; every SID maps to the defaults
+    pub fn new(default_uid: u32, default_gid: u32) -> Self {
+        Self {
+            users: Vec::new(),
+            groups: Vec::new(),
+            default_uid,
+            default_gid,
+        }
+    }
+
+    /// Parse the contents of a user mapping file
+    pub fn parse(text: &str, default_uid: u32, default_gid: u32) -> Result<Self> {
+        let mut mapping = Self::new(default_uid, default_gid);
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let invalid = |reason: &str| {
+                SMNtfsError::InvalidUserMapping(format!("line {}: {} in '{}'", number + 1, reason, line))
+            };
+            let fields: Vec<&str> = line.splitn(3, ':').collect();
+            let &[uid, gid, sid] = fields.as_slice() else {
+                return Err(invalid("expected uid:gid:SID"));
+            };
+            let id = |field: &str| -> Result<Option<u32>> {
+                match field.trim() {
+                    "" => Ok(None),
+                    field => field.parse().map(Some).map_err(|_| invalid("invalid id")),
+                }
+            };
+
+            let sid: Sid = sid.trim().parse()?;
+            match (id(uid)?, id(gid)?) {
+                (None, None) => return Err(invalid("no uid or gid")),
+                (uid, gid) => {
+                    if let Some(uid) = uid {
+                        mapping.add_user(sid.clone(), uid);
+                    }
+                    if let Some(gid) = gid {
+                        mapping.add_group(sid, gid);
+                    }
+                }
+            }
+        }
+
+        Ok(mapping)
+    }
+
+    /// Read a user mapping file
+    pub fn load(path: impl AsRef<Path>, default_uid: u32, default_gid: u32) -> Result<Self> {
+        let text = std::fs::read_to_string(path)?;
+        Self::parse(&text, default_uid, default_gid)
+    }
+
+    /// Map a SID to a uid
+    pub fn add_user(&mut self, sid: Sid, uid: u32) {
+        self.users.push((sid, uid));
+    }
+
+    /// Map a SID to a gid
+    pub fn add_group(&mut self, sid: Sid, gid: u32) {
+        self.groups.push((sid, gid));
+    }
+
+    /// uid of an owner SID
+    pub fn uid(&self, sid: &Sid) -> u32 {
+        lookup(&self.users, sid).unwrap_or(self.default_uid)
+    }
+
+    /// gid of a group SID
+    pub fn gid(&self, sid: &Sid) -> u32 {
+        lookup(&self.groups, sid).unwrap_or(self.default_gid)
+    }
+
+    /// SID mapped to a uid, if any
+    pub fn user_sid(&self, uid: u32) -> Option<&Sid> {
+        self.users.iter().find(|(_, id)| *id == uid).map(|(sid, _)| sid)
+    }
+
+    /// SID mapped to a gid, if any
+    pub fn group_sid(&self, gid: u32) -> Option<&Sid> {
+        self.groups.iter().find(|(_, id)| *id == gid).map(|(sid, _)| sid)
+    }
+
+    /// Derive POSIX ownership and mode bits from a security descriptor
+    pub fn permissions(&self, descriptor: &SecurityDescriptor) -> PosixPermissions {
+        let uid = descriptor.owner.as_ref().map_or(self.default_uid, |sid| self.uid(sid));
+        let gid = descriptor.group.as_ref().map_or(self.default_gid, |sid| self.gid(sid));
+
+        PosixPermissions {
+            uid,
+            gid,
+            mode: posix_mode(descriptor),
+        }
+    }
+}
+
+impl Default for UserMapping {
+    fn default() -> Self {
+        Self::new(0, 0)
+    }
+}
+
+fn lookup(entries: &[(Sid, u32)], sid: &Sid) -> Option<u32> {
+    entries.iter().find(|(mapped, _)| mapped == sid).map(|(_, id)| *id)
+}
+
+/// Derive `rwxrwxrwx` bits from the DACL
+///
+/// ACEs are evaluated in order as Windows does: the first ACE that allows or
+/// denies a bit for a trustee decides it. The owner and group classes also
+/// receive what is granted to Everyone. A null DACL grants everything.
+pub fn posix_mode(descriptor: &SecurityDescriptor) -> u32 {
+    let Some(dacl) = &descriptor.dacl else {
+        return 0o777;
+    };
+    let everyone = Sid::everyone();
+
+    let class_bits = |trustee: Option<&Sid>| -> u32 {
+        let mut allowed = 0;
+        let mut denied = 0;
+        for ace in dacl.aces.iter().filter(|ace| ace.is_effective()) {
+            if ace.sid != everyone && Some(&ace.sid) != trustee {
+                continue;
+            }
+            let bits = rwx_bits(ace.mask);
+            if ace.ace_type == ace_type::ACCESS_DENIED {
+                denied |= bits & !allowed;
+            } else {
+                allowed |= bits & !denied;
+            }
+        }
+        allowed
+    };
+
+    (class_bits(descriptor.owner.as_ref()) << 6)
+        | (class_bits(descriptor.group.as_ref()) << 3)
+        | class_bits(None)
+}
+
+/// Map an access mask to `rwx` bits
+fn rwx_bits(mask: u32) -> u32 {
+    let any = |bits: u32| mask & (bits | access::GENERIC_ALL) != 0;
+    let mut rwx = 0;
+    if any(access::FILE_READ_DATA | access::GENERIC_READ) {
+        rwx |= 0o4;
+    }
+    if any(access::FILE_WRITE_DATA | access::FILE_APPEND_DATA | access::GENERIC_WRITE) {
+        rwx |= 0o2;
+    }
+    if any(access::FILE_EXECUTE | access::GENERIC_EXECUTE) {
+        rwx |= 0o1;
+    }
+    rwx
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::security::tests::{ace, security_descriptor};
+    use crate::parser::security::{ace_flags, control};
+
+    const ALICE: &str = "S-1-5-21-3141592653-589793238-462643383-1001";
+    const USERS: &str = "S-1-5-21-3141592653-589793238-462643383-513";
+
+    #[test]
+    fn test_parse_mapping() {
+        let text = format!("# comment\n\n501::{}\n:20:{}\n", ALICE, USERS);
+        let mapping = UserMapping::parse(&text, 99, 98).unwrap();
+
+        assert_eq!(mapping.uid(&ALICE.parse().unwrap()), 501);
+        assert_eq!(mapping.gid(&USERS.parse().unwrap()), 20);
+        assert_eq!(mapping.gid(&ALICE.parse().unwrap()), 98);
+        assert_eq!(mapping.uid(&Sid::everyone()), 99);
+        assert_eq!(mapping.user_sid(501).unwrap().to_string(), ALICE);
+
+        assert!(UserMapping::parse("501:S-1-5-18", 0, 0).is_err());
+        assert!(UserMapping::parse("::S-1-5-18", 0, 0).is_err());
+        assert!(UserMapping::parse("x::S-1-5-18", 0, 0).is_err());
+    }
+
+    #[test]
+    fn test_permissions() {
+        let data = security_descriptor(
+            ALICE,
+            USERS,
+            &[
+                ace(ace_type::ACCESS_DENIED, 0, access::FILE_WRITE_DATA, USERS),
+                ace(ace_type::ACCESS_ALLOWED, 0, access::FILE_ALL_ACCESS, ALICE),
+                ace(ace_type::ACCESS_ALLOWED, 0, access::FILE_GENERIC_READ | access::FILE_GENERIC_WRITE, USERS),
+                ace(ace_type::ACCESS_ALLOWED, 0, access::FILE_GENERIC_READ | access::FILE_GENERIC_EXECUTE, "S-1-1-0"),
+                // Only inherited by children
+                ace(ace_type::ACCESS_ALLOWED, ace_flags::INHERIT_ONLY, access::GENERIC_ALL, "S-1-1-0"),
+            ],
+            control::DACL_PROTECTED,
+        );
+        let descriptor = SecurityDescriptor::parse(&data).unwrap();
+        let mapping = UserMapping::parse(&format!("501::{}\n:20:{}", ALICE, USERS), 99, 99).unwrap();
+
+        assert_eq!(
+            mapping.permissions(&descriptor),
+            PosixPermissions {
+                uid: 501,
+                gid: 20,
+                mode: 0o755,
+            }
+        );
+    }
+
+    #[test]
+    fn test_null_and_empty_dacl() {
+        let mut descriptor = SecurityDescriptor::default();
+        assert_eq!(posix_mode(&descriptor), 0o777);
+
+        descriptor.dacl = Some(Default::default());
+        assert_eq!(posix_mode(&descriptor), 0);
+    }
+}
@@ -0,0 +1,302 @@
+//! Filesystem detection from volume signatures
+//!
+//! Recognises what a device holds when it is not NTFS, so the user is told
+//! "this is BitLocker" or "this is exFAT" instead of "not a valid NTFS
+//! volume". Boot-sector filesystems (NTFS, BitLocker, ReFS, exFAT, FAT) are
+//! told apart by their OEM ID at byte 3; FAT has none that can be trusted,
+//! so its BIOS parameter block is validated and the type follows from the
+//! cluster count, as the FAT specification defines it. APFS, HFS+ and ext
+//! are found by their superblock magic.
+
+use std::io::{self, Read, Seek, SeekFrom};
+use crate::parser::boot::NTFS_OEM_ID;
+use crate::utils::bytes::{le_u16, le_u32, le_u8};
+use crate::utils::error::{Result, SMNtfsError};
+
+/// Bytes read from the start of the device for probing
+pub const PROBE_SIZE: usize = 4096;
+
+/// OEM ID of BitLocker-encrypted NTFS volumes
+pub(crate) const BITLOCKER_OEM_ID: &[u8; 8] = b"-FVE-FS-";
+/// OEM ID of BitLocker To Go volumes, shared with FAT
+pub(crate) const BITLOCKER_TO_GO_OEM_ID: &[u8; 8] = b"MSWIN4.1";
+/// BitLocker identifier GUID, as stored on disk
+const BITLOCKER_GUID: [u8; 16] = [
+    0x3B, 0xD6, 0x67, 0x49, 0x29, 0x2E, 0xD8, 0x4A, 0x83, 0x99, 0xF6, 0xA3, 0x39, 0xE3, 0xD0, 0x01,
+];
+/// Offset of the identifier GUID in a BitLocker To Go boot sector
+const BITLOCKER_TO_GO_GUID_OFFSET: usize = 0x1A8;
+const REFS_OEM_ID: &[u8; 8] = b"ReFS\0\0\0\0";
+const EXFAT_OEM_ID: &[u8; 8] = b"EXFAT   ";
+/// APFS container superblock magic, at byte 32 of block 0
+const APFS_MAGIC: &[u8; 4] = b"NXSB";
+/// Offset of the HFS+ volume header and of the ext superblock
+const SUPERBLOCK_OFFSET: usize = 1024;
+const EXT_MAGIC: u16 = 0xEF53;
+
+/// ext superblock feature flags that tell the versions apart
+mod ext_features {
+    /// Compatible feature: has a journal (ext3 and later)
+    pub const COMPAT_HAS_JOURNAL: u32 = 0x0004;
+    /// Incompatible features only ext4 uses: extents, 64-bit, flex_bg
+    pub const INCOMPAT_EXT4: u32 = 0x0040 | 0x0080 | 0x0200;
+}
+
+/// A filesystem recognised on a device
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Filesystem {
+    Ntfs,
+    /// BitLocker-encrypted, whatever the filesystem inside
+    BitLocker,
+    ReFs,
+    ExFat,
+    Fat12,
+    Fat16,
+    Fat32,
+    Apfs,
+    HfsPlus,
+    Ext2,
+    Ext3,
+    Ext4,
+}
+
+impl Filesystem {
+    /// Display name, e.g. `FAT32`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Ntfs => "NTFS",
+            Self::BitLocker => "BitLocker",
+            Self::ReFs => "ReFS",
+            Self::ExFat => "exFAT",
+            Self::Fat12 => "FAT12",
+            Self::Fat16 => "FAT16",
+            Self::Fat32 => "FAT32",
+            Self::Apfs => "APFS",
+            Self::HfsPlus => "HFS+",
+            Self::Ext2 => "ext2",
+            Self::Ext3 => "ext3",
+            Self::Ext4 => "ext4",
+        }
+    }
+
+    /// The error opening this filesystem as NTFS fails with (`None` for NTFS)
+    pub fn error(&self) -> Option<SMNtfsError> {
+        Some(match self {
+            Self::Ntfs => return None,
+            Self::BitLocker => SMNtfsError::BitLockerVolume,
+            Self::ReFs => SMNtfsError::RefsVolume,
+            Self::ExFat => SMNtfsError::ExfatVolume,
+            Self::Fat12 | Self::Fat16 | Self::Fat32 => SMNtfsError::FatVolume(self.name().to_string()),
+            Self::Apfs => SMNtfsError::ApfsVolume,
+            Self::HfsPlus => SMNtfsError::HfsPlusVolume,
+            Self::Ext2 | Self::Ext3 | Self::Ext4 => SMNtfsError::ExtVolume(self.name().to_string()),
+        })
+    }
+}
+
+impl std::fmt::Display for Filesystem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Recognise the filesystem from the first bytes of a device
+///
+/// `data` should hold [`PROBE_SIZE`] bytes; superblocks past its end are
+/// not looked for.
+pub fn probe(data: &[u8]) -> Option<Filesystem> {
+    if data.len() >= 512 {
+        match &data[0x03..0x0B] {
+            id if id == NTFS_OEM_ID => return Some(Filesystem::Ntfs),
+            id if id == BITLOCKER_OEM_ID => return Some(Filesystem::BitLocker),
+            id if id == REFS_OEM_ID => return Some(Filesystem::ReFs),
+            id if id == EXFAT_OEM_ID => return Some(Filesystem::ExFat),
+            id if id == BITLOCKER_TO_GO_OEM_ID && is_bitlocker_to_go(data) => return Some(Filesystem::BitLocker),
+            _ => {}
+        }
+        if let Some(fat) = probe_fat(data) {
+            return Some(fat);
+        }
+    }
+
+    if data.len() >= 36 && &data[32..36] == APFS_MAGIC {
+        return Some(Filesystem::Apfs);
+    }
+    let hfs_signature = data.get(SUPERBLOCK_OFFSET..SUPERBLOCK_OFFSET + 2);
+    if matches!(hfs_signature, Some(b"H+" | b"HX")) {
+        return Some(Filesystem::HfsPlus);
+    }
+    if data.len() >= SUPERBLOCK_OFFSET + 0x68 && le_u16(data, SUPERBLOCK_OFFSET + 0x38) == EXT_MAGIC {
+        let compat = le_u32(data, SUPERBLOCK_OFFSET + 0x5C);
+        let incompat = le_u32(data, SUPERBLOCK_OFFSET + 0x60);
+        return Some(if incompat & ext_features::INCOMPAT_EXT4 != 0 {
+            Filesystem::Ext4
+        } else if compat & ext_features::COMPAT_HAS_JOURNAL != 0 {
+            Filesystem::Ext3
+        } else {
+            Filesystem::Ext2
+        });
+    }
+    None
+}
+
+/// Read the start of a device and recognise its filesystem
+pub fn probe_device<T: Read + Seek>(fs: &mut T) -> Result<Option<Filesystem>> {
+    let mut data = vec![0u8; PROBE_SIZE];
+    fs.seek(SeekFrom::Start(0))?;
+    let mut length = 0;
+    while length < data.len() {
+        match fs.read(&mut data[length..]) {
+            Ok(0) => break,
+            Ok(count) => length += count,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    data.truncate(length);
+    Ok(probe(&data))
+}
+
+/// Fail with the specific error of a device holding something other than NTFS
+///
+/// Devices whose filesystem is not recognised pass, so a damaged NTFS boot
+/// sector can still be recovered from its backup.
+pub fn ensure_ntfs<T: Read + Seek>(fs: &mut T) -> Result<()> {
+    match probe_device(fs)?.and_then(|filesystem| filesystem.error()) {
+        Some(error) => Err(error),
+        None => Ok(()),
+    }
+}
+
+fn is_bitlocker_to_go(data: &[u8]) -> bool {
+    data.get(BITLOCKER_TO_GO_GUID_OFFSET..BITLOCKER_TO_GO_GUID_OFFSET + 16) == Some(&BITLOCKER_GUID[..])
+}
+
+/// Validate a FAT BIOS parameter block and classify it by cluster count
+fn probe_fat(data: &[u8]) -> Option<Filesystem> {
+    let sector_size = le_u16(data, 0x0B) as u64;
+    let sectors_per_cluster = le_u8(data, 0x0D) as u64;
+    let reserved_sectors = le_u16(data, 0x0E) as u64;
+    let fat_count = le_u8(data, 0x10) as u64;
+    let root_entries = le_u16(data, 0x11) as u64;
+    let media = le_u8(data, 0x15);
+
+    let valid = matches!(data[0], 0xEB | 0xE9)
+        && le_u16(data, 0x1FE) == 0xAA55
+        && matches!(sector_size, 512 | 1024 | 2048 | 4096)
+        && sectors_per_cluster.is_power_of_two()
+        && reserved_sectors > 0
+        && matches!(fat_count, 1 | 2)
+        && (media == 0xF0 || media >= 0xF8);
+    if !valid {
+        return None;
+    }
+
+    let fat_sectors = match le_u16(data, 0x16) {
+        0 => le_u32(data, 0x24) as u64,
+        sectors => sectors as u64,
+    };
+    let total_sectors = match le_u16(data, 0x13) {
+        0 => le_u32(data, 0x20) as u64,
+        sectors => sectors as u64,
+    };
+    let root_sectors = (root_entries * 32).div_ceil(sector_size);
+    // Fits in u64 whatever the BPB holds, unlike u32
+    let metadata_sectors = reserved_sectors + fat_count * fat_sectors + root_sectors;
+    if fat_sectors == 0 || total_sectors <= metadata_sectors {
+        return None;
+    }
+
+    let clusters = (total_sectors - metadata_sectors) / sectors_per_cluster;
+    Some(match clusters {
+        0..=4084 => Filesystem::Fat12,
+        4085..=65524 => Filesystem::Fat16,
+        _ => Filesystem::Fat32,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn boot_sector(oem_id: &[u8; 8]) -> Vec<u8> {
+        let mut data = vec![0u8; PROBE_SIZE];
+        data[0] = 0xEB;
+        data[0x03..0x0B].copy_from_slice(oem_id);
+        data[0x1FE..0x200].copy_from_slice(&0xAA55u16.to_le_bytes());
+        data
+    }
+
+    fn fat_boot_sector(total_sectors: u32, sectors_per_cluster: u8, fat_sectors: u32, root_entries: u16) -> Vec<u8> {
+        let mut data = boot_sector(b"mkfs.fat");
+        data[0x0B..0x0D].copy_from_slice(&512u16.to_le_bytes());
+        data[0x0D] = sectors_per_cluster;
+        data[0x0E..0x10].copy_from_slice(&32u16.to_le_bytes());
+        data[0x10] = 2;
+        data[0x11..0x13].copy_from_slice(&root_entries.to_le_bytes());
+        data[0x15] = 0xF8;
+        data[0x20..0x24].copy_from_slice(&total_sectors.to_le_bytes());
+        if root_entries == 0 {
+            data[0x24..0x28].copy_from_slice(&fat_sectors.to_le_bytes());
+        } else {
+            data[0x16..0x18].copy_from_slice(&(fat_sectors as u16).to_le_bytes());
+        }
+        data
+    }
+
+    #[test]
+    fn test_boot_sector_signatures() {
+        assert_eq!(probe(&boot_sector(NTFS_OEM_ID)), Some(Filesystem::Ntfs));
+        assert_eq!(probe(&boot_sector(BITLOCKER_OEM_ID)), Some(Filesystem::BitLocker));
+        assert_eq!(probe(&boot_sector(REFS_OEM_ID)), Some(Filesystem::ReFs));
+        assert_eq!(probe(&boot_sector(EXFAT_OEM_ID)), Some(Filesystem::ExFat));
+
+        let mut to_go = fat_boot_sector(8 * 1024 * 1024, 8, 8192, 0);
+        to_go[0x03..0x0B].copy_from_slice(BITLOCKER_TO_GO_OEM_ID);
+        assert_eq!(probe(&to_go), Some(Filesystem::Fat32));
+        to_go[BITLOCKER_TO_GO_GUID_OFFSET..BITLOCKER_TO_GO_GUID_OFFSET + 16].copy_from_slice(&BITLOCKER_GUID);
+        assert_eq!(probe(&to_go), Some(Filesystem::BitLocker));
+
+        assert!(Filesystem::Ntfs.error().is_none());
+        assert!(matches!(Filesystem::BitLocker.error(), Some(SMNtfsError::BitLockerVolume)));
+    }
+
+    #[test]
+    fn test_fat_types() {
+        assert_eq!(probe(&fat_boot_sector(2880, 1, 9, 224)), Some(Filesystem::Fat12));
+        assert_eq!(probe(&fat_boot_sector(1024 * 1024, 32, 128, 512)), Some(Filesystem::Fat16));
+        assert_eq!(probe(&fat_boot_sector(8 * 1024 * 1024, 8, 8192, 0)), Some(Filesystem::Fat32));
+
+        let mut no_fats = fat_boot_sector(2880, 1, 9, 224);
+        no_fats[0x10] = 0;
+        assert_eq!(probe(&no_fats), None);
+
+        // Metadata larger than u32 must not overflow
+        let mut huge = fat_boot_sector(u32::MAX, 1, u32::MAX, 0);
+        huge[0x0E..0x10].copy_from_slice(&u16::MAX.to_le_bytes());
+        assert_eq!(probe(&huge), None);
+        assert!(matches!(Filesystem::Fat32.error(), Some(SMNtfsError::FatVolume(name)) if name == "FAT32"));
+    }
+
+    #[test]
+    fn test_superblocks() {
+        let mut apfs = vec![0u8; PROBE_SIZE];
+        apfs[32..36].copy_from_slice(APFS_MAGIC);
+        assert_eq!(probe(&apfs), Some(Filesystem::Apfs));
+
+        let mut hfs = vec![0u8; PROBE_SIZE];
+        hfs[SUPERBLOCK_OFFSET..SUPERBLOCK_OFFSET + 2].copy_from_slice(b"H+");
+        assert_eq!(probe(&hfs), Some(Filesystem::HfsPlus));
+
+        let mut ext = vec![0u8; PROBE_SIZE];
+        ext[SUPERBLOCK_OFFSET + 0x38..SUPERBLOCK_OFFSET + 0x3A].copy_from_slice(&EXT_MAGIC.to_le_bytes());
+        assert_eq!(probe(&ext), Some(Filesystem::Ext2));
+        ext[SUPERBLOCK_OFFSET + 0x5C] = ext_features::COMPAT_HAS_JOURNAL as u8;
+        assert_eq!(probe(&ext), Some(Filesystem::Ext3));
+        ext[SUPERBLOCK_OFFSET + 0x60] = 0x40;
+        assert_eq!(probe(&ext), Some(Filesystem::Ext4));
+
+        assert_eq!(probe(&vec![0u8; PROBE_SIZE]), None);
+        assert_eq!(probe(&[]), None);
+    }
+}
@@ -0,0 +1,725 @@
+//! Streaming `Read + Seek` access to attribute values
+//!
+//! Unlike [`read_default_stream`](crate::parser::read_default_stream), which
+//! loads a whole stream into memory, [`StreamReader`] maps each read through
+//! the data runs and only touches the clusters it needs. Sparse runs and the
+//! area between the initialized size and the data size read as zeros.
+//!
+//! Compressed streams are read one compression unit at a time: a unit with
+//! no clusters allocated is all zeros, a unit with every cluster allocated is
+//! stored as is, and anything in between holds LZNT1 data in its allocated
+//! clusters. The last decompressed unit is kept for sequential reads.
+//!
+//! WOF compressed files are read through their `WofCompressedData` stream,
+//! one chunk at a time with the same cache.
+//!
+//! [`StreamReader::data_ranges`], [`StreamReader::seek_data`] and
+//! [`StreamReader::seek_hole`] expose holes like `SEEK_DATA`/`SEEK_HOLE`,
+//! so copies of sparse streams can stay sparse.
+
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+use ntfs::{NtfsAttributeType, NtfsFile};
+use crate::parser::attribute::AttributeStream;
+use crate::parser::lznt1;
+use crate::parser::reparse::read_reparse_point;
+use crate::parser::runs::DataRun;
+use crate::parser::upcase::UpcaseTable;
+use crate::parser::wof::{WofAlgorithm, WofChunks, WOF_STREAM_NAME};
+use crate::utils::error::{Result, SMNtfsError};
+
+/// Most memory [`StreamReader::read_all`] reserves up front, whatever the
+/// stream claims its size is
+const PREALLOCATION_LIMIT: u64 = 1024 * 1024;
+
+/// Compression unit of compressed streams, as a power of two clusters
+///
+/// Windows only ever writes 16-cluster units, and never compresses volumes
+/// with clusters over 4 KiB.
+pub const COMPRESSION_UNIT: u8 = 4;
+
+/// A reader over one attribute value that borrows the device handle
+pub struct StreamReader<'a, T: Read + Seek> {
+    fs: &'a mut T,
+    stream: AttributeStream,
+    cluster_size: u64,
+    position: u64,
+    /// Last decompressed compression unit or WOF chunk, by index
+    unit_cache: Option<(u64, Vec<u8>)>,
+    wof: Option<WofChunks>,
+}
+
+impl<'a, T: Read + Seek> StreamReader<'a, T> {
+    /// Create a reader over an already collected attribute value, as stored on disk
+    pub fn new(fs: &'a mut T, stream: AttributeStream, cluster_size: u32) -> Self {
+        Self {
+            fs,
+            stream,
+            cluster_size: cluster_size as u64,
+            position: 0,
+            unit_cache: None,
+            wof: None,
+        }
+    }
+
+    /// Open the default $DATA stream of a file
+    ///
+    /// The stream of a WOF compressed file reads decompressed.
+    pub fn open_default(file: &NtfsFile, fs: &'a mut T) -> Result<Self> {
+        if let Some(point) = read_reparse_point(file.ntfs(), file, fs)? {
+            if let Some(algorithm) = WofAlgorithm::from_reparse_point(&point)? {
+                return Self::open_wof(file, fs, algorithm);
+            }
+        }
+
+        let stream = AttributeStream::collect(file, fs, NtfsAttributeType::Data, "")?
+            .ok_or_else(|| SMNtfsError::ReadError("No default data stream found".to_string()))?;
+        Self::from_stream(fs, stream, file.ntfs().cluster_size())
+    }
+
+    /// Open a $DATA stream of a file (empty name for the default stream)
+    ///
+    /// Names are compared through `upcase`, the $UpCase table of the volume.
+    /// The default stream of a WOF compressed file reads decompressed.
+    pub fn open(file: &NtfsFile, fs: &'a mut T, stream_name: &str, upcase: &UpcaseTable) -> Result<Self> {
+        if stream_name.is_empty() {
+            return Self::open_default(file, fs);
+        }
+
+        let stream = AttributeStream::collect_ignore_case(file, fs, NtfsAttributeType::Data, stream_name, upcase)?
+            .ok_or_else(|| SMNtfsError::ReadError(format!("Stream '{}' not found", stream_name)))?;
+        Self::from_stream(fs, stream, file.ntfs().cluster_size())
+    }
+
+    /// Open the default stream of a WOF compressed file
+    fn open_wof(file: &NtfsFile, fs: &'a mut T, algorithm: WofAlgorithm) -> Result<Self> {
+        let size = AttributeStream::collect(file, fs, NtfsAttributeType::Data, "")?
+            .map_or(0, |stream| stream.data_size);
+        let stream = AttributeStream::collect(file, fs, NtfsAttributeType::Data, WOF_STREAM_NAME)?
+            .ok_or_else(|| SMNtfsError::ReadError(format!("WOF file has no '{}' stream", WOF_STREAM_NAME)))?;
+
+        let reader = Self::from_stream(fs, stream, file.ntfs().cluster_size())?;
+        reader.with_wof(algorithm, size)
+    }
+
+    /// Read the value as a WOF `WofCompressedData` stream of a `size` byte file
+    pub fn with_wof(mut self, algorithm: WofAlgorithm, size: u64) -> Result<Self> {
+        let table_size = WofChunks::table_size(algorithm, size).min(self.stream.data_size);
+        let mut table = vec![0u8; table_size as usize];
+        self.read_value_exact(0, &mut table)
+            .map_err(|e| SMNtfsError::ReadError(format!("Failed to read WOF chunk table: {}", e)))?;
+
+        self.wof = Some(WofChunks::parse(algorithm, size, &table, self.stream.data_size)?);
+        self.unit_cache = None;
+        self.position = 0;
+        Ok(self)
+    }
+
+    /// Create a reader over a collected value, failing if it cannot be decoded
+    pub fn from_stream(fs: &'a mut T, stream: AttributeStream, cluster_size: u32) -> Result<Self> {
+        if stream.is_encrypted() {
+            return Err(SMNtfsError::ReadError(format!("Stream '{}' is encrypted", stream.name)));
+        }
+        if stream.is_compressed() && !stream.is_resident() && stream.compression_unit == 0 {
+            return Err(SMNtfsError::ReadError(format!(
+                "Stream '{}' is compressed without a compression unit",
+                stream.name
+            )));
+        }
+        if stream.is_compressed() && !stream.is_resident() && stream.compression_unit != COMPRESSION_UNIT {
+            return Err(SMNtfsError::ReadError(format!(
+                "Stream '{}' has an unsupported compression unit of 2^{} clusters",
+                stream.name, stream.compression_unit
+            )));
+        }
+
+        Ok(Self::new(fs, stream, cluster_size))
+    }
+
+    /// Logical length of the stream, decompressed for WOF files
+    pub fn len(&self) -> u64 {
+        match &self.wof {
+            Some(wof) => wof.size,
+            None => self.stream.data_size,
+        }
+    }
+
+    /// Check if the stream is empty
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Current read position
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// The attribute value being read
+    pub fn stream(&self) -> &AttributeStream {
+        &self.stream
+    }
+
+    /// Chunk layout if this reads a WOF compressed file
+    pub fn wof(&self) -> Option<&WofChunks> {
+        self.wof.as_ref()
+    }
+
+    /// Swap in a newer description of the same value, keeping the position
+    ///
+    /// Used to pick up streams that grew since the reader was created.
+    pub fn set_stream(&mut self, stream: AttributeStream) {
+        self.stream = stream;
+        self.unit_cache = None;
+    }
+
+    /// The underlying device handle
+    pub fn get_mut(&mut self) -> &mut T {
+        self.fs
+    }
+
+    /// Read the remainder of the stream into memory
+    pub fn read_all(&mut self) -> Result<Vec<u8>> {
+        // The size comes from disk; let a damaged one fail when the runs end
+        let remaining = self.len().saturating_sub(self.position);
+        let mut data = Vec::with_capacity(remaining.min(PREALLOCATION_LIMIT) as usize);
+        self.read_to_end(&mut data)
+            .map_err(|e| SMNtfsError::ReadError(format!("Failed to read data: {}", e)))?;
+        Ok(data)
+    }
+
+    /// Byte ranges backed by clusters, in order; everything else reads as zeros
+    ///
+    /// Sparse runs and the area past the initialized size are holes. For
+    /// compressed streams a compression unit is data if any of its clusters
+    /// is allocated. WOF files are all data.
+    pub fn data_ranges(&self) -> Vec<Range<u64>> {
+        let end = self.len().min(self.stream.initialized_size);
+        if self.stream.is_resident() || self.wof.is_some() {
+            let whole = 0..self.len();
+            return if whole.is_empty() { Vec::new() } else { vec![whole] };
+        }
+
+        let granularity = self.compression_unit_size().unwrap_or(self.cluster_size);
+        let mut ranges: Vec<Range<u64>> = Vec::new();
+        for run in self.stream.runs.iter().filter(|run| !run.is_sparse()) {
+            let start = run.vcn.saturating_mul(self.cluster_size) / granularity * granularity;
+            // Clamped before rounding up, so a damaged run cannot overflow
+            let run_end = run.end_vcn().saturating_mul(self.cluster_size).min(end);
+            let run_end = run_end.next_multiple_of(granularity).min(end);
+            if start >= run_end {
+                continue;
+            }
+            match ranges.last_mut() {
+                Some(last) if last.end >= start => last.end = last.end.max(run_end),
+                _ => ranges.push(start..run_end),
+            }
+        }
+        ranges
+    }
+
+    /// Move to the first data at or after `offset`, like `lseek(SEEK_DATA)`
+    ///
+    /// Returns `None` (`ENXIO`) if there is no data from `offset` to the end.
+    pub fn seek_data(&mut self, offset: u64) -> Option<u64> {
+        if offset >= self.len() {
+            return None;
+        }
+        let range = self.data_ranges().into_iter().find(|range| range.end > offset)?;
+        self.position = range.start.max(offset);
+        Some(self.position)
+    }
+
+    /// Move to the first hole at or after `offset`, like `lseek(SEEK_HOLE)`
+    ///
+    /// The end of the stream counts as a hole. Returns `None` (`ENXIO`) if
+    /// `offset` is at or past the end.
+    pub fn seek_hole(&mut self, offset: u64) -> Option<u64> {
+        if offset >= self.len() {
+            return None;
+        }
+        let hole = self
+            .data_ranges()
+            .into_iter()
+            .find(|range| range.contains(&offset))
+            .map_or(offset, |range| range.end);
+        self.position = hole;
+        Some(hole)
+    }
+
+    /// Copy the stream to `writer`, seeking over holes instead of writing zeros
+    ///
+    /// Returns the stream length. A trailing hole is not written, so a file
+    /// being written to should be extended to that length afterwards.
+    pub fn copy_sparse<W: Write + Seek>(&mut self, writer: &mut W) -> Result<u64> {
+        let copy_error = |e: io::Error| SMNtfsError::ReadError(format!("Failed to copy stream: {}", e));
+
+        for range in self.data_ranges() {
+            self.position = range.start;
+            writer.seek(SeekFrom::Start(range.start)).map_err(copy_error)?;
+            io::copy(&mut (&mut *self).take(range.end - range.start), writer).map_err(copy_error)?;
+        }
+        Ok(self.len())
+    }
+
+    /// Read the stored value at `offset`; `buf` never crosses the data size
+    fn read_value(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        match &self.stream.resident_data {
+            Some(data) => {
+                let start = offset as usize;
+                let available = data.len().saturating_sub(start).min(buf.len());
+                buf[..available].copy_from_slice(&data[start..start + available]);
+                buf[available..].fill(0);
+                Ok(buf.len())
+            }
+            None => self.read_non_resident(offset, buf),
+        }
+    }
+
+    /// Fill `buf` from the stored value at `offset`
+    fn read_value_exact(&mut self, mut offset: u64, mut buf: &mut [u8]) -> io::Result<()> {
+        while !buf.is_empty() {
+            let count = self.read_value(offset, buf)?;
+            offset += count as u64;
+            buf = &mut buf[count..];
+        }
+        Ok(())
+    }
+
+    /// Read from a WOF file, decompressing the chunk under the position
+    fn read_wof(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let Some(wof) = &self.wof else {
+            unreachable!("only called for WOF files");
+        };
+        let chunk_size = wof.algorithm.chunk_size();
+        let chunk = self.position / chunk_size;
+
+        if !matches!(&self.unit_cache, Some((cached, _)) if *cached == chunk) {
+            let range = wof.stored_range(chunk).ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, format!("WOF chunk {} is missing", chunk))
+            })?;
+            let mut stored = vec![0u8; (range.end - range.start) as usize];
+            self.read_value_exact(range.start, &mut stored)?;
+
+            let Some(wof) = &self.wof else {
+                unreachable!("only called for WOF files");
+            };
+            let data = wof
+                .decode(chunk, &stored)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+            self.unit_cache = Some((chunk, data));
+        }
+        let Some((_, data)) = &self.unit_cache else {
+            unreachable!("WOF chunk was just cached");
+        };
+
+        let offset = (self.position - chunk * chunk_size) as usize;
+        let count = buf.len().min(data.len().saturating_sub(offset));
+        buf[..count].copy_from_slice(&data[offset..offset + count]);
+        Ok(count)
+    }
+
+    /// Read from a non-resident value; `buf` never crosses the data size
+    fn read_non_resident(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        // Nothing has been written past the initialized size yet
+        if offset >= self.stream.initialized_size {
+            buf.fill(0);
+            return Ok(buf.len());
+        }
+
+        if let Some(unit_size) = self.compression_unit_size() {
+            return self.read_compressed(offset, buf, unit_size);
+        }
+
+        let vcn = offset / self.cluster_size;
+        let run = self.run_at(vcn)?;
+
+        let run_end = run.end_vcn().saturating_mul(self.cluster_size);
+        let count = (buf.len() as u64)
+            .min(run_end - offset)
+            .min(self.stream.initialized_size - offset) as usize;
+        let buf = &mut buf[..count];
+
+        match run.lcn {
+            None => buf.fill(0),
+            Some(lcn) => {
+                let offset = offset - run.vcn * self.cluster_size;
+                self.fs.seek(SeekFrom::Start(self.device_position(lcn, offset)?))?;
+                self.fs.read_exact(buf)?;
+            }
+        }
+
+        Ok(count)
+    }
+
+    /// Read from a compressed value, decompressing the unit under the position
+    fn read_compressed(&mut self, position: u64, buf: &mut [u8], unit_size: u64) -> io::Result<usize> {
+        let unit = position / unit_size;
+
+        if !matches!(&self.unit_cache, Some((cached, _)) if *cached == unit) {
+            let data = self.read_unit(unit, unit_size)?;
+            self.unit_cache = Some((unit, data));
+        }
+        let Some((_, data)) = &self.unit_cache else {
+            unreachable!("compression unit was just cached");
+        };
+
+        let offset = (position - unit * unit_size) as usize;
+        let count = (buf.len() as u64)
+            .min(unit_size - offset as u64)
+            .min(self.stream.initialized_size - position) as usize;
+        buf[..count].copy_from_slice(&data[offset..offset + count]);
+        Ok(count)
+    }
+
+    /// Read and, if needed, decompress one compression unit
+    fn read_unit(&mut self, unit: u64, unit_size: u64) -> io::Result<Vec<u8>> {
+        let clusters = 1u64 << COMPRESSION_UNIT;
+        let first_vcn = unit * clusters;
+        let mut stored = Vec::new();
+        let mut vcn = first_vcn;
+
+        while vcn < first_vcn + clusters {
+            let run = self.run_at(vcn)?;
+            let count = run.end_vcn().min(first_vcn + clusters) - vcn;
+            if let Some(lcn) = run.lcn {
+                let start = stored.len();
+                stored.resize(start + (count * self.cluster_size) as usize, 0);
+                let position = self.device_position(lcn, (vcn - run.vcn) * self.cluster_size)?;
+                self.fs.seek(SeekFrom::Start(position))?;
+                self.fs.read_exact(&mut stored[start..])?;
+            }
+            vcn += count;
+        }
+
+        // Fully allocated units are stored uncompressed
+        if stored.len() as u64 == unit_size {
+            return Ok(stored);
+        }
+
+        let mut data = vec![0u8; unit_size as usize];
+        if !stored.is_empty() {
+            lznt1::decompress(&stored, &mut data).map_err(|e| {
+                io::Error::new(io::ErrorKind::InvalidData, format!("Compression unit {}: {}", unit, e))
+            })?;
+        }
+        Ok(data)
+    }
+
+    /// Device position of byte `offset` from the start of cluster `lcn`
+    ///
+    /// Runs are only checked in clusters when decoded, so a damaged LCN can
+    /// still overflow once converted to bytes.
+    fn device_position(&self, lcn: u64, offset: u64) -> io::Result<u64> {
+        lcn.checked_mul(self.cluster_size)
+            .and_then(|position| position.checked_add(offset))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("LCN {} is out of range", lcn)))
+    }
+
+    /// Bytes in a compression unit, or `None` if the stream is read as stored
+    ///
+    /// [`StreamReader::from_stream`] rejects any other unit than
+    /// [`COMPRESSION_UNIT`]; streams given to [`StreamReader::new`] with one
+    /// are read as stored.
+    fn compression_unit_size(&self) -> Option<u64> {
+        (self.stream.is_compressed() && self.stream.compression_unit == COMPRESSION_UNIT)
+            .then_some(self.cluster_size << COMPRESSION_UNIT)
+    }
+
+    /// Find the run mapping a VCN
+    fn run_at(&self, vcn: u64) -> io::Result<DataRun> {
+        self.stream.run_at(vcn).copied().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("No data run maps VCN {} of a {} byte stream", vcn, self.len()),
+            )
+        })
+    }
+}
+
+impl<T: Read + Seek> Read for StreamReader<'_, T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let remaining = self.len().saturating_sub(self.position);
+        let count = (buf.len() as u64).min(remaining) as usize;
+        if count == 0 {
+            return Ok(0);
+        }
+
+        let bytes_read = match self.wof {
+            Some(_) => self.read_wof(&mut buf[..count])?,
+            None => self.read_value(self.position, &mut buf[..count])?,
+        };
+
+        self.position += bytes_read as u64;
+        Ok(bytes_read)
+    }
+}
+
+impl<T: Read + Seek> Seek for StreamReader<'_, T> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let new_position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+            SeekFrom::End(offset) => self.len().checked_add_signed(offset),
+        };
+
+        self.position = new_position.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "Seek to a negative or overflowing position")
+        })?;
+        Ok(self.position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::lznt1::tests::{abc_chunk, stored_chunks};
+    use crate::parser::record::attribute_flags;
+    use crate::parser::xpress::tests::abab_block;
+    use std::io::Cursor;
+
+    /// 8 clusters of 16 bytes, cluster N filled with byte N
+    fn device() -> Cursor<Vec<u8>> {
+        Cursor::new((0..8u8).flat_map(|n| [n; 16]).collect())
+    }
+
+    fn stream(runs: Vec<DataRun>, data_size: u64, initialized_size: u64) -> AttributeStream {
+        AttributeStream {
+            runs,
+            data_size,
+            initialized_size,
+            ..AttributeStream::default()
+        }
+    }
+
+    #[test]
+    fn test_read_across_runs() {
+        let runs = vec![
+            DataRun { vcn: 0, lcn: Some(5), length: 1 },
+            DataRun { vcn: 1, lcn: None, length: 1 },
+            DataRun { vcn: 2, lcn: Some(2), length: 2 },
+        ];
+        let mut fs = device();
+        let mut reader = StreamReader::new(&mut fs, stream(runs, 60, 60), 16);
+        let data = reader.read_all().unwrap();
+
+        assert_eq!(data.len(), 60);
+        assert!(data[..16].iter().all(|b| *b == 5));
+        assert!(data[16..32].iter().all(|b| *b == 0));
+        assert!(data[32..48].iter().all(|b| *b == 2));
+        assert!(data[48..].iter().all(|b| *b == 3));
+    }
+
+    #[test]
+    fn test_seek_and_read() {
+        let runs = vec![DataRun { vcn: 0, lcn: Some(4), length: 4 }];
+        let mut fs = device();
+        let mut reader = StreamReader::new(&mut fs, stream(runs, 64, 64), 16);
+
+        reader.seek(SeekFrom::Start(40)).unwrap();
+        let mut buf = [0u8; 4];
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, [6; 4]);
+
+        assert_eq!(reader.seek(SeekFrom::End(-1)).unwrap(), 63);
+        assert_eq!(reader.read(&mut buf).unwrap(), 1);
+        assert_eq!(buf[0], 7);
+        assert_eq!(reader.read(&mut buf).unwrap(), 0);
+        assert!(reader.seek(SeekFrom::Current(-100)).is_err());
+    }
+
+    #[test]
+    fn test_uninitialized_tail_reads_zero() {
+        let runs = vec![DataRun { vcn: 0, lcn: Some(1), length: 2 }];
+        let mut fs = device();
+        let data = StreamReader::new(&mut fs, stream(runs, 32, 20), 16).read_all().unwrap();
+
+        assert!(data[..16].iter().all(|b| *b == 1));
+        assert_eq!(&data[16..20], &[2; 4]);
+        assert!(data[20..].iter().all(|b| *b == 0));
+    }
+
+    #[test]
+    fn test_missing_run_is_an_error() {
+        let runs = vec![DataRun { vcn: 0, lcn: Some(1), length: 1 }];
+        let mut fs = device();
+        let mut reader = StreamReader::new(&mut fs, stream(runs, 48, 48), 16);
+        assert!(reader.read_all().is_err());
+    }
+
+    #[test]
+    fn test_damaged_sizes_and_lcns() {
+        // A size no run could hold fails when the runs end instead of being allocated
+        let runs = vec![DataRun { vcn: 0, lcn: Some(1), length: 1 }];
+        let mut fs = device();
+        let mut reader = StreamReader::new(&mut fs, stream(runs, 1 << 60, 1 << 60), 16);
+        assert!(reader.read_all().is_err());
+
+        // An LCN that overflows in bytes is invalid data, not a read somewhere else
+        for runs in [
+            vec![DataRun { vcn: 0, lcn: Some(1 << 62), length: 1 }],
+            vec![DataRun { vcn: 0, lcn: Some(u64::MAX - 1), length: 1 }],
+        ] {
+            let mut reader = StreamReader::new(&mut fs, stream(runs.clone(), 16, 16), 16);
+            let error = reader.read_all().unwrap_err();
+            assert!(error.to_string().contains("out of range"), "{}", error);
+            assert_eq!(stream(runs, 16, 16).device_offset(0, 16), None);
+        }
+    }
+
+    #[test]
+    fn test_resident_read() {
+        let value = AttributeStream {
+            resident_data: Some(b"file-with-12345".to_vec()),
+            data_size: 15,
+            initialized_size: 15,
+            ..AttributeStream::default()
+        };
+        let mut fs = device();
+        let mut reader = StreamReader::new(&mut fs, value, 16);
+        reader.seek(SeekFrom::Start(10)).unwrap();
+        assert_eq!(reader.read_all().unwrap(), b"12345");
+    }
+
+    /// Compressed stream of four 4 KiB units (16 clusters of 256 bytes each)
+    ///
+    /// Unit 0 is compressed into one cluster, unit 1 is sparse, unit 2 is
+    /// stored uncompressed and unit 3 is compressed into two clusters.
+    fn compressed_fixture() -> (Cursor<Vec<u8>>, AttributeStream) {
+        let mut device = vec![0u8; 19 * 256];
+        let chunk = abc_chunk();
+        device[..chunk.len()].copy_from_slice(&chunk);
+        for (i, byte) in device[256..17 * 256].iter_mut().enumerate() {
+            *byte = (i / 256) as u8;
+        }
+        let mut unit3 = stored_chunks(&[0x5A; 300]);
+        unit3.extend_from_slice(&[0, 0]);
+        device[17 * 256..17 * 256 + unit3.len()].copy_from_slice(&unit3);
+
+        let runs = vec![
+            DataRun { vcn: 0, lcn: Some(0), length: 1 },
+            DataRun { vcn: 1, lcn: None, length: 31 },
+            DataRun { vcn: 32, lcn: Some(1), length: 16 },
+            DataRun { vcn: 48, lcn: Some(17), length: 2 },
+            DataRun { vcn: 50, lcn: None, length: 14 },
+        ];
+        let stream = AttributeStream {
+            flags: attribute_flags::COMPRESSED,
+            compression_unit: COMPRESSION_UNIT,
+            ..stream(runs, 4 * 4096 - 100, 4 * 4096 - 100)
+        };
+        (Cursor::new(device), stream)
+    }
+
+    #[test]
+    fn test_compressed_read() {
+        let (mut fs, stream) = compressed_fixture();
+        let data = StreamReader::from_stream(&mut fs, stream, 256).unwrap().read_all().unwrap();
+
+        assert_eq!(data.len(), 4 * 4096 - 100);
+        assert_eq!(&data[..12], b"abcabcabcabc");
+        assert!(data[12..2 * 4096].iter().all(|b| *b == 0));
+        assert_eq!(data[2 * 4096], 0);
+        assert_eq!(data[2 * 4096 + 256 * 15], 15);
+        assert!(data[3 * 4096..3 * 4096 + 300].iter().all(|b| *b == 0x5A));
+        assert!(data[3 * 4096 + 300..].iter().all(|b| *b == 0));
+    }
+
+    #[test]
+    fn test_compressed_seek() {
+        let (mut fs, stream) = compressed_fixture();
+        let mut reader = StreamReader::from_stream(&mut fs, stream, 256).unwrap();
+        let mut buf = [0u8; 6];
+
+        reader.seek(SeekFrom::Start(3 * 4096 + 298)).unwrap();
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, [0x5A, 0x5A, 0, 0, 0, 0]);
+
+        reader.seek(SeekFrom::Start(3)).unwrap();
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"abcabc");
+    }
+
+    #[test]
+    fn test_compression_unit_rejected() {
+        for compression_unit in [0, 3, 5, 63, 64, 255] {
+            let (mut fs, stream) = compressed_fixture();
+            let stream = AttributeStream {
+                compression_unit,
+                ..stream
+            };
+            assert!(StreamReader::from_stream(&mut fs, stream.clone(), 256).is_err());
+
+            // Read as stored when not checked, instead of overflowing
+            let mut reader = StreamReader::new(&mut fs, stream, 256);
+            reader.read_all().unwrap();
+            assert!(!reader.data_ranges().is_empty());
+        }
+    }
+
+    #[test]
+    fn test_holes() {
+        let runs = vec![
+            DataRun { vcn: 0, lcn: Some(5), length: 1 },
+            DataRun { vcn: 1, lcn: Some(2), length: 1 },
+            DataRun { vcn: 2, lcn: None, length: 3 },
+            DataRun { vcn: 5, lcn: Some(1), length: 3 },
+        ];
+        let mut fs = device();
+        let mut reader = StreamReader::new(&mut fs, stream(runs, 120, 100), 16);
+
+        assert_eq!(reader.data_ranges(), vec![0..32, 80..100]);
+        assert_eq!(reader.seek_data(10), Some(10));
+        assert_eq!(reader.seek_data(40), Some(80));
+        assert_eq!(reader.seek_data(100), None);
+        assert_eq!(reader.seek_hole(10), Some(32));
+        assert_eq!(reader.seek_hole(40), Some(40));
+        assert_eq!(reader.seek_hole(90), Some(100));
+        assert_eq!(reader.position(), 100);
+        assert_eq!(reader.seek_hole(120), None);
+
+        let mut copy = Cursor::new(Vec::new());
+        assert_eq!(reader.copy_sparse(&mut copy).unwrap(), 120);
+        let copy = copy.into_inner();
+        assert_eq!(copy.len(), 100);
+        assert_eq!(copy[16], 2);
+        assert!(copy[32..80].iter().all(|b| *b == 0));
+        assert_eq!(copy[99], 2);
+    }
+
+    #[test]
+    fn test_compressed_holes() {
+        let (mut fs, stream) = compressed_fixture();
+        let reader = StreamReader::new(&mut fs, stream, 256);
+
+        assert_eq!(reader.data_ranges(), vec![0..4096, 2 * 4096..4 * 4096 - 100]);
+    }
+
+    #[test]
+    fn test_wof_read() {
+        // A compressed 4 KiB chunk, then an 8 byte tail stored as is
+        let chunk = abab_block(4096);
+        let mut data = (chunk.len() as u32).to_le_bytes().to_vec();
+        data.extend_from_slice(&chunk);
+        data.extend_from_slice(b"tail1234");
+        let value = AttributeStream {
+            data_size: data.len() as u64,
+            initialized_size: data.len() as u64,
+            resident_data: Some(data),
+            ..AttributeStream::default()
+        };
+
+        let mut fs = device();
+        let mut reader = StreamReader::new(&mut fs, value, 16)
+            .with_wof(WofAlgorithm::Xpress4k, 4096 + 8)
+            .unwrap();
+        assert_eq!(reader.len(), 4104);
+        assert_eq!(reader.data_ranges(), vec![0..4104]);
+
+        reader.seek(SeekFrom::Start(4094)).unwrap();
+        assert_eq!(reader.read_all().unwrap(), b"abtail1234");
+        reader.seek(SeekFrom::Start(1)).unwrap();
+        let mut buf = [0u8; 3];
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"bab");
+    }
+}
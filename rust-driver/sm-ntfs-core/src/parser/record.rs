@@ -0,0 +1,632 @@
+//! Raw MFT file records and attribute headers
+//!
+//! The `ntfs` crate keeps record bytes and most attribute header fields
+//! private. This module reads a record straight from the device, applies the
+//! update sequence (fixup) array and exposes the on-disk header fields that
+//! the higher-level APIs need.
+
+use std::io::{Read, Seek, SeekFrom};
+use ntfs::NtfsAttributeType;
+use crate::parser::runs::{decode_data_runs, DataRun};
+use crate::utils::bytes::{le_u16, le_u32, le_u64, le_u8, utf16_string};
+use crate::utils::error::{Result, SMNtfsError};
+
+/// Signature of a valid file record
+pub const FILE_RECORD_SIGNATURE: &[u8; 4] = b"FILE";
+
+/// Stride of the update sequence array; NTFS always protects 512-byte blocks
+pub const FIXUP_STRIDE: usize = 512;
+
+/// Marker ending the attribute list of a record
+const ATTRIBUTE_END: u32 = 0xFFFF_FFFF;
+
+/// File record header flags
+pub mod record_flags {
+    /// Record is in use
+    pub const IN_USE: u16 = 0x0001;
+    /// Record has a $I30 directory index
+    pub const DIRECTORY: u16 = 0x0002;
+    /// Record is in the $Extend directory
+    pub const EXTEND: u16 = 0x0004;
+    /// Record has a view index other than $I30
+    pub const VIEW_INDEX: u16 = 0x0008;
+}
+
+/// Attribute header flags
+pub mod attribute_flags {
+    /// Value is LZNT1 compressed
+    pub const COMPRESSED: u16 = 0x0001;
+    /// Value is EFS encrypted
+    pub const ENCRYPTED: u16 = 0x4000;
+    /// Value is stored sparsely
+    pub const SPARSE: u16 = 0x8000;
+}
+
+/// Conventional name of an attribute type code, e.g. `$DATA` for 0x80
+pub fn attribute_type_name(type_code: u32) -> &'static str {
+    match type_code {
+        0x10 => "$STANDARD_INFORMATION",
+        0x20 => "$ATTRIBUTE_LIST",
+        0x30 => "$FILE_NAME",
+        0x40 => "$OBJECT_ID",
+        0x50 => "$SECURITY_DESCRIPTOR",
+        0x60 => "$VOLUME_NAME",
+        0x70 => "$VOLUME_INFORMATION",
+        0x80 => "$DATA",
+        0x90 => "$INDEX_ROOT",
+        0xA0 => "$INDEX_ALLOCATION",
+        0xB0 => "$BITMAP",
+        0xC0 => "$REPARSE_POINT",
+        0xD0 => "$EA_INFORMATION",
+        0xE0 => "$EA",
+        0x100 => "$LOGGED_UTILITY_STREAM",
+        _ => "$UNKNOWN",
+    }
+}
+
+/// Apply the update sequence array of a multi-sector record in place
+///
+/// Returns `false` if a protected sector does not end with the update
+/// sequence number, i.e. the record was torn or is damaged. Sectors are
+/// still restored as far as possible.
+pub fn apply_fixups(data: &mut [u8]) -> bool {
+    let usa_offset = le_u16(data, 0x04) as usize;
+    let usa_count = le_u16(data, 0x06) as usize;
+
+    if usa_count == 0 || usa_offset + usa_count * 2 > data.len() {
+        return false;
+    }
+
+    let usn = le_u16(data, usa_offset);
+    let mut valid = true;
+
+    for i in 1..usa_count {
+        let sector_end = i * FIXUP_STRIDE;
+        if sector_end > data.len() {
+            valid = false;
+            break;
+        }
+        if le_u16(data, sector_end - 2) != usn {
+            valid = false;
+        }
+        data[sector_end - 2] = data[usa_offset + i * 2];
+        data[sector_end - 1] = data[usa_offset + i * 2 + 1];
+    }
+
+    valid
+}
+
+/// Protect a multi-sector record before writing it, the inverse of [`apply_fixups`]
+///
+/// Bumps the update sequence number, saves the last two bytes of every
+/// sector in the update sequence array and replaces them with the number.
+/// Returns `false` if the header's array does not fit the record.
+pub fn protect_fixups(data: &mut [u8]) -> bool {
+    let usa_offset = le_u16(data, 0x04) as usize;
+    let usa_count = le_u16(data, 0x06) as usize;
+
+    if usa_count == 0 || usa_offset + usa_count * 2 > data.len() || (usa_count - 1) * FIXUP_STRIDE > data.len() {
+        return false;
+    }
+
+    // 0 and 0xFFFF are never used as update sequence numbers
+    let usn = match le_u16(data, usa_offset).wrapping_add(1) {
+        0 | 0xFFFF => 1,
+        usn => usn,
+    };
+    data[usa_offset..usa_offset + 2].copy_from_slice(&usn.to_le_bytes());
+
+    for i in 1..usa_count {
+        let sector_end = i * FIXUP_STRIDE;
+        data[usa_offset + i * 2] = data[sector_end - 2];
+        data[usa_offset + i * 2 + 1] = data[sector_end - 1];
+        data[sector_end - 2..sector_end].copy_from_slice(&usn.to_le_bytes());
+    }
+
+    true
+}
+
+/// An MFT file record with fixups applied
+#[derive(Debug, Clone)]
+pub struct FileRecord {
+    data: Vec<u8>,
+    record_number: u64,
+    position: u64,
+    fixups_valid: bool,
+}
+
+impl FileRecord {
+    /// Parse a record from raw on-disk bytes
+    pub fn from_bytes(mut data: Vec<u8>, record_number: u64, position: u64) -> Self {
+        let fixups_valid = data.get(..4) == Some(FILE_RECORD_SIGNATURE.as_slice()) && apply_fixups(&mut data);
+
+        Self {
+            data,
+            record_number,
+            position,
+            fixups_valid,
+        }
+    }
+
+    /// Read the record stored at `position` on the device
+    pub fn read_at<T: Read + Seek>(fs: &mut T, position: u64, record_size: usize, record_number: u64) -> Result<Self> {
+        let mut data = vec![0u8; record_size];
+        fs.seek(SeekFrom::Start(position))?;
+        fs.read_exact(&mut data)
+            .map_err(|e| SMNtfsError::ReadError(format!("Failed to read MFT record {}: {}", record_number, e)))?;
+
+        Ok(Self::from_bytes(data, record_number, position))
+    }
+
+    /// Read the raw record backing an `NtfsFile`
+    pub fn read<T: Read + Seek>(file: &ntfs::NtfsFile, fs: &mut T) -> Result<Self> {
+        let position = file
+            .position()
+            .value()
+            .ok_or(SMNtfsError::CorruptedMft {
+                offset: file.file_record_number(),
+            })?
+            .get();
+        let record_size = file.ntfs().file_record_size() as usize;
+
+        let record = Self::read_at(fs, position, record_size, file.file_record_number())?;
+        record.validate()?;
+        Ok(record)
+    }
+
+    /// Fail unless the signature and fixups are intact
+    pub fn validate(&self) -> Result<()> {
+        if self.is_valid() {
+            Ok(())
+        } else {
+            Err(SMNtfsError::CorruptedMft {
+                offset: self.record_number,
+            })
+        }
+    }
+
+    /// Record bytes with fixups applied
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// MFT record number
+    pub fn record_number(&self) -> u64 {
+        self.record_number
+    }
+
+    /// Absolute byte position on the device
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Check for the `FILE` signature
+    pub fn has_valid_signature(&self) -> bool {
+        self.data.get(..4) == Some(FILE_RECORD_SIGNATURE.as_slice())
+    }
+
+    /// Check that the signature is present and every sector passed the fixup check
+    pub fn is_valid(&self) -> bool {
+        self.fixups_valid
+    }
+
+    /// $LogFile sequence number of the last change
+    pub fn lsn(&self) -> u64 {
+        le_u64(&self.data, 0x08)
+    }
+
+    /// Sequence number (incremented each time the record is freed)
+    pub fn sequence_number(&self) -> u16 {
+        le_u16(&self.data, 0x10)
+    }
+
+    /// Hard link count
+    pub fn hard_link_count(&self) -> u16 {
+        le_u16(&self.data, 0x12)
+    }
+
+    /// Header flags, see [`record_flags`]
+    pub fn flags(&self) -> u16 {
+        le_u16(&self.data, 0x16)
+    }
+
+    /// Check if the record is allocated
+    pub fn is_in_use(&self) -> bool {
+        self.flags() & record_flags::IN_USE != 0
+    }
+
+    /// Check if the record is a directory
+    pub fn is_directory(&self) -> bool {
+        self.flags() & record_flags::DIRECTORY != 0
+    }
+
+    /// Bytes used by the record
+    pub fn used_size(&self) -> u32 {
+        le_u32(&self.data, 0x18)
+    }
+
+    /// Bytes allocated for the record
+    pub fn allocated_size(&self) -> u32 {
+        le_u32(&self.data, 0x1C)
+    }
+
+    /// Raw reference to the base record (zero for base records)
+    pub fn base_reference(&self) -> u64 {
+        le_u64(&self.data, 0x20)
+    }
+
+    /// Record number of the base record this extension record belongs to
+    pub fn base_record_number(&self) -> Option<u64> {
+        match self.base_reference() & 0x0000_FFFF_FFFF_FFFF {
+            0 => None,
+            number => Some(number),
+        }
+    }
+
+    /// Check if this is a base record rather than an extension record
+    pub fn is_base_record(&self) -> bool {
+        self.base_record_number().is_none()
+    }
+
+    /// Iterate over the attributes stored in this record
+    pub fn attributes(&self) -> RawAttributes<'_> {
+        RawAttributes {
+            record: self,
+            offset: le_u16(&self.data, 0x14) as usize,
+        }
+    }
+
+    /// Find the first attribute of a type and name (case-sensitive)
+    pub fn find_attribute(&self, ty: NtfsAttributeType, name: &str) -> Option<RawAttribute<'_>> {
+        self.attributes()
+            .filter_map(|attribute| attribute.ok())
+            .find(|attribute| attribute.type_code() == ty as u32 && attribute.name() == name)
+    }
+}
+
+/// Iterator over the attributes of a [`FileRecord`]
+pub struct RawAttributes<'r> {
+    record: &'r FileRecord,
+    offset: usize,
+}
+
+impl<'r> Iterator for RawAttributes<'r> {
+    type Item = Result<RawAttribute<'r>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let data = self.record.data();
+        let limit = (self.record.used_size() as usize).min(data.len());
+
+        if self.offset == 0 || self.offset + 8 > limit {
+            return None;
+        }
+
+        let ty = le_u32(data, self.offset);
+        if ty == ATTRIBUTE_END {
+            return None;
+        }
+
+        let length = le_u32(data, self.offset + 4) as usize;
+        if length < 0x18 || self.offset + length > limit {
+            let offset = self.offset;
+            self.offset = 0;
+            return Some(Err(SMNtfsError::ReadError(format!(
+                "Invalid attribute length {} at offset {} of MFT record {}",
+                length,
+                offset,
+                self.record.record_number()
+            ))));
+        }
+
+        let attribute = RawAttribute {
+            data: &data[self.offset..self.offset + length],
+            offset: self.offset,
+            record_number: self.record.record_number(),
+        };
+        self.offset += length;
+
+        Some(Ok(attribute))
+    }
+}
+
+/// An attribute header (and resident value) inside a [`FileRecord`]
+#[derive(Debug, Clone, Copy)]
+pub struct RawAttribute<'r> {
+    data: &'r [u8],
+    offset: usize,
+    record_number: u64,
+}
+
+impl<'r> RawAttribute<'r> {
+    /// Raw attribute type code
+    pub fn type_code(&self) -> u32 {
+        le_u32(self.data, 0x00)
+    }
+
+    /// Check the attribute type
+    pub fn is_type(&self, ty: NtfsAttributeType) -> bool {
+        self.type_code() == ty as u32
+    }
+
+    /// Offset of the attribute within its record
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Record this attribute is stored in
+    pub fn record_number(&self) -> u64 {
+        self.record_number
+    }
+
+    /// Check if the value is stored inside the record
+    pub fn is_resident(&self) -> bool {
+        le_u8(self.data, 0x08) == 0
+    }
+
+    /// Attribute name (empty for unnamed attributes)
+    pub fn name(&self) -> String {
+        let length = le_u8(self.data, 0x09) as usize;
+        let offset = le_u16(self.data, 0x0A) as usize;
+        utf16_string(self.data, offset, length)
+    }
+
+    /// Header flags, see [`attribute_flags`]
+    pub fn flags(&self) -> u16 {
+        le_u16(self.data, 0x0C)
+    }
+
+    /// Check if the value is compressed
+    pub fn is_compressed(&self) -> bool {
+        self.flags() & attribute_flags::COMPRESSED != 0
+    }
+
+    /// Check if the value is encrypted
+    pub fn is_encrypted(&self) -> bool {
+        self.flags() & attribute_flags::ENCRYPTED != 0
+    }
+
+    /// Check if the value is sparse
+    pub fn is_sparse(&self) -> bool {
+        self.flags() & attribute_flags::SPARSE != 0
+    }
+
+    /// Identifier unique within the record
+    pub fn instance(&self) -> u16 {
+        le_u16(self.data, 0x0E)
+    }
+
+    /// Resident value bytes (empty for non-resident attributes)
+    pub fn resident_value(&self) -> &'r [u8] {
+        if !self.is_resident() {
+            return &[];
+        }
+        let length = le_u32(self.data, 0x10) as usize;
+        let offset = le_u16(self.data, 0x14) as usize;
+        self.data.get(offset..offset + length).unwrap_or(&[])
+    }
+
+    /// First VCN described by this attribute fragment
+    pub fn lowest_vcn(&self) -> u64 {
+        if self.is_resident() { 0 } else { le_u64(self.data, 0x10) }
+    }
+
+    /// Last VCN described by this attribute fragment
+    pub fn highest_vcn(&self) -> u64 {
+        if self.is_resident() { 0 } else { le_u64(self.data, 0x18) }
+    }
+
+    /// Compression unit size as a power of two in clusters (0 if uncompressed)
+    pub fn compression_unit(&self) -> u8 {
+        if self.is_resident() { 0 } else { le_u8(self.data, 0x22) }
+    }
+
+    /// Bytes allocated on disk (rounded to clusters or compression units)
+    pub fn allocated_size(&self) -> u64 {
+        if self.is_resident() {
+            self.resident_value().len() as u64
+        } else {
+            le_u64(self.data, 0x28)
+        }
+    }
+
+    /// Logical size of the value
+    pub fn data_size(&self) -> u64 {
+        if self.is_resident() {
+            self.resident_value().len() as u64
+        } else {
+            le_u64(self.data, 0x30)
+        }
+    }
+
+    /// Bytes written so far; reads beyond return zeros
+    pub fn initialized_size(&self) -> u64 {
+        if self.is_resident() {
+            self.resident_value().len() as u64
+        } else {
+            le_u64(self.data, 0x38)
+        }
+    }
+
+    /// Clusters actually in use, present for compressed and sparse attributes
+    pub fn compressed_size(&self) -> Option<u64> {
+        let has_field = !self.is_resident()
+            && self.flags() & (attribute_flags::COMPRESSED | attribute_flags::SPARSE) != 0
+            && self.data.len() >= 0x48;
+        has_field.then(|| le_u64(self.data, 0x40))
+    }
+
+    /// Decode the data runs of a non-resident attribute
+    pub fn data_runs(&self) -> Result<Vec<DataRun>> {
+        if self.is_resident() {
+            return Ok(Vec::new());
+        }
+        let offset = le_u16(self.data, 0x20) as usize;
+        let mapping_pairs = self.data.get(offset..).ok_or(SMNtfsError::CorruptedMft {
+            offset: self.record_number,
+        })?;
+        decode_data_runs(mapping_pairs, self.lowest_vcn())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Build a 1024-byte record with the given attributes and valid fixups
+    pub(crate) fn build_record(attributes: &[Vec<u8>], flags: u16) -> Vec<u8> {
+        let mut data = vec![0u8; 1024];
+        data[..4].copy_from_slice(FILE_RECORD_SIGNATURE);
+        data[0x04..0x06].copy_from_slice(&0x30u16.to_le_bytes());
+        data[0x06..0x08].copy_from_slice(&3u16.to_le_bytes());
+        data[0x10..0x12].copy_from_slice(&1u16.to_le_bytes());
+        data[0x12..0x14].copy_from_slice(&1u16.to_le_bytes());
+        data[0x14..0x16].copy_from_slice(&0x38u16.to_le_bytes());
+        data[0x16..0x18].copy_from_slice(&flags.to_le_bytes());
+        data[0x1C..0x20].copy_from_slice(&1024u32.to_le_bytes());
+
+        let mut offset = 0x38;
+        for attribute in attributes {
+            data[offset..offset + attribute.len()].copy_from_slice(attribute);
+            offset += attribute.len();
+        }
+        data[offset..offset + 4].copy_from_slice(&ATTRIBUTE_END.to_le_bytes());
+        data[0x18..0x1C].copy_from_slice(&((offset + 8) as u32).to_le_bytes());
+
+        // Update sequence number 0x0001, saving the real sector tails
+        data[0x30..0x32].copy_from_slice(&1u16.to_le_bytes());
+        for i in 1..3 {
+            let end = i * FIXUP_STRIDE;
+            data[0x30 + i * 2] = data[end - 2];
+            data[0x31 + i * 2] = data[end - 1];
+            data[end - 2..end].copy_from_slice(&1u16.to_le_bytes());
+        }
+        data
+    }
+
+    /// Build a resident attribute
+    pub(crate) fn resident_attribute(ty: u32, name: &str, value: &[u8]) -> Vec<u8> {
+        let name: Vec<u16> = name.encode_utf16().collect();
+        let value_offset = (0x18 + name.len() * 2 + 7) & !7;
+        let length = (value_offset + value.len() + 7) & !7;
+
+        let mut data = vec![0u8; length];
+        data[0..4].copy_from_slice(&ty.to_le_bytes());
+        data[4..8].copy_from_slice(&(length as u32).to_le_bytes());
+        data[9] = name.len() as u8;
+        data[0x0A..0x0C].copy_from_slice(&0x18u16.to_le_bytes());
+        data[0x10..0x14].copy_from_slice(&(value.len() as u32).to_le_bytes());
+        data[0x14..0x16].copy_from_slice(&(value_offset as u16).to_le_bytes());
+        for (i, unit) in name.iter().enumerate() {
+            data[0x18 + i * 2..0x1A + i * 2].copy_from_slice(&unit.to_le_bytes());
+        }
+        data[value_offset..value_offset + value.len()].copy_from_slice(value);
+        data
+    }
+
+    /// Build a non-resident attribute with the given mapping pairs
+    pub(crate) fn non_resident_attribute(
+        ty: u32,
+        name: &str,
+        flags: u16,
+        sizes: (u64, u64, u64),
+        mapping_pairs: &[u8],
+    ) -> Vec<u8> {
+        let name: Vec<u16> = name.encode_utf16().collect();
+        let header_length = if flags & (attribute_flags::COMPRESSED | attribute_flags::SPARSE) != 0 { 0x48 } else { 0x40 };
+        let runs_offset = (header_length + name.len() * 2 + 7) & !7;
+        let length = (runs_offset + mapping_pairs.len() + 1 + 7) & !7;
+        let (allocated, data_size, initialized) = sizes;
+
+        let mut data = vec![0u8; length];
+        data[0..4].copy_from_slice(&ty.to_le_bytes());
+        data[4..8].copy_from_slice(&(length as u32).to_le_bytes());
+        data[8] = 1;
+        data[9] = name.len() as u8;
+        data[0x0A..0x0C].copy_from_slice(&(header_length as u16).to_le_bytes());
+        data[0x0C..0x0E].copy_from_slice(&flags.to_le_bytes());
+        data[0x20..0x22].copy_from_slice(&(runs_offset as u16).to_le_bytes());
+        if flags & attribute_flags::COMPRESSED != 0 {
+            data[0x22] = 4;
+        }
+        data[0x28..0x30].copy_from_slice(&allocated.to_le_bytes());
+        data[0x30..0x38].copy_from_slice(&data_size.to_le_bytes());
+        data[0x38..0x40].copy_from_slice(&initialized.to_le_bytes());
+        for (i, unit) in name.iter().enumerate() {
+            let offset = header_length + i * 2;
+            data[offset..offset + 2].copy_from_slice(&unit.to_le_bytes());
+        }
+        data[runs_offset..runs_offset + mapping_pairs.len()].copy_from_slice(mapping_pairs);
+        data
+    }
+
+    #[test]
+    fn test_fixups_roundtrip() {
+        let mut data = build_record(&[], record_flags::IN_USE);
+        data[510] = 0x01;
+        data[511] = 0x00;
+        let record = FileRecord::from_bytes(data, 7, 0);
+
+        assert!(record.is_valid());
+        assert!(record.is_in_use());
+        assert!(record.is_base_record());
+        assert_eq!(record.attributes().count(), 0);
+    }
+
+    #[test]
+    fn test_protect_fixups_roundtrip() {
+        let mut data = build_record(&[], record_flags::IN_USE);
+        assert!(apply_fixups(&mut data));
+        let original = data.clone();
+
+        assert!(protect_fixups(&mut data));
+        assert_eq!(le_u16(&data, 0x30), 2);
+        assert_eq!(le_u16(&data, 1022), 2);
+        assert!(apply_fixups(&mut data));
+        assert_eq!(data[0x32..], original[0x32..]);
+    }
+
+    #[test]
+    fn test_torn_record_fails_fixups() {
+        let mut data = build_record(&[], record_flags::IN_USE);
+        data[1022] = 0x99;
+        let record = FileRecord::from_bytes(data, 7, 0);
+
+        assert!(!record.is_valid());
+        assert!(record.validate().is_err());
+    }
+
+    #[test]
+    fn test_parse_attributes() {
+        let data = build_record(
+            &[
+                resident_attribute(NtfsAttributeType::Data as u32, "", b"12345"),
+                non_resident_attribute(
+                    NtfsAttributeType::Data as u32,
+                    "big",
+                    attribute_flags::SPARSE,
+                    (8192, 10000, 6000),
+                    &[0x11, 0x02, 0x10, 0x01, 0x02],
+                ),
+            ],
+            record_flags::IN_USE,
+        );
+        let record = FileRecord::from_bytes(data, 42, 0);
+        let attributes: Vec<_> = record.attributes().map(|a| a.unwrap()).collect();
+
+        assert_eq!(attributes.len(), 2);
+        assert!(attributes[0].is_resident());
+        assert_eq!(attributes[0].resident_value(), b"12345");
+
+        let big = record.find_attribute(NtfsAttributeType::Data, "big").unwrap();
+        assert!(!big.is_resident());
+        assert!(big.is_sparse());
+        assert_eq!(big.allocated_size(), 8192);
+        assert_eq!(big.data_size(), 10000);
+        assert_eq!(big.initialized_size(), 6000);
+        assert_eq!(big.compressed_size(), Some(0));
+
+        let runs = big.data_runs().unwrap();
+        assert_eq!(runs.len(), 2);
+        assert!(runs[1].is_sparse());
+    }
+}
@@ -0,0 +1,523 @@
+//! Opening volumes with a damaged boot sector or damaged system records
+//!
+//! NTFS keeps a backup of the boot sector in the last sector of the volume
+//! and copies of the first MFT records ($MFT, $MFTMirr, $LogFile, $Volume)
+//! in $MFTMirr. [`open_volume`] checks the primary copies before handing the
+//! device to the ntfs crate; any that fail their signature or fixup checks
+//! are replaced by their backup through a [`PatchedDevice`], which overlays
+//! the good copies on reads without writing anything. Every fallback is
+//! logged and returned as a warning. A device that [`probe`](mod@crate::parser::probe)
+//! recognises as another filesystem is refused with its specific error before
+//! any backup is looked for, so a reformatted volume is not opened through a
+//! stale NTFS backup boot sector.
+//!
+//! [`open_volume_writable`] also refuses volumes that a hibernated Windows
+//! may resume on, see [`hibernation`], and writes the backup copies in use
+//! over the damaged primaries before anything else is written.
+
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use ntfs::Ntfs;
+use crate::parser::boot::{BootSector, BOOT_SECTOR_SIZE};
+use crate::parser::hibernation;
+use crate::parser::probe::ensure_ntfs;
+use crate::parser::record::FileRecord;
+use crate::parser::volume::NtfsVolume;
+use crate::utils::error::{Result, SMNtfsError};
+
+/// Records always present in $MFTMirr
+pub const MIRRORED_RECORDS: u64 = 4;
+
+/// Names of the mirrored records, for warnings
+const MIRRORED_NAMES: [&str; 4] = ["$MFT", "$MFTMirr", "$LogFile", "$Volume"];
+
+/// Sector sizes tried when looking for the backup boot sector
+const BACKUP_SECTOR_SIZES: [u64; 4] = [512, 1024, 2048, 4096];
+
+/// A device with some byte ranges replaced on reads
+///
+/// Writes go to the underlying device and also update overlapping patches,
+/// so reads stay consistent with what was written.
+pub struct PatchedDevice<T> {
+    inner: T,
+    patches: Vec<(u64, Vec<u8>)>,
+    position: u64,
+}
+
+impl<T> PatchedDevice<T> {
+    /// Wrap a device without any patches
+    pub fn new(inner: T) -> Self {
+        Self {
+            inner,
+            patches: Vec::new(),
+            position: 0,
+        }
+    }
+
+    /// Replace the bytes at `offset` on reads
+    pub fn patch(&mut self, offset: u64, data: Vec<u8>) {
+        let end = offset + data.len() as u64;
+        self.patches.retain(|(start, old)| end <= *start || start + old.len() as u64 <= offset);
+        self.patches.push((offset, data));
+    }
+
+    /// Patched ranges as `(offset, length)`
+    pub fn patched_ranges(&self) -> Vec<(u64, u64)> {
+        self.patches.iter().map(|(offset, data)| (*offset, data.len() as u64)).collect()
+    }
+
+    /// The underlying device
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    /// Unwrap the underlying device, dropping the patches
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    /// Overlaps of the patches with `length` bytes at the position, as
+    /// `(patch index, buffer offset, patch offset, length)`
+    fn overlaps(&self, length: usize) -> Vec<(usize, usize, usize, usize)> {
+        let end = self.position + length as u64;
+        self.patches
+            .iter()
+            .enumerate()
+            .filter_map(|(index, (start, data))| {
+                let from = self.position.max(*start);
+                let to = end.min(start + data.len() as u64);
+                (from < to).then(|| {
+                    (index, (from - self.position) as usize, (from - start) as usize, (to - from) as usize)
+                })
+            })
+            .collect()
+    }
+}
+
+impl<T: Read + Seek> Read for PatchedDevice<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.seek(SeekFrom::Start(self.position))?;
+        let count = self.inner.read(buf)?;
+
+        for (index, at, from, length) in self.overlaps(count) {
+            buf[at..at + length].copy_from_slice(&self.patches[index].1[from..from + length]);
+        }
+        self.position += count as u64;
+        Ok(count)
+    }
+}
+
+impl<T: Write + Seek> PatchedDevice<T> {
+    /// Write every patch to the underlying device and drop it, returning
+    /// how many were written
+    pub fn write_patches(&mut self) -> io::Result<usize> {
+        let count = self.patches.len();
+        for (offset, data) in self.patches.drain(..) {
+            self.inner.seek(SeekFrom::Start(offset))?;
+            self.inner.write_all(&data)?;
+        }
+        self.inner.flush()?;
+        Ok(count)
+    }
+}
+
+impl<T: Write + Seek> Write for PatchedDevice<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.seek(SeekFrom::Start(self.position))?;
+        let count = self.inner.write(buf)?;
+
+        for (index, at, from, length) in self.overlaps(count) {
+            self.patches[index].1[from..from + length].copy_from_slice(&buf[at..at + length]);
+        }
+        self.position += count as u64;
+        Ok(count)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<T: Seek> Seek for PatchedDevice<T> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let new_position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+            SeekFrom::End(offset) => self.inner.seek(SeekFrom::End(0))?.checked_add_signed(offset),
+        };
+
+        self.position = new_position.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "Seek to a negative or overflowing position")
+        })?;
+        Ok(self.position)
+    }
+}
+
+/// A volume opened by [`open_volume`]
+pub struct OpenedVolume<T> {
+    /// The device, with any backup copies overlaid
+    pub fs: PatchedDevice<T>,
+
+    /// Parsed volume, with the $UpCase table loaded if it could be read
+    pub ntfs: Ntfs,
+
+    /// Boot sector in use
+    pub boot_sector: BootSector,
+
+    /// One message per fallback that was needed
+    pub warnings: Vec<String>,
+}
+
+/// Open a volume, falling back to backup copies of damaged structures
+pub fn open_volume<T: Read + Seek>(fs: T) -> Result<OpenedVolume<T>> {
+    let mut fs = PatchedDevice::new(fs);
+    let mut warnings = Vec::new();
+
+    ensure_ntfs(&mut fs)?;
+
+    let boot_sector = recover_boot_sector(&mut fs, &mut warnings)?;
+    recover_system_records(&mut fs, &boot_sector, &mut warnings)?;
+
+    let mut ntfs = Ntfs::new(&mut fs).map_err(|e| SMNtfsError::InvalidNtfs(e.to_string()))?;
+    // Names are compared through the table of NtfsVolume, which has its own fallback
+    if let Err(e) = ntfs.read_upcase_table(&mut fs) {
+        warn(&mut warnings, format!("Failed to read the $UpCase table: {}", e));
+    }
+
+    Ok(OpenedVolume {
+        fs,
+        ntfs,
+        boot_sector,
+        warnings,
+    })
+}
+
+/// What [`open_volume_writable`] lets through
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WriteOptions {
+    /// Discard the hibernation file of a hibernated Windows instead of refusing
+    pub remove_hiberfile: bool,
+
+    /// Accept $LogFile transactions left to apply, to replay or repair them
+    pub unclean_logfile: bool,
+}
+
+/// Open a volume to write to it
+///
+/// Like [`open_volume`], but refuses a volume that is hibernated or, unless
+/// `options` allows it, has $LogFile transactions left to apply. Every
+/// writer should open volumes this way; the hibernation file is only
+/// discarded when [`WriteOptions::remove_hiberfile`] asks for it.
+///
+/// Backup copies that replaced damaged structures are written over them
+/// before the volume is returned, so that nothing written afterwards
+/// depends on data only patched in memory. Wrap `fs` in an
+/// [`UndoDevice`](crate::io::UndoDevice) to keep their pre-images.
+pub fn open_volume_writable<T: Read + Write + Seek>(fs: T, options: WriteOptions) -> Result<OpenedVolume<T>> {
+    let mut opened = open_volume(fs)?;
+    let volume = NtfsVolume::new(&opened.ntfs, &mut opened.fs)?;
+    match options.unclean_logfile {
+        true => hibernation::ensure_not_hibernated(&volume, &mut opened.fs, options.remove_hiberfile)?,
+        false => hibernation::ensure_writable(&volume, &mut opened.fs, options.remove_hiberfile)?,
+    }
+
+    let restored = opened
+        .fs
+        .write_patches()
+        .map_err(|e| SMNtfsError::WriteError(format!("Failed to write backup copies over damaged structures: {}", e)))?;
+    if restored > 0 {
+        warn(&mut opened.warnings, format!("Wrote {} backup copies over damaged structures", restored));
+    }
+    Ok(opened)
+}
+
+fn warn(warnings: &mut Vec<String>, message: String) {
+    tracing::warn!("{}", message);
+    warnings.push(message);
+}
+
+/// Read the boot sector, patching in the backup if the primary is damaged
+pub fn recover_boot_sector<T: Read + Seek>(
+    fs: &mut PatchedDevice<T>,
+    warnings: &mut Vec<String>,
+) -> Result<BootSector> {
+    let primary = match BootSector::read(fs) {
+        Ok(boot_sector) => return Ok(boot_sector),
+        Err(e) => e,
+    };
+
+    let device_size = fs.seek(SeekFrom::End(0))?;
+    for sector_size in BACKUP_SECTOR_SIZES {
+        let Some(offset) = device_size.checked_sub(sector_size) else {
+            continue;
+        };
+        let mut data = vec![0u8; BOOT_SECTOR_SIZE];
+        fs.seek(SeekFrom::Start(offset))?;
+        if fs.read_exact(&mut data).is_err() {
+            continue;
+        }
+
+        match BootSector::parse(&data) {
+            Ok(boot_sector) if boot_sector.sector_size as u64 == sector_size => {
+                fs.patch(0, data);
+                warn(
+                    warnings,
+                    format!("Boot sector is damaged ({}); using the backup at byte {}", primary, offset),
+                );
+                return Ok(boot_sector);
+            }
+            _ => continue,
+        }
+    }
+
+    Err(SMNtfsError::InvalidNtfs(format!("{}, and no backup boot sector was found", primary)))
+}
+
+/// Patch in $MFTMirr copies of damaged system records
+///
+/// A damaged $MFT record without a usable copy is an error, since nothing
+/// else can be found without it; other records only produce a warning.
+pub fn recover_system_records<T: Read + Seek>(
+    fs: &mut PatchedDevice<T>,
+    boot_sector: &BootSector,
+    warnings: &mut Vec<String>,
+) -> Result<()> {
+    let record_size = boot_sector.file_record_size as u64;
+    // $MFTMirr covers at least one cluster
+    let count = MIRRORED_RECORDS.max(boot_sector.cluster_size as u64 / record_size);
+
+    for record_number in 0..count {
+        let primary_position = boot_sector.mft_offset() + record_number * record_size;
+        let primary = FileRecord::read_at(fs, primary_position, record_size as usize, record_number);
+        if primary.as_ref().is_ok_and(FileRecord::is_valid) {
+            continue;
+        }
+
+        let name = MIRRORED_NAMES
+            .get(record_number as usize)
+            .map_or_else(|| format!("MFT record {}", record_number), |name| name.to_string());
+        let mirror_position = boot_sector.mft_mirror_offset() + record_number * record_size;
+        let mut raw = vec![0u8; record_size as usize];
+        fs.seek(SeekFrom::Start(mirror_position))?;
+        let mirror_valid = fs.read_exact(&mut raw).is_ok()
+            && FileRecord::from_bytes(raw.clone(), record_number, mirror_position).is_valid();
+
+        if mirror_valid {
+            // Patched unfixed, as the ntfs crate applies fixups itself
+            fs.patch(primary_position, raw);
+            warn(warnings, format!("{} record is damaged; using its copy in $MFTMirr", name));
+        } else if record_number == 0 {
+            return Err(SMNtfsError::CorruptedMft { offset: 0 });
+        } else if record_number < MIRRORED_RECORDS {
+            warn(warnings, format!("{} record and its $MFTMirr copy are both damaged", name));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::boot::tests::boot_sector;
+    use crate::parser::record::record_flags;
+    use crate::parser::record::tests::build_record;
+    use crate::parser::volume::tests::test_image;
+    use crate::parser::{BlockDeviceAdapter, NtfsVolume};
+    use crate::io::{rollback, BlockDevice, UndoDevice};
+    use std::io::Cursor;
+
+    /// 1 MiB volume with 4 KiB clusters: MFT at cluster 4, $MFTMirr at cluster 128
+    fn volume() -> Vec<u8> {
+        let mut data = vec![0u8; 1024 * 1024];
+        let boot = boot_sector(2047, 4, 128);
+        data[..BOOT_SECTOR_SIZE].copy_from_slice(&boot);
+        data[1024 * 1024 - 512..].copy_from_slice(&boot);
+
+        for i in 0..4 {
+            let mut record = build_record(&[], record_flags::IN_USE);
+            record[0x2C..0x30].copy_from_slice(&(i as u32).to_le_bytes());
+            data[4 * 4096 + i * 1024..][..1024].copy_from_slice(&record);
+            data[128 * 4096 + i * 1024..][..1024].copy_from_slice(&record);
+        }
+        data
+    }
+
+    #[test]
+    fn test_patched_reads_and_writes() {
+        let mut fs = PatchedDevice::new(Cursor::new(b"0123456789".to_vec()));
+        fs.patch(3, b"abc".to_vec());
+
+        let mut buf = [0u8; 5];
+        fs.seek(SeekFrom::Start(2)).unwrap();
+        fs.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"2abc6");
+
+        fs.seek(SeekFrom::Start(4)).unwrap();
+        fs.write_all(b"XY").unwrap();
+        fs.seek(SeekFrom::Start(0)).unwrap();
+        let mut all = Vec::new();
+        fs.read_to_end(&mut all).unwrap();
+        assert_eq!(all, b"012aXY6789");
+        assert_eq!(fs.into_inner().into_inner(), b"0123XY6789");
+    }
+
+    #[test]
+    fn test_backup_boot_sector() {
+        let mut data = volume();
+        data[..16].fill(0);
+        let mut fs = PatchedDevice::new(Cursor::new(data));
+        let mut warnings = Vec::new();
+
+        let boot = recover_boot_sector(&mut fs, &mut warnings).unwrap();
+        assert_eq!(boot.mft_lcn, 4);
+        assert_eq!(warnings.len(), 1);
+        // Sector 0 now reads as the backup
+        assert_eq!(BootSector::read(&mut fs).unwrap(), boot);
+
+        let mut fs = PatchedDevice::new(Cursor::new(vec![0u8; 8192]));
+        assert!(recover_boot_sector(&mut fs, &mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_backup_boot_sector_of_device() {
+        let mut image = tempfile::NamedTempFile::new().unwrap();
+        let mut data = test_image();
+        data[..16].fill(0);
+        image.write_all(&data).unwrap();
+
+        // The backup is found at the end of the device, not of a Cursor
+        let device = BlockDevice::open(image.path()).unwrap();
+        let opened = open_volume(BlockDeviceAdapter::new(device)).unwrap();
+        assert_eq!(opened.warnings.len(), 1);
+        assert!(opened.warnings[0].contains(&format!("backup at byte {}", data.len() - 512)));
+
+        let (mut fs, ntfs) = (opened.fs, opened.ntfs);
+        let volume = NtfsVolume::new(&ntfs, &mut fs).unwrap();
+        assert_eq!(volume.volume_name(), "mylabel");
+    }
+
+    /// The test image with `1000-bytes-file` renamed to a hibernation file
+    /// holding a saved system
+    fn hibernated_image() -> Vec<u8> {
+        let mut image = test_image();
+        let name: Vec<u8> = "1000-bytes-file".encode_utf16().flat_map(u16::to_le_bytes).collect();
+        // The last copy of the name is the key of its entry in the root's $I30
+        let position = image.windows(name.len()).rposition(|window| window == name).unwrap();
+        let hiberfil: Vec<u8> = "hiberfil.sys".encode_utf16().flat_map(u16::to_le_bytes).collect();
+        image[position - 2] = 12;
+        image[position..position + hiberfil.len()].copy_from_slice(&hiberfil);
+
+        let opened = open_volume(Cursor::new(image)).unwrap();
+        let (mut fs, ntfs) = (opened.fs, opened.ntfs);
+        let volume = NtfsVolume::new(&ntfs, &mut fs).unwrap();
+        let reader = volume.open_stream(&mut fs, hibernation::HIBERFIL_PATH).unwrap();
+        let lcn = reader.stream().runs[0].lcn.unwrap();
+        let mut image = fs.into_inner().into_inner();
+        let offset = (lcn * volume.cluster_size() as u64) as usize;
+        image[offset..offset + 4].copy_from_slice(b"HIBR");
+        image
+    }
+
+    #[test]
+    fn test_open_volume_writable() {
+        assert!(open_volume_writable(Cursor::new(test_image()), WriteOptions::default()).is_ok());
+
+        // Refused without writing anything, but still readable
+        let image = hibernated_image();
+        for unclean_logfile in [false, true] {
+            let options = WriteOptions { remove_hiberfile: false, unclean_logfile };
+            assert!(matches!(
+                open_volume_writable(Cursor::new(image.clone()), options),
+                Err(SMNtfsError::VolumeHibernated(_))
+            ));
+        }
+        let opened = open_volume(Cursor::new(image.clone())).unwrap();
+        let (mut fs, ntfs) = (opened.fs, opened.ntfs);
+        assert!(NtfsVolume::new(&ntfs, &mut fs).unwrap().is_hibernated(&mut fs).unwrap());
+
+        let options = WriteOptions {
+            remove_hiberfile: true,
+            ..WriteOptions::default()
+        };
+        let opened = open_volume_writable(Cursor::new(image), options).unwrap();
+        let (mut fs, ntfs) = (opened.fs, opened.ntfs);
+        let volume = NtfsVolume::new(&ntfs, &mut fs).unwrap();
+        assert!(!volume.is_hibernated(&mut fs).unwrap());
+        let mut data = Vec::new();
+        volume.open_stream(&mut fs, hibernation::HIBERFIL_PATH).unwrap().read_to_end(&mut data).unwrap();
+        assert!(data.len() == 1000 && data.iter().all(|byte| *byte == 0));
+    }
+
+    #[test]
+    fn test_repair_torn_boot_sector() {
+        let mut torn = test_image();
+        torn[0x1FE] = 0;
+        let undo = tempfile::tempfile().unwrap();
+        let device = UndoDevice::new(Cursor::new(torn.clone()), undo.try_clone().unwrap(), 512).unwrap();
+
+        let options = WriteOptions {
+            unclean_logfile: true,
+            ..WriteOptions::default()
+        };
+        let opened = open_volume_writable(device, options).unwrap();
+        assert_eq!(opened.warnings.len(), 2);
+        assert!(opened.warnings[1].starts_with("Wrote 1 backup"));
+        assert!(opened.fs.patched_ranges().is_empty());
+
+        let (mut fs, ntfs) = (opened.fs, opened.ntfs);
+        let volume = NtfsVolume::new(&ntfs, &mut fs).unwrap();
+        let report = volume.check(&mut fs).unwrap();
+        volume.repair(&mut fs, &report).unwrap();
+
+        // The backup is on disk, and the undo file holds the torn sector
+        let mut repaired = fs.into_inner().into_inner().into_inner();
+        assert_eq!(repaired[..512], repaired[repaired.len() - 512..]);
+        assert!(open_volume(Cursor::new(repaired.clone())).unwrap().warnings.is_empty());
+        let mut undo = undo;
+        undo.seek(SeekFrom::Start(0)).unwrap();
+        rollback(&mut undo, &mut Cursor::new(&mut repaired)).unwrap();
+        assert_eq!(repaired, torn);
+    }
+
+    #[test]
+    fn test_mirrored_records() {
+        let mut data = volume();
+        // Tear $MFT and $Volume, and $Volume's mirror copy too
+        data[4 * 4096 + 510] ^= 0xFF;
+        data[4 * 4096 + 3 * 1024 + 510] ^= 0xFF;
+        data[128 * 4096 + 3 * 1024 + 510] ^= 0xFF;
+        let mut fs = PatchedDevice::new(Cursor::new(data));
+        let boot = BootSector::read(&mut fs).unwrap();
+        let mut warnings = Vec::new();
+
+        recover_system_records(&mut fs, &boot, &mut warnings).unwrap();
+        assert_eq!(warnings.len(), 2);
+        assert!(warnings[0].starts_with("$MFT record"));
+        assert!(warnings[1].contains("both damaged"));
+        assert!(FileRecord::read_at(&mut fs, 4 * 4096, 1024, 0).unwrap().is_valid());
+        assert_eq!(fs.patched_ranges(), vec![(4 * 4096, 1024)]);
+    }
+
+    #[test]
+    fn test_unrecoverable_mft_record() {
+        let mut data = volume();
+        data[4 * 4096 + 510] ^= 0xFF;
+        data[128 * 4096 + 510] ^= 0xFF;
+        let mut fs = PatchedDevice::new(Cursor::new(data));
+        let boot = BootSector::read(&mut fs).unwrap();
+
+        assert!(recover_system_records(&mut fs, &boot, &mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_damaged_upcase() {
+        let mut data = test_image();
+        data[16384 + 10 * 1024..][..4].copy_from_slice(b"BAAD");
+        let opened = open_volume(Cursor::new(data)).unwrap();
+        assert_eq!(opened.warnings.len(), 1);
+        assert!(opened.warnings[0].contains("$UpCase"));
+
+        let (mut fs, ntfs) = (opened.fs, opened.ntfs);
+        let volume = NtfsVolume::new(&ntfs, &mut fs).unwrap();
+        assert!(volume.resolve_path(&mut fs, "/file-WITH-12345").is_ok());
+    }
+}
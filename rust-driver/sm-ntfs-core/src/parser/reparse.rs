@@ -0,0 +1,371 @@
+//! Reparse points ($REPARSE_POINT)
+//!
+//! Symbolic links, junctions and the special files created by WSL are all
+//! stored as a reparse tag plus tag-specific data. Tags this module does not
+//! understand (cloud files, deduplication, WOF, ...) are kept as raw data so
+//! callers can report them instead of showing an empty file.
+
+use std::io::{Read, Seek};
+use ntfs::{Ntfs, NtfsAttributeType, NtfsFile};
+use crate::parser::attribute::AttributeStream;
+use crate::parser::reader::StreamReader;
+use crate::utils::bytes::{le_u16, le_u32, utf16_string};
+use crate::utils::error::{Result, SMNtfsError};
+
+/// Reparse tag (IO_REPARSE_TAG_*)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct ReparseTag(pub u32);
+
+impl ReparseTag {
+    /// Directory junction or volume mount point
+    pub const MOUNT_POINT: u32 = 0xA000_0003;
+    /// Hierarchical storage management
+    pub const HSM: u32 = 0xC000_0004;
+    /// Single-instance storage
+    pub const SIS: u32 = 0x8000_0007;
+    /// Distributed file system
+    pub const DFS: u32 = 0x8000_000A;
+    /// Symbolic link
+    pub const SYMLINK: u32 = 0xA000_000C;
+    /// Deduplicated file
+    pub const DEDUP: u32 = 0x8000_0013;
+    /// Compressed by the Windows Overlay Filter
+    pub const WOF: u32 = 0x8000_0017;
+    /// Cloud files placeholder (OneDrive); bits 12-15 vary
+    pub const CLOUD: u32 = 0x9000_001A;
+    /// App execution alias
+    pub const APPEXECLINK: u32 = 0x8000_001B;
+    /// WSL symbolic link
+    pub const LX_SYMLINK: u32 = 0xA000_001D;
+    /// WSL Unix domain socket
+    pub const AF_UNIX: u32 = 0x8000_0023;
+    /// WSL named pipe
+    pub const LX_FIFO: u32 = 0x8000_0024;
+    /// WSL character device
+    pub const LX_CHR: u32 = 0x8000_0025;
+    /// WSL block device
+    pub const LX_BLK: u32 = 0x8000_0026;
+
+    /// Check if the tag is owned by Microsoft
+    pub fn is_microsoft(&self) -> bool {
+        self.0 & 0x8000_0000 != 0
+    }
+
+    /// Check if the reparse point refers to another file (a link)
+    pub fn is_name_surrogate(&self) -> bool {
+        self.0 & 0x2000_0000 != 0
+    }
+
+    /// Check if this is a cloud files placeholder
+    pub fn is_cloud(&self) -> bool {
+        self.0 & 0xFFFF_0FFF == Self::CLOUD
+    }
+
+    /// Short name of the tag
+    pub fn name(&self) -> &'static str {
+        if self.is_cloud() {
+            return "cloud";
+        }
+        match self.0 {
+            Self::MOUNT_POINT => "mount point",
+            Self::HSM => "HSM",
+            Self::SIS => "SIS",
+            Self::DFS => "DFS",
+            Self::SYMLINK => "symlink",
+            Self::DEDUP => "dedup",
+            Self::WOF => "WOF",
+            Self::APPEXECLINK => "app execution alias",
+            Self::LX_SYMLINK => "WSL symlink",
+            Self::AF_UNIX => "WSL socket",
+            Self::LX_FIFO => "WSL FIFO",
+            Self::LX_CHR => "WSL character device",
+            Self::LX_BLK => "WSL block device",
+            _ => "unknown",
+        }
+    }
+}
+
+/// Symbolic link flag: the substitute name is relative to the link
+const SYMLINK_FLAG_RELATIVE: u32 = 0x1;
+
+/// A decoded reparse point
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReparsePoint {
+    /// Windows symbolic link
+    Symlink {
+        /// Target as used by the system (e.g. `\??\C:\Users`)
+        substitute_name: String,
+        /// Target as shown to users (e.g. `C:\Users`)
+        print_name: String,
+        /// The target is relative to the directory containing the link
+        relative: bool,
+    },
+
+    /// Directory junction or volume mount point
+    MountPoint {
+        /// Target as used by the system
+        substitute_name: String,
+        /// Target as shown to users
+        print_name: String,
+    },
+
+    /// Symbolic link created by WSL, with a POSIX target
+    LxSymlink {
+        /// Target path
+        target: String,
+    },
+
+    /// Unix domain socket created by WSL
+    UnixSocket,
+
+    /// Named pipe created by WSL
+    Fifo,
+
+    /// Character device created by WSL (device numbers are in the $LXDEV EA)
+    CharDevice,
+
+    /// Block device created by WSL (device numbers are in the $LXDEV EA)
+    BlockDevice,
+
+    /// Any other tag, such as cloud files, dedup or WOF
+    Other {
+        /// Reparse tag
+        tag: ReparseTag,
+        /// Tag-specific data
+        data: Vec<u8>,
+    },
+}
+
+impl ReparsePoint {
+    /// Decode a $REPARSE_POINT value
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let length = le_u16(data, 0x04) as usize;
+        if data.len() < 8 || data.len() < 8 + length {
+            return Err(SMNtfsError::ReadError(format!(
+                "Truncated reparse point of {} bytes",
+                data.len()
+            )));
+        }
+
+        let tag = ReparseTag(le_u32(data, 0x00));
+        let buffer = &data[8..8 + length];
+        let names = |path_buffer: usize| -> Result<(String, String)> {
+            let name = |offset: usize| -> Result<String> {
+                let start = path_buffer + le_u16(buffer, offset) as usize;
+                let bytes = le_u16(buffer, offset + 2) as usize;
+                if start + bytes > buffer.len() {
+                    return Err(SMNtfsError::ReadError(format!(
+                        "Reparse point name exceeds its {} byte buffer",
+                        buffer.len()
+                    )));
+                }
+                Ok(utf16_string(buffer, start, bytes / 2))
+            };
+            Ok((name(0x00)?, name(0x04)?))
+        };
+
+        Ok(match tag.0 {
+            ReparseTag::SYMLINK => {
+                let (substitute_name, print_name) = names(0x0C)?;
+                Self::Symlink {
+                    substitute_name,
+                    print_name,
+                    relative: le_u32(buffer, 0x08) & SYMLINK_FLAG_RELATIVE != 0,
+                }
+            }
+            ReparseTag::MOUNT_POINT => {
+                let (substitute_name, print_name) = names(0x08)?;
+                Self::MountPoint {
+                    substitute_name,
+                    print_name,
+                }
+            }
+            // A version number, then the UTF-8 target without a terminator
+            ReparseTag::LX_SYMLINK if buffer.len() >= 4 => Self::LxSymlink {
+                target: String::from_utf8_lossy(&buffer[4..]).into_owned(),
+            },
+            ReparseTag::AF_UNIX => Self::UnixSocket,
+            ReparseTag::LX_FIFO => Self::Fifo,
+            ReparseTag::LX_CHR => Self::CharDevice,
+            ReparseTag::LX_BLK => Self::BlockDevice,
+            _ => Self::Other {
+                tag,
+                data: buffer.to_vec(),
+            },
+        })
+    }
+
+    /// Reparse tag of this point
+    pub fn tag(&self) -> ReparseTag {
+        ReparseTag(match self {
+            Self::Symlink { .. } => ReparseTag::SYMLINK,
+            Self::MountPoint { .. } => ReparseTag::MOUNT_POINT,
+            Self::LxSymlink { .. } => ReparseTag::LX_SYMLINK,
+            Self::UnixSocket => ReparseTag::AF_UNIX,
+            Self::Fifo => ReparseTag::LX_FIFO,
+            Self::CharDevice => ReparseTag::LX_CHR,
+            Self::BlockDevice => ReparseTag::LX_BLK,
+            Self::Other { tag, .. } => return *tag,
+        })
+    }
+
+    /// Check if this should be presented as a symbolic link
+    pub fn is_link(&self) -> bool {
+        matches!(self, Self::Symlink { .. } | Self::MountPoint { .. } | Self::LxSymlink { .. })
+    }
+
+    /// Link target as a POSIX path usable on the host
+    ///
+    /// `link_path` is the path of the link on the volume, such as
+    /// `/Users/alice/link`. Absolute Windows targets are assumed to be on
+    /// this volume and are made relative to the link's directory, so they
+    /// resolve wherever the volume is mounted. Returns `None` for reparse
+    /// points that are not links and for targets on other machines.
+    pub fn host_target(&self, link_path: &str) -> Option<String> {
+        match self {
+            Self::LxSymlink { target } => Some(target.clone()),
+            Self::Symlink {
+                substitute_name,
+                relative: true,
+                ..
+            } if !substitute_name.starts_with('\\') => Some(substitute_name.replace('\\', "/")),
+            Self::Symlink { substitute_name, .. } | Self::MountPoint { substitute_name, .. } => {
+                let target = volume_relative(substitute_name)?;
+                Some(relative_to_link(link_path, &target))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Strip the NT namespace and drive or volume prefix from an absolute target
+///
+/// Returns the path components on the volume, or `None` for UNC targets.
+fn volume_relative(target: &str) -> Option<Vec<&str>> {
+    let path = target
+        .strip_prefix("\\??\\")
+        .or_else(|| target.strip_prefix("\\\\?\\"))
+        .unwrap_or(target);
+
+    let path = if path.len() >= 2 && path.as_bytes()[1] == b':' {
+        &path[2..]
+    } else if path.starts_with("Volume{") {
+        &path[path.find('\\').unwrap_or(path.len())..]
+    } else if path.starts_with('\\') {
+        path
+    } else {
+        // UNC\server\share or another device
+        return None;
+    };
+
+    Some(path.split('\\').filter(|c| !c.is_empty()).collect())
+}
+
+/// Express `target` relative to the directory containing `link_path`
+fn relative_to_link(link_path: &str, target: &[&str]) -> String {
+    let mut directory: Vec<&str> = link_path.split('/').filter(|c| !c.is_empty()).collect();
+    directory.pop();
+
+    let common = directory
+        .iter()
+        .zip(target)
+        .take_while(|(a, b)| a.eq_ignore_ascii_case(b))
+        .count();
+
+    let mut parts = vec![".."; directory.len() - common];
+    parts.extend_from_slice(&target[common..]);
+    match parts.is_empty() {
+        true => ".".to_string(),
+        false => parts.join("/"),
+    }
+}
+
+/// Read and decode the reparse point of a file, if it has one
+pub fn read_reparse_point<T: Read + Seek>(ntfs: &Ntfs, file: &NtfsFile, fs: &mut T) -> Result<Option<ReparsePoint>> {
+    let Some(stream) = AttributeStream::collect(file, fs, NtfsAttributeType::ReparsePoint, "")? else {
+        return Ok(None);
+    };
+    let data = StreamReader::new(fs, stream, ntfs.cluster_size()).read_all()?;
+    ReparsePoint::parse(&data).map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Build a symlink (with flags) or mount point (without) reparse value
+    fn link_value(tag: u32, substitute: &str, print: &str, flags: Option<u32>) -> Vec<u8> {
+        let encode = |s: &str| -> Vec<u8> { s.encode_utf16().flat_map(u16::to_le_bytes).collect() };
+        let (substitute, print) = (encode(substitute), encode(print));
+
+        let mut buffer = Vec::new();
+        for value in [0, substitute.len(), substitute.len(), print.len()] {
+            buffer.extend_from_slice(&(value as u16).to_le_bytes());
+        }
+        if let Some(flags) = flags {
+            buffer.extend_from_slice(&flags.to_le_bytes());
+        }
+        buffer.extend_from_slice(&substitute);
+        buffer.extend_from_slice(&print);
+
+        let mut data = tag.to_le_bytes().to_vec();
+        data.extend_from_slice(&(buffer.len() as u16).to_le_bytes());
+        data.extend_from_slice(&[0, 0]);
+        data.extend_from_slice(&buffer);
+        data
+    }
+
+    #[test]
+    fn test_symlinks() {
+        let data = link_value(ReparseTag::SYMLINK, "\\??\\C:\\Windows\\System32", "C:\\Windows\\System32", Some(0));
+        let point = ReparsePoint::parse(&data).unwrap();
+        assert!(matches!(&point, ReparsePoint::Symlink { print_name, relative: false, .. } if print_name == "C:\\Windows\\System32"));
+        assert_eq!(point.host_target("/Users/alice/link").as_deref(), Some("../../Windows/System32"));
+        assert_eq!(point.host_target("/windows/link").as_deref(), Some("System32"));
+
+        let data = link_value(ReparseTag::SYMLINK, "..\\docs\\a.txt", "..\\docs\\a.txt", Some(SYMLINK_FLAG_RELATIVE));
+        let point = ReparsePoint::parse(&data).unwrap();
+        assert_eq!(point.host_target("/Users/alice/link").as_deref(), Some("../docs/a.txt"));
+
+        let data = link_value(ReparseTag::SYMLINK, "\\??\\UNC\\server\\share", "\\\\server\\share", Some(0));
+        assert_eq!(ReparsePoint::parse(&data).unwrap().host_target("/link"), None);
+        assert!(ReparsePoint::parse(&data[..data.len() - 2]).is_err());
+    }
+
+    #[test]
+    fn test_junction() {
+        let data = link_value(ReparseTag::MOUNT_POINT, "\\??\\C:\\Users\\Public", "C:\\Users\\Public", None);
+        let point = ReparsePoint::parse(&data).unwrap();
+
+        assert_eq!(point.tag().name(), "mount point");
+        assert!(point.is_link());
+        assert_eq!(point.host_target("/Users/All Users").as_deref(), Some("Public"));
+        assert_eq!(point.host_target("/Users/Public/self").as_deref(), Some("."));
+    }
+
+    #[test]
+    fn test_wsl_files() {
+        let mut data = ReparseTag::LX_SYMLINK.to_le_bytes().to_vec();
+        data.extend_from_slice(&[13, 0, 0, 0, 2, 0, 0, 0]);
+        data.extend_from_slice(b"../bin/sh");
+        let point = ReparsePoint::parse(&data).unwrap();
+        assert_eq!(point, ReparsePoint::LxSymlink { target: "../bin/sh".to_string() });
+        assert_eq!(point.host_target("/usr/bin/sh").as_deref(), Some("../bin/sh"));
+
+        let fifo = [ReparseTag::LX_FIFO.to_le_bytes().as_slice(), &[0; 4]].concat();
+        assert_eq!(ReparsePoint::parse(&fifo).unwrap(), ReparsePoint::Fifo);
+        assert_eq!(ReparsePoint::Fifo.host_target("/pipe"), None);
+    }
+
+    #[test]
+    fn test_unknown_tags() {
+        let data = [0x9000_101Au32.to_le_bytes().as_slice(), &[2, 0, 0, 0, 0xAB, 0xCD]].concat();
+        let point = ReparsePoint::parse(&data).unwrap();
+
+        assert!(point.tag().is_cloud());
+        assert_eq!(point.tag().name(), "cloud");
+        assert!(!point.is_link());
+        assert!(matches!(point, ReparsePoint::Other { data, .. } if data == [0xAB, 0xCD]));
+        assert_eq!(ReparseTag(ReparseTag::WOF).name(), "WOF");
+    }
+}
@@ -0,0 +1,202 @@
+//! Data run (mapping pairs) decoding
+
+use crate::utils::error::{Result, SMNtfsError};
+
+/// A contiguous range of clusters of a non-resident attribute
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DataRun {
+    /// First virtual cluster number within the stream
+    pub vcn: u64,
+
+    /// First logical cluster number on the volume (`None` for a sparse run)
+    pub lcn: Option<u64>,
+
+    /// Length in clusters
+    pub length: u64,
+}
+
+impl DataRun {
+    /// Check if this run is a hole with no clusters allocated
+    pub fn is_sparse(&self) -> bool {
+        self.lcn.is_none()
+    }
+
+    /// Virtual cluster number just past the end of this run
+    ///
+    /// Decoded runs never overflow; others saturate at `u64::MAX`.
+    pub fn end_vcn(&self) -> u64 {
+        self.vcn.saturating_add(self.length)
+    }
+
+    /// Check if the run covers `vcn`
+    pub fn contains_vcn(&self, vcn: u64) -> bool {
+        vcn >= self.vcn && vcn < self.end_vcn()
+    }
+}
+
+/// Decode the mapping pairs of a non-resident attribute
+///
+/// `start_vcn` is the attribute's lowest VCN, which is non-zero for the
+/// later fragments of an attribute split across an $ATTRIBUTE_LIST.
+pub fn decode_data_runs(data: &[u8], start_vcn: u64) -> Result<Vec<DataRun>> {
+    let mut runs = Vec::new();
+    let mut offset = 0;
+    let mut vcn = start_vcn;
+    let mut lcn: i64 = 0;
+
+    while offset < data.len() {
+        let header = data[offset];
+        if header == 0 {
+            break;
+        }
+
+        let length_size = (header & 0x0F) as usize;
+        let offset_size = (header >> 4) as usize;
+        let end = offset + 1 + length_size + offset_size;
+
+        if length_size == 0 || length_size > 8 || offset_size > 8 || end > data.len() {
+            return Err(SMNtfsError::ReadError(format!(
+                "Invalid data run header 0x{:02X} at offset {}",
+                header, offset
+            )));
+        }
+
+        let length = read_unsigned(&data[offset + 1..offset + 1 + length_size]);
+        if length == 0 {
+            return Err(SMNtfsError::ReadError(format!("Zero-length data run at offset {}", offset)));
+        }
+        let end_vcn = vcn.checked_add(length).ok_or_else(|| {
+            SMNtfsError::ReadError(format!("Data run at offset {} ends past the last VCN", offset))
+        })?;
+
+        // A missing LCN delta marks a sparse run
+        let run_lcn = if offset_size == 0 {
+            None
+        } else {
+            let delta = read_signed(&data[offset + 1 + length_size..end]);
+            lcn = lcn.checked_add(delta).filter(|lcn| *lcn >= 0).ok_or_else(|| {
+                SMNtfsError::ReadError(format!("Data run at offset {} points before the volume", offset))
+            })?;
+            if (lcn as u64).checked_add(length).is_none() {
+                return Err(SMNtfsError::ReadError(format!(
+                    "Data run at offset {} ends past the last LCN",
+                    offset
+                )));
+            }
+            Some(lcn as u64)
+        };
+
+        runs.push(DataRun {
+            vcn,
+            lcn: run_lcn,
+            length,
+        });
+
+        vcn = end_vcn;
+        offset = end;
+    }
+
+    Ok(runs)
+}
+
+/// Merge adjacent runs that continue each other on disk (or are both sparse)
+pub fn coalesce_runs(runs: &[DataRun]) -> Vec<DataRun> {
+    let mut merged: Vec<DataRun> = Vec::with_capacity(runs.len());
+
+    for run in runs {
+        if let Some(last) = merged.last_mut() {
+            let continues = match (last.lcn, run.lcn) {
+                (Some(last_lcn), Some(lcn)) => last_lcn + last.length == lcn,
+                (None, None) => true,
+                _ => false,
+            };
+            if continues && last.end_vcn() == run.vcn {
+                last.length += run.length;
+                continue;
+            }
+        }
+        merged.push(*run);
+    }
+
+    merged
+}
+
+fn read_unsigned(bytes: &[u8]) -> u64 {
+    bytes.iter().rev().fold(0u64, |value, byte| (value << 8) | *byte as u64)
+}
+
+fn read_signed(bytes: &[u8]) -> i64 {
+    let value = read_unsigned(bytes) as i64;
+    let unused_bits = 64 - bytes.len() as u32 * 8;
+    if unused_bits == 0 {
+        value
+    } else {
+        (value << unused_bits) >> unused_bits
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_runs() {
+        // 0x18 clusters at LCN 0x5634, then 0x10 clusters 0x20 clusters back
+        let data = [0x21, 0x18, 0x34, 0x56, 0x11, 0x10, 0xE0, 0x00];
+        let runs = decode_data_runs(&data, 0).unwrap();
+
+        assert_eq!(runs.len(), 2);
+        assert_eq!(runs[0], DataRun { vcn: 0, lcn: Some(0x5634), length: 0x18 });
+        assert_eq!(runs[1], DataRun { vcn: 0x18, lcn: Some(0x5614), length: 0x10 });
+    }
+
+    #[test]
+    fn test_decode_sparse_run() {
+        let data = [0x11, 0x04, 0x20, 0x01, 0x08, 0x11, 0x04, 0x10, 0x00];
+        let runs = decode_data_runs(&data, 100).unwrap();
+
+        assert_eq!(runs[0].vcn, 100);
+        assert!(runs[1].is_sparse());
+        assert_eq!(runs[1].length, 8);
+        // LCN deltas skip over the sparse run
+        assert_eq!(runs[2].lcn, Some(0x30));
+        assert_eq!(runs[2].vcn, 112);
+    }
+
+    #[test]
+    fn test_decode_invalid_run() {
+        assert!(decode_data_runs(&[0x91, 0x01], 0).is_err());
+        assert!(decode_data_runs(&[0x11, 0x00, 0x01], 0).is_err());
+    }
+
+    #[test]
+    fn test_decode_overflowing_runs() {
+        // Two runs of 2^63 clusters: the second ends past the last VCN
+        let mut data = vec![0x08, 0, 0, 0, 0, 0, 0, 0, 0x80];
+        data.extend_from_within(..);
+        assert!(decode_data_runs(&data[..9], 0).is_ok());
+        assert!(decode_data_runs(&data, 0).is_err());
+        assert!(decode_data_runs(&[0x01, 0x02], u64::MAX - 1).is_err());
+
+        // LCN 2^62 + 2^62 clusters fits, the same run at LCN 2^63 - 1 does not
+        let mut data = vec![0x88, 0, 0, 0, 0, 0, 0, 0, 0x40, 0, 0, 0, 0, 0, 0, 0, 0x40];
+        assert!(decode_data_runs(&data, 0).is_ok());
+        data[1..9].copy_from_slice(&(u64::MAX / 4 * 3).to_le_bytes());
+        data[9..17].copy_from_slice(&(i64::MAX as u64).to_le_bytes());
+        assert!(decode_data_runs(&data, 0).is_err());
+    }
+
+    #[test]
+    fn test_coalesce_runs() {
+        let runs = [
+            DataRun { vcn: 0, lcn: Some(10), length: 2 },
+            DataRun { vcn: 2, lcn: Some(12), length: 3 },
+            DataRun { vcn: 5, lcn: None, length: 1 },
+            DataRun { vcn: 6, lcn: None, length: 1 },
+        ];
+        let merged = coalesce_runs(&runs);
+        assert_eq!(merged.len(), 2);
+        assert_eq!(merged[0].length, 5);
+        assert_eq!(merged[1].length, 2);
+    }
+}
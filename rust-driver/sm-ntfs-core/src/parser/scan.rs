@@ -0,0 +1,221 @@
+//! Raw enumeration of every MFT record
+//!
+//! Walking the directory tree only reaches files that still have a name in
+//! some index. Scanning the $MFT data stream directly also finds deleted
+//! records, orphans and extension records, and reads the MFT sequentially in
+//! large chunks, which is much faster on spinning disks and USB sticks.
+
+use std::io::{Read, Seek, SeekFrom};
+use ntfs::{Ntfs, NtfsAttributeType};
+use crate::parser::attribute::AttributeStream;
+use crate::parser::reader::StreamReader;
+use crate::parser::record::FileRecord;
+use crate::utils::error::{Result, SMNtfsError};
+
+/// Number of records read from the device at once
+const RECORDS_PER_CHUNK: u64 = 64;
+
+/// Iterator over the records of the MFT, in record number order
+///
+/// Yields every record that carries a `FILE` signature, whether in use or
+/// not, including records whose fixups fail (see [`FileRecord::is_valid`]).
+/// Never-used slots and records that cannot be read are skipped.
+pub struct MftRecords<'a, T: Read + Seek> {
+    reader: StreamReader<'a, T>,
+    cluster_size: u32,
+    record_size: usize,
+    record_count: u64,
+    next_record: u64,
+    chunk: Vec<u8>,
+    chunk_start: u64,
+    chunk_records: u64,
+    skipped: Vec<u64>,
+}
+
+impl<'a, T: Read + Seek> MftRecords<'a, T> {
+    /// Start scanning the MFT of a volume
+    pub fn new(ntfs: &Ntfs, fs: &'a mut T) -> Result<Self> {
+        let mft = ntfs
+            .file(fs, 0)
+            .map_err(|e| SMNtfsError::ReadError(format!("Failed to read $MFT: {}", e)))?;
+        let stream = AttributeStream::collect(&mft, fs, NtfsAttributeType::Data, "")?
+            .ok_or(SMNtfsError::CorruptedMft { offset: 0 })?;
+
+        Ok(Self::from_stream(fs, stream, ntfs.cluster_size(), ntfs.file_record_size() as usize))
+    }
+
+    /// Scan an already collected $MFT data stream
+    pub fn from_stream(fs: &'a mut T, stream: AttributeStream, cluster_size: u32, record_size: usize) -> Self {
+        let record_count = stream.data_size / record_size as u64;
+
+        Self {
+            reader: StreamReader::new(fs, stream, cluster_size),
+            cluster_size,
+            record_size,
+            record_count,
+            next_record: 0,
+            chunk: Vec::new(),
+            chunk_start: 0,
+            chunk_records: 0,
+            skipped: Vec::new(),
+        }
+    }
+
+    /// Number of record slots in the MFT
+    pub fn record_count(&self) -> u64 {
+        self.record_count
+    }
+
+    /// Number of slots skipped so far because they could not be read
+    pub fn skipped(&self) -> u64 {
+        self.skipped.len() as u64
+    }
+
+    /// Numbers of the records skipped so far
+    pub fn skipped_records(&self) -> &[u64] {
+        &self.skipped
+    }
+
+    /// Continue the scan at `record_number`
+    pub fn seek_to(&mut self, record_number: u64) {
+        self.next_record = record_number;
+    }
+
+    /// Load the chunk containing `next_record`
+    ///
+    /// If the chunk cannot be read as a whole, the readable records are
+    /// read one by one and the rest are left zeroed, so they get skipped.
+    fn fill_chunk(&mut self) {
+        self.chunk_start = self.next_record;
+        self.chunk_records = RECORDS_PER_CHUNK.min(self.record_count - self.next_record);
+        self.chunk.clear();
+        self.chunk.resize(self.chunk_records as usize * self.record_size, 0);
+
+        let offset = self.chunk_start * self.record_size as u64;
+        let whole = self
+            .reader
+            .seek(SeekFrom::Start(offset))
+            .and_then(|_| self.reader.read_exact(&mut self.chunk));
+        if whole.is_ok() {
+            return;
+        }
+
+        for (i, record) in self.chunk.chunks_mut(self.record_size).enumerate() {
+            let position = offset + (i * self.record_size) as u64;
+            let read = self
+                .reader
+                .seek(SeekFrom::Start(position))
+                .and_then(|_| self.reader.read_exact(record));
+
+            if let Err(e) = read {
+                tracing::warn!("Skipping unreadable MFT record {}: {}", self.chunk_start + i as u64, e);
+                record.fill(0);
+                self.skipped.push(self.chunk_start + i as u64);
+            }
+        }
+    }
+}
+
+impl<T: Read + Seek> Iterator for MftRecords<'_, T> {
+    type Item = FileRecord;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.next_record < self.record_count {
+            if self.next_record < self.chunk_start || self.next_record >= self.chunk_start + self.chunk_records {
+                self.fill_chunk();
+            }
+
+            let record_number = self.next_record;
+            self.next_record += 1;
+
+            let index = (record_number - self.chunk_start) as usize;
+            let data = &self.chunk[index * self.record_size..(index + 1) * self.record_size];
+            let offset = record_number * self.record_size as u64;
+            let position = self.reader.stream().device_offset(offset, self.cluster_size).unwrap_or(0);
+
+            let record = FileRecord::from_bytes(data.to_vec(), record_number, position);
+            if record.has_valid_signature() {
+                return Some(record);
+            }
+
+            // Zeroed slots were never used; anything else is damaged
+            if data.iter().any(|byte| *byte != 0) {
+                self.skipped.push(record_number);
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::record::record_flags;
+    use crate::parser::record::tests::build_record;
+    use crate::parser::runs::DataRun;
+    use std::io::Cursor;
+
+    /// An MFT of five 1024-byte records at the start of the device
+    fn mft_device() -> (Cursor<Vec<u8>>, AttributeStream) {
+        let mut device = Vec::new();
+        device.extend(build_record(&[], record_flags::IN_USE));
+        device.extend(build_record(&[], record_flags::IN_USE | record_flags::DIRECTORY));
+        device.extend(vec![0u8; 1024]);
+        device.extend(build_record(&[], 0));
+
+        let mut extension = build_record(&[], record_flags::IN_USE);
+        extension[0x20..0x28].copy_from_slice(&(1u64 | (1 << 48)).to_le_bytes());
+        extension[1022] ^= 0xFF; // torn write
+        device.extend(extension);
+
+        let stream = AttributeStream {
+            runs: vec![DataRun { vcn: 0, lcn: Some(0), length: 10 }],
+            data_size: 5 * 1024,
+            initialized_size: 5 * 1024,
+            ..AttributeStream::default()
+        };
+        (Cursor::new(device), stream)
+    }
+
+    #[test]
+    fn test_scan_records() {
+        let (mut device, stream) = mft_device();
+        let scan = MftRecords::from_stream(&mut device, stream, 512, 1024);
+        assert_eq!(scan.record_count(), 5);
+
+        let records: Vec<FileRecord> = scan.collect();
+        let numbers: Vec<u64> = records.iter().map(|r| r.record_number()).collect();
+        assert_eq!(numbers, vec![0, 1, 3, 4]);
+
+        assert!(records[1].is_directory());
+        assert!(!records[2].is_in_use());
+        assert_eq!(records[2].position(), 3 * 1024);
+        assert_eq!(records[3].base_record_number(), Some(1));
+        assert!(!records[3].is_valid());
+    }
+
+    #[test]
+    fn test_unreadable_records_are_skipped() {
+        let (mut device, mut stream) = mft_device();
+        // Records 2 and 3 are not mapped by any run
+        stream.runs = vec![
+            DataRun { vcn: 0, lcn: Some(0), length: 4 },
+            DataRun { vcn: 8, lcn: Some(8), length: 2 },
+        ];
+
+        let mut scan = MftRecords::from_stream(&mut device, stream, 512, 1024);
+        let numbers: Vec<u64> = scan.by_ref().map(|r| r.record_number()).collect();
+        assert_eq!(numbers, vec![0, 1, 4]);
+        assert_eq!(scan.skipped(), 2);
+        assert_eq!(scan.skipped_records(), &[2, 3]);
+    }
+
+    #[test]
+    fn test_seek_to() {
+        let (mut device, stream) = mft_device();
+        let mut scan = MftRecords::from_stream(&mut device, stream, 512, 1024);
+        scan.seek_to(3);
+        assert_eq!(scan.next().map(|r| r.record_number()), Some(3));
+    }
+}
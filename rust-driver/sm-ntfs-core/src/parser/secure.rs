@@ -0,0 +1,420 @@
+//! Shared security descriptors ($Secure)
+//!
+//! Since NTFS 3.0 files do not carry their own $SECURITY_DESCRIPTOR but a
+//! security ID in $STANDARD_INFORMATION. The descriptors live in the $SDS
+//! stream of $Secure, each behind a header with its hash, ID, offset and
+//! length. Two view indexes point into it: $SII keyed by security ID and
+//! $SDH keyed by hash, used to share identical descriptors.
+//!
+//! $SDS is written in 256 KiB blocks, each followed by a mirror copy.
+
+use std::collections::HashMap;
+use std::io::{Read, Seek};
+use ntfs::{Ntfs, NtfsAttributeType, NtfsFile};
+use crate::parser::attribute::AttributeStream;
+use crate::parser::reader::StreamReader;
+use crate::parser::record::apply_fixups;
+use crate::parser::security::SecurityDescriptor;
+use crate::utils::bytes::{le_u16, le_u32, le_u64};
+use crate::utils::error::{Result, SMNtfsError};
+
+/// Record number of the $Secure system file
+pub const SECURE_RECORD_NUMBER: u64 = 9;
+
+/// Name of the stream holding the descriptors
+pub const SDS_STREAM: &str = "$SDS";
+
+/// Name of the index keyed by security ID
+pub const SII_INDEX: &str = "$SII";
+
+/// Name of the index keyed by descriptor hash
+pub const SDH_INDEX: &str = "$SDH";
+
+/// Size of each $SDS block; every block is followed by its mirror
+const SDS_BLOCK_SIZE: u64 = 0x40000;
+
+/// Size of the header before each descriptor in $SDS
+const SDS_HEADER_SIZE: usize = 0x14;
+
+/// Entry of $SDS, also the data of $SII and $SDH index entries
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SdsEntry {
+    /// Hash of the descriptor, see [`descriptor_hash`]
+    pub hash: u32,
+
+    /// Security ID files refer to
+    pub security_id: u32,
+
+    /// Offset of the header in $SDS
+    pub offset: u64,
+
+    /// Length of the header and descriptor
+    pub length: u32,
+}
+
+impl SdsEntry {
+    /// Decode a header at `offset`
+    pub fn parse(data: &[u8], offset: usize) -> Self {
+        Self {
+            hash: le_u32(data, offset),
+            security_id: le_u32(data, offset + 0x04),
+            offset: le_u64(data, offset + 0x08),
+            length: le_u32(data, offset + 0x10),
+        }
+    }
+}
+
+/// Hash of a self-relative descriptor, as stored in $SDS and $SDH
+pub fn descriptor_hash(descriptor: &[u8]) -> u32 {
+    descriptor
+        .chunks_exact(4)
+        .fold(0u32, |hash, word| le_u32(word, 0).wrapping_add(hash.rotate_left(3)))
+}
+
+/// The descriptors of $Secure, indexed by security ID and by hash
+#[derive(Debug, Clone, Default)]
+pub struct SecureFile {
+    sds: Vec<u8>,
+    by_id: HashMap<u32, SdsEntry>,
+    by_hash: HashMap<u32, Vec<u32>>,
+}
+
+impl SecureFile {
+    /// Read $Secure and its indexes
+    ///
+    /// Falls back to scanning $SDS if $SII is missing or unreadable.
+    pub fn load<T: Read + Seek>(ntfs: &Ntfs, fs: &mut T) -> Result<Self> {
+        let file = ntfs
+            .file(fs, SECURE_RECORD_NUMBER)
+            .map_err(|e| SMNtfsError::ReadError(format!("Failed to read $Secure: {}", e)))?;
+        let stream = AttributeStream::collect(&file, fs, NtfsAttributeType::Data, SDS_STREAM)?
+            .ok_or(SMNtfsError::CorruptedMft {
+                offset: SECURE_RECORD_NUMBER,
+            })?;
+        let sds = StreamReader::new(fs, stream, ntfs.cluster_size()).read_all()?;
+
+        let mut index = |name: &str| -> Result<Vec<SdsEntry>> {
+            let root = AttributeStream::collect(&file, fs, NtfsAttributeType::IndexRoot, name)?
+                .and_then(|root| root.resident_data)
+                .ok_or(SMNtfsError::CorruptedMft {
+                    offset: SECURE_RECORD_NUMBER,
+                })?;
+            let allocation = match AttributeStream::collect(&file, fs, NtfsAttributeType::IndexAllocation, name)? {
+                Some(stream) => StreamReader::new(fs, stream, ntfs.cluster_size()).read_all()?,
+                None => Vec::new(),
+            };
+            index_entries(&root, allocation)
+        };
+
+        let sii = index(SII_INDEX).unwrap_or_else(|e| {
+            tracing::warn!("Ignoring unreadable $Secure:{}: {}", SII_INDEX, e);
+            Vec::new()
+        });
+        if sii.is_empty() {
+            return Ok(Self::from_sds(sds));
+        }
+        let sdh = index(SDH_INDEX).unwrap_or_else(|e| {
+            tracing::warn!("Ignoring unreadable $Secure:{}: {}", SDH_INDEX, e);
+            Vec::new()
+        });
+
+        Ok(Self::from_indexes(sds, &sii, &sdh))
+    }
+
+    /// Build from $SDS and the entries of $SII and $SDH
+    ///
+    /// Index entries that do not match a header in $SDS are dropped. If
+    /// $SDH is empty, the hash lookup is built from $SII instead.
+    pub fn from_indexes(sds: Vec<u8>, sii: &[SdsEntry], sdh: &[SdsEntry]) -> Self {
+        let mut secure = Self {
+            sds,
+            ..Self::default()
+        };
+
+        for entry in sii {
+            if secure.is_valid(entry) {
+                secure.by_id.insert(entry.security_id, *entry);
+            }
+        }
+        let hashed = if sdh.is_empty() { sii } else { sdh };
+        for entry in hashed {
+            if secure.by_id.get(&entry.security_id) == Some(entry) {
+                secure.by_hash.entry(entry.hash).or_default().push(entry.security_id);
+            }
+        }
+
+        secure
+    }
+
+    /// Build by scanning the primary blocks of $SDS
+    pub fn from_sds(sds: Vec<u8>) -> Self {
+        let mut entries = Vec::new();
+        let mut offset = 0u64;
+
+        while offset as usize + SDS_HEADER_SIZE <= sds.len() {
+            let entry = SdsEntry::parse(&sds, offset as usize);
+            let next = offset + (entry.length as u64).next_multiple_of(16);
+
+            // Unused space or a mirror block: go to the next primary block
+            if entry.length as usize <= SDS_HEADER_SIZE
+                || entry.offset != offset
+                || next % (2 * SDS_BLOCK_SIZE) > SDS_BLOCK_SIZE
+            {
+                offset = (offset / (2 * SDS_BLOCK_SIZE) + 1) * 2 * SDS_BLOCK_SIZE;
+                continue;
+            }
+
+            entries.push(entry);
+            offset = next;
+        }
+
+        Self::from_indexes(sds, &entries, &[])
+    }
+
+    /// Number of distinct descriptors
+    pub fn len(&self) -> usize {
+        self.by_id.len()
+    }
+
+    /// Check if no descriptor was found
+    pub fn is_empty(&self) -> bool {
+        self.by_id.is_empty()
+    }
+
+    /// Security IDs in ascending order
+    pub fn security_ids(&self) -> Vec<u32> {
+        let mut ids: Vec<u32> = self.by_id.keys().copied().collect();
+        ids.sort_unstable();
+        ids
+    }
+
+    /// Raw self-relative descriptor of a security ID
+    pub fn raw_descriptor(&self, security_id: u32) -> Option<&[u8]> {
+        let entry = self.by_id.get(&security_id)?;
+        let start = entry.offset as usize + SDS_HEADER_SIZE;
+        self.sds.get(start..entry.offset as usize + entry.length as usize)
+    }
+
+    /// Decode the descriptor of a security ID
+    pub fn descriptor(&self, security_id: u32) -> Result<SecurityDescriptor> {
+        let raw = self.raw_descriptor(security_id).ok_or_else(|| {
+            SMNtfsError::ReadError(format!("Security ID {} is not in $Secure", security_id))
+        })?;
+        SecurityDescriptor::parse(raw)
+    }
+
+    /// Find the security ID already assigned to an identical descriptor
+    pub fn find_security_id(&self, descriptor: &[u8]) -> Option<u32> {
+        self.by_hash
+            .get(&descriptor_hash(descriptor))?
+            .iter()
+            .copied()
+            .find(|id| self.raw_descriptor(*id) == Some(descriptor))
+    }
+
+    /// Check that an index entry points at a matching header in $SDS
+    fn is_valid(&self, entry: &SdsEntry) -> bool {
+        let end = entry.offset.saturating_add(entry.length as u64);
+        entry.length as usize > SDS_HEADER_SIZE
+            && end <= self.sds.len() as u64
+            && SdsEntry::parse(&self.sds, entry.offset as usize) == *entry
+    }
+}
+
+/// Resolve the security descriptor of a file
+///
+/// A $SECURITY_DESCRIPTOR attribute, as found on volumes older than NTFS
+/// 3.0, takes precedence over the security ID. Returns `None` if the file
+/// has neither.
+pub fn file_security_descriptor<T: Read + Seek>(
+    ntfs: &Ntfs,
+    file: &NtfsFile,
+    fs: &mut T,
+    secure: &SecureFile,
+) -> Result<Option<SecurityDescriptor>> {
+    if let Some(stream) = AttributeStream::collect(file, fs, NtfsAttributeType::SecurityDescriptor, "")? {
+        let raw = StreamReader::new(fs, stream, ntfs.cluster_size()).read_all()?;
+        return SecurityDescriptor::parse(&raw).map(Some);
+    }
+
+    let security_id = file
+        .info()
+        .map_err(|_| SMNtfsError::CorruptedMft {
+            offset: file.file_record_number(),
+        })?
+        .security_id()
+        .unwrap_or(0);
+    match security_id {
+        0 => Ok(None),
+        id => secure.descriptor(id).map(Some),
+    }
+}
+
+/// Collect the data of every entry of a view index whose data is an $SDS header
+///
+/// Walks the root and every INDX block rather than the B-tree, since only
+/// the full set of entries is needed.
+fn index_entries(root: &[u8], mut allocation: Vec<u8>) -> Result<Vec<SdsEntry>> {
+    let mut entries = Vec::new();
+    node_entries(root, 0x10, &mut entries)?;
+
+    let record_size = le_u32(root, 0x08) as usize;
+    if record_size == 0 {
+        return Ok(entries);
+    }
+    for block in allocation.chunks_exact_mut(record_size) {
+        // Blocks not in use are left zeroed or hold stale entries with a bad USA
+        if &block[0..4] != b"INDX" || !apply_fixups(block) {
+            continue;
+        }
+        node_entries(block, 0x18, &mut entries)?;
+    }
+
+    Ok(entries)
+}
+
+/// Collect the entries of one index node whose header is at `header`
+fn node_entries(data: &[u8], header: usize, entries: &mut Vec<SdsEntry>) -> Result<()> {
+    let mut position = header + le_u32(data, header) as usize;
+    let end = (header + le_u32(data, header + 0x04) as usize).min(data.len());
+
+    while position + 0x10 <= end {
+        let length = le_u16(data, position + 0x08) as usize;
+        let flags = le_u16(data, position + 0x0C);
+        // The last entry of a node has no key or data
+        if flags & 0x2 != 0 {
+            break;
+        }
+        if length < 0x10 || position + length > end {
+            return Err(SMNtfsError::ReadError(format!(
+                "Invalid index entry length {} at offset {}",
+                length, position
+            )));
+        }
+
+        let data_offset = position + le_u16(data, position) as usize;
+        if le_u16(data, position + 0x02) as usize >= SDS_HEADER_SIZE {
+            entries.push(SdsEntry::parse(data, data_offset));
+        }
+        position += length;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::security::ace_type;
+    use crate::parser::security::tests::{ace, security_descriptor};
+
+    /// Append a descriptor to $SDS, returning its header
+    fn push_descriptor(sds: &mut Vec<u8>, security_id: u32, descriptor: &[u8]) -> SdsEntry {
+        let entry = SdsEntry {
+            hash: descriptor_hash(descriptor),
+            security_id,
+            offset: sds.len() as u64,
+            length: (SDS_HEADER_SIZE + descriptor.len()) as u32,
+        };
+        sds.extend_from_slice(&entry.hash.to_le_bytes());
+        sds.extend_from_slice(&entry.security_id.to_le_bytes());
+        sds.extend_from_slice(&entry.offset.to_le_bytes());
+        sds.extend_from_slice(&entry.length.to_le_bytes());
+        sds.extend_from_slice(descriptor);
+        sds.resize(sds.len().next_multiple_of(16), 0);
+        entry
+    }
+
+    /// Build an index root whose entries carry `entries` as data
+    fn index_root(entries: &[SdsEntry]) -> Vec<u8> {
+        let mut nodes = Vec::new();
+        for entry in entries {
+            let mut node = vec![0u8; 0x10];
+            node[0..2].copy_from_slice(&0x14u16.to_le_bytes());
+            node[2..4].copy_from_slice(&(SDS_HEADER_SIZE as u16).to_le_bytes());
+            node[8..10].copy_from_slice(&0x28u16.to_le_bytes());
+            node[0x0A..0x0C].copy_from_slice(&4u16.to_le_bytes());
+            node.extend_from_slice(&entry.security_id.to_le_bytes());
+            node.extend_from_slice(&entry.hash.to_le_bytes());
+            node.extend_from_slice(&entry.security_id.to_le_bytes());
+            node.extend_from_slice(&entry.offset.to_le_bytes());
+            node.extend_from_slice(&entry.length.to_le_bytes());
+            nodes.extend_from_slice(&node);
+        }
+        let mut last = vec![0u8; 0x10];
+        last[8..10].copy_from_slice(&0x10u16.to_le_bytes());
+        last[0x0C..0x0E].copy_from_slice(&2u16.to_le_bytes());
+        nodes.extend_from_slice(&last);
+
+        let mut root = vec![0u8; 0x20];
+        root[0x08..0x0C].copy_from_slice(&4096u32.to_le_bytes());
+        root[0x10..0x14].copy_from_slice(&0x10u32.to_le_bytes());
+        root[0x14..0x18].copy_from_slice(&(0x10 + nodes.len() as u32).to_le_bytes());
+        root.extend_from_slice(&nodes);
+        root
+    }
+
+    fn descriptors() -> (Vec<u8>, Vec<SdsEntry>, Vec<Vec<u8>>) {
+        let admins = security_descriptor(
+            "S-1-5-32-544",
+            "S-1-5-18",
+            &[ace(ace_type::ACCESS_ALLOWED, 0, 0x1F01FF, "S-1-5-32-544")],
+            0,
+        );
+        let users = security_descriptor(
+            "S-1-5-32-545",
+            "S-1-5-32-545",
+            &[ace(ace_type::ACCESS_ALLOWED, 0, 0x120089, "S-1-1-0")],
+            0,
+        );
+
+        let mut sds = Vec::new();
+        let entries = vec![
+            push_descriptor(&mut sds, 0x100, &admins),
+            push_descriptor(&mut sds, 0x101, &users),
+        ];
+        (sds, entries, vec![admins, users])
+    }
+
+    #[test]
+    fn test_descriptor_hash() {
+        assert_eq!(descriptor_hash(&[1, 0, 0, 0, 2, 0, 0, 0]), 10);
+    }
+
+    #[test]
+    fn test_from_indexes() {
+        let (sds, mut entries, raw) = descriptors();
+        // Entry whose header in $SDS does not match
+        entries.push(SdsEntry {
+            security_id: 0x102,
+            ..entries[0]
+        });
+        let sii = index_entries(&index_root(&entries), Vec::new()).unwrap();
+        assert_eq!(sii.len(), 3);
+
+        let secure = SecureFile::from_indexes(sds, &sii, &[]);
+        assert_eq!(secure.security_ids(), vec![0x100, 0x101]);
+        assert_eq!(secure.raw_descriptor(0x101), Some(raw[1].as_slice()));
+        assert_eq!(
+            secure.descriptor(0x100).unwrap().owner.unwrap().to_string(),
+            "S-1-5-32-544"
+        );
+        assert!(secure.descriptor(0x102).is_err());
+        assert_eq!(secure.find_security_id(&raw[0]), Some(0x100));
+        assert_eq!(secure.find_security_id(&raw[0][..raw[0].len() - 4]), None);
+    }
+
+    #[test]
+    fn test_scan_sds_skips_mirror() {
+        let (mut sds, _, raw) = descriptors();
+        // Mirror copy of the first block, then a descriptor in the next primary block
+        sds.resize(SDS_BLOCK_SIZE as usize, 0);
+        let primary = sds.clone();
+        sds.extend_from_slice(&primary);
+        push_descriptor(&mut sds, 0x102, &raw[0]);
+
+        let secure = SecureFile::from_sds(sds);
+        assert_eq!(secure.security_ids(), vec![0x100, 0x101, 0x102]);
+        assert_eq!(secure.raw_descriptor(0x102), Some(raw[0].as_slice()));
+    }
+}
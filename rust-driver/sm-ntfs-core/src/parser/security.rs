@@ -0,0 +1,653 @@
+//! Windows security descriptors
+//!
+//! Decodes self-relative security descriptors as stored in $Secure:$SDS or
+//! a $SECURITY_DESCRIPTOR attribute, and formats them as SDDL strings.
+
+use std::fmt;
+use std::str::FromStr;
+use crate::utils::bytes::{le_u16, le_u32, le_u8};
+use crate::utils::error::{Result, SMNtfsError};
+
+/// Security descriptor control flags (SE_*)
+pub mod control {
+    /// The descriptor has a DACL (which may be null)
+    pub const DACL_PRESENT: u16 = 0x0004;
+    /// The descriptor has a SACL
+    pub const SACL_PRESENT: u16 = 0x0010;
+    /// DACL was set up by automatic inheritance
+    pub const DACL_AUTO_INHERITED: u16 = 0x0400;
+    /// SACL was set up by automatic inheritance
+    pub const SACL_AUTO_INHERITED: u16 = 0x0800;
+    /// DACL does not inherit ACEs from the parent
+    pub const DACL_PROTECTED: u16 = 0x1000;
+    /// SACL does not inherit ACEs from the parent
+    pub const SACL_PROTECTED: u16 = 0x2000;
+    /// Components are stored at offsets after the header
+    pub const SELF_RELATIVE: u16 = 0x8000;
+}
+
+/// ACE types
+pub mod ace_type {
+    /// Grants access
+    pub const ACCESS_ALLOWED: u8 = 0x00;
+    /// Denies access
+    pub const ACCESS_DENIED: u8 = 0x01;
+    /// Audits access (SACL)
+    pub const SYSTEM_AUDIT: u8 = 0x02;
+    /// Raises an alarm on access (SACL)
+    pub const SYSTEM_ALARM: u8 = 0x03;
+    /// Grants access to an object type
+    pub const ACCESS_ALLOWED_OBJECT: u8 = 0x05;
+    /// Denies access to an object type
+    pub const ACCESS_DENIED_OBJECT: u8 = 0x06;
+    /// Audits access to an object type
+    pub const SYSTEM_AUDIT_OBJECT: u8 = 0x07;
+    /// Raises an alarm on access to an object type
+    pub const SYSTEM_ALARM_OBJECT: u8 = 0x08;
+    /// Grants access if a condition holds
+    pub const ACCESS_ALLOWED_CALLBACK: u8 = 0x09;
+    /// Denies access if a condition holds
+    pub const ACCESS_DENIED_CALLBACK: u8 = 0x0A;
+    /// Mandatory integrity label (SACL)
+    pub const SYSTEM_MANDATORY_LABEL: u8 = 0x11;
+}
+
+/// ACE inheritance flags
+pub mod ace_flags {
+    /// Inherited by files
+    pub const OBJECT_INHERIT: u8 = 0x01;
+    /// Inherited by directories
+    pub const CONTAINER_INHERIT: u8 = 0x02;
+    /// Inherited by direct children only
+    pub const NO_PROPAGATE_INHERIT: u8 = 0x04;
+    /// Does not apply to the object itself, only to children
+    pub const INHERIT_ONLY: u8 = 0x08;
+    /// Inherited from the parent
+    pub const INHERITED: u8 = 0x10;
+    /// Audit successful access
+    pub const SUCCESSFUL_ACCESS: u8 = 0x40;
+    /// Audit failed access
+    pub const FAILED_ACCESS: u8 = 0x80;
+}
+
+/// Access mask bits
+pub mod access {
+    /// Read data / list directory
+    pub const FILE_READ_DATA: u32 = 0x0000_0001;
+    /// Write data / add file
+    pub const FILE_WRITE_DATA: u32 = 0x0000_0002;
+    /// Append data / add subdirectory
+    pub const FILE_APPEND_DATA: u32 = 0x0000_0004;
+    /// Execute / traverse directory
+    pub const FILE_EXECUTE: u32 = 0x0000_0020;
+    /// Delete the object
+    pub const DELETE: u32 = 0x0001_0000;
+    /// Change the DACL
+    pub const WRITE_DAC: u32 = 0x0004_0000;
+    /// Change the owner
+    pub const WRITE_OWNER: u32 = 0x0008_0000;
+    /// Full control of a file
+    pub const FILE_ALL_ACCESS: u32 = 0x001F_01FF;
+    /// Generic read of a file
+    pub const FILE_GENERIC_READ: u32 = 0x0012_0089;
+    /// Generic write of a file
+    pub const FILE_GENERIC_WRITE: u32 = 0x0012_0116;
+    /// Generic execute of a file
+    pub const FILE_GENERIC_EXECUTE: u32 = 0x0012_00A0;
+    /// All access
+    pub const GENERIC_ALL: u32 = 0x1000_0000;
+    /// Execute access
+    pub const GENERIC_EXECUTE: u32 = 0x2000_0000;
+    /// Write access
+    pub const GENERIC_WRITE: u32 = 0x4000_0000;
+    /// Read access
+    pub const GENERIC_READ: u32 = 0x8000_0000;
+}
+
+/// Well-known SIDs with an SDDL alias
+const SID_ALIASES: &[(&str, &str)] = &[
+    ("WD", "S-1-1-0"),
+    ("CO", "S-1-3-0"),
+    ("CG", "S-1-3-1"),
+    ("OW", "S-1-3-4"),
+    ("NU", "S-1-5-2"),
+    ("IU", "S-1-5-4"),
+    ("SU", "S-1-5-6"),
+    ("AN", "S-1-5-7"),
+    ("ED", "S-1-5-9"),
+    ("PS", "S-1-5-10"),
+    ("AU", "S-1-5-11"),
+    ("RC", "S-1-5-12"),
+    ("SY", "S-1-5-18"),
+    ("LS", "S-1-5-19"),
+    ("NS", "S-1-5-20"),
+    ("BA", "S-1-5-32-544"),
+    ("BU", "S-1-5-32-545"),
+    ("BG", "S-1-5-32-546"),
+    ("PU", "S-1-5-32-547"),
+    ("AC", "S-1-15-2-1"),
+    ("LW", "S-1-16-4096"),
+    ("ME", "S-1-16-8192"),
+    ("HI", "S-1-16-12288"),
+    ("SI", "S-1-16-16384"),
+];
+
+/// A security identifier
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Sid {
+    /// SID revision (always 1)
+    pub revision: u8,
+
+    /// 48-bit identifier authority
+    pub authority: u64,
+
+    /// Sub-authorities; the last one is the relative ID
+    pub sub_authorities: Vec<u32>,
+}
+
+impl Sid {
+    /// Everyone (S-1-1-0)
+    pub fn everyone() -> Self {
+        Self {
+            revision: 1,
+            authority: 1,
+            sub_authorities: vec![0],
+        }
+    }
+
+    /// Decode a binary SID at `offset`, returning it and its length
+    pub fn parse(data: &[u8], offset: usize) -> Result<(Self, usize)> {
+        let count = le_u8(data, offset + 1) as usize;
+        let length = 8 + count * 4;
+        if offset + length > data.len() {
+            return Err(SMNtfsError::ReadError(format!("Truncated SID at offset {}", offset)));
+        }
+
+        let authority = data[offset + 2..offset + 8]
+            .iter()
+            .fold(0u64, |value, byte| (value << 8) | *byte as u64);
+        let sub_authorities = (0..count).map(|i| le_u32(data, offset + 8 + i * 4)).collect();
+
+        Ok((
+            Self {
+                revision: le_u8(data, offset),
+                authority,
+                sub_authorities,
+            },
+            length,
+        ))
+    }
+
+    /// Encode as a binary SID
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![self.revision, self.sub_authorities.len() as u8];
+        bytes.extend_from_slice(&self.authority.to_be_bytes()[2..]);
+        for sub_authority in &self.sub_authorities {
+            bytes.extend_from_slice(&sub_authority.to_le_bytes());
+        }
+        bytes
+    }
+
+    /// Relative ID (last sub-authority)
+    pub fn rid(&self) -> Option<u32> {
+        self.sub_authorities.last().copied()
+    }
+
+    /// SDDL alias (such as `BA`) or the full `S-1-...` form
+    pub fn to_sddl(&self) -> String {
+        let string = self.to_string();
+        SID_ALIASES
+            .iter()
+            .find(|(_, sid)| *sid == string)
+            .map_or(string, |(alias, _)| alias.to_string())
+    }
+}
+
+impl fmt::Display for Sid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "S-{}-", self.revision)?;
+        if self.authority >> 32 == 0 {
+            write!(f, "{}", self.authority)?;
+        } else {
+            write!(f, "{:#x}", self.authority)?;
+        }
+        for sub_authority in &self.sub_authorities {
+            write!(f, "-{}", sub_authority)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Sid {
+    type Err = SMNtfsError;
+
+    /// Parse the `S-1-...` form
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || SMNtfsError::InvalidUserMapping(format!("Invalid SID '{}'", s));
+        let mut parts = s.strip_prefix("S-").ok_or_else(invalid)?.split('-');
+
+        let revision = parts.next().and_then(|p| p.parse().ok()).ok_or_else(invalid)?;
+        let authority = parts
+            .next()
+            .and_then(|p| match p.strip_prefix("0x") {
+                Some(hex) => u64::from_str_radix(hex, 16).ok(),
+                None => p.parse().ok(),
+            })
+            .ok_or_else(invalid)?;
+        let sub_authorities = parts
+            .map(|p| p.parse().map_err(|_| invalid()))
+            .collect::<Result<Vec<u32>>>()?;
+
+        if authority >> 48 != 0 || sub_authorities.len() > 15 {
+            return Err(invalid());
+        }
+
+        Ok(Self {
+            revision,
+            authority,
+            sub_authorities,
+        })
+    }
+}
+
+/// An access control entry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ace {
+    /// ACE type, see [`ace_type`]
+    pub ace_type: u8,
+
+    /// Inheritance and audit flags, see [`ace_flags`]
+    pub flags: u8,
+
+    /// Access mask, see [`access`]
+    pub mask: u32,
+
+    /// Trustee the ACE applies to
+    pub sid: Sid,
+
+    /// Object type GUID of an object ACE
+    pub object_type: Option<[u8; 16]>,
+
+    /// Inherited object type GUID of an object ACE
+    pub inherited_object_type: Option<[u8; 16]>,
+}
+
+impl Ace {
+    /// Check if this is an allow or deny ACE that applies to the object itself
+    pub fn is_effective(&self) -> bool {
+        self.flags & ace_flags::INHERIT_ONLY == 0
+            && matches!(self.ace_type, ace_type::ACCESS_ALLOWED | ace_type::ACCESS_DENIED)
+    }
+
+    /// SDDL form, such as `(A;OICI;FA;;;SY)`
+    pub fn to_sddl(&self) -> String {
+        let guid = |guid: &Option<[u8; 16]>| guid.as_ref().map(format_guid).unwrap_or_default();
+        format!(
+            "({};{};{};{};{};{})",
+            ace_type_sddl(self.ace_type),
+            ace_flags_sddl(self.flags),
+            access_mask_sddl(self.ace_type, self.mask),
+            guid(&self.object_type),
+            guid(&self.inherited_object_type),
+            self.sid.to_sddl()
+        )
+    }
+}
+
+/// An access control list
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Acl {
+    /// ACL revision
+    pub revision: u8,
+
+    /// Entries, in evaluation order
+    pub aces: Vec<Ace>,
+}
+
+impl Acl {
+    /// Decode an ACL at `offset`
+    pub fn parse(data: &[u8], offset: usize) -> Result<Self> {
+        let size = le_u16(data, offset + 2) as usize;
+        let count = le_u16(data, offset + 4) as usize;
+        if size < 8 || offset + size > data.len() {
+            return Err(SMNtfsError::ReadError(format!("Invalid ACL size {} at offset {}", size, offset)));
+        }
+
+        let end = offset + size;
+        let mut aces = Vec::with_capacity(count);
+        let mut position = offset + 8;
+
+        for _ in 0..count {
+            let ace_size = le_u16(data, position + 2) as usize;
+            if ace_size < 8 || position + ace_size > end {
+                return Err(SMNtfsError::ReadError(format!(
+                    "Invalid ACE size {} at offset {}",
+                    ace_size, position
+                )));
+            }
+            aces.push(parse_ace(&data[..position + ace_size], position)?);
+            position += ace_size;
+        }
+
+        Ok(Self {
+            revision: le_u8(data, offset),
+            aces,
+        })
+    }
+}
+
+/// Decode one ACE whose bytes end at the end of `data`
+fn parse_ace(data: &[u8], offset: usize) -> Result<Ace> {
+    let ty = le_u8(data, offset);
+    let mut position = offset + 8;
+    let mut object_type = None;
+    let mut inherited_object_type = None;
+
+    let is_object = matches!(
+        ty,
+        ace_type::ACCESS_ALLOWED_OBJECT
+            | ace_type::ACCESS_DENIED_OBJECT
+            | ace_type::SYSTEM_AUDIT_OBJECT
+            | ace_type::SYSTEM_ALARM_OBJECT
+            | 0x0B
+            | 0x0C
+            | 0x0F
+            | 0x10
+    );
+    if is_object {
+        let object_flags = le_u32(data, position);
+        position += 4;
+        let mut guid = || -> Option<[u8; 16]> {
+            let bytes = data.get(position..position + 16)?.try_into().ok()?;
+            position += 16;
+            Some(bytes)
+        };
+        if object_flags & 0x1 != 0 {
+            object_type = guid();
+        }
+        if object_flags & 0x2 != 0 {
+            inherited_object_type = guid();
+        }
+    }
+
+    let (sid, _) = Sid::parse(data, position)?;
+
+    Ok(Ace {
+        ace_type: ty,
+        flags: le_u8(data, offset + 1),
+        mask: le_u32(data, offset + 4),
+        sid,
+        object_type,
+        inherited_object_type,
+    })
+}
+
+/// A decoded security descriptor
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SecurityDescriptor {
+    /// Control flags, see [`control`]
+    pub control: u16,
+
+    /// Owner of the object
+    pub owner: Option<Sid>,
+
+    /// Primary group of the object
+    pub group: Option<Sid>,
+
+    /// Discretionary ACL (`None` if absent or null, which grants everyone full access)
+    pub dacl: Option<Acl>,
+
+    /// System ACL (auditing and integrity labels)
+    pub sacl: Option<Acl>,
+}
+
+impl SecurityDescriptor {
+    /// Decode a self-relative security descriptor
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        if data.len() < 20 {
+            return Err(SMNtfsError::ReadError(format!(
+                "Truncated security descriptor of {} bytes",
+                data.len()
+            )));
+        }
+
+        let control = le_u16(data, 0x02);
+        let sid_at = |offset: u32| -> Result<Option<Sid>> {
+            match offset {
+                0 => Ok(None),
+                offset => Sid::parse(data, offset as usize).map(|(sid, _)| Some(sid)),
+            }
+        };
+        let acl_at = |offset: u32, present: u16| -> Result<Option<Acl>> {
+            match offset {
+                0 => Ok(None),
+                _ if control & present == 0 => Ok(None),
+                offset => Acl::parse(data, offset as usize).map(Some),
+            }
+        };
+
+        Ok(Self {
+            control,
+            owner: sid_at(le_u32(data, 0x04))?,
+            group: sid_at(le_u32(data, 0x08))?,
+            sacl: acl_at(le_u32(data, 0x0C), control::SACL_PRESENT)?,
+            dacl: acl_at(le_u32(data, 0x10), control::DACL_PRESENT)?,
+        })
+    }
+
+    /// Format as an SDDL string, such as `O:BAG:SYD:PAI(A;OICI;FA;;;SY)`
+    pub fn to_sddl(&self) -> String {
+        let mut sddl = String::new();
+
+        if let Some(owner) = &self.owner {
+            sddl.push_str(&format!("O:{}", owner.to_sddl()));
+        }
+        if let Some(group) = &self.group {
+            sddl.push_str(&format!("G:{}", group.to_sddl()));
+        }
+        if self.control & control::DACL_PRESENT != 0 {
+            sddl.push_str("D:");
+            sddl.push_str(&self.acl_sddl(
+                &self.dacl,
+                control::DACL_PROTECTED,
+                control::DACL_AUTO_INHERITED,
+            ));
+        }
+        if self.control & control::SACL_PRESENT != 0 {
+            sddl.push_str("S:");
+            sddl.push_str(&self.acl_sddl(
+                &self.sacl,
+                control::SACL_PROTECTED,
+                control::SACL_AUTO_INHERITED,
+            ));
+        }
+
+        sddl
+    }
+
+    fn acl_sddl(&self, acl: &Option<Acl>, protected: u16, auto_inherited: u16) -> String {
+        let mut sddl = String::new();
+        if self.control & protected != 0 {
+            sddl.push('P');
+        }
+        if self.control & auto_inherited != 0 {
+            sddl.push_str("AI");
+        }
+        match acl {
+            Some(acl) => acl.aces.iter().for_each(|ace| sddl.push_str(&ace.to_sddl())),
+            None => sddl.push_str("NO_ACCESS_CONTROL"),
+        }
+        sddl
+    }
+}
+
+fn ace_type_sddl(ty: u8) -> String {
+    match ty {
+        ace_type::ACCESS_ALLOWED => "A".to_string(),
+        ace_type::ACCESS_DENIED => "D".to_string(),
+        ace_type::SYSTEM_AUDIT => "AU".to_string(),
+        ace_type::SYSTEM_ALARM => "AL".to_string(),
+        ace_type::ACCESS_ALLOWED_OBJECT => "OA".to_string(),
+        ace_type::ACCESS_DENIED_OBJECT => "OD".to_string(),
+        ace_type::SYSTEM_AUDIT_OBJECT => "OU".to_string(),
+        ace_type::SYSTEM_ALARM_OBJECT => "OL".to_string(),
+        ace_type::ACCESS_ALLOWED_CALLBACK => "XA".to_string(),
+        ace_type::ACCESS_DENIED_CALLBACK => "XD".to_string(),
+        ace_type::SYSTEM_MANDATORY_LABEL => "ML".to_string(),
+        other => format!("{:#x}", other),
+    }
+}
+
+fn ace_flags_sddl(flags: u8) -> String {
+    [
+        (ace_flags::OBJECT_INHERIT, "OI"),
+        (ace_flags::CONTAINER_INHERIT, "CI"),
+        (ace_flags::NO_PROPAGATE_INHERIT, "NP"),
+        (ace_flags::INHERIT_ONLY, "IO"),
+        (ace_flags::INHERITED, "ID"),
+        (ace_flags::SUCCESSFUL_ACCESS, "SA"),
+        (ace_flags::FAILED_ACCESS, "FA"),
+    ]
+    .iter()
+    .filter(|(flag, _)| flags & flag != 0)
+    .map(|(_, name)| *name)
+    .collect()
+}
+
+fn access_mask_sddl(ty: u8, mask: u32) -> String {
+    if ty == ace_type::SYSTEM_MANDATORY_LABEL {
+        let policy: String = [(0x1, "NW"), (0x2, "NR"), (0x4, "NX")]
+            .iter()
+            .filter(|(bit, _)| mask & bit != 0)
+            .map(|(_, name)| *name)
+            .collect();
+        return policy;
+    }
+
+    let named = match mask {
+        access::FILE_ALL_ACCESS => Some("FA"),
+        access::FILE_GENERIC_READ => Some("FR"),
+        access::FILE_GENERIC_WRITE => Some("FW"),
+        access::FILE_GENERIC_EXECUTE => Some("FX"),
+        _ => None,
+    };
+    if let Some(named) = named {
+        return named.to_string();
+    }
+
+    let generic = [
+        (access::GENERIC_ALL, "GA"),
+        (access::GENERIC_READ, "GR"),
+        (access::GENERIC_WRITE, "GW"),
+        (access::GENERIC_EXECUTE, "GX"),
+    ];
+    let generic_bits = generic.iter().fold(0, |bits, (bit, _)| bits | bit);
+    if mask != 0 && mask & !generic_bits == 0 {
+        return generic
+            .iter()
+            .filter(|(bit, _)| mask & bit != 0)
+            .map(|(_, name)| *name)
+            .collect();
+    }
+
+    format!("{:#x}", mask)
+}
+
+/// Format a GUID in its usual mixed-endian string form
+pub(crate) fn format_guid(guid: &[u8; 16]) -> String {
+    format!(
+        "{:08x}-{:04x}-{:04x}-{:02x}{:02x}-{}",
+        le_u32(guid, 0),
+        le_u16(guid, 4),
+        le_u16(guid, 6),
+        guid[8],
+        guid[9],
+        guid[10..].iter().map(|b| format!("{:02x}", b)).collect::<String>()
+    )
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Build an allow or deny ACE
+    pub(crate) fn ace(ty: u8, flags: u8, mask: u32, sid: &str) -> Vec<u8> {
+        let sid = sid.parse::<Sid>().unwrap().to_bytes();
+        let mut ace = vec![ty, flags];
+        ace.extend_from_slice(&((8 + sid.len()) as u16).to_le_bytes());
+        ace.extend_from_slice(&mask.to_le_bytes());
+        ace.extend_from_slice(&sid);
+        ace
+    }
+
+    /// Build a self-relative security descriptor with a DACL
+    pub(crate) fn security_descriptor(owner: &str, group: &str, aces: &[Vec<u8>], control: u16) -> Vec<u8> {
+        let owner = owner.parse::<Sid>().unwrap().to_bytes();
+        let group = group.parse::<Sid>().unwrap().to_bytes();
+        let acl_size = 8 + aces.iter().map(Vec::len).sum::<usize>();
+
+        let mut data = vec![1, 0];
+        data.extend_from_slice(&(control | control::DACL_PRESENT | control::SELF_RELATIVE).to_le_bytes());
+        data.extend_from_slice(&20u32.to_le_bytes());
+        data.extend_from_slice(&(20 + owner.len() as u32).to_le_bytes());
+        data.extend_from_slice(&0u32.to_le_bytes());
+        data.extend_from_slice(&(20 + (owner.len() + group.len()) as u32).to_le_bytes());
+        data.extend_from_slice(&owner);
+        data.extend_from_slice(&group);
+        data.extend_from_slice(&[2, 0]);
+        data.extend_from_slice(&(acl_size as u16).to_le_bytes());
+        data.extend_from_slice(&(aces.len() as u16).to_le_bytes());
+        data.extend_from_slice(&[0, 0]);
+        for ace in aces {
+            data.extend_from_slice(ace);
+        }
+        data
+    }
+
+    #[test]
+    fn test_sid_round_trip() {
+        let sid: Sid = "S-1-5-21-1004336348-1177238915-682003330-1001".parse().unwrap();
+        let (parsed, length) = Sid::parse(&sid.to_bytes(), 0).unwrap();
+
+        assert_eq!(parsed, sid);
+        assert_eq!(length, 28);
+        assert_eq!(parsed.rid(), Some(1001));
+        assert_eq!(parsed.to_string(), "S-1-5-21-1004336348-1177238915-682003330-1001");
+        assert_eq!("S-1-5-32-544".parse::<Sid>().unwrap().to_sddl(), "BA");
+        assert!("S-1-x".parse::<Sid>().is_err());
+    }
+
+    #[test]
+    fn test_parse_descriptor() {
+        let data = security_descriptor(
+            "S-1-5-32-544",
+            "S-1-5-18",
+            &[
+                ace(ace_type::ACCESS_ALLOWED, 0x03, access::FILE_ALL_ACCESS, "S-1-5-18"),
+                ace(ace_type::ACCESS_DENIED, 0x00, access::FILE_WRITE_DATA, "S-1-1-0"),
+                ace(ace_type::ACCESS_ALLOWED, 0x13, 0x1301BF, "S-1-5-11"),
+            ],
+            control::DACL_PROTECTED | control::DACL_AUTO_INHERITED,
+        );
+        let descriptor = SecurityDescriptor::parse(&data).unwrap();
+
+        assert_eq!(descriptor.owner.as_ref().unwrap().to_string(), "S-1-5-32-544");
+        assert_eq!(descriptor.dacl.as_ref().unwrap().aces.len(), 3);
+        assert_eq!(
+            descriptor.to_sddl(),
+            "O:BAG:SYD:PAI(A;OICI;FA;;;SY)(D;;0x2;;;WD)(A;OICIID;0x1301bf;;;AU)"
+        );
+        assert!(SecurityDescriptor::parse(&data[..data.len() - 4]).is_err());
+    }
+
+    #[test]
+    fn test_null_dacl() {
+        let mut data = security_descriptor("S-1-5-18", "S-1-5-18", &[], 0);
+        // Clear the DACL offset but keep DACL_PRESENT: a null DACL
+        data[0x10..0x14].copy_from_slice(&0u32.to_le_bytes());
+        let descriptor = SecurityDescriptor::parse(&data).unwrap();
+
+        assert!(descriptor.dacl.is_none());
+        assert_eq!(descriptor.to_sddl(), "O:SYG:SYD:NO_ACCESS_CONTROL");
+    }
+}
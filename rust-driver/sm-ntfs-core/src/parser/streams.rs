@@ -1,11 +1,14 @@
 //! NTFS data streams handling
 
-use ntfs::{NtfsAttributeType, NtfsFile, NtfsReadSeek};
+use ntfs::{NtfsAttributeType, NtfsFile};
 use std::io::{Read, Seek};
-use crate::utils::error::{Result, SMNtfsError};
+use crate::parser::attribute::AttributeStream;
+use crate::parser::reader::StreamReader;
+use crate::parser::upcase::UpcaseTable;
+use crate::utils::error::Result;
 
 /// Information about a data stream
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct StreamInfo {
     /// Stream name (empty for default stream)
     pub name: String,
@@ -13,117 +16,93 @@ pub struct StreamInfo {
     /// Stream size
     pub size: u64,
 
-    /// Allocated size
+    /// Allocated size (clusters reserved on disk, including sparse holes)
     pub allocated_size: u64,
-}
 
-/// Get all data streams for a file
-pub fn list_streams<T: Read + Seek>(
-    file: &NtfsFile,
-    fs: &mut T,
-) -> Result<Vec<StreamInfo>> {
-    let mut streams = Vec::new();
+    /// Initialized size (bytes past it read as zeros)
+    pub initialized_size: u64,
 
-    // Iterate through all attributes
-    let mut attributes = file.attributes();
+    /// Clusters actually in use for sparse and compressed streams, in bytes
+    pub compressed_size: Option<u64>,
 
-    while let Some(attr_item_result) = attributes.next(fs) {
-        let attr_item = attr_item_result
-            .map_err(|e| SMNtfsError::ReadError(format!("Failed to read attribute: {}", e)))?;
+    /// Is the stream stored inside the MFT record?
+    pub is_resident: bool,
 
-        let attr = attr_item
-            .to_attribute()
-            .map_err(|e| SMNtfsError::ReadError(format!("Failed to get attribute: {}", e)))?;
+    /// Compression unit as a power of two in clusters (0 if uncompressed)
+    pub compression_unit: u8,
 
-        // We're only interested in DATA attributes
-        if !matches!(attr.ty(), Ok(NtfsAttributeType::Data)) {
-            continue;
-        }
+    /// Is the stream compressed?
+    pub is_compressed: bool,
 
-        let name = match attr.name() {
-            Ok(name) => name.to_string_lossy(),
-            Err(_) => String::new(), // Default stream has no name
-        };
+    /// Is the stream sparse?
+    pub is_sparse: bool,
 
-        let size = attr.value_length();
-        let allocated_size = attr.value_length();
+    /// Is the stream encrypted?
+    pub is_encrypted: bool,
+}
 
-        streams.push(StreamInfo {
-            name,
-            size,
-            allocated_size,
-        });
+impl StreamInfo {
+    /// Bytes the stream occupies on disk (0 for resident streams)
+    pub fn on_disk_size(&self) -> u64 {
+        if self.is_resident {
+            0
+        } else {
+            self.compressed_size.unwrap_or(self.allocated_size)
+        }
     }
+}
 
-    Ok(streams)
+impl From<&AttributeStream> for StreamInfo {
+    fn from(stream: &AttributeStream) -> Self {
+        Self {
+            name: stream.name.clone(),
+            size: stream.data_size,
+            allocated_size: stream.allocated_size,
+            initialized_size: stream.initialized_size,
+            compressed_size: stream.compressed_size,
+            is_resident: stream.is_resident(),
+            compression_unit: stream.compression_unit,
+            is_compressed: stream.is_compressed(),
+            is_sparse: stream.is_sparse(),
+            is_encrypted: stream.is_encrypted(),
+        }
+    }
 }
 
-/// Read data from the default stream
-pub fn read_default_stream<T: Read + Seek>(
+/// Get all data streams for a file
+///
+/// Sizes and flags are read from the attribute headers, including streams
+/// whose fragments are spread over extension records.
+pub fn list_streams<T: Read + Seek>(
     file: &NtfsFile,
     fs: &mut T,
-) -> Result<Vec<u8>> {
-    read_stream(file, fs, "")
+) -> Result<Vec<StreamInfo>> {
+    let streams = AttributeStream::collect_all(file, fs)?
+        .iter()
+        .filter(|stream| stream.type_code == NtfsAttributeType::Data as u32)
+        .map(StreamInfo::from)
+        .collect();
+
+    Ok(streams)
 }
 
-/// Read data from a named stream
-pub fn read_named_stream<T: Read + Seek>(
+/// Read data from the default stream
+pub fn read_default_stream<T: Read + Seek>(
     file: &NtfsFile,
     fs: &mut T,
-    stream_name: &str,
 ) -> Result<Vec<u8>> {
-    read_stream(file, fs, stream_name)
+    StreamReader::open_default(file, fs)?.read_all()
 }
 
-/// Internal helper to read a stream
-fn read_stream<T: Read + Seek>(
+/// Read data from a named stream, comparing names through the $UpCase
+/// table of the volume (see [`NtfsVolume::upcase_table`](crate::parser::NtfsVolume::upcase_table))
+pub fn read_named_stream<T: Read + Seek>(
     file: &NtfsFile,
     fs: &mut T,
     stream_name: &str,
+    upcase: &UpcaseTable,
 ) -> Result<Vec<u8>> {
-    // Get the DATA attribute
-    let data_item = match file.data(fs, stream_name) {
-        Some(item) => item,
-        None => {
-            let msg = if stream_name.is_empty() {
-                "No default data stream found".to_string()
-            } else {
-                format!("Stream '{}' not found", stream_name)
-            };
-            return Err(SMNtfsError::ReadError(msg));
-        }
-    };
-
-    let data_item = data_item
-        .map_err(|e| SMNtfsError::ReadError(format!("Failed to get data attribute: {}", e)))?;
-
-    let data_attr = data_item
-        .to_attribute()
-        .map_err(|e| SMNtfsError::ReadError(format!("Failed to get attribute: {}", e)))?;
-
-    let mut data_value = data_attr
-        .value(fs)
-        .map_err(|e| SMNtfsError::ReadError(format!("Failed to get data value: {}", e)))?;
-
-    let size = data_value.len();
-    let mut data = vec![0u8; size as usize];
-    let mut buf = [0u8; 4096];
-    let mut pos = 0;
-
-    loop {
-        let bytes_read = data_value
-            .read(fs, &mut buf)
-            .map_err(|e| SMNtfsError::ReadError(format!("Failed to read data: {}", e)))?;
-
-        if bytes_read == 0 {
-            break;
-        }
-
-        data[pos..pos + bytes_read].copy_from_slice(&buf[..bytes_read]);
-        pos += bytes_read;
-    }
-
-    Ok(data)
+    StreamReader::open(file, fs, stream_name, upcase)?.read_all()
 }
 
 #[cfg(test)]
@@ -136,6 +115,7 @@ mod tests {
             name: String::new(), // Default stream
             size: 1024,
             allocated_size: 4096,
+            ..StreamInfo::default()
         };
 
         assert!(stream.name.is_empty());
@@ -148,9 +128,30 @@ mod tests {
             name: "Zone.Identifier".to_string(),
             size: 26,
             allocated_size: 512,
+            ..StreamInfo::default()
         };
 
         assert!(!stream.name.is_empty());
         assert_eq!(stream.name, "Zone.Identifier");
     }
+
+    #[test]
+    fn test_on_disk_size() {
+        let sparse = StreamInfo {
+            size: 1 << 20,
+            allocated_size: 1 << 20,
+            compressed_size: Some(8192),
+            is_sparse: true,
+            ..StreamInfo::default()
+        };
+        assert_eq!(sparse.on_disk_size(), 8192);
+
+        let resident = StreamInfo {
+            size: 26,
+            allocated_size: 26,
+            is_resident: true,
+            ..StreamInfo::default()
+        };
+        assert_eq!(resident.on_disk_size(), 0);
+    }
 }
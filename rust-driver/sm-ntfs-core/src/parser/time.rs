@@ -0,0 +1,97 @@
+//! NTFS timestamp conversion
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use ntfs::NtfsTime;
+
+/// 100-nanosecond intervals between 1601-01-01 (NTFS epoch) and 1970-01-01 (Unix epoch)
+const EPOCH_DIFFERENCE_IN_INTERVALS: u64 = 116_444_736_000_000_000;
+
+/// Number of 100-nanosecond intervals in a second
+const INTERVALS_PER_SECOND: u64 = 10_000_000;
+
+/// Convert a raw NT timestamp (100 ns intervals since 1601) to `SystemTime`
+pub fn nt_timestamp_to_system_time(timestamp: u64) -> SystemTime {
+    let to_duration = |intervals: u64| {
+        Duration::new(
+            intervals / INTERVALS_PER_SECOND,
+            ((intervals % INTERVALS_PER_SECOND) * 100) as u32,
+        )
+    };
+
+    if timestamp >= EPOCH_DIFFERENCE_IN_INTERVALS {
+        UNIX_EPOCH + to_duration(timestamp - EPOCH_DIFFERENCE_IN_INTERVALS)
+    } else {
+        UNIX_EPOCH
+            .checked_sub(to_duration(EPOCH_DIFFERENCE_IN_INTERVALS - timestamp))
+            .unwrap_or(UNIX_EPOCH)
+    }
+}
+
+/// Convert an [`NtfsTime`] to `SystemTime`
+pub fn ntfs_time_to_system_time(time: NtfsTime) -> SystemTime {
+    nt_timestamp_to_system_time(time.nt_timestamp())
+}
+
+/// The four timestamps NTFS keeps per $STANDARD_INFORMATION or $FILE_NAME
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileTimes {
+    /// Creation time
+    pub created: SystemTime,
+
+    /// Last data modification time
+    pub modified: SystemTime,
+
+    /// Last MFT record change time (metadata change, like `ctime`)
+    pub mft_modified: SystemTime,
+
+    /// Last access time
+    pub accessed: SystemTime,
+}
+
+impl FileTimes {
+    /// Build from raw NTFS timestamps
+    pub fn from_ntfs(created: NtfsTime, modified: NtfsTime, mft_modified: NtfsTime, accessed: NtfsTime) -> Self {
+        Self {
+            created: ntfs_time_to_system_time(created),
+            modified: ntfs_time_to_system_time(modified),
+            mft_modified: ntfs_time_to_system_time(mft_modified),
+            accessed: ntfs_time_to_system_time(accessed),
+        }
+    }
+}
+
+impl Default for FileTimes {
+    fn default() -> Self {
+        Self {
+            created: UNIX_EPOCH,
+            modified: UNIX_EPOCH,
+            mft_modified: UNIX_EPOCH,
+            accessed: UNIX_EPOCH,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unix_epoch() {
+        assert_eq!(nt_timestamp_to_system_time(EPOCH_DIFFERENCE_IN_INTERVALS), UNIX_EPOCH);
+    }
+
+    #[test]
+    fn test_known_timestamp() {
+        // 2021-01-01 00:00:00 UTC
+        let time = nt_timestamp_to_system_time(132_539_328_000_000_000);
+        let since_epoch = time.duration_since(UNIX_EPOCH).unwrap();
+        assert_eq!(since_epoch.as_secs(), 1_609_459_200);
+        assert_eq!(since_epoch.subsec_nanos(), 0);
+    }
+
+    #[test]
+    fn test_before_unix_epoch() {
+        let time = nt_timestamp_to_system_time(EPOCH_DIFFERENCE_IN_INTERVALS - INTERVALS_PER_SECOND);
+        assert_eq!(UNIX_EPOCH.duration_since(time).unwrap(), Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,570 @@
+//! Deleted file recovery (undelete)
+//!
+//! Deleting a file on NTFS clears the in-use flag of its MFT record and
+//! frees its clusters in $Bitmap, but leaves the record itself alone until
+//! the slot is reused. This module finds such records with an MFT scan,
+//! rebuilds their paths from the parent references in $FILE_NAME, and scores
+//! each data stream by how many of its clusters have since been reallocated.
+//!
+//! Everything here only needs `Read + Seek` on the device: recovered data is
+//! written to files on the host, never back to the volume.
+
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek};
+use std::path::{Path, PathBuf};
+use ntfs::{Ntfs, NtfsAttributeType};
+use crate::parser::attribute::AttributeStream;
+use crate::parser::bitmap::ClusterBitmap;
+use crate::parser::links::ROOT_RECORD_NUMBER;
+use crate::parser::mft::{FileAttributes, FileReference};
+use crate::parser::reader::StreamReader;
+use crate::parser::record::FileRecord;
+use crate::parser::scan::MftRecords;
+use crate::parser::time::FileTimes;
+use crate::parser::values::{FileNameValue, StandardInformationValue};
+use crate::utils::error::{Result, SMNtfsError};
+
+/// Deepest directory nesting followed when rebuilding a path
+const MAX_PATH_DEPTH: usize = 256;
+
+/// How much of a deleted stream can still be recovered
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecoveryChance {
+    /// No cluster has been reallocated since the file was deleted
+    Full,
+    /// Some clusters have been reallocated and may hold other data
+    Partial,
+    /// Every cluster has been reallocated
+    Lost,
+}
+
+/// Cluster accounting of a deleted stream against the current $Bitmap
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Recoverability {
+    /// Clusters the stream occupied (sparse holes excluded)
+    pub total_clusters: u64,
+
+    /// Clusters that are now allocated to something else
+    pub reallocated_clusters: u64,
+}
+
+impl Recoverability {
+    /// Check the runs of a stream against the allocation bitmap
+    pub fn of_stream(stream: &AttributeStream, bitmap: &ClusterBitmap) -> Self {
+        stream
+            .runs
+            .iter()
+            .filter_map(|run| run.lcn.map(|lcn| (lcn, run.length)))
+            .fold(Self::default(), |total, (lcn, length)| Self {
+                total_clusters: total.total_clusters + length,
+                reallocated_clusters: total.reallocated_clusters + bitmap.allocated_in_range(lcn, length),
+            })
+    }
+
+    /// Fraction of clusters still free, from 0.0 to 1.0 (1.0 for resident streams)
+    pub fn score(&self) -> f64 {
+        if self.total_clusters == 0 {
+            return 1.0;
+        }
+        1.0 - self.reallocated_clusters as f64 / self.total_clusters as f64
+    }
+
+    /// Classify the score
+    pub fn chance(&self) -> RecoveryChance {
+        if self.reallocated_clusters == 0 {
+            RecoveryChance::Full
+        } else if self.reallocated_clusters >= self.total_clusters {
+            RecoveryChance::Lost
+        } else {
+            RecoveryChance::Partial
+        }
+    }
+
+    /// Combine the accounting of two streams
+    fn merge(self, other: Self) -> Self {
+        Self {
+            total_clusters: self.total_clusters + other.total_clusters,
+            reallocated_clusters: self.reallocated_clusters + other.reallocated_clusters,
+        }
+    }
+}
+
+/// A $DATA stream of a deleted file
+#[derive(Debug, Clone)]
+pub struct DeletedStream {
+    /// The attribute value as it was when the file was deleted
+    pub stream: AttributeStream,
+
+    /// How much of it is still intact
+    pub recoverability: Recoverability,
+}
+
+impl DeletedStream {
+    /// Stream name (empty for the default stream)
+    pub fn name(&self) -> &str {
+        &self.stream.name
+    }
+
+    /// Logical size in bytes
+    pub fn size(&self) -> u64 {
+        self.stream.data_size
+    }
+}
+
+/// A deleted file or directory whose MFT record is still intact
+#[derive(Debug, Clone)]
+pub struct DeletedFile {
+    /// MFT record number
+    pub record_number: u64,
+
+    /// Sequence number of the freed record
+    pub sequence_number: u16,
+
+    /// Was it a directory?
+    pub is_directory: bool,
+
+    /// File name (the long name when there is also an 8.3 alias)
+    pub name: String,
+
+    /// Directory the file was deleted from
+    pub parent: FileReference,
+
+    /// Full path rebuilt from parent references, e.g. `/Users/alice/report.docx`
+    ///
+    /// When an ancestor directory is gone or its record was reused, the
+    /// path starts with `?/` followed by the components that are known.
+    pub path: String,
+
+    /// Windows attribute flags from $STANDARD_INFORMATION
+    pub attributes: FileAttributes,
+
+    /// Timestamps from $STANDARD_INFORMATION, if the attribute survived
+    pub times: Option<FileTimes>,
+
+    /// $DATA streams, default stream first
+    pub streams: Vec<DeletedStream>,
+}
+
+impl DeletedFile {
+    /// Check if the full path up to the root could be rebuilt
+    pub fn is_orphan(&self) -> bool {
+        self.path.starts_with("?/")
+    }
+
+    /// Size of the default stream
+    pub fn size(&self) -> u64 {
+        self.stream("").map_or(0, DeletedStream::size)
+    }
+
+    /// Find a stream by name (empty for the default stream)
+    pub fn stream(&self, name: &str) -> Option<&DeletedStream> {
+        self.streams.iter().find(|stream| stream.name() == name)
+    }
+
+    /// Combined recoverability of every stream
+    pub fn recoverability(&self) -> Recoverability {
+        self.streams
+            .iter()
+            .fold(Recoverability::default(), |total, stream| total.merge(stream.recoverability))
+    }
+}
+
+/// Name and parent of a record, used to rebuild paths
+#[derive(Debug, Clone)]
+struct NameEntry {
+    sequence_number: u16,
+    in_use: bool,
+    name: String,
+    parent: FileReference,
+}
+
+impl NameEntry {
+    /// Check if this record is the one a parent reference points to
+    ///
+    /// Freeing a record bumps its sequence number, so a deleted directory
+    /// is one ahead of the references its children still hold.
+    fn matches(&self, reference: FileReference) -> bool {
+        self.sequence_number == reference.sequence_number
+            || (!self.in_use && self.sequence_number == reference.sequence_number.wrapping_add(1))
+    }
+}
+
+/// Pick the name to show: the long name if there is one
+fn preferred_name(names: &[FileNameValue]) -> Option<&FileNameValue> {
+    names.iter().find(|name| !name.is_dos_only()).or_else(|| names.first())
+}
+
+/// Decode every $FILE_NAME of a record
+fn file_names(record: &FileRecord) -> Vec<FileNameValue> {
+    record
+        .attributes()
+        .filter_map(|attribute| attribute.ok())
+        .filter(|attribute| attribute.is_type(NtfsAttributeType::FileName))
+        .filter_map(|attribute| FileNameValue::parse(attribute.resident_value()).ok())
+        .collect()
+}
+
+/// Find deleted files on a volume
+///
+/// Scans the whole MFT and reads $Bitmap once. Only base records that are
+/// not in use, pass the fixup check and still have a $FILE_NAME are
+/// returned, in record number order.
+pub fn find_deleted_files<T: Read + Seek>(ntfs: &Ntfs, fs: &mut T) -> Result<Vec<DeletedFile>> {
+    let bitmap = ClusterBitmap::load(ntfs, fs)?;
+    let records = MftRecords::new(ntfs, fs)?;
+    Ok(collect_deleted_files(records, &bitmap))
+}
+
+/// Find deleted files among already scanned records
+pub fn collect_deleted_files(records: impl IntoIterator<Item = FileRecord>, bitmap: &ClusterBitmap) -> Vec<DeletedFile> {
+    let mut names: HashMap<u64, NameEntry> = HashMap::new();
+    let mut deleted = Vec::new();
+    let mut extensions: HashMap<u64, Vec<FileRecord>> = HashMap::new();
+
+    for record in records {
+        if !record.is_valid() {
+            continue;
+        }
+
+        if let Some(base) = record.base_record_number() {
+            // Extension records are freed together with their base record
+            if !record.is_in_use() {
+                extensions.entry(base).or_default().push(record);
+            }
+            continue;
+        }
+
+        let record_names = file_names(&record);
+        let Some(name) = preferred_name(&record_names) else {
+            continue;
+        };
+        names.insert(
+            record.record_number(),
+            NameEntry {
+                sequence_number: record.sequence_number(),
+                in_use: record.is_in_use(),
+                name: name.name.clone(),
+                parent: name.parent,
+            },
+        );
+
+        if !record.is_in_use() {
+            deleted.push(record);
+        }
+    }
+
+    deleted
+        .iter()
+        .map(|record| {
+            let extension_records = extensions.remove(&record.record_number()).unwrap_or_default();
+            build_deleted_file(record, &extension_records, &names, bitmap)
+        })
+        .collect()
+}
+
+/// Assemble a [`DeletedFile`] from its base and extension records
+fn build_deleted_file(
+    record: &FileRecord,
+    extensions: &[FileRecord],
+    names: &HashMap<u64, NameEntry>,
+    bitmap: &ClusterBitmap,
+) -> DeletedFile {
+    let entry = &names[&record.record_number()];
+    let standard_information = record
+        .find_attribute(NtfsAttributeType::StandardInformation, "")
+        .and_then(|attribute| StandardInformationValue::parse(attribute.resident_value()).ok());
+
+    DeletedFile {
+        record_number: record.record_number(),
+        sequence_number: record.sequence_number(),
+        is_directory: record.is_directory(),
+        name: entry.name.clone(),
+        parent: entry.parent,
+        path: rebuild_path(record.record_number(), names),
+        attributes: standard_information.map(|value| value.attributes).unwrap_or_default(),
+        times: standard_information.map(|value| value.times),
+        streams: data_streams(record, extensions, bitmap),
+    }
+}
+
+/// Collect the $DATA streams of a deleted record, default stream first
+fn data_streams(record: &FileRecord, extensions: &[FileRecord], bitmap: &ClusterBitmap) -> Vec<DeletedStream> {
+    let mut groups: Vec<(String, Vec<_>)> = Vec::new();
+
+    for attribute in std::iter::once(record)
+        .chain(extensions)
+        .flat_map(|record| record.attributes())
+        .filter_map(|attribute| attribute.ok())
+        .filter(|attribute| attribute.is_type(NtfsAttributeType::Data))
+    {
+        let name = attribute.name();
+        match groups.iter_mut().find(|(group, _)| *group == name) {
+            Some((_, fragments)) => fragments.push(attribute),
+            None => groups.push((name, vec![attribute])),
+        }
+    }
+
+    let mut streams: Vec<DeletedStream> = groups
+        .iter()
+        .filter_map(|(name, fragments)| match AttributeStream::from_fragments(fragments) {
+            Ok(stream) => Some(stream),
+            Err(e) => {
+                tracing::debug!("Skipping stream '{}' of deleted record {}: {}", name, record.record_number(), e);
+                None
+            }
+        })
+        .map(|stream| DeletedStream {
+            recoverability: Recoverability::of_stream(&stream, bitmap),
+            stream,
+        })
+        .collect();
+
+    streams.sort_by_key(|stream| !stream.name().is_empty());
+    streams
+}
+
+/// Rebuild the path of a record by following parent references to the root
+fn rebuild_path(record_number: u64, names: &HashMap<u64, NameEntry>) -> String {
+    let mut components = Vec::new();
+    let mut current = record_number;
+    let mut reached_root = false;
+
+    while components.len() < MAX_PATH_DEPTH {
+        let Some(entry) = names.get(&current) else {
+            break;
+        };
+        components.push(entry.name.as_str());
+
+        let parent = entry.parent;
+        if parent.record_number == ROOT_RECORD_NUMBER {
+            reached_root = true;
+            break;
+        }
+        match names.get(&parent.record_number) {
+            Some(parent_entry) if parent_entry.matches(parent) && parent.record_number != current => {
+                current = parent.record_number;
+            }
+            _ => break,
+        }
+    }
+
+    components.reverse();
+    let prefix = if reached_root { "/" } else { "?/" };
+    format!("{}{}", prefix, components.join("/"))
+}
+
+/// Make a name safe to use as a single host path component
+fn host_file_name(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| if c == '/' || c == '\0' { '_' } else { c })
+        .collect();
+    match name.as_str() {
+        "" | "." | ".." => format!("_{}", name),
+        _ => name,
+    }
+}
+
+/// Copy a deleted stream to a new file on the host
+///
+/// Fails if `destination` already exists, so earlier exports are never
+/// overwritten. Clusters that were reallocated are copied as they are now,
+/// so a partially recoverable stream will contain foreign data. Returns the
+/// number of bytes written.
+pub fn export_stream<T: Read + Seek>(
+    fs: &mut T,
+    stream: &DeletedStream,
+    cluster_size: u32,
+    destination: &Path,
+) -> Result<u64> {
+    let mut reader = StreamReader::from_stream(fs, stream.stream.clone(), cluster_size)?;
+    let mut output = create_new(destination)?;
+
+    // Holes are skipped, so sparse streams stay sparse on the host
+    let length = reader.copy_sparse(&mut output)?;
+    output.set_len(length).map_err(|e| {
+        SMNtfsError::WriteError(format!("Failed to recover to {}: {}", destination.display(), e))
+    })?;
+    Ok(length)
+}
+
+/// Copy every stream of a deleted file into `directory` on the host
+///
+/// The default stream is saved under the file's name and named streams as
+/// `name_stream`. Returns the paths written.
+pub fn export_file<T: Read + Seek>(
+    fs: &mut T,
+    file: &DeletedFile,
+    cluster_size: u32,
+    directory: &Path,
+) -> Result<Vec<PathBuf>> {
+    let base_name = host_file_name(&file.name);
+    let mut written = Vec::new();
+
+    for stream in &file.streams {
+        let file_name = match stream.name() {
+            "" => base_name.clone(),
+            name => format!("{}_{}", base_name, host_file_name(name)),
+        };
+        let destination = directory.join(file_name);
+        export_stream(fs, stream, cluster_size, &destination)?;
+        written.push(destination);
+    }
+
+    Ok(written)
+}
+
+/// Create a file, refusing to replace an existing one
+fn create_new(path: &Path) -> Result<File> {
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .map_err(|e| SMNtfsError::SystemError(format!("Failed to create {}: {}", path.display(), e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::record::record_flags;
+    use crate::parser::record::tests::{build_record, non_resident_attribute, resident_attribute};
+    use crate::parser::values::namespace;
+    use crate::parser::values::tests::file_name_value;
+    use std::io::Cursor;
+
+    const FILE_NAME: u32 = NtfsAttributeType::FileName as u32;
+    const DATA: u32 = NtfsAttributeType::Data as u32;
+
+    /// Build a record with the given sequence number
+    fn record(record_number: u64, sequence: u16, flags: u16, attributes: &[Vec<u8>]) -> FileRecord {
+        let mut data = build_record(attributes, flags);
+        data[0x10..0x12].copy_from_slice(&sequence.to_le_bytes());
+        FileRecord::from_bytes(data, record_number, 0)
+    }
+
+    fn name(parent: u64, parent_sequence: u16, name: &str) -> Vec<u8> {
+        let value = file_name_value(parent | ((parent_sequence as u64) << 48), name, namespace::WIN32);
+        resident_attribute(FILE_NAME, "", &value)
+    }
+
+    /// Clusters 0-7 are in use, 8-15 are free
+    fn bitmap() -> ClusterBitmap {
+        ClusterBitmap::from_bytes(vec![0xFF, 0x00], 16)
+    }
+
+    fn volume() -> Vec<FileRecord> {
+        vec![
+            record(5, 5, record_flags::IN_USE | record_flags::DIRECTORY, &[name(5, 5, ".")]),
+            record(40, 2, record_flags::IN_USE | record_flags::DIRECTORY, &[name(5, 5, "Users")]),
+            // Deleted directory and a file deleted from it
+            record(41, 4, record_flags::DIRECTORY, &[name(40, 2, "alice")]),
+            record(
+                42,
+                8,
+                0,
+                &[
+                    name(41, 3, "report.docx"),
+                    resident_attribute(FILE_NAME, "", &file_name_value(41 | (3 << 48), "REPORT~1.DOC", namespace::DOS)),
+                    non_resident_attribute(DATA, "", 0, (4096, 4000, 4000), &[0x11, 0x04, 0x06]),
+                    resident_attribute(DATA, "Zone.Identifier", b"[ZoneTransfer]"),
+                ],
+            ),
+            // Parent record 43 was reused by another file
+            record(43, 9, record_flags::IN_USE, &[name(40, 2, "new.txt")]),
+            record(44, 2, 0, &[name(43, 7, "lost.txt"), resident_attribute(DATA, "", b"hello")]),
+        ]
+    }
+
+    #[test]
+    fn test_collect_deleted_files() {
+        let files = collect_deleted_files(volume(), &bitmap());
+        let numbers: Vec<u64> = files.iter().map(|f| f.record_number).collect();
+        assert_eq!(numbers, vec![41, 42, 44]);
+
+        assert!(files[0].is_directory);
+        assert_eq!(files[0].path, "/Users/alice");
+
+        let report = &files[1];
+        assert_eq!(report.name, "report.docx");
+        assert_eq!(report.path, "/Users/alice/report.docx");
+        assert!(!report.is_orphan());
+        assert_eq!(report.size(), 4000);
+        assert_eq!(report.streams.len(), 2);
+        assert_eq!(report.streams[1].name(), "Zone.Identifier");
+
+        assert_eq!(files[2].path, "?/lost.txt");
+        assert!(files[2].is_orphan());
+    }
+
+    #[test]
+    fn test_recoverability() {
+        let files = collect_deleted_files(volume(), &bitmap());
+        let report = files[1].stream("").unwrap().recoverability;
+
+        // Clusters 6-9: two reallocated, two still free
+        assert_eq!(report.total_clusters, 4);
+        assert_eq!(report.reallocated_clusters, 2);
+        assert_eq!(report.chance(), RecoveryChance::Partial);
+        assert!((report.score() - 0.5).abs() < f64::EPSILON);
+
+        let resident = files[2].recoverability();
+        assert_eq!(resident.chance(), RecoveryChance::Full);
+        assert_eq!(resident.score(), 1.0);
+
+        let lost = Recoverability { total_clusters: 3, reallocated_clusters: 3 };
+        assert_eq!(lost.chance(), RecoveryChance::Lost);
+    }
+
+    #[test]
+    fn test_torn_and_extension_records() {
+        let mut torn = build_record(&[name(5, 5, "torn.txt")], 0);
+        torn[1022] ^= 0xFF;
+
+        let mut extension = build_record(&[non_resident_attribute(DATA, "ads", 0, (512, 512, 512), &[0x11, 0x01, 0x0A])], 0);
+        extension[0x20..0x28].copy_from_slice(&(50u64 | (1 << 48)).to_le_bytes());
+
+        let records = vec![
+            record(5, 5, record_flags::IN_USE | record_flags::DIRECTORY, &[name(5, 5, ".")]),
+            FileRecord::from_bytes(torn, 49, 0),
+            record(50, 2, 0, &[name(5, 5, "big.bin")]),
+            FileRecord::from_bytes(extension, 51, 0),
+        ];
+        let files = collect_deleted_files(records, &bitmap());
+
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].path, "/big.bin");
+        assert_eq!(files[0].stream("ads").unwrap().recoverability.chance(), RecoveryChance::Full);
+    }
+
+    #[test]
+    fn test_export_file() {
+        let files = collect_deleted_files(volume(), &bitmap());
+        let report = &files[1];
+
+        // 16 clusters of 1024 bytes, cluster N filled with byte N
+        let mut device = Cursor::new((0..16u8).flat_map(|n| [n; 1024]).collect::<Vec<u8>>());
+        let directory = tempfile::tempdir().unwrap();
+
+        let written = export_file(&mut device, report, 1024, directory.path()).unwrap();
+        assert_eq!(written.len(), 2);
+
+        let data = std::fs::read(directory.path().join("report.docx")).unwrap();
+        assert_eq!(data.len(), 4000);
+        assert!(data[..1024].iter().all(|b| *b == 6));
+        assert!(data[3072..].iter().all(|b| *b == 9));
+
+        let zone = std::fs::read(directory.path().join("report.docx_Zone.Identifier")).unwrap();
+        assert_eq!(zone, b"[ZoneTransfer]");
+
+        // Never overwrite an earlier export
+        assert!(export_file(&mut device, report, 1024, directory.path()).is_err());
+    }
+
+    #[test]
+    fn test_host_file_name() {
+        assert_eq!(host_file_name("a/b"), "a_b");
+        assert_eq!(host_file_name(".."), "_..");
+        assert_eq!(host_file_name("report.docx"), "report.docx");
+    }
+}
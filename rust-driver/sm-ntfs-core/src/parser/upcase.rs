@@ -0,0 +1,133 @@
+//! $UpCase: the uppercase mapping of UTF-16 code units
+//!
+//! NTFS compares file and attribute names case-insensitively by mapping
+//! every UTF-16 code unit through the 128 KiB table in $UpCase, which the
+//! volume was formatted with. Directory indexes are sorted by the mapped
+//! names, so lookups must use the table of the volume rather than Unicode
+//! case folding of the host.
+
+use std::cmp::Ordering;
+use std::io::{Read, Seek};
+use ntfs::{Ntfs, NtfsAttributeType};
+use crate::parser::attribute::AttributeStream;
+use crate::parser::reader::StreamReader;
+use crate::utils::error::{Result, SMNtfsError};
+
+/// Record number of the $UpCase system file
+pub const UPCASE_RECORD_NUMBER: u64 = 10;
+
+/// Number of entries of a complete table, one per UTF-16 code unit
+pub const UPCASE_ENTRIES: usize = 0x10000;
+
+/// Uppercase mapping of a volume
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UpcaseTable {
+    table: Vec<u16>,
+}
+
+impl UpcaseTable {
+    /// Read $UpCase
+    pub fn load<T: Read + Seek>(ntfs: &Ntfs, fs: &mut T) -> Result<Self> {
+        let file = ntfs
+            .file(fs, UPCASE_RECORD_NUMBER)
+            .map_err(|e| SMNtfsError::ReadError(format!("Failed to read $UpCase: {}", e)))?;
+        let stream = AttributeStream::collect(&file, fs, NtfsAttributeType::Data, "")?.ok_or(
+            SMNtfsError::CorruptedMft {
+                offset: UPCASE_RECORD_NUMBER,
+            },
+        )?;
+        if stream.data_size != (UPCASE_ENTRIES * 2) as u64 {
+            return Err(SMNtfsError::InvalidNtfs(format!(
+                "$UpCase is {} bytes instead of {}",
+                stream.data_size,
+                UPCASE_ENTRIES * 2
+            )));
+        }
+
+        let data = StreamReader::new(fs, stream, ntfs.cluster_size()).read_all()?;
+        Ok(Self::parse(&data))
+    }
+
+    /// Decode a table; code units past the end of `data` map to themselves
+    pub fn parse(data: &[u8]) -> Self {
+        let mut table: Vec<u16> = (0..UPCASE_ENTRIES).map(|unit| unit as u16).collect();
+        for (entry, bytes) in table.iter_mut().zip(data.chunks_exact(2)) {
+            *entry = u16::from_le_bytes([bytes[0], bytes[1]]);
+        }
+        Self { table }
+    }
+
+    /// Table built from the Unicode uppercase mapping of the host, for
+    /// volumes whose $UpCase cannot be read
+    ///
+    /// Only code units that uppercase to a single code unit are mapped, as
+    /// in the tables mkntfs and Windows write.
+    pub fn host() -> Self {
+        let table = (0..UPCASE_ENTRIES as u32)
+            .map(|unit| {
+                let mut upper = char::from_u32(unit).into_iter().flat_map(char::to_uppercase);
+                match (upper.next(), upper.next()) {
+                    (Some(c), None) if (c as u32) < UPCASE_ENTRIES as u32 => c as u16,
+                    _ => unit as u16,
+                }
+            })
+            .collect();
+        Self { table }
+    }
+
+    /// Uppercase of one UTF-16 code unit
+    pub fn to_uppercase(&self, unit: u16) -> u16 {
+        self.table[unit as usize]
+    }
+
+    /// Compare two names the way directory indexes are sorted
+    pub fn compare<A, B>(&self, a: A, b: B) -> Ordering
+    where
+        A: IntoIterator<Item = u16>,
+        B: IntoIterator<Item = u16>,
+    {
+        a.into_iter()
+            .map(|unit| self.to_uppercase(unit))
+            .cmp(b.into_iter().map(|unit| self.to_uppercase(unit)))
+    }
+
+    /// Check if two names are equal, ignoring case
+    pub fn names_equal(&self, a: &str, b: &str) -> bool {
+        self.compare(a.encode_utf16(), b.encode_utf16()) == Ordering::Equal
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Table that uppercases ASCII letters only
+    fn ascii_table() -> UpcaseTable {
+        let data: Vec<u8> = (0..UPCASE_ENTRIES as u32)
+            .map(|unit| char::from_u32(unit).map_or(unit, |c| c.to_ascii_uppercase() as u32) as u16)
+            .flat_map(u16::to_le_bytes)
+            .collect();
+        UpcaseTable::parse(&data)
+    }
+
+    #[test]
+    fn test_compare() {
+        let table = ascii_table();
+        assert!(table.names_equal("Readme.TXT", "README.txt"));
+        assert!(!table.names_equal("readme", "readme.txt"));
+        assert_eq!(table.compare("a".encode_utf16(), "B".encode_utf16()), Ordering::Less);
+        assert_eq!(table.compare("b".encode_utf16(), "A".encode_utf16()), Ordering::Greater);
+
+        // Only what the table maps is folded
+        assert!(!table.names_equal("é", "É"));
+        assert!(!UpcaseTable::parse(&[]).names_equal("a", "A"));
+    }
+
+    #[test]
+    fn test_host_table() {
+        let table = UpcaseTable::host();
+        assert!(table.names_equal("été", "ÉTÉ"));
+        // ß uppercases to two letters, so it maps to itself
+        assert_eq!(table.to_uppercase('ß' as u16), 'ß' as u16);
+    }
+}
@@ -0,0 +1,675 @@
+//! Change journal ($Extend\$UsnJrnl) reader
+//!
+//! Windows appends a USN record to the `$J` stream of $UsnJrnl for every
+//! change to a file. A record's USN is its byte offset in `$J`. Old records
+//! are discarded by deallocating the start of the stream, which leaves a
+//! sparse leading region that grows over time. Records never span a 4 KiB
+//! page; the unused tail of a page is zero-filled.
+
+use std::io::{Read, Seek, SeekFrom};
+use std::time::SystemTime;
+use ntfs::{Ntfs, NtfsAttributeType, NtfsFile};
+use crate::parser::attribute::AttributeStream;
+use crate::parser::mft::{FileAttributes, FileReference};
+use crate::parser::reader::StreamReader;
+use crate::parser::time::nt_timestamp_to_system_time;
+use crate::parser::values::file_reference;
+use crate::utils::bytes::{le_u16, le_u32, le_u64, utf16_string};
+use crate::utils::error::{Result, SMNtfsError};
+
+/// Path of the change journal on the volume
+pub const USN_JOURNAL_PATH: &str = "/$Extend/$UsnJrnl";
+
+/// Name of the stream holding the records
+pub const USN_DATA_STREAM: &str = "$J";
+
+/// Name of the stream holding the journal limits
+pub const USN_MAX_STREAM: &str = "$Max";
+
+/// Records never cross a boundary of this size
+const USN_PAGE_SIZE: u64 = 0x1000;
+
+/// USN reason flags (USN_REASON_*)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct UsnReason(pub u32);
+
+impl UsnReason {
+    /// Default stream overwritten
+    pub const DATA_OVERWRITE: u32 = 0x0000_0001;
+    /// Default stream extended
+    pub const DATA_EXTEND: u32 = 0x0000_0002;
+    /// Default stream truncated
+    pub const DATA_TRUNCATION: u32 = 0x0000_0004;
+    /// Named stream overwritten
+    pub const NAMED_DATA_OVERWRITE: u32 = 0x0000_0010;
+    /// Named stream extended
+    pub const NAMED_DATA_EXTEND: u32 = 0x0000_0020;
+    /// Named stream truncated
+    pub const NAMED_DATA_TRUNCATION: u32 = 0x0000_0040;
+    /// File or directory created
+    pub const FILE_CREATE: u32 = 0x0000_0100;
+    /// File or directory deleted
+    pub const FILE_DELETE: u32 = 0x0000_0200;
+    /// Extended attributes changed
+    pub const EA_CHANGE: u32 = 0x0000_0400;
+    /// Security descriptor changed
+    pub const SECURITY_CHANGE: u32 = 0x0000_0800;
+    /// Renamed; the record carries the old name
+    pub const RENAME_OLD_NAME: u32 = 0x0000_1000;
+    /// Renamed; the record carries the new name
+    pub const RENAME_NEW_NAME: u32 = 0x0000_2000;
+    /// Content indexed attribute changed
+    pub const INDEXABLE_CHANGE: u32 = 0x0000_4000;
+    /// Attributes or timestamps changed
+    pub const BASIC_INFO_CHANGE: u32 = 0x0000_8000;
+    /// Hard link added or removed
+    pub const HARD_LINK_CHANGE: u32 = 0x0001_0000;
+    /// Compression state changed
+    pub const COMPRESSION_CHANGE: u32 = 0x0002_0000;
+    /// Encryption state changed
+    pub const ENCRYPTION_CHANGE: u32 = 0x0004_0000;
+    /// Object ID changed
+    pub const OBJECT_ID_CHANGE: u32 = 0x0008_0000;
+    /// Reparse point changed
+    pub const REPARSE_POINT_CHANGE: u32 = 0x0010_0000;
+    /// Named stream added, removed or renamed
+    pub const STREAM_CHANGE: u32 = 0x0020_0000;
+    /// Changed by a TxF transaction
+    pub const TRANSACTED_CHANGE: u32 = 0x0040_0000;
+    /// Integrity stream setting changed
+    pub const INTEGRITY_CHANGE: u32 = 0x0080_0000;
+    /// Last handle closed; the reasons are the union since it was opened
+    pub const CLOSE: u32 = 0x8000_0000;
+
+    const NAMES: [(u32, &'static str); 23] = [
+        (Self::DATA_OVERWRITE, "DATA_OVERWRITE"),
+        (Self::DATA_EXTEND, "DATA_EXTEND"),
+        (Self::DATA_TRUNCATION, "DATA_TRUNCATION"),
+        (Self::NAMED_DATA_OVERWRITE, "NAMED_DATA_OVERWRITE"),
+        (Self::NAMED_DATA_EXTEND, "NAMED_DATA_EXTEND"),
+        (Self::NAMED_DATA_TRUNCATION, "NAMED_DATA_TRUNCATION"),
+        (Self::FILE_CREATE, "FILE_CREATE"),
+        (Self::FILE_DELETE, "FILE_DELETE"),
+        (Self::EA_CHANGE, "EA_CHANGE"),
+        (Self::SECURITY_CHANGE, "SECURITY_CHANGE"),
+        (Self::RENAME_OLD_NAME, "RENAME_OLD_NAME"),
+        (Self::RENAME_NEW_NAME, "RENAME_NEW_NAME"),
+        (Self::INDEXABLE_CHANGE, "INDEXABLE_CHANGE"),
+        (Self::BASIC_INFO_CHANGE, "BASIC_INFO_CHANGE"),
+        (Self::HARD_LINK_CHANGE, "HARD_LINK_CHANGE"),
+        (Self::COMPRESSION_CHANGE, "COMPRESSION_CHANGE"),
+        (Self::ENCRYPTION_CHANGE, "ENCRYPTION_CHANGE"),
+        (Self::OBJECT_ID_CHANGE, "OBJECT_ID_CHANGE"),
+        (Self::REPARSE_POINT_CHANGE, "REPARSE_POINT_CHANGE"),
+        (Self::STREAM_CHANGE, "STREAM_CHANGE"),
+        (Self::TRANSACTED_CHANGE, "TRANSACTED_CHANGE"),
+        (Self::INTEGRITY_CHANGE, "INTEGRITY_CHANGE"),
+        (Self::CLOSE, "CLOSE"),
+    ];
+
+    /// Check whether all bits of `flag` are set
+    pub fn contains(&self, flag: u32) -> bool {
+        self.0 & flag == flag
+    }
+
+    /// Names of the flags that are set, e.g. `["FILE_CREATE", "CLOSE"]`
+    pub fn names(&self) -> Vec<&'static str> {
+        Self::NAMES
+            .iter()
+            .filter(|(flag, _)| self.contains(*flag))
+            .map(|(_, name)| *name)
+            .collect()
+    }
+}
+
+/// A byte range of a file touched by a change (USN_RECORD_V4 only)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UsnExtent {
+    /// Offset in the file
+    pub offset: u64,
+
+    /// Length in bytes
+    pub length: u64,
+}
+
+/// A change journal record
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UsnRecord {
+    /// Update sequence number (offset of the record in `$J`)
+    pub usn: u64,
+
+    /// Record format: 2, 3 or 4
+    pub major_version: u16,
+
+    /// Minor format version
+    pub minor_version: u16,
+
+    /// File that changed
+    pub file: FileReference,
+
+    /// Directory containing the file
+    pub parent: FileReference,
+
+    /// Time of the change (not recorded by version 4)
+    pub timestamp: Option<SystemTime>,
+
+    /// What changed
+    pub reason: UsnReason,
+
+    /// USN_SOURCE_* flags set by the application making the change
+    pub source_info: u32,
+
+    /// Index into $Secure (0 for version 4)
+    pub security_id: u32,
+
+    /// Windows attribute flags of the file (empty for version 4)
+    pub attributes: FileAttributes,
+
+    /// File name, without a path (empty for version 4)
+    pub name: String,
+
+    /// Byte ranges that changed (version 4 only)
+    pub extents: Vec<UsnExtent>,
+}
+
+impl UsnRecord {
+    /// Decode one record; `data` starts at the record and may extend past it
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let length = le_u32(data, 0x00) as usize;
+        let major_version = le_u16(data, 0x04);
+        let minimum_length = match major_version {
+            2 => 0x3C,
+            3 => 0x4C,
+            4 => 0x40,
+            _ => {
+                return Err(SMNtfsError::ReadError(format!(
+                    "Unsupported USN record version {}",
+                    major_version
+                )))
+            }
+        };
+        if length < minimum_length || length > data.len() {
+            return Err(SMNtfsError::ReadError(format!(
+                "Invalid length {} of a version {} USN record",
+                length, major_version
+            )));
+        }
+        let data = &data[..length];
+
+        let mut record = Self {
+            usn: 0,
+            major_version,
+            minor_version: le_u16(data, 0x06),
+            file: FileReference::default(),
+            parent: FileReference::default(),
+            timestamp: None,
+            reason: UsnReason::default(),
+            source_info: 0,
+            security_id: 0,
+            attributes: FileAttributes::default(),
+            name: String::new(),
+            extents: Vec::new(),
+        };
+
+        // Version 2 uses 64-bit file references, later versions 128-bit
+        // file IDs whose upper half is always zero on NTFS
+        let (fixed, name_fields) = match major_version {
+            2 => {
+                record.file = file_reference(le_u64(data, 0x08));
+                record.parent = file_reference(le_u64(data, 0x10));
+                (0x18, Some(0x38))
+            }
+            _ => {
+                record.file = file_reference(le_u64(data, 0x08));
+                record.parent = file_reference(le_u64(data, 0x18));
+                (0x28, (major_version == 3).then_some(0x48))
+            }
+        };
+        record.usn = le_u64(data, fixed);
+
+        match name_fields {
+            Some(name_fields) => {
+                record.timestamp = Some(nt_timestamp_to_system_time(le_u64(data, fixed + 0x08)));
+                record.reason = UsnReason(le_u32(data, fixed + 0x10));
+                record.source_info = le_u32(data, fixed + 0x14);
+                record.security_id = le_u32(data, fixed + 0x18);
+                record.attributes = FileAttributes(le_u32(data, fixed + 0x1C));
+
+                let name_length = le_u16(data, name_fields) as usize;
+                let name_offset = le_u16(data, name_fields + 2) as usize;
+                if name_offset + name_length > length {
+                    return Err(SMNtfsError::ReadError(format!(
+                        "USN record {} has a name past its end",
+                        record.usn
+                    )));
+                }
+                record.name = utf16_string(data, name_offset, name_length / 2);
+            }
+            None => {
+                record.reason = UsnReason(le_u32(data, 0x30));
+                record.source_info = le_u32(data, 0x34);
+
+                let count = le_u16(data, 0x3C) as usize;
+                let size = (le_u16(data, 0x3E) as usize).max(16);
+                record.extents = (0..count)
+                    .map(|i| 0x40 + i * size)
+                    .take_while(|offset| offset + 16 <= length)
+                    .map(|offset| UsnExtent {
+                        offset: le_u64(data, offset),
+                        length: le_u64(data, offset + 8),
+                    })
+                    .collect();
+            }
+        }
+
+        Ok(record)
+    }
+}
+
+/// Limits of the journal, from the `$Max` stream
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct UsnJournalInfo {
+    /// Size the journal is trimmed back to
+    pub maximum_size: u64,
+
+    /// Bytes added or trimmed at a time
+    pub allocation_delta: u64,
+
+    /// Identifier that changes whenever the journal is recreated
+    pub journal_id: u64,
+
+    /// Oldest USN that has not been discarded
+    pub lowest_valid_usn: u64,
+}
+
+impl UsnJournalInfo {
+    /// Decode the value of the `$Max` stream
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        if data.len() < 0x20 {
+            return Err(SMNtfsError::ReadError(format!(
+                "Truncated $UsnJrnl:$Max value of {} bytes",
+                data.len()
+            )));
+        }
+
+        Ok(Self {
+            maximum_size: le_u64(data, 0x00),
+            allocation_delta: le_u64(data, 0x08),
+            journal_id: le_u64(data, 0x10),
+            lowest_valid_usn: le_u64(data, 0x18),
+        })
+    }
+}
+
+/// Iterator over the records of the change journal, in USN order
+///
+/// Stops at the current end of `$J`; call [`UsnJournal::refresh`] to pick up
+/// records appended since, then keep iterating. Damaged pages are skipped.
+pub struct UsnJournal<'a, T: Read + Seek> {
+    ntfs: Option<&'a Ntfs>,
+    record_number: u64,
+    reader: StreamReader<'a, T>,
+    cluster_size: u64,
+    info: UsnJournalInfo,
+    next_usn: u64,
+    page: Vec<u8>,
+    page_start: u64,
+    skipped: u64,
+}
+
+impl<'a, T: Read + Seek> UsnJournal<'a, T> {
+    /// Open the journal from the $UsnJrnl file, positioned at its oldest record
+    pub fn open(file: &NtfsFile<'a>, fs: &'a mut T) -> Result<Self> {
+        let (stream, info) = read_journal_streams(file, fs)?;
+        let mut journal = Self::from_stream(fs, stream, info, file.ntfs().cluster_size());
+        journal.ntfs = Some(file.ntfs());
+        journal.record_number = file.file_record_number();
+        Ok(journal)
+    }
+
+    /// Read an already collected `$J` stream
+    ///
+    /// A journal created this way cannot [`refresh`](Self::refresh) itself;
+    /// use [`update_stream`](Self::update_stream) instead.
+    pub fn from_stream(fs: &'a mut T, stream: AttributeStream, info: UsnJournalInfo, cluster_size: u32) -> Self {
+        Self {
+            ntfs: None,
+            record_number: 0,
+            reader: StreamReader::new(fs, stream, cluster_size),
+            cluster_size: cluster_size as u64,
+            info,
+            next_usn: info.lowest_valid_usn,
+            page: Vec::new(),
+            page_start: 0,
+            skipped: 0,
+        }
+    }
+
+    /// Journal limits and identifier
+    pub fn info(&self) -> &UsnJournalInfo {
+        &self.info
+    }
+
+    /// USN the next record will be read from; save it to resume later
+    pub fn next_usn(&self) -> u64 {
+        self.next_usn
+    }
+
+    /// USN the next appended record will get
+    pub fn end_usn(&self) -> u64 {
+        self.reader.len()
+    }
+
+    /// Number of damaged pages skipped so far
+    pub fn skipped(&self) -> u64 {
+        self.skipped
+    }
+
+    /// Continue reading at `usn`
+    ///
+    /// Fails with [`SMNtfsError::UsnUnavailable`] if records from `usn` on
+    /// have already been discarded, in which case changes may have been
+    /// missed and a full scan is needed.
+    pub fn seek_usn(&mut self, usn: u64) -> Result<()> {
+        if usn < self.info.lowest_valid_usn {
+            return Err(SMNtfsError::UsnUnavailable {
+                requested: usn,
+                lowest_valid: self.info.lowest_valid_usn,
+            });
+        }
+        self.next_usn = usn.next_multiple_of(8);
+        Ok(())
+    }
+
+    /// Re-read the journal from disk to pick up appended records
+    ///
+    /// Returns whether there are unread records. Fails if the journal was
+    /// deleted and recreated, since USNs from the old journal mean nothing
+    /// in the new one.
+    pub fn refresh(&mut self) -> Result<bool> {
+        let Some(ntfs) = self.ntfs else {
+            return Ok(self.has_unread_records());
+        };
+
+        let fs = self.reader.get_mut();
+        let file = ntfs
+            .file(fs, self.record_number)
+            .map_err(|e| SMNtfsError::ReadError(format!("Failed to reread $UsnJrnl: {}", e)))?;
+        let (stream, info) = read_journal_streams(&file, fs)?;
+
+        if info.journal_id != self.info.journal_id {
+            return Err(SMNtfsError::ReadError(format!(
+                "Change journal was recreated (ID {:#x}, previously {:#x})",
+                info.journal_id, self.info.journal_id
+            )));
+        }
+
+        self.info = info;
+        self.next_usn = self.next_usn.max(info.lowest_valid_usn);
+        Ok(self.update_stream(stream))
+    }
+
+    /// Replace the `$J` stream with a newer copy; returns whether there are unread records
+    pub fn update_stream(&mut self, stream: AttributeStream) -> bool {
+        self.reader.set_stream(stream);
+        // The last page may have been partially written when it was cached
+        self.page.clear();
+        self.has_unread_records()
+    }
+
+    fn has_unread_records(&self) -> bool {
+        self.next_usn < self.end_usn()
+    }
+
+    /// Load the page starting at `page_start`
+    ///
+    /// Returns `false` without reading if it lies in a sparse run, after
+    /// moving past the run.
+    fn load_page(&mut self, page_start: u64) -> Result<bool> {
+        let stream = self.reader.stream();
+        if page_start >= stream.initialized_size {
+            self.next_usn = self.end_usn();
+            return Ok(false);
+        }
+        if let Some(run) = stream.run_at(page_start / self.cluster_size).filter(|run| run.is_sparse()) {
+            self.next_usn = self.next_usn.max(run.end_vcn() * self.cluster_size);
+            return Ok(false);
+        }
+
+        let length = USN_PAGE_SIZE.min(self.end_usn() - page_start) as usize;
+        self.page.clear();
+        self.page.resize(length, 0);
+        let read = self
+            .reader
+            .seek(SeekFrom::Start(page_start))
+            .and_then(|_| self.reader.read_exact(&mut self.page));
+        if let Err(e) = read {
+            self.page.clear();
+            return Err(e.into());
+        }
+
+        self.page_start = page_start;
+        Ok(true)
+    }
+
+    /// Give up on the rest of the current page
+    fn skip_page(&mut self, page_start: u64, reason: &str) {
+        tracing::warn!("Skipping change journal page at USN {}: {}", page_start, reason);
+        self.skipped += 1;
+        self.next_usn = page_start + USN_PAGE_SIZE;
+    }
+}
+
+impl<T: Read + Seek> Iterator for UsnJournal<'_, T> {
+    type Item = UsnRecord;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.has_unread_records() {
+            let page_start = self.next_usn - self.next_usn % USN_PAGE_SIZE;
+            if self.page.is_empty() || self.page_start != page_start {
+                match self.load_page(page_start) {
+                    Ok(true) => {}
+                    Ok(false) => continue,
+                    Err(e) => {
+                        self.skip_page(page_start, &e.to_string());
+                        continue;
+                    }
+                }
+            }
+
+            let offset = (self.next_usn - page_start) as usize;
+            let length = le_u32(&self.page, offset) as usize;
+
+            // Zero padding up to the end of the page
+            if length == 0 {
+                self.next_usn = page_start + USN_PAGE_SIZE;
+                continue;
+            }
+            if length % 8 != 0 || offset + length > self.page.len() {
+                self.skip_page(page_start, &format!("invalid record length {}", length));
+                continue;
+            }
+
+            match UsnRecord::parse(&self.page[offset..offset + length]) {
+                Ok(record) => {
+                    self.next_usn += length as u64;
+                    return Some(record);
+                }
+                Err(e) => self.skip_page(page_start, &e.to_string()),
+            }
+        }
+
+        None
+    }
+}
+
+/// Collect the `$J` stream and decode `$Max` of the $UsnJrnl file
+fn read_journal_streams<T: Read + Seek>(file: &NtfsFile, fs: &mut T) -> Result<(AttributeStream, UsnJournalInfo)> {
+    let missing = |name: &str| SMNtfsError::ReadError(format!("$UsnJrnl has no {} stream", name));
+
+    let max = AttributeStream::collect(file, fs, NtfsAttributeType::Data, USN_MAX_STREAM)?
+        .ok_or_else(|| missing(USN_MAX_STREAM))?;
+    let info = UsnJournalInfo::parse(&StreamReader::new(fs, max, file.ntfs().cluster_size()).read_all()?)?;
+    let stream = AttributeStream::collect(file, fs, NtfsAttributeType::Data, USN_DATA_STREAM)?
+        .ok_or_else(|| missing(USN_DATA_STREAM))?;
+
+    Ok((stream, info))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::runs::DataRun;
+    use std::io::Cursor;
+
+    fn v2_record(usn: u64, file: u64, reason: u32, name: &str) -> Vec<u8> {
+        let name: Vec<u16> = name.encode_utf16().collect();
+        let length = (0x3C + name.len() * 2 + 7) & !7;
+        let mut data = vec![0u8; length];
+        data[0..4].copy_from_slice(&(length as u32).to_le_bytes());
+        data[4..6].copy_from_slice(&2u16.to_le_bytes());
+        data[0x08..0x10].copy_from_slice(&(file | (3 << 48)).to_le_bytes());
+        data[0x10..0x18].copy_from_slice(&5u64.to_le_bytes());
+        data[0x18..0x20].copy_from_slice(&usn.to_le_bytes());
+        data[0x20..0x28].copy_from_slice(&132_539_328_000_000_000u64.to_le_bytes());
+        data[0x28..0x2C].copy_from_slice(&reason.to_le_bytes());
+        data[0x38..0x3A].copy_from_slice(&((name.len() * 2) as u16).to_le_bytes());
+        data[0x3A..0x3C].copy_from_slice(&0x3Cu16.to_le_bytes());
+        for (i, unit) in name.iter().enumerate() {
+            data[0x3C + i * 2..0x3E + i * 2].copy_from_slice(&unit.to_le_bytes());
+        }
+        data
+    }
+
+    /// A 20 KiB journal whose first two pages have been discarded
+    fn journal_device() -> (Cursor<Vec<u8>>, AttributeStream, UsnJournalInfo) {
+        let mut device = vec![0u8; 0x2000];
+
+        let first = v2_record(0x2000, 40, UsnReason::FILE_CREATE, "a.txt");
+        let second = v2_record(0x2000 + first.len() as u64, 41, UsnReason::CLOSE, "b.txt");
+        device[..first.len()].copy_from_slice(&first);
+        device[first.len()..first.len() + second.len()].copy_from_slice(&second);
+
+        // Next page: garbage, then a record on the last page
+        device[0x1000..0x1004].copy_from_slice(&13u32.to_le_bytes());
+        let last = v2_record(0x4000, 42, UsnReason::FILE_DELETE | UsnReason::CLOSE, "c.txt");
+        device.extend(vec![0u8; 0x1000]);
+        device[0x2000..0x2000 + last.len()].copy_from_slice(&last);
+
+        let stream = AttributeStream {
+            runs: vec![
+                DataRun { vcn: 0, lcn: None, length: 2 },
+                DataRun { vcn: 2, lcn: Some(0), length: 3 },
+            ],
+            data_size: 0x4000 + last.len() as u64,
+            initialized_size: 0x4000 + last.len() as u64,
+            ..AttributeStream::default()
+        };
+        let info = UsnJournalInfo {
+            lowest_valid_usn: 0x2000,
+            journal_id: 0x1D0,
+            ..UsnJournalInfo::default()
+        };
+        (Cursor::new(device), stream, info)
+    }
+
+    #[test]
+    fn test_parse_v2_record() {
+        let data = v2_record(0x2000, 40, UsnReason::FILE_CREATE | UsnReason::CLOSE, "a.txt");
+        let record = UsnRecord::parse(&data).unwrap();
+
+        assert_eq!(record.usn, 0x2000);
+        assert_eq!(record.file, FileReference { record_number: 40, sequence_number: 3 });
+        assert_eq!(record.parent.record_number, 5);
+        assert_eq!(record.name, "a.txt");
+        assert_eq!(record.reason.names(), vec!["FILE_CREATE", "CLOSE"]);
+        assert!(record.timestamp.is_some());
+    }
+
+    #[test]
+    fn test_parse_v3_and_v4_records() {
+        let mut v3 = vec![0u8; 0x58];
+        v3[0..4].copy_from_slice(&0x58u32.to_le_bytes());
+        v3[4..6].copy_from_slice(&3u16.to_le_bytes());
+        v3[0x08..0x10].copy_from_slice(&77u64.to_le_bytes());
+        v3[0x18..0x20].copy_from_slice(&5u64.to_le_bytes());
+        v3[0x28..0x30].copy_from_slice(&4096u64.to_le_bytes());
+        v3[0x38..0x3C].copy_from_slice(&UsnReason::RENAME_NEW_NAME.to_le_bytes());
+        v3[0x48..0x4A].copy_from_slice(&4u16.to_le_bytes());
+        v3[0x4A..0x4C].copy_from_slice(&0x4Cu16.to_le_bytes());
+        v3[0x4C..0x50].copy_from_slice(&[b'x', 0, b'y', 0]);
+        let record = UsnRecord::parse(&v3).unwrap();
+        assert_eq!(record.file.record_number, 77);
+        assert_eq!(record.usn, 4096);
+        assert_eq!(record.name, "xy");
+        assert!(record.reason.contains(UsnReason::RENAME_NEW_NAME));
+
+        let mut v4 = vec![0u8; 0x60];
+        v4[0..4].copy_from_slice(&0x60u32.to_le_bytes());
+        v4[4..6].copy_from_slice(&4u16.to_le_bytes());
+        v4[0x08..0x10].copy_from_slice(&77u64.to_le_bytes());
+        v4[0x28..0x30].copy_from_slice(&8192u64.to_le_bytes());
+        v4[0x30..0x34].copy_from_slice(&UsnReason::DATA_OVERWRITE.to_le_bytes());
+        v4[0x3C..0x3E].copy_from_slice(&2u16.to_le_bytes());
+        v4[0x3E..0x40].copy_from_slice(&16u16.to_le_bytes());
+        v4[0x50..0x58].copy_from_slice(&65536u64.to_le_bytes());
+        v4[0x58..0x60].copy_from_slice(&4096u64.to_le_bytes());
+        let record = UsnRecord::parse(&v4).unwrap();
+        assert_eq!(record.usn, 8192);
+        assert!(record.timestamp.is_none());
+        assert_eq!(record.extents.len(), 2);
+        assert_eq!(record.extents[1], UsnExtent { offset: 65536, length: 4096 });
+
+        v4[4] = 9;
+        assert!(UsnRecord::parse(&v4).is_err());
+    }
+
+    #[test]
+    fn test_read_journal() {
+        let (mut device, stream, info) = journal_device();
+        let mut journal = UsnJournal::from_stream(&mut device, stream, info, 4096);
+
+        let names: Vec<String> = journal.by_ref().map(|r| r.name).collect();
+        assert_eq!(names, vec!["a.txt", "b.txt", "c.txt"]);
+        assert_eq!(journal.skipped(), 1);
+        assert_eq!(journal.next_usn(), journal.end_usn());
+    }
+
+    #[test]
+    fn test_seek_usn() {
+        let (mut device, stream, info) = journal_device();
+        let mut journal = UsnJournal::from_stream(&mut device, stream, info, 4096);
+
+        assert!(matches!(journal.seek_usn(0x1000), Err(SMNtfsError::UsnUnavailable { .. })));
+        journal.seek_usn(0x4000).unwrap();
+        assert_eq!(journal.next().map(|r| r.file.record_number), Some(42));
+    }
+
+    #[test]
+    fn test_sparse_region_is_skipped() {
+        let (mut device, stream, mut info) = journal_device();
+        info.lowest_valid_usn = 0;
+        let mut journal = UsnJournal::from_stream(&mut device, stream, info, 4096);
+
+        assert_eq!(journal.next().map(|r| r.usn), Some(0x2000));
+    }
+
+    #[test]
+    fn test_follow_appended_records() {
+        let (mut device, mut stream, info) = journal_device();
+        let end = stream.data_size;
+        let appended = v2_record(end, 43, UsnReason::FILE_CREATE, "d.txt");
+        let offset = (end - 0x2000) as usize;
+        device.get_mut()[offset..offset + appended.len()].copy_from_slice(&appended);
+
+        let mut journal = UsnJournal::from_stream(&mut device, stream.clone(), info, 4096);
+        assert_eq!(journal.by_ref().count(), 3);
+        assert!(!journal.update_stream(stream.clone()));
+
+        stream.data_size += appended.len() as u64;
+        stream.initialized_size = stream.data_size;
+        assert!(journal.update_stream(stream));
+        assert_eq!(journal.next().map(|r| r.name), Some("d.txt".to_string()));
+        assert_eq!(journal.next(), None);
+    }
+}
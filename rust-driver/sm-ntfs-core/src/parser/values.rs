@@ -0,0 +1,166 @@
+//! Raw decoding of structured attribute values
+//!
+//! Used where the `ntfs` crate cannot be: records that are not in use,
+//! extension records, and values read from a [`FileRecord`](crate::parser::FileRecord).
+
+use crate::parser::mft::{FileAttributes, FileReference};
+use crate::parser::time::{nt_timestamp_to_system_time, FileTimes};
+use crate::utils::bytes::{le_u32, le_u64, le_u8, utf16_string};
+use crate::utils::error::{Result, SMNtfsError};
+
+/// Split a raw 64-bit file reference into record and sequence numbers
+pub fn file_reference(raw: u64) -> FileReference {
+    FileReference {
+        record_number: raw & 0x0000_FFFF_FFFF_FFFF,
+        sequence_number: (raw >> 48) as u16,
+    }
+}
+
+/// Read four consecutive NT timestamps
+fn file_times(data: &[u8], offset: usize) -> FileTimes {
+    FileTimes {
+        created: nt_timestamp_to_system_time(le_u64(data, offset)),
+        modified: nt_timestamp_to_system_time(le_u64(data, offset + 8)),
+        mft_modified: nt_timestamp_to_system_time(le_u64(data, offset + 16)),
+        accessed: nt_timestamp_to_system_time(le_u64(data, offset + 24)),
+    }
+}
+
+/// $FILE_NAME namespaces
+pub mod namespace {
+    /// Case-sensitive POSIX name
+    pub const POSIX: u8 = 0;
+    /// Long Windows name
+    pub const WIN32: u8 = 1;
+    /// Short 8.3 name
+    pub const DOS: u8 = 2;
+    /// Name that is valid in both the Win32 and DOS namespaces
+    pub const WIN32_AND_DOS: u8 = 3;
+}
+
+/// A decoded $FILE_NAME value
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileNameValue {
+    /// Directory containing this name
+    pub parent: FileReference,
+
+    /// Timestamps, as of the last time the name was updated
+    pub times: FileTimes,
+
+    /// Allocated size of the default stream (often stale)
+    pub allocated_size: u64,
+
+    /// Size of the default stream (often stale)
+    pub data_size: u64,
+
+    /// Windows attribute flags
+    pub attributes: FileAttributes,
+
+    /// File name
+    pub name: String,
+
+    /// Namespace, see [`namespace`]
+    pub namespace: u8,
+}
+
+impl FileNameValue {
+    /// Decode a $FILE_NAME value
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let name_length = le_u8(data, 0x40) as usize;
+        if data.len() < 0x42 + name_length * 2 {
+            return Err(SMNtfsError::ReadError(format!(
+                "Truncated $FILE_NAME value of {} bytes",
+                data.len()
+            )));
+        }
+
+        Ok(Self {
+            parent: file_reference(le_u64(data, 0x00)),
+            times: file_times(data, 0x08),
+            allocated_size: le_u64(data, 0x28),
+            data_size: le_u64(data, 0x30),
+            attributes: FileAttributes(le_u32(data, 0x38)),
+            name: utf16_string(data, 0x42, name_length),
+            namespace: le_u8(data, 0x41),
+        })
+    }
+
+    /// Check if this is a short 8.3 alias only
+    pub fn is_dos_only(&self) -> bool {
+        self.namespace == namespace::DOS
+    }
+}
+
+/// A decoded $STANDARD_INFORMATION value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StandardInformationValue {
+    /// Timestamps
+    pub times: FileTimes,
+
+    /// Windows attribute flags
+    pub attributes: FileAttributes,
+
+    /// Index into $Secure (NTFS 3.0+, 0 otherwise)
+    pub security_id: u32,
+}
+
+impl StandardInformationValue {
+    /// Decode a $STANDARD_INFORMATION value
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        if data.len() < 0x30 {
+            return Err(SMNtfsError::ReadError(format!(
+                "Truncated $STANDARD_INFORMATION value of {} bytes",
+                data.len()
+            )));
+        }
+
+        Ok(Self {
+            times: file_times(data, 0x00),
+            attributes: FileAttributes(le_u32(data, 0x20)),
+            security_id: if data.len() >= 0x38 { le_u32(data, 0x34) } else { 0 },
+        })
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Build a $FILE_NAME value
+    pub(crate) fn file_name_value(parent: u64, name: &str, namespace: u8) -> Vec<u8> {
+        let name: Vec<u16> = name.encode_utf16().collect();
+        let mut data = vec![0u8; 0x42 + name.len() * 2];
+        data[0..8].copy_from_slice(&parent.to_le_bytes());
+        data[0x30..0x38].copy_from_slice(&1234u64.to_le_bytes());
+        data[0x40] = name.len() as u8;
+        data[0x41] = namespace;
+        for (i, unit) in name.iter().enumerate() {
+            data[0x42 + i * 2..0x44 + i * 2].copy_from_slice(&unit.to_le_bytes());
+        }
+        data
+    }
+
+    #[test]
+    fn test_parse_file_name() {
+        let data = file_name_value(5 | (5 << 48), "report.docx", namespace::WIN32);
+        let value = FileNameValue::parse(&data).unwrap();
+
+        assert_eq!(value.parent, FileReference { record_number: 5, sequence_number: 5 });
+        assert_eq!(value.name, "report.docx");
+        assert_eq!(value.data_size, 1234);
+        assert!(!value.is_dos_only());
+        assert!(FileNameValue::parse(&data[..0x44]).is_err());
+    }
+
+    #[test]
+    fn test_parse_standard_information() {
+        let mut data = vec![0u8; 0x48];
+        data[0x20..0x24].copy_from_slice(&FileAttributes::HIDDEN.to_le_bytes());
+        data[0x34..0x38].copy_from_slice(&0x100u32.to_le_bytes());
+        let value = StandardInformationValue::parse(&data).unwrap();
+
+        assert!(value.attributes.is_hidden());
+        assert_eq!(value.security_id, 0x100);
+        assert!(StandardInformationValue::parse(&data[..0x20]).is_err());
+    }
+}
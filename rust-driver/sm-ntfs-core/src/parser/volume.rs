@@ -1,8 +1,28 @@
 //! NTFS Volume operations and wrapper
 
-use ntfs::{Ntfs, NtfsFile};
-use std::io::{Read, Seek, SeekFrom};
+use ntfs::{Ntfs, NtfsAttributeType, NtfsFile};
+use std::io::{Read, Seek, SeekFrom, Write};
 use crate::io::BlockDevice;
+use crate::ntfs::journal::{self, ReplaySummary};
+use crate::ntfs::repair::{repair, RepairSummary};
+use crate::parser::bitmap::ClusterBitmap;
+use crate::parser::boot::BootSector;
+use crate::parser::check::{check_volume, CheckReport};
+use crate::parser::attribute::AttributeStream;
+use crate::parser::ea::{read_extended_attributes, ExtendedAttribute, WslMetadata};
+use crate::parser::hibernation;
+use crate::parser::links::{hard_link_paths, hard_links, HardLink};
+use crate::parser::logfile::{LogFile, LogFileReport};
+use crate::parser::path::NtfsPath;
+use crate::parser::reader::StreamReader;
+use crate::parser::reparse::{read_reparse_point, ReparsePoint};
+use crate::parser::scan::MftRecords;
+use crate::parser::secure::{file_security_descriptor, SecureFile};
+use crate::parser::security::SecurityDescriptor;
+use crate::parser::undelete::{find_deleted_files, DeletedFile};
+use crate::parser::upcase::UpcaseTable;
+use crate::parser::usn::{UsnJournal, USN_JOURNAL_PATH};
+use crate::parser::volume_info::VolumeInformation;
 use crate::utils::error::{Result, SMNtfsError};
 
 /// Wrapper around ntfs::Ntfs for easier volume operations
@@ -10,6 +30,9 @@ pub struct NtfsVolume<'n> {
     ntfs: &'n Ntfs,
     volume_name: String,
     serial_number: u64,
+    boot_sector: Option<BootSector>,
+    volume_information: Option<VolumeInformation>,
+    upcase: UpcaseTable,
 }
 
 impl<'n> NtfsVolume<'n> {
@@ -25,16 +48,35 @@ impl<'n> NtfsVolume<'n> {
         // Serial number is directly available
         let serial_number = ntfs.serial_number();
 
+        // The ntfs crate does not expose the MFT mirror or index record size.
+        // None of these are needed to read files, so damage only disables
+        // what depends on them.
+        let boot_sector = BootSector::read(fs)
+            .map_err(|e| tracing::warn!("Boot sector not decoded, checks and repairs are disabled: {}", e))
+            .ok();
+        let volume_information = VolumeInformation::read(ntfs, fs)
+            .map_err(|e| tracing::warn!("NTFS version and volume flags are unknown: {}", e))
+            .ok();
+        let upcase = UpcaseTable::load(ntfs, fs).unwrap_or_else(|e| {
+            tracing::warn!("Comparing names with the Unicode uppercase mapping of the host: {}", e);
+            UpcaseTable::host()
+        });
+
         tracing::info!(
-            "NTFS Volume initialized - Name: '{}', Serial: 0x{:X}",
+            "NTFS Volume initialized - Name: '{}', Serial: 0x{:X}, Version: {}, Flags: {:?}",
             volume_name,
-            serial_number
+            serial_number,
+            volume_information.map_or_else(|| "unknown".to_string(), |info| info.version()),
+            volume_information.map(|info| info.flags.names())
         );
 
         Ok(Self {
             ntfs,
             volume_name,
             serial_number,
+            boot_sector,
+            volume_information,
+            upcase,
         })
     }
 
@@ -45,6 +87,198 @@ impl<'n> NtfsVolume<'n> {
             .map_err(|e| SMNtfsError::InvalidNtfs(format!("Failed to get root directory: {}", e)))
     }
 
+    /// Resolve a full path to a file and an optional stream name
+    ///
+    /// Each component is looked up in its parent's $I30 index, compared
+    /// case-insensitively through the $UpCase table read when the volume was
+    /// opened. Both `/` and `\` are accepted as separators, and the last
+    /// component may carry a `file:stream` suffix, whose name is compared
+    /// the same way and checked to exist.
+    pub fn resolve_path<T: Read + Seek>(&self, fs: &mut T, path: &str) -> Result<ResolvedPath<'n>> {
+        let parsed = NtfsPath::parse(path)?;
+        let mut file = self.root_directory(fs)?;
+        let mut parent_record_number = None;
+        let mut previous = "/";
+
+        for component in &parsed.components {
+            if !file.is_directory() {
+                return Err(SMNtfsError::NotADirectory {
+                    path: path.to_string(),
+                    component: previous.to_string(),
+                });
+            }
+
+            let next = {
+                let index = file
+                    .directory_index(fs)
+                    .map_err(|e| SMNtfsError::ReadError(format!("Failed to get directory index of '{}': {}", previous, e)))?;
+                let mut finder = index.finder();
+
+                let entry = finder
+                    .find(fs, |key| {
+                        self.upcase.compare(component.encode_utf16(), key.name().u16_iter())
+                    })
+                    .ok_or_else(|| SMNtfsError::PathNotFound {
+                        path: path.to_string(),
+                        component: component.to_string(),
+                    })?
+                    .map_err(|e| SMNtfsError::ReadError(format!("Failed to look up '{}': {}", component, e)))?;
+
+                entry
+                    .to_file(self.ntfs, fs)
+                    .map_err(|e| SMNtfsError::ReadError(format!("Failed to open '{}': {}", component, e)))?
+            };
+
+            parent_record_number = Some(file.file_record_number());
+            file = next;
+            previous = component;
+        }
+
+        if !parsed.stream_name.is_empty()
+            && AttributeStream::collect_ignore_case(&file, fs, NtfsAttributeType::Data, parsed.stream_name, &self.upcase)?
+                .is_none()
+        {
+            return Err(SMNtfsError::StreamNotFound {
+                path: path.to_string(),
+                stream: parsed.stream_name.to_string(),
+            });
+        }
+
+        Ok(ResolvedPath {
+            file,
+            stream_name: parsed.stream_name.to_string(),
+            parent_record_number,
+        })
+    }
+
+    /// Resolve a path to a file, ignoring any stream suffix
+    pub fn open_path<T: Read + Seek>(&self, fs: &mut T, path: &str) -> Result<NtfsFile<'n>> {
+        self.resolve_path(fs, path).map(|resolved| resolved.file)
+    }
+
+    /// Resolve a path and open its data stream for streaming reads
+    ///
+    /// The stream is taken from a `file:stream` suffix, defaulting to the
+    /// unnamed $DATA stream. The reader borrows `fs` until it is dropped.
+    pub fn open_stream<'a, T: Read + Seek>(&self, fs: &'a mut T, path: &str) -> Result<StreamReader<'a, T>> {
+        let resolved = self.resolve_path(fs, path)?;
+        StreamReader::open(&resolved.file, fs, &resolved.stream_name, &self.upcase)
+    }
+
+    /// Iterate over every record of the MFT, including deleted ones
+    pub fn mft_records<'a, T: Read + Seek>(&self, fs: &'a mut T) -> Result<MftRecords<'a, T>> {
+        MftRecords::new(self.ntfs, fs)
+    }
+
+    /// Load the cluster allocation bitmap, for free space and allocation
+    pub fn cluster_bitmap<T: Read + Seek>(&self, fs: &mut T) -> Result<ClusterBitmap> {
+        ClusterBitmap::load(self.ntfs, fs)
+    }
+
+    /// Every name of a file, including DOS-only aliases
+    pub fn hard_links<T: Read + Seek>(&self, fs: &mut T, file: &NtfsFile) -> Result<Vec<HardLink>> {
+        hard_links(file, fs)
+    }
+
+    /// Full path of every hard link of a file
+    pub fn hard_link_paths<T: Read + Seek>(&self, fs: &mut T, file: &NtfsFile) -> Result<Vec<String>> {
+        hard_link_paths(self.ntfs, file, fs)
+    }
+
+    /// Read the reparse point of a file (symlink, junction, WSL special file, ...)
+    pub fn reparse_point<T: Read + Seek>(&self, fs: &mut T, file: &NtfsFile) -> Result<Option<ReparsePoint>> {
+        read_reparse_point(self.ntfs, file, fs)
+    }
+
+    /// Read the extended attributes of a file
+    pub fn extended_attributes<T: Read + Seek>(&self, fs: &mut T, file: &NtfsFile) -> Result<Vec<ExtendedAttribute>> {
+        read_extended_attributes(self.ntfs, file, fs)
+    }
+
+    /// Read the POSIX metadata WSL stored for a file, if any
+    pub fn wsl_metadata<T: Read + Seek>(&self, fs: &mut T, file: &NtfsFile) -> Result<Option<WslMetadata>> {
+        Ok(WslMetadata::from_attributes(&self.extended_attributes(fs, file)?))
+    }
+
+    /// Load the shared security descriptors of $Secure
+    pub fn secure_file<T: Read + Seek>(&self, fs: &mut T) -> Result<SecureFile> {
+        SecureFile::load(self.ntfs, fs)
+    }
+
+    /// Resolve the security descriptor of a file, see [`file_security_descriptor`]
+    pub fn security_descriptor<T: Read + Seek>(
+        &self,
+        fs: &mut T,
+        file: &NtfsFile,
+        secure: &SecureFile,
+    ) -> Result<Option<SecurityDescriptor>> {
+        file_security_descriptor(self.ntfs, file, fs, secure)
+    }
+
+    /// Check the volume for inconsistencies without modifying it
+    pub fn check<T: Read + Seek>(&self, fs: &mut T) -> Result<CheckReport> {
+        check_volume(self.ntfs, self.require_boot_sector()?, fs)
+    }
+
+    /// Apply the safe fixes of a check report and check again
+    ///
+    /// `fs` must be writable, opened with
+    /// [`open_volume_writable`](crate::parser::open_volume_writable); wrap it
+    /// in an [`UndoDevice`](crate::io::UndoDevice) to be able to roll the
+    /// repair back.
+    pub fn repair<T: Read + Write + Seek>(&self, fs: &mut T, report: &CheckReport) -> Result<RepairSummary> {
+        repair(self.ntfs, self.require_boot_sector()?, fs, report)
+    }
+
+    /// Find deleted files whose MFT records are still intact
+    pub fn deleted_files<T: Read + Seek>(&self, fs: &mut T) -> Result<Vec<DeletedFile>> {
+        find_deleted_files(self.ntfs, fs)
+    }
+
+    /// Open the change journal, positioned at its oldest record
+    ///
+    /// Fails with [`SMNtfsError::PathNotFound`] if the journal is not enabled.
+    pub fn usn_journal<'a, T: Read + Seek>(&self, fs: &'a mut T) -> Result<UsnJournal<'a, T>>
+    where
+        'n: 'a,
+    {
+        let file = self.open_path(fs, USN_JOURNAL_PATH)?;
+        UsnJournal::open(&file, fs)
+    }
+
+    /// Report the transactions $LogFile still has to redo or undo
+    pub fn logfile_report<T: Read + Seek>(&self, fs: &mut T) -> Result<LogFileReport> {
+        LogFile::open(self.ntfs, fs)?.report()
+    }
+
+    /// Replay $LogFile and mark it clean
+    ///
+    /// `fs` must be writable, opened with
+    /// [`open_volume_writable`](crate::parser::open_volume_writable). The
+    /// volume should be reopened afterwards, as anything read before the
+    /// replay may be out of date.
+    pub fn replay_journal<T: Read + Write + Seek>(&self, fs: &mut T) -> Result<ReplaySummary> {
+        journal::replay(self.ntfs, self.require_boot_sector()?, fs)
+    }
+
+    /// Check if a hibernated Windows, or one shut down with Fast Startup,
+    /// may resume on the volume
+    pub fn is_hibernated<T: Read + Seek>(&self, fs: &mut T) -> Result<bool> {
+        hibernation::is_hibernated(self, fs)
+    }
+
+    /// Refuse writing while Windows is hibernated, unless `remove_hiberfile`
+    /// asks for its hibernation file to be discarded
+    pub fn ensure_not_hibernated<T: Read + Write + Seek>(&self, fs: &mut T, remove_hiberfile: bool) -> Result<()> {
+        hibernation::ensure_not_hibernated(self, fs, remove_hiberfile)
+    }
+
+    /// Refuse writing while Windows is hibernated or $LogFile has
+    /// transactions to apply, see [`hibernation::ensure_writable`]
+    pub fn ensure_writable<T: Read + Write + Seek>(&self, fs: &mut T, remove_hiberfile: bool) -> Result<()> {
+        hibernation::ensure_writable(self, fs, remove_hiberfile)
+    }
+
     /// Get volume serial number
     pub fn serial_number(&self) -> u64 {
         self.serial_number
@@ -69,6 +303,69 @@ impl<'n> NtfsVolume<'n> {
     pub fn size(&self) -> u64 {
         self.ntfs.size()
     }
+
+    /// $UpCase table names are compared through, or the host's mapping if
+    /// $UpCase could not be read
+    pub fn upcase_table(&self) -> &UpcaseTable {
+        &self.upcase
+    }
+
+    /// Decoded boot sector, `None` if it failed validation
+    pub fn boot_sector(&self) -> Option<&BootSector> {
+        self.boot_sector.as_ref()
+    }
+
+    fn require_boot_sector(&self) -> Result<&BootSector> {
+        self.boot_sector
+            .as_ref()
+            .ok_or_else(|| SMNtfsError::InvalidNtfs("The boot sector could not be decoded".to_string()))
+    }
+
+    /// NTFS version and volume flags, as read when the volume was opened,
+    /// `None` if $Volume could not be read
+    pub fn volume_information(&self) -> Option<&VolumeInformation> {
+        self.volume_information.as_ref()
+    }
+
+    /// Check if the volume was not cleanly unmounted by Windows
+    ///
+    /// Returns false if $Volume could not be read.
+    pub fn is_dirty(&self) -> bool {
+        self.volume_information.is_some_and(|info| info.is_dirty())
+    }
+
+    /// Byte offset of the MFT
+    pub fn mft_offset(&self) -> u64 {
+        self.ntfs.mft_position().value().map_or(0, |position| position.get())
+    }
+
+    /// Byte offset of $MFTMirr, `None` if the boot sector could not be decoded
+    pub fn mft_mirror_offset(&self) -> Option<u64> {
+        self.boot_sector.map(|boot_sector| boot_sector.mft_mirror_offset())
+    }
+
+    /// Get MFT record size
+    pub fn file_record_size(&self) -> u32 {
+        self.ntfs.file_record_size()
+    }
+
+    /// Get index record (INDX block) size, `None` if the boot sector could
+    /// not be decoded
+    pub fn index_record_size(&self) -> Option<u32> {
+        self.boot_sector.map(|boot_sector| boot_sector.index_record_size)
+    }
+}
+
+/// Result of [`NtfsVolume::resolve_path`]
+pub struct ResolvedPath<'n> {
+    /// The file or directory the path points to
+    pub file: NtfsFile<'n>,
+
+    /// Stream named with `file:stream` syntax (empty for the default stream)
+    pub stream_name: String,
+
+    /// Record number of the directory the file was found in (`None` for the root)
+    pub parent_record_number: Option<u64>,
 }
 
 /// Helper struct to adapt BlockDevice to NtfsReadSeek
@@ -101,7 +398,7 @@ impl Read for BlockDeviceAdapter {
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
         let data = self.device
             .read_at(self.position, buf.len())
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e.to_string()))?;
+            .map_err(|e| std::io::Error::other(e.to_string()))?;
 
         let bytes_read = data.len().min(buf.len());
         buf[..bytes_read].copy_from_slice(&data[..bytes_read]);
@@ -111,6 +408,23 @@ impl Read for BlockDeviceAdapter {
     }
 }
 
+impl Write for BlockDeviceAdapter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.device
+            .write_at(self.position, buf)
+            .map_err(|e| std::io::Error::other(e.to_string()))?;
+
+        self.position += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.device
+            .flush()
+            .map_err(|e| std::io::Error::other(e.to_string()))
+    }
+}
+
 impl Seek for BlockDeviceAdapter {
     fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
         let new_position = match pos {
@@ -138,10 +452,101 @@ impl Seek for BlockDeviceAdapter {
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
-    use std::io::Write;
+    use std::io::{Cursor, Write};
     use tempfile::NamedTempFile;
+    use crate::parser::recovery::open_volume;
+
+    /// 2 MiB NTFS image made by mkntfs, see testdata/README.md
+    pub(crate) const TEST_IMAGE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/testfs1");
+
+    pub(crate) fn test_image() -> Vec<u8> {
+        std::fs::read(TEST_IMAGE).unwrap()
+    }
+
+    #[test]
+    fn test_resolve_path() {
+        let opened = open_volume(Cursor::new(test_image())).unwrap();
+        let (mut fs, ntfs) = (opened.fs, opened.ntfs);
+        let volume = NtfsVolume::new(&ntfs, &mut fs).unwrap();
+
+        let root = volume.resolve_path(&mut fs, "/").unwrap();
+        assert_eq!(root.file.file_record_number(), 5);
+        assert_eq!(root.parent_record_number, None);
+
+        // Names are compared through $UpCase, with either separator
+        let file = volume.resolve_path(&mut fs, "\\FILE-with-12345").unwrap();
+        assert!(!file.file.is_directory());
+        assert_eq!(file.parent_record_number, Some(5));
+
+        // Deep enough in the B-tree to need $INDEX_ALLOCATION
+        let directory = volume.open_path(&mut fs, "/many_subdirs").unwrap();
+        for name in ["1", "256", "512"] {
+            let resolved = volume.resolve_path(&mut fs, &format!("C:/Many_Subdirs/{}", name)).unwrap();
+            assert!(resolved.file.is_directory());
+            assert_eq!(resolved.parent_record_number, Some(directory.file_record_number()));
+        }
+
+        let mut data = Vec::new();
+        volume.open_stream(&mut fs, "/1000-bytes-file").unwrap().read_to_end(&mut data).unwrap();
+        assert_eq!(data, b"12345".repeat(200));
+    }
+
+    #[test]
+    fn test_resolve_path_errors() {
+        let opened = open_volume(Cursor::new(test_image())).unwrap();
+        let (mut fs, ntfs) = (opened.fs, opened.ntfs);
+        let volume = NtfsVolume::new(&ntfs, &mut fs).unwrap();
+
+        assert!(matches!(
+            volume.resolve_path(&mut fs, "/many_subdirs/513"),
+            Err(SMNtfsError::PathNotFound { component, .. }) if component == "513"
+        ));
+        assert!(matches!(
+            volume.resolve_path(&mut fs, "/empty-file/x"),
+            Err(SMNtfsError::NotADirectory { component, .. }) if component == "empty-file"
+        ));
+        assert!(matches!(
+            volume.resolve_path(&mut fs, "/empty-file:stream"),
+            Err(SMNtfsError::StreamNotFound { stream, .. }) if stream == "stream"
+        ));
+    }
+
+    #[test]
+    fn test_resolve_path_without_ntfs_upcase_table() {
+        // The $UpCase table of the ntfs crate is never needed
+        let mut fs = Cursor::new(test_image());
+        let ntfs = Ntfs::new(&mut fs).unwrap();
+        let volume = NtfsVolume::new(&ntfs, &mut fs).unwrap();
+
+        let file = volume.resolve_path(&mut fs, "/SPARSE-FILE").unwrap();
+        assert_eq!(file.parent_record_number, Some(5));
+    }
+
+    #[test]
+    fn test_damaged_system_files() {
+        let mut image = test_image();
+        // Invalid index record size in the boot sector, and no FILE
+        // signature on the records of $Volume and $UpCase
+        image[0x44] = 0;
+        image[16384 + 3 * 1024..][..4].copy_from_slice(b"BAAD");
+        image[16384 + 10 * 1024..][..4].copy_from_slice(b"BAAD");
+        let mut fs = Cursor::new(image);
+        let ntfs = Ntfs::new(&mut fs).unwrap();
+        let volume = NtfsVolume::new(&ntfs, &mut fs).unwrap();
+
+        assert!(volume.boot_sector().is_none());
+        assert!(volume.volume_information().is_none());
+        assert!(!volume.is_dirty());
+        assert_eq!(volume.mft_offset(), 16384);
+        assert_eq!(volume.index_record_size(), None);
+
+        // Files are still found, with the host's uppercase mapping
+        let file = volume.resolve_path(&mut fs, "/Sparse-File").unwrap();
+        assert_eq!(file.file.file_record_number(), 67);
+        assert!(matches!(volume.check(&mut fs), Err(SMNtfsError::InvalidNtfs(_))));
+    }
 
     #[test]
     fn test_block_device_adapter_read() {
@@ -160,6 +565,26 @@ mod tests {
         assert_eq!(&buf, test_data);
     }
 
+    #[test]
+    fn test_block_device_adapter_write() {
+        let mut temp = NamedTempFile::new().unwrap();
+        temp.write_all(b"0123456789").unwrap();
+        temp.flush().unwrap();
+
+        let device = BlockDevice::open_with_options(temp.path(), false).unwrap();
+        let mut adapter = BlockDeviceAdapter::new(device);
+        adapter.seek(SeekFrom::Start(4)).unwrap();
+        adapter.write_all(b"ab").unwrap();
+
+        let mut buf = vec![0u8; 10];
+        adapter.seek(SeekFrom::Start(0)).unwrap();
+        adapter.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"0123ab6789");
+
+        let mut read_only = BlockDeviceAdapter::new(BlockDevice::open(temp.path()).unwrap());
+        assert!(read_only.write_all(b"x").is_err());
+    }
+
     #[test]
     fn test_block_device_adapter_seek() {
         let mut temp = NamedTempFile::new().unwrap();
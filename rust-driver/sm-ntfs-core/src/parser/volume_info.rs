@@ -0,0 +1,139 @@
+//! $VOLUME_INFORMATION: NTFS version and volume state flags
+//!
+//! The attribute lives in the $Volume file (record 3). Windows sets the
+//! dirty flag while the volume is mounted and clears it on a clean
+//! shutdown, so a volume that is dirty when found here was not unmounted
+//! cleanly and chkdsk will run on it at the next Windows boot.
+
+use std::io::{Read, Seek};
+use ntfs::Ntfs;
+use crate::utils::bytes::{le_u16, le_u8};
+use crate::utils::error::{Result, SMNtfsError};
+
+/// Record number of the $Volume system file
+pub const VOLUME_RECORD_NUMBER: u64 = 3;
+
+/// Volume state flags (VOLUME_*)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct VolumeFlags(pub u16);
+
+impl VolumeFlags {
+    /// Not cleanly unmounted; chkdsk is scheduled for the next mount
+    pub const IS_DIRTY: u16 = 0x0001;
+    /// $LogFile is to be resized
+    pub const RESIZE_LOG_FILE: u16 = 0x0002;
+    /// Metadata is to be upgraded to the current NTFS version on mount
+    pub const UPGRADE_ON_MOUNT: u16 = 0x0004;
+    /// Mounted by Windows NT 4
+    pub const MOUNTED_ON_NT4: u16 = 0x0008;
+    /// The change journal is being deleted
+    pub const DELETE_USN_UNDERWAY: u16 = 0x0010;
+    /// $ObjId is to be repaired
+    pub const REPAIR_OBJECT_ID: u16 = 0x0020;
+    /// chkdsk was started and has not finished
+    pub const CHKDSK_UNDERWAY: u16 = 0x4000;
+    /// chkdsk changed the volume
+    pub const MODIFIED_BY_CHKDSK: u16 = 0x8000;
+
+    const NAMES: [(u16, &'static str); 8] = [
+        (Self::IS_DIRTY, "IS_DIRTY"),
+        (Self::RESIZE_LOG_FILE, "RESIZE_LOG_FILE"),
+        (Self::UPGRADE_ON_MOUNT, "UPGRADE_ON_MOUNT"),
+        (Self::MOUNTED_ON_NT4, "MOUNTED_ON_NT4"),
+        (Self::DELETE_USN_UNDERWAY, "DELETE_USN_UNDERWAY"),
+        (Self::REPAIR_OBJECT_ID, "REPAIR_OBJECT_ID"),
+        (Self::CHKDSK_UNDERWAY, "CHKDSK_UNDERWAY"),
+        (Self::MODIFIED_BY_CHKDSK, "MODIFIED_BY_CHKDSK"),
+    ];
+
+    /// Check whether all bits of `flag` are set
+    pub fn contains(&self, flag: u16) -> bool {
+        self.0 & flag == flag
+    }
+
+    /// Names of the flags that are set, e.g. `["IS_DIRTY"]`
+    pub fn names(&self) -> Vec<&'static str> {
+        Self::NAMES
+            .iter()
+            .filter(|(flag, _)| self.contains(*flag))
+            .map(|(_, name)| *name)
+            .collect()
+    }
+}
+
+/// Decoded $VOLUME_INFORMATION value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VolumeInformation {
+    /// NTFS major version (3 for Windows 2000 and later)
+    pub major_version: u8,
+
+    /// NTFS minor version (1 for Windows XP and later)
+    pub minor_version: u8,
+
+    /// Volume state flags
+    pub flags: VolumeFlags,
+}
+
+impl VolumeInformation {
+    /// Decode a $VOLUME_INFORMATION value
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        if data.len() < 12 {
+            return Err(SMNtfsError::InvalidNtfs(format!(
+                "Truncated $VOLUME_INFORMATION of {} bytes",
+                data.len()
+            )));
+        }
+        Ok(Self {
+            major_version: le_u8(data, 0x08),
+            minor_version: le_u8(data, 0x09),
+            flags: VolumeFlags(le_u16(data, 0x0A)),
+        })
+    }
+
+    /// Read the $VOLUME_INFORMATION of $Volume
+    pub fn read<T: Read + Seek>(ntfs: &Ntfs, fs: &mut T) -> Result<Self> {
+        let info = ntfs
+            .volume_info(fs)
+            .map_err(|e| SMNtfsError::InvalidNtfs(format!("Failed to read $VOLUME_INFORMATION: {}", e)))?;
+        Ok(Self {
+            major_version: info.major_version(),
+            minor_version: info.minor_version(),
+            flags: VolumeFlags(info.flags().bits()),
+        })
+    }
+
+    /// Version as `major.minor`, e.g. `3.1`
+    pub fn version(&self) -> String {
+        format!("{}.{}", self.major_version, self.minor_version)
+    }
+
+    /// Check if the volume was not cleanly unmounted
+    pub fn is_dirty(&self) -> bool {
+        self.flags.contains(VolumeFlags::IS_DIRTY)
+    }
+
+    /// Check if Windows will run chkdsk on the volume (dirty or interrupted chkdsk)
+    pub fn is_chkdsk_scheduled(&self) -> bool {
+        self.is_dirty() || self.flags.contains(VolumeFlags::CHKDSK_UNDERWAY)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let mut data = vec![0u8; 12];
+        data[0x08] = 3;
+        data[0x09] = 1;
+        data[0x0A..0x0C].copy_from_slice(&(VolumeFlags::IS_DIRTY | VolumeFlags::MODIFIED_BY_CHKDSK).to_le_bytes());
+        let info = VolumeInformation::parse(&data).unwrap();
+
+        assert_eq!(info.version(), "3.1");
+        assert!(info.is_dirty());
+        assert!(info.is_chkdsk_scheduled());
+        assert_eq!(info.flags.names(), vec!["IS_DIRTY", "MODIFIED_BY_CHKDSK"]);
+        assert!(VolumeInformation::parse(&data[..8]).is_err());
+    }
+}
@@ -0,0 +1,233 @@
+//! Windows Overlay Filter (WOF) compressed files
+//!
+//! `compact /exe` and CompactOS leave the default $DATA stream sparse and
+//! empty, with only its data size set, and store the file in a
+//! `WofCompressedData` stream instead. A WOF reparse point names the
+//! algorithm. The stream starts with a table of chunk end offsets, one per
+//! chunk but the last (32-bit, or 64-bit for files over 4 GiB), counted from
+//! the end of the table. Chunks are compressed independently; a chunk stored
+//! at its uncompressed size is kept as is.
+//!
+//! [`StreamReader::open`](crate::parser::StreamReader::open) detects these
+//! files and decompresses them on the fly.
+
+use std::ops::Range;
+use crate::parser::reparse::{ReparsePoint, ReparseTag};
+use crate::parser::{lzx, xpress};
+use crate::utils::bytes::{le_u32, le_u64};
+use crate::utils::error::{Result, SMNtfsError};
+
+/// Name of the stream holding the compressed data
+pub const WOF_STREAM_NAME: &str = "WofCompressedData";
+
+/// WOF providers
+pub mod provider {
+    /// Data lives in a WIM image
+    pub const WIM: u32 = 1;
+    /// Data lives in the file's `WofCompressedData` stream
+    pub const FILE: u32 = 2;
+}
+
+/// Compression algorithm of a WOF file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WofAlgorithm {
+    Xpress4k,
+    Lzx,
+    Xpress8k,
+    Xpress16k,
+}
+
+impl WofAlgorithm {
+    /// Decode the algorithm number of the file provider
+    pub fn from_u32(value: u32) -> Option<Self> {
+        match value {
+            0 => Some(Self::Xpress4k),
+            1 => Some(Self::Lzx),
+            2 => Some(Self::Xpress8k),
+            3 => Some(Self::Xpress16k),
+            _ => None,
+        }
+    }
+
+    /// Uncompressed size of a chunk
+    pub fn chunk_size(self) -> u64 {
+        match self {
+            Self::Xpress4k => 4096,
+            Self::Xpress8k => 8192,
+            Self::Xpress16k => 16384,
+            Self::Lzx => 32768,
+        }
+    }
+
+    /// Name as used by `compact /exe`
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Xpress4k => "XPRESS4K",
+            Self::Lzx => "LZX",
+            Self::Xpress8k => "XPRESS8K",
+            Self::Xpress16k => "XPRESS16K",
+        }
+    }
+
+    /// Decompress one chunk filling `output`
+    pub fn decompress(self, input: &[u8], output: &mut [u8]) -> Result<()> {
+        match self {
+            Self::Lzx => lzx::decompress(input, output),
+            _ => xpress::decompress(input, output),
+        }
+    }
+
+    /// Algorithm of a WOF reparse point, `None` for other reparse points
+    ///
+    /// WIM-backed files and unknown algorithms are errors, since their data
+    /// is not on the volume in a form this parser can read.
+    pub fn from_reparse_point(point: &ReparsePoint) -> Result<Option<Self>> {
+        let ReparsePoint::Other { tag, data } = point else {
+            return Ok(None);
+        };
+        if tag.0 != ReparseTag::WOF {
+            return Ok(None);
+        }
+        if data.len() < 16 {
+            return Err(SMNtfsError::ReadError(format!("Truncated WOF reparse data of {} bytes", data.len())));
+        }
+
+        match le_u32(data, 0x04) {
+            provider::FILE => {}
+            provider::WIM => {
+                return Err(SMNtfsError::ReadError("WOF file is backed by a WIM image".to_string()))
+            }
+            other => return Err(SMNtfsError::ReadError(format!("Unknown WOF provider {}", other))),
+        }
+        let algorithm = le_u32(data, 0x0C);
+        Self::from_u32(algorithm)
+            .map(Some)
+            .ok_or_else(|| SMNtfsError::ReadError(format!("Unknown WOF compression algorithm {}", algorithm)))
+    }
+}
+
+/// Chunk layout of a `WofCompressedData` stream
+#[derive(Debug, Clone)]
+pub struct WofChunks {
+    /// Compression algorithm
+    pub algorithm: WofAlgorithm,
+
+    /// Uncompressed size, the data size of the default stream
+    pub size: u64,
+
+    /// Stored offset of every chunk, plus the end of the last one
+    offsets: Vec<u64>,
+}
+
+impl WofChunks {
+    /// Number of chunks of a file
+    pub fn chunk_count(algorithm: WofAlgorithm, size: u64) -> u64 {
+        size.div_ceil(algorithm.chunk_size())
+    }
+
+    /// Size of the chunk table at the start of the stream
+    pub fn table_size(algorithm: WofAlgorithm, size: u64) -> u64 {
+        let entry_size = if size > u32::MAX as u64 { 8 } else { 4 };
+        Self::chunk_count(algorithm, size).saturating_sub(1) * entry_size
+    }
+
+    /// Parse the chunk table of a stream of `stream_size` bytes
+    pub fn parse(algorithm: WofAlgorithm, size: u64, table: &[u8], stream_size: u64) -> Result<Self> {
+        let table_size = Self::table_size(algorithm, size);
+        if (table.len() as u64) < table_size || stream_size < table_size {
+            return Err(SMNtfsError::ReadError(format!(
+                "WOF chunk table of {} bytes is truncated",
+                table_size
+            )));
+        }
+
+        let count = Self::chunk_count(algorithm, size) as usize;
+        let mut offsets = vec![table_size];
+        for i in 0..count.saturating_sub(1) {
+            let end = match size > u32::MAX as u64 {
+                true => le_u64(table, i * 8),
+                false => le_u32(table, i * 4) as u64,
+            };
+            offsets.push(table_size + end);
+        }
+        if count > 0 {
+            offsets.push(stream_size);
+        }
+
+        if offsets.windows(2).any(|pair| pair[0] > pair[1]) {
+            return Err(SMNtfsError::ReadError("WOF chunk table is out of order".to_string()));
+        }
+        Ok(Self {
+            algorithm,
+            size,
+            offsets,
+        })
+    }
+
+    /// Stored byte range of a chunk in the stream
+    pub fn stored_range(&self, index: u64) -> Option<Range<u64>> {
+        let index = index as usize;
+        Some(*self.offsets.get(index)?..*self.offsets.get(index + 1)?)
+    }
+
+    /// Uncompressed size of a chunk
+    pub fn chunk_size(&self, index: u64) -> u64 {
+        let chunk_size = self.algorithm.chunk_size();
+        chunk_size.min(self.size.saturating_sub(index * chunk_size))
+    }
+
+    /// Decode a chunk from its stored bytes
+    pub fn decode(&self, index: u64, stored: &[u8]) -> Result<Vec<u8>> {
+        let size = self.chunk_size(index) as usize;
+        if stored.len() == size {
+            return Ok(stored.to_vec());
+        }
+
+        let mut data = vec![0u8; size];
+        self.algorithm
+            .decompress(stored, &mut data)
+            .map_err(|e| SMNtfsError::ReadError(format!("WOF chunk {}: {}", index, e)))?;
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// WOF reparse data for the file provider
+    pub(crate) fn wof_reparse_point(algorithm: u32) -> ReparsePoint {
+        let data = [1u32, provider::FILE, 1, algorithm].iter().flat_map(|v| v.to_le_bytes()).collect();
+        ReparsePoint::Other {
+            tag: ReparseTag(ReparseTag::WOF),
+            data,
+        }
+    }
+
+    #[test]
+    fn test_reparse_point() {
+        assert_eq!(
+            WofAlgorithm::from_reparse_point(&wof_reparse_point(1)).unwrap(),
+            Some(WofAlgorithm::Lzx)
+        );
+        assert_eq!(WofAlgorithm::from_reparse_point(&ReparsePoint::Fifo).unwrap(), None);
+        assert!(WofAlgorithm::from_reparse_point(&wof_reparse_point(7)).is_err());
+    }
+
+    #[test]
+    fn test_chunk_table() {
+        // Three 4 KiB chunks: 100 bytes, 4096 bytes stored as is, and a 10 byte tail
+        let table: Vec<u8> = [100u32, 4196].iter().flat_map(|v| v.to_le_bytes()).collect();
+        let chunks = WofChunks::parse(WofAlgorithm::Xpress4k, 8192 + 10, &table, 8 + 4196 + 7).unwrap();
+
+        assert_eq!(chunks.stored_range(0), Some(8..108));
+        assert_eq!(chunks.stored_range(1), Some(108..4204));
+        assert_eq!(chunks.stored_range(2), Some(4204..4211));
+        assert_eq!(chunks.stored_range(3), None);
+        assert_eq!(chunks.chunk_size(2), 10);
+        assert_eq!(chunks.decode(1, &[7u8; 4096]).unwrap(), vec![7u8; 4096]);
+
+        assert!(WofChunks::parse(WofAlgorithm::Xpress4k, 8192 + 10, &table[..4], 4211).is_err());
+        assert!(WofChunks::parse(WofAlgorithm::Xpress4k, 8192 + 10, &table, 100).is_err());
+    }
+}
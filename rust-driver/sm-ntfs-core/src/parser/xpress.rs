@@ -0,0 +1,151 @@
+//! XPRESS Huffman decompression
+//!
+//! Used by WOF for its XPRESS4K/8K/16K algorithms, one independent block per
+//! chunk. A block starts with 256 bytes holding 4-bit code lengths for 512
+//! symbols: 256 literals and 256 matches whose low nibble is a length header
+//! and high nibble the number of offset bits. The bitstream that follows is
+//! read 16 bits at a time, while long match lengths are taken as whole bytes
+//! from wherever the bitstream has got to.
+
+use crate::parser::huffman::HuffmanTable;
+use crate::utils::bytes::{le_u16, le_u32, le_u8};
+use crate::utils::error::{Result, SMNtfsError};
+
+/// Size of the code length table at the start of a block
+const TABLE_SIZE: usize = 256;
+
+/// Longest code
+const MAX_CODE_LENGTH: u32 = 15;
+
+/// Decompress one XPRESS Huffman block filling `output`
+pub fn decompress(input: &[u8], output: &mut [u8]) -> Result<()> {
+    if input.len() < TABLE_SIZE + 4 {
+        return Err(SMNtfsError::ReadError(format!(
+            "XPRESS block of {} bytes is too short",
+            input.len()
+        )));
+    }
+
+    let lengths: Vec<u8> = input[..TABLE_SIZE].iter().flat_map(|byte| [byte & 0x0F, byte >> 4]).collect();
+    let table = HuffmanTable::new(&lengths, MAX_CODE_LENGTH)?;
+
+    let mut position = TABLE_SIZE + 4;
+    let mut bits = ((le_u16(input, TABLE_SIZE) as u32) << 16) | le_u16(input, TABLE_SIZE + 2) as u32;
+    let mut extra_bits = 16i32;
+    let mut consume = |bits: &mut u32, position: &mut usize, count: u32| {
+        *bits = bits.checked_shl(count).unwrap_or(0);
+        extra_bits -= count as i32;
+        if extra_bits < 0 {
+            *bits |= (le_u16(input, *position) as u32) << -extra_bits;
+            extra_bits += 16;
+            *position += 2;
+        }
+    };
+
+    let mut out = 0;
+    while out < output.len() {
+        let (symbol, length) = table.decode(bits >> (32 - MAX_CODE_LENGTH))?;
+        consume(&mut bits, &mut position, length);
+
+        if symbol < 256 {
+            output[out] = symbol as u8;
+            out += 1;
+            continue;
+        }
+
+        let offset_bits = ((symbol - 256) >> 4) as u32;
+        let mut length = ((symbol - 256) & 0x0F) as usize;
+        if length == 15 {
+            length = le_u8(input, position) as usize;
+            position += 1;
+            if length == 255 {
+                length = le_u16(input, position) as usize;
+                position += 2;
+                if length == 0 {
+                    length = le_u32(input, position) as usize;
+                    position += 4;
+                }
+                length = length
+                    .checked_sub(15)
+                    .ok_or_else(|| SMNtfsError::ReadError("Invalid XPRESS match length".to_string()))?;
+            }
+            length += 15;
+        }
+        length += 3;
+
+        let offset = ((bits.checked_shr(32 - offset_bits).unwrap_or(0)) as usize) + (1 << offset_bits);
+        consume(&mut bits, &mut position, offset_bits);
+
+        if offset > out {
+            return Err(SMNtfsError::ReadError(format!(
+                "XPRESS match {} bytes before the start of the block",
+                offset - out
+            )));
+        }
+        // Byte by byte, since the source may overlap what is being written
+        for i in out..(out + length).min(output.len()) {
+            output[i] = output[i - offset];
+        }
+        out += length;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::parser::huffman::tests::pack_bits;
+
+    /// A block decoding to `length` bytes of `abab...`
+    pub(crate) fn abab_block(length: usize) -> Vec<u8> {
+        let mut bits = vec![(0b0, 1), (0b10, 2)];
+        let mut produced = 2;
+        while produced < length {
+            match length - produced {
+                6.. => bits.extend([(0b11, 2), (0, 1)]),
+                _ if produced % 2 == 0 => bits.push((0b0, 1)),
+                _ => bits.push((0b10, 2)),
+            }
+            produced += if length - produced >= 6 { 6 } else { 1 };
+        }
+        block(&bits)
+    }
+
+    /// A block with codes 'a' = 0, 'b' = 10 and a 6 byte match with 1 offset bit = 11
+    fn block(bits: &[(u32, u32)]) -> Vec<u8> {
+        let mut lengths = [0u8; 512];
+        lengths[b'a' as usize] = 1;
+        lengths[b'b' as usize] = 2;
+        lengths[256 + (1 << 4) + 3] = 2;
+
+        let mut input: Vec<u8> = lengths.chunks(2).map(|pair| pair[0] | (pair[1] << 4)).collect();
+        input.extend(pack_bits(bits));
+        input.resize(input.len() + 4, 0);
+        input
+    }
+
+    #[test]
+    fn test_literals_and_match() {
+        // a, b, then 6 bytes from offset 2 + 0
+        let input = block(&[(0b0, 1), (0b10, 2), (0b11, 2), (0, 1)]);
+        let mut output = [0u8; 8];
+        decompress(&input, &mut output).unwrap();
+        assert_eq!(&output, b"abababab");
+    }
+
+    #[test]
+    fn test_long_block() {
+        let mut output = vec![0u8; 4096];
+        decompress(&abab_block(4096), &mut output).unwrap();
+        assert!(output.chunks(2).all(|pair| pair == b"ab"));
+    }
+
+    #[test]
+    fn test_invalid_blocks() {
+        // A match before any literal
+        let input = block(&[(0b11, 2), (0, 1)]);
+        assert!(decompress(&input, &mut [0u8; 8]).is_err());
+        assert!(decompress(&[0u8; 16], &mut [0u8; 8]).is_err());
+    }
+}
@@ -0,0 +1,67 @@
+//! Little-endian field access for on-disk structures
+//!
+//! Reads past the end of the slice return zero instead of panicking, so
+//! parsers of damaged metadata can validate lengths where it matters and
+//! otherwise degrade gracefully.
+
+/// Read a `u8` at `offset`
+pub(crate) fn le_u8(data: &[u8], offset: usize) -> u8 {
+    data.get(offset).copied().unwrap_or(0)
+}
+
+/// Read a little-endian `u16` at `offset`
+pub(crate) fn le_u16(data: &[u8], offset: usize) -> u16 {
+    data.get(offset..offset + 2)
+        .map(|b| u16::from_le_bytes([b[0], b[1]]))
+        .unwrap_or(0)
+}
+
+/// Read a little-endian `u32` at `offset`
+pub(crate) fn le_u32(data: &[u8], offset: usize) -> u32 {
+    data.get(offset..offset + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .unwrap_or(0)
+}
+
+/// Read a little-endian `u64` at `offset`
+pub(crate) fn le_u64(data: &[u8], offset: usize) -> u64 {
+    data.get(offset..offset + 8)
+        .map(|b| {
+            let mut buf = [0u8; 8];
+            buf.copy_from_slice(b);
+            u64::from_le_bytes(buf)
+        })
+        .unwrap_or(0)
+}
+
+/// Decode a UTF-16LE string of `length` code units at `offset`
+pub(crate) fn utf16_string(data: &[u8], offset: usize, length: usize) -> String {
+    let units: Vec<u16> = (0..length).map(|i| le_u16(data, offset + i * 2)).collect();
+    String::from_utf16_lossy(&units)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_le_reads() {
+        let data = [0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08];
+        assert_eq!(le_u16(&data, 0), 0x0201);
+        assert_eq!(le_u32(&data, 4), 0x0807_0605);
+        assert_eq!(le_u64(&data, 0), 0x0807_0605_0403_0201);
+    }
+
+    #[test]
+    fn test_out_of_bounds_reads_zero() {
+        let data = [0xFF; 3];
+        assert_eq!(le_u32(&data, 0), 0);
+        assert_eq!(le_u8(&data, 10), 0);
+    }
+
+    #[test]
+    fn test_utf16_string() {
+        let data = [b'$', 0, b'M', 0, b'F', 0, b'T', 0];
+        assert_eq!(utf16_string(&data, 0, 4), "$MFT");
+    }
+}
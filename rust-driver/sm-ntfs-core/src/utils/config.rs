@@ -1,6 +1,7 @@
 //! Configuration management
 
 use serde::{Deserialize, Serialize};
+use crate::cache::CachePolicy;
 
 /// Configuration for SM-NTFS
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -8,6 +9,10 @@ pub struct Config {
     /// Cache size in megabytes
     pub cache_size_mb: usize,
 
+    /// Cache replacement policy
+    #[serde(default)]
+    pub cache_policy: CachePolicy,
+
     /// Write-back buffer size in megabytes
     pub write_buffer_size_mb: usize,
 
@@ -22,6 +27,7 @@ impl Default for Config {
     fn default() -> Self {
         Self {
             cache_size_mb: 64,
+            cache_policy: CachePolicy::default(),
             write_buffer_size_mb: 32,
             enable_read_ahead: true,
             enable_write_coalescing: true,
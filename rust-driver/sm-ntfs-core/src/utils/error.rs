@@ -22,9 +22,61 @@ pub enum SMNtfsError {
     #[error("Corrupted MFT entry at offset {offset}")]
     CorruptedMft { offset: u64 },
 
+    #[error("Volume is encrypted with BitLocker")]
+    BitLockerVolume,
+
+    #[error("Invalid BitLocker metadata: {0}")]
+    BitLockerMetadata(String),
+
+    #[error("BitLocker key rejected: {0}")]
+    BitLockerKeyRejected(String),
+
+    #[error("Volume is ReFS, not NTFS")]
+    RefsVolume,
+
+    #[error("Volume is exFAT, not NTFS")]
+    ExfatVolume,
+
+    #[error("Volume is {0}, not NTFS")]
+    FatVolume(String),
+
+    #[error("Volume is APFS, not NTFS")]
+    ApfsVolume,
+
+    #[error("Volume is HFS+, not NTFS")]
+    HfsPlusVolume,
+
+    #[error("Volume is {0}, not NTFS")]
+    ExtVolume(String),
+
     #[error("Journal replay failed: {0}")]
     JournalError(String),
 
+    #[error("Windows is hibernated: {0}")]
+    VolumeHibernated(String),
+
+    #[error("Volume was not shut down cleanly: {0}")]
+    UncleanShutdown(String),
+
+    #[error("USN {requested} is older than the oldest change journal record (USN {lowest_valid})")]
+    UsnUnavailable { requested: u64, lowest_valid: u64 },
+
+    // Path Errors
+    #[error("Invalid path: {0}")]
+    InvalidPath(String),
+
+    #[error("'{component}' not found while resolving '{path}'")]
+    PathNotFound { path: String, component: String },
+
+    #[error("'{component}' is not a directory while resolving '{path}'")]
+    NotADirectory { path: String, component: String },
+
+    #[error("Stream '{stream}' not found on '{path}'")]
+    StreamNotFound { path: String, stream: String },
+
+    #[error("Invalid user mapping: {0}")]
+    InvalidUserMapping(String),
+
     // Mount Errors
     #[error("Mount failed: {0}")]
     MountFailed(String),
@@ -66,6 +118,48 @@ impl SMNtfsError {
             Self::InvalidNtfs(_) => {
                 "This is not a valid NTFS volume.".to_string()
             }
+            Self::BitLockerVolume => {
+                "This volume is encrypted with BitLocker. Unlock it with its recovery password, \
+                 password or startup key file."
+                    .to_string()
+            }
+            Self::BitLockerKeyRejected(reason) => {
+                format!("Could not unlock the BitLocker volume: {}.", reason)
+            }
+            Self::RefsVolume => {
+                "This is a ReFS volume, which only Windows can read.".to_string()
+            }
+            Self::ExfatVolume => {
+                "This is an exFAT volume. macOS can read and write it without SM-NTFS.".to_string()
+            }
+            Self::FatVolume(kind) => {
+                format!("This is a {} volume. macOS can read and write it without SM-NTFS.", kind)
+            }
+            Self::ApfsVolume => {
+                "This is an APFS volume, which macOS mounts natively.".to_string()
+            }
+            Self::HfsPlusVolume => {
+                "This is an HFS+ (Mac OS Extended) volume, which macOS mounts natively.".to_string()
+            }
+            Self::ExtVolume(kind) => {
+                format!("This is a Linux {} volume, which SM-NTFS cannot read.", kind)
+            }
+            Self::VolumeHibernated(_) => {
+                "Windows is hibernated or was shut down with Fast Startup, and writing now would corrupt \
+                 data when it resumes. Shut Windows down fully (or turn off Fast Startup), or mount read-only."
+                    .to_string()
+            }
+            Self::UncleanShutdown(_) => {
+                "The volume was not shut down cleanly. Replay its journal or run chkdsk on Windows, \
+                 or mount read-only."
+                    .to_string()
+            }
+            Self::PathNotFound { component, .. } => {
+                format!("'{}' does not exist.", component)
+            }
+            Self::NotADirectory { component, .. } => {
+                format!("'{}' is a file, not a folder.", component)
+            }
             Self::AlreadyMounted(path) => {
                 format!("Already mounted at '{}'", path)
             }
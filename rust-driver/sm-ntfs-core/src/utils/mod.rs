@@ -3,5 +3,6 @@
 pub mod error;
 pub mod logging;
 pub mod config;
+pub mod bytes;
 
 pub use error::{SMNtfsError, Result};